@@ -0,0 +1,308 @@
+//! The WebSocket bridge to the browser extension
+//!
+//! The server listens on `bridge_port`, or on one of the next few ports when
+//! something else holds it. The endpoint it ends up on is published in
+//! `~/.noet/bridge.json`, which the Native Messaging host passes to the extension.
+//! Once connected, the extension is pinged regularly so a dead connection is
+//! noticed even while no request is in flight.
+
+use crate::error::{NoetError, Result};
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio::time::{interval_at, Instant, MissedTickBehavior};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+const DISCOVERY_DIR: &str = ".noet";
+const DISCOVERY_FILE: &str = "bridge.json";
+
+/// Ports tried after `bridge_port` before letting the OS pick one
+const FALLBACK_PORTS: u16 = 10;
+
+/// How often the extension is pinged
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+/// The connection is given up after this long without hearing from the extension
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(45);
+
+/// Error code of requests that were in flight when the connection dropped
+pub const DISCONNECTED: &str = "DISCONNECTED";
+
+/// Error code of requests the extension never connected for
+pub const NOT_CONNECTED: &str = "NOT_CONNECTED";
+
+/// Error code when only peers failing authentication connected
+pub const UNAUTHORIZED: &str = "UNAUTHORIZED";
+
+/// Contents of the discovery file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Endpoint {
+    pub url: String,
+    pub port: u16,
+    /// Process serving the endpoint
+    pub pid: u32,
+    pub version: String,
+}
+
+impl Endpoint {
+    fn new(port: u16) -> Self {
+        Self {
+            url: format!("ws://127.0.0.1:{port}"),
+            port,
+            pid: std::process::id(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+}
+
+/// Path of the discovery file (`~/.noet/bridge.json`)
+pub fn discovery_path() -> Result<PathBuf> {
+    let home = dirs::home_dir()
+        .ok_or_else(|| NoetError::ConfigError("ホームディレクトリが見つかりません".to_string()))?;
+    Ok(home.join(DISCOVERY_DIR).join(DISCOVERY_FILE))
+}
+
+/// Listen on `preferred`, falling back to a nearby port and then any free one
+pub async fn bind(preferred: u16) -> Result<TcpListener> {
+    for port in candidates(preferred) {
+        match TcpListener::bind(("127.0.0.1", port)).await {
+            Ok(listener) => {
+                if port != preferred {
+                    log::warn!("Port {preferred} is in use, listening on {port} instead");
+                }
+                return Ok(listener);
+            }
+            Err(e) => log::debug!("Port {port} unavailable: {e}"),
+        }
+    }
+
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await.map_err(|e| {
+        NoetError::Network(format!("WebSocket サーバーを起動できませんでした: {e}"))
+    })?;
+    log::warn!(
+        "Ports {preferred}-{} are in use, listening on {} instead",
+        preferred.saturating_add(FALLBACK_PORTS),
+        listener.local_addr()?.port()
+    );
+    Ok(listener)
+}
+
+fn candidates(preferred: u16) -> impl Iterator<Item = u16> {
+    (0..=FALLBACK_PORTS)
+        .map_while(move |i| preferred.checked_add(i))
+        .filter(|port| *port != 0)
+}
+
+/// The published endpoint. The file is removed again when this is dropped.
+#[derive(Debug)]
+pub struct Published {
+    path: PathBuf,
+}
+
+impl Drop for Published {
+    fn drop(&mut self) {
+        // Leave it alone if another process has published since
+        if read_from(&self.path).is_ok_and(|e| e.is_some_and(|e| e.pid == std::process::id())) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Publish the endpoint for `port` in the discovery file
+pub fn publish(port: u16) -> Result<Published> {
+    publish_to(&discovery_path()?, port)
+}
+
+fn publish_to(path: &Path, port: u16) -> Result<Published> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // Write then rename, so a reader never sees half a file
+    let temp = path.with_extension("json.tmp");
+    fs::write(&temp, serde_json::to_string_pretty(&Endpoint::new(port))?)?;
+    fs::rename(&temp, path)?;
+
+    Ok(Published {
+        path: path.to_path_buf(),
+    })
+}
+
+/// The currently published endpoint, if any
+pub fn read() -> Result<Option<Endpoint>> {
+    read_from(&discovery_path()?)
+}
+
+fn read_from(path: &Path) -> Result<Option<Endpoint>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = fs::read_to_string(path)?;
+    Ok(Some(serde_json::from_str(&content)?))
+}
+
+/// Why a connection ended
+#[derive(Debug, Clone, PartialEq)]
+pub enum Disconnect {
+    /// Either side closed it
+    Closed,
+    /// The extension stopped answering pings
+    Unresponsive,
+    Error(String),
+}
+
+impl fmt::Display for Disconnect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Disconnect::Closed => write!(f, "接続が閉じられました"),
+            Disconnect::Unresponsive => write!(
+                f,
+                "拡張機能が {} 秒間応答しませんでした",
+                HEARTBEAT_TIMEOUT.as_secs()
+            ),
+            Disconnect::Error(e) => write!(f, "通信エラー: {e}"),
+        }
+    }
+}
+
+/// Run a connection: send `outgoing`, pass incoming text to `incoming`, and ping
+/// the extension. Returns once the connection is gone; dropping the sender of
+/// `outgoing` closes it.
+pub async fn pump<S>(
+    ws_stream: WebSocketStream<S>,
+    mut outgoing: mpsc::Receiver<String>,
+    incoming: mpsc::Sender<String>,
+) -> Disconnect
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut write, mut read) = ws_stream.split();
+    let mut heartbeat = interval_at(Instant::now() + HEARTBEAT_INTERVAL, HEARTBEAT_INTERVAL);
+    heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut last_seen = Instant::now();
+
+    loop {
+        tokio::select! {
+            msg = read.next() => match msg {
+                Some(Ok(Message::Text(text))) => {
+                    last_seen = Instant::now();
+                    if incoming.send(text).await.is_err() {
+                        let _ = write.send(Message::Close(None)).await;
+                        return Disconnect::Closed;
+                    }
+                }
+                Some(Ok(Message::Close(_))) | None => return Disconnect::Closed,
+                // Pongs, and pings which tungstenite answers by itself
+                Some(Ok(_)) => last_seen = Instant::now(),
+                Some(Err(e)) => return Disconnect::Error(e.to_string()),
+            },
+            msg = outgoing.recv() => match msg {
+                Some(text) => {
+                    if let Err(e) = write.send(Message::Text(text)).await {
+                        return Disconnect::Error(e.to_string());
+                    }
+                }
+                None => {
+                    let _ = write.send(Message::Close(None)).await;
+                    return Disconnect::Closed;
+                }
+            },
+            _ = heartbeat.tick() => {
+                if last_seen.elapsed() >= HEARTBEAT_TIMEOUT {
+                    return Disconnect::Unresponsive;
+                }
+                if let Err(e) = write.send(Message::Ping(Vec::new())).await {
+                    return Disconnect::Error(e.to_string());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_candidates() {
+        let ports: Vec<u16> = candidates(9876).collect();
+        assert_eq!(ports.first(), Some(&9876));
+        assert_eq!(ports.last(), Some(&9886));
+
+        assert_eq!(candidates(65535).collect::<Vec<_>>(), vec![65535]);
+        assert_eq!(candidates(0).next(), Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_bind_falls_back_when_taken() {
+        let taken = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = taken.local_addr().unwrap().port();
+
+        let listener = bind(port).await.unwrap();
+        assert_ne!(listener.local_addr().unwrap().port(), port);
+    }
+
+    #[tokio::test]
+    async fn test_pump_relays_until_closed() {
+        let (server_io, client_io) = tokio::io::duplex(4096);
+        let (client, server) = tokio::join!(
+            tokio_tungstenite::client_async("ws://127.0.0.1/", client_io),
+            tokio_tungstenite::accept_async(server_io)
+        );
+        let (mut client, _) = client.unwrap();
+
+        let (tx, outgoing) = mpsc::channel(1);
+        let (incoming_tx, mut incoming) = mpsc::channel(1);
+        let pump = tokio::spawn(pump(server.unwrap(), outgoing, incoming_tx));
+
+        tx.send("request".to_string()).await.unwrap();
+        assert_eq!(
+            client.next().await.unwrap().unwrap(),
+            Message::Text("request".to_string())
+        );
+        client
+            .send(Message::Text("response".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(incoming.recv().await.as_deref(), Some("response"));
+
+        client.close(None).await.unwrap();
+        assert_eq!(pump.await.unwrap(), Disconnect::Closed);
+        assert!(tx.is_closed());
+    }
+
+    #[test]
+    fn test_publish_and_remove() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join(".noet/bridge.json");
+
+        let published = publish_to(&path, 9877).unwrap();
+        let endpoint = read_from(&path).unwrap().unwrap();
+        assert_eq!(endpoint.url, "ws://127.0.0.1:9877");
+        assert_eq!(endpoint.pid, std::process::id());
+
+        drop(published);
+        assert!(read_from(&path).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_keeps_a_newer_publication() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("bridge.json");
+
+        let published = publish_to(&path, 9876).unwrap();
+        let mut other = Endpoint::new(9877);
+        other.pid += 1;
+        fs::write(&path, serde_json::to_string(&other).unwrap()).unwrap();
+
+        drop(published);
+        assert_eq!(read_from(&path).unwrap(), Some(other));
+    }
+}
@@ -0,0 +1,332 @@
+//! Browsers the native messaging host can be registered with
+
+use clap::ValueEnum;
+use std::path::{Path, PathBuf};
+
+/// Name of the native messaging host, also the manifest's file name without `.json`
+pub const HOST_NAME: &str = "com.noet.host";
+
+/// ID of the extension in Firefox, set by `browser_specific_settings` in its manifest
+pub const FIREFOX_EXTENSION_ID: &str = "noet@kako-jun.github.io";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Browser {
+    Chrome,
+    Chromium,
+    Brave,
+    Edge,
+    Vivaldi,
+    Firefox,
+}
+
+impl Browser {
+    pub const ALL: [Browser; 6] = [
+        Browser::Chrome,
+        Browser::Chromium,
+        Browser::Brave,
+        Browser::Edge,
+        Browser::Vivaldi,
+        Browser::Firefox,
+    ];
+
+    /// Name shown to the user
+    pub fn name(self) -> &'static str {
+        match self {
+            Browser::Chrome => "Chrome",
+            Browser::Chromium => "Chromium",
+            Browser::Brave => "Brave",
+            Browser::Edge => "Edge",
+            Browser::Vivaldi => "Vivaldi",
+            Browser::Firefox => "Firefox",
+        }
+    }
+
+    pub fn is_firefox(self) -> bool {
+        self == Browser::Firefox
+    }
+
+    /// Page listing the installed extensions
+    pub fn extensions_page(self) -> &'static str {
+        match self {
+            Browser::Chrome | Browser::Chromium => "chrome://extensions",
+            Browser::Brave => "brave://extensions",
+            Browser::Edge => "edge://extensions",
+            Browser::Vivaldi => "vivaldi://extensions",
+            Browser::Firefox => "about:debugging#/runtime/this-firefox",
+        }
+    }
+
+    /// The browser's own directory under `home`, created the first time it runs
+    fn profile_dir(self, home: &Path) -> PathBuf {
+        #[cfg(target_os = "macos")]
+        let relative = match self {
+            Browser::Chrome => "Library/Application Support/Google/Chrome",
+            Browser::Chromium => "Library/Application Support/Chromium",
+            Browser::Brave => "Library/Application Support/BraveSoftware/Brave-Browser",
+            Browser::Edge => "Library/Application Support/Microsoft Edge",
+            Browser::Vivaldi => "Library/Application Support/Vivaldi",
+            Browser::Firefox => "Library/Application Support/Firefox",
+        };
+
+        #[cfg(target_os = "windows")]
+        let relative = match self {
+            Browser::Chrome => "AppData/Local/Google/Chrome/User Data",
+            Browser::Chromium => "AppData/Local/Chromium/User Data",
+            Browser::Brave => "AppData/Local/BraveSoftware/Brave-Browser/User Data",
+            Browser::Edge => "AppData/Local/Microsoft/Edge/User Data",
+            Browser::Vivaldi => "AppData/Local/Vivaldi/User Data",
+            Browser::Firefox => "AppData/Roaming/Mozilla/Firefox",
+        };
+
+        #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+        let relative = match self {
+            Browser::Chrome => ".config/google-chrome",
+            Browser::Chromium => ".config/chromium",
+            Browser::Brave => ".config/BraveSoftware/Brave-Browser",
+            Browser::Edge => ".config/microsoft-edge",
+            Browser::Vivaldi => ".config/vivaldi",
+            Browser::Firefox => ".mozilla",
+        };
+
+        home.join(relative)
+    }
+
+    /// Directory the browser reads host manifests from
+    ///
+    /// On Windows the browser finds the manifest through the registry, so it's
+    /// kept with noet's own files, one directory per browser.
+    pub fn manifest_dir(self, home: &Path) -> PathBuf {
+        #[cfg(target_os = "windows")]
+        {
+            home.join("AppData/Roaming/noet/NativeMessagingHosts")
+                .join(self.name().to_lowercase())
+        }
+
+        #[cfg(target_os = "macos")]
+        {
+            match self {
+                Browser::Firefox => {
+                    home.join("Library/Application Support/Mozilla/NativeMessagingHosts")
+                }
+                _ => self.profile_dir(home).join("NativeMessagingHosts"),
+            }
+        }
+
+        #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+        {
+            match self {
+                Browser::Firefox => home.join(".mozilla/native-messaging-hosts"),
+                _ => self.profile_dir(home).join("NativeMessagingHosts"),
+            }
+        }
+    }
+
+    pub fn manifest_path(self, home: &Path) -> PathBuf {
+        self.manifest_dir(home).join(format!("{HOST_NAME}.json"))
+    }
+
+    /// Registry key pointing the browser at the manifest
+    #[cfg(target_os = "windows")]
+    pub fn registry_key(self) -> String {
+        let vendor = match self {
+            Browser::Chrome => "Google\\Chrome",
+            Browser::Chromium => "Chromium",
+            Browser::Brave => "BraveSoftware\\Brave-Browser",
+            Browser::Edge => "Microsoft\\Edge",
+            Browser::Vivaldi => "Vivaldi",
+            Browser::Firefox => "Mozilla",
+        };
+        format!("HKCU\\Software\\{vendor}\\NativeMessagingHosts\\{HOST_NAME}")
+    }
+
+    /// Browsers that have been run by the user owning `home`
+    pub fn detect(home: &Path) -> Vec<Browser> {
+        Browser::ALL
+            .into_iter()
+            .filter(|browser| browser.profile_dir(home).is_dir())
+            .collect()
+    }
+
+    /// Host manifest letting `extension_id` start `exe`
+    ///
+    /// Chromium-based browsers list allowed origins, Firefox lists add-on IDs.
+    pub fn host_manifest(self, exe: &Path, extension_id: &str) -> serde_json::Value {
+        let mut manifest = serde_json::json!({
+            "name": HOST_NAME,
+            "description": "noet Native Messaging Host",
+            "path": exe.to_string_lossy(),
+            "type": "stdio",
+        });
+        if self.is_firefox() {
+            manifest["allowed_extensions"] = serde_json::json!([FIREFOX_EXTENSION_ID]);
+        } else {
+            manifest["allowed_origins"] =
+                serde_json::json!([format!("chrome-extension://{extension_id}/")]);
+        }
+        manifest
+    }
+
+    /// Open the extensions page, ignoring failures
+    pub fn open_extensions_page(self) {
+        let page = self.extensions_page();
+
+        #[cfg(target_os = "linux")]
+        {
+            // xdg-open doesn't handle chrome:// and friends, so start the browser itself
+            let command = match self {
+                Browser::Chrome => "google-chrome",
+                Browser::Chromium => "chromium",
+                Browser::Brave => "brave-browser",
+                Browser::Edge => "microsoft-edge",
+                Browser::Vivaldi => "vivaldi",
+                Browser::Firefox => "firefox",
+            };
+            let _ = std::process::Command::new(command).arg(page).spawn();
+        }
+
+        #[cfg(target_os = "macos")]
+        {
+            let app = match self {
+                Browser::Chrome => "Google Chrome",
+                Browser::Chromium => "Chromium",
+                Browser::Brave => "Brave Browser",
+                Browser::Edge => "Microsoft Edge",
+                Browser::Vivaldi => "Vivaldi",
+                Browser::Firefox => "Firefox",
+            };
+            let _ = std::process::Command::new("open")
+                .args(["-a", app, page])
+                .spawn();
+        }
+
+        #[cfg(target_os = "windows")]
+        {
+            let command = match self {
+                Browser::Chrome => "chrome",
+                Browser::Chromium => "chromium",
+                Browser::Brave => "brave",
+                Browser::Edge => "msedge",
+                Browser::Vivaldi => "vivaldi",
+                Browser::Firefox => "firefox",
+            };
+            let _ = std::process::Command::new("cmd")
+                .args(["/C", "start", "", command, page])
+                .spawn();
+        }
+    }
+}
+
+/// ID Chromium-based browsers give the extension when it's loaded unpacked from `dir`
+///
+/// It's derived from the directory's absolute path: the first 128 bits of its
+/// SHA-256, written with the letters a to p instead of hex digits.
+pub fn unpacked_extension_id(dir: &Path) -> String {
+    use sha2::{Digest, Sha256};
+
+    let path = std::fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+
+    #[cfg(target_os = "windows")]
+    let bytes: Vec<u8> = {
+        use std::os::windows::ffi::OsStrExt;
+        // Chrome hashes the UTF-16 path without the verbatim prefix canonicalize adds
+        let path = path.to_string_lossy();
+        let path = path.strip_prefix(r"\\?\").unwrap_or(&path);
+        std::ffi::OsStr::new(path)
+            .encode_wide()
+            .flat_map(u16::to_le_bytes)
+            .collect()
+    };
+
+    #[cfg(not(target_os = "windows"))]
+    let bytes = path.to_string_lossy().into_owned().into_bytes();
+
+    Sha256::digest(&bytes)[..16]
+        .iter()
+        .flat_map(|byte| [byte >> 4, byte & 0x0f])
+        .map(|nibble| (b'a' + nibble) as char)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_detect_installed_browsers() {
+        let home = TempDir::new().unwrap();
+        assert!(Browser::detect(home.path()).is_empty());
+
+        std::fs::create_dir_all(Browser::Brave.profile_dir(home.path())).unwrap();
+        std::fs::create_dir_all(Browser::Firefox.profile_dir(home.path())).unwrap();
+        assert_eq!(
+            Browser::detect(home.path()),
+            vec![Browser::Brave, Browser::Firefox]
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_linux_manifest_paths() {
+        let home = Path::new("/home/me");
+        assert_eq!(
+            Browser::Chromium.manifest_path(home),
+            Path::new("/home/me/.config/chromium/NativeMessagingHosts/com.noet.host.json")
+        );
+        assert_eq!(
+            Browser::Brave.manifest_dir(home),
+            Path::new("/home/me/.config/BraveSoftware/Brave-Browser/NativeMessagingHosts")
+        );
+        assert_eq!(
+            Browser::Firefox.manifest_dir(home),
+            Path::new("/home/me/.mozilla/native-messaging-hosts")
+        );
+    }
+
+    #[test]
+    fn test_host_manifest() {
+        let exe = Path::new("/usr/bin/noet");
+
+        let chromium = Browser::Edge.host_manifest(exe, "abcdefghijklmnopabcdefghijklmnop");
+        assert_eq!(chromium["name"], HOST_NAME);
+        assert_eq!(chromium["path"], "/usr/bin/noet");
+        assert_eq!(
+            chromium["allowed_origins"],
+            serde_json::json!(["chrome-extension://abcdefghijklmnopabcdefghijklmnop/"])
+        );
+        assert!(chromium.get("allowed_extensions").is_none());
+
+        let firefox = Browser::Firefox.host_manifest(exe, "abcdefghijklmnopabcdefghijklmnop");
+        assert_eq!(
+            firefox["allowed_extensions"],
+            serde_json::json!([FIREFOX_EXTENSION_ID])
+        );
+        assert!(firefox.get("allowed_origins").is_none());
+    }
+
+    #[test]
+    fn test_firefox_id_matches_extension_manifest() {
+        let manifest: serde_json::Value =
+            serde_json::from_str(include_str!("../../extension/manifest.json")).unwrap();
+        assert_eq!(
+            manifest["browser_specific_settings"]["gecko"]["id"],
+            FIREFOX_EXTENSION_ID
+        );
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn test_unpacked_extension_id() {
+        let dir = TempDir::new().unwrap();
+        let id = unpacked_extension_id(dir.path());
+        assert_eq!(id.len(), 32);
+        assert!(id.chars().all(|c| ('a'..='p').contains(&c)));
+        assert_eq!(id, unpacked_extension_id(dir.path()));
+
+        // A path that can't be canonicalized is hashed as given
+        assert_eq!(
+            unpacked_extension_id(Path::new("/nonexistent/noet")),
+            "cbfilofjgkamkcbonjfacaoginkfcaop"
+        );
+    }
+}
@@ -1,4 +1,5 @@
-use clap::{Parser, Subcommand};
+use crate::browser::Browser;
+use clap::{Parser, Subcommand, ValueEnum};
 use std::path::PathBuf;
 
 #[derive(Parser)]
@@ -17,7 +18,19 @@ pub struct Cli {
 #[derive(Subcommand)]
 pub enum Commands {
     /// Setup browser extension (download, install guide, configure native messaging)
-    Setup,
+    Setup {
+        /// Replace the downloaded extension with the one matching this version of noet
+        #[arg(long)]
+        upgrade: bool,
+
+        /// Install from a downloaded zip or an unpacked directory instead of GitHub
+        #[arg(long, value_name = "ZIP_OR_DIR")]
+        from: Option<PathBuf>,
+
+        /// Register the native messaging host only with this browser (default: every one found)
+        #[arg(long, value_enum, conflicts_with = "upgrade")]
+        browser: Option<Browser>,
+    },
 
     /// Initialize a noet working directory
     Init {
@@ -25,9 +38,38 @@ pub enum Commands {
         path: Option<PathBuf>,
     },
 
+    /// Create a new article from a template and open it in the editor
+    New {
+        /// Article title (prompted if omitted)
+        title: Option<String>,
+
+        /// Template name (picked interactively if omitted)
+        #[arg(short, long)]
+        template: Option<String>,
+
+        /// Set a template variable instead of being asked (NAME=VALUE)
+        #[arg(long = "var", value_name = "NAME=VALUE")]
+        vars: Vec<String>,
+
+        /// Also create a draft on note.com and record its key
+        #[arg(short, long)]
+        draft: bool,
+
+        /// Don't open the editor
+        #[arg(long)]
+        no_edit: bool,
+    },
+
     /// Check connection to browser extension
     Ping,
 
+    /// Check the extension, native messaging, config and workspace, and say how to fix problems
+    Doctor {
+        /// Also write a diagnostic zip for bug reports, with secrets removed
+        #[arg(long, value_name = "PATH")]
+        bundle: Option<Option<PathBuf>>,
+    },
+
     /// Check authentication status (Note.com login)
     Auth,
 
@@ -36,9 +78,9 @@ pub enum Commands {
 
     /// Get article content
     Get {
-        /// Username
+        /// Username (default: `username` from config)
         #[arg(short, long)]
-        username: String,
+        username: Option<String>,
 
         /// Article key
         key: String,
@@ -48,6 +90,28 @@ pub enum Commands {
         save: Option<PathBuf>,
     },
 
+    /// Export articles as Markdown with images
+    Export {
+        /// Article key (omit with --all)
+        key: Option<String>,
+
+        /// Export every article into the workspace
+        #[arg(short, long, conflicts_with = "key")]
+        all: bool,
+
+        /// Export only published articles when some are drafts (drafts can't be fetched)
+        #[arg(long, requires = "all")]
+        skip_drafts: bool,
+
+        /// Username (default: `username` from config)
+        #[arg(short, long)]
+        username: Option<String>,
+
+        /// Output file, or directory with --all (default: the articles directory)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
     /// Create a new article from markdown file
     Create {
         /// Path to the markdown file
@@ -56,19 +120,89 @@ pub enum Commands {
         /// Save as draft instead of publishing
         #[arg(short, long)]
         draft: bool,
+
+        /// Publish even if `default_status` is draft
+        #[arg(short, long, conflicts_with = "draft")]
+        publish: bool,
     },
 
     /// Update an existing article
     Update {
-        /// Article key
-        key: String,
-
         /// Path to the markdown file
         file: PathBuf,
 
+        /// File of the deprecated `update <KEY> <FILE>` form, whose first argument is the key
+        #[arg(hide = true)]
+        legacy_file: Option<PathBuf>,
+
+        /// Article key (default: `note_key` from the frontmatter)
+        #[arg(short, long)]
+        key: Option<String>,
+
+        /// Save as draft instead of publishing
+        #[arg(short, long)]
+        draft: bool,
+
+        /// Publish even if `default_status` is draft
+        #[arg(short, long, conflicts_with = "draft")]
+        publish: bool,
+
+        /// Overwrite even if the article was edited on note.com since the last sync
+        #[arg(short, long)]
+        force: bool,
+    },
+
+    /// Show which workspace articles differ from note.com
+    Status {
+        /// Only compare against the last sync, without contacting the extension
+        #[arg(long)]
+        offline: bool,
+    },
+
+    /// Publish new and modified workspace articles
+    Push {
         /// Save as draft instead of publishing
         #[arg(short, long)]
         draft: bool,
+
+        /// Publish even if `default_status` is draft
+        #[arg(short, long, conflicts_with = "draft")]
+        publish: bool,
+
+        /// Overwrite articles that were edited on note.com since the last sync
+        #[arg(short, long)]
+        force: bool,
+    },
+
+    /// Download changed articles from note.com into the workspace
+    Pull {
+        /// Username (default: `username` from config)
+        #[arg(short, long)]
+        username: Option<String>,
+    },
+
+    /// Merge changes made on note.com into a local file (three-way merge)
+    Merge {
+        /// Path to the markdown file
+        file: PathBuf,
+
+        /// Username (default: `username` from config)
+        #[arg(short, long)]
+        username: Option<String>,
+    },
+
+    /// Check articles for Markdown note.com can't display and other problems
+    Lint {
+        /// Files or directories (default: the workspace articles directory)
+        paths: Vec<PathBuf>,
+
+        /// Output format
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+
+        /// Fail on warnings as well as errors
+        #[arg(long)]
+        strict: bool,
     },
 
     /// Delete an article
@@ -80,6 +214,26 @@ pub enum Commands {
     /// Template management commands
     #[command(subcommand)]
     Template(TemplateCommands),
+
+    /// Configuration commands
+    #[command(subcommand)]
+    Config(ConfigCommands),
+
+    /// Background connection to the browser extension (started automatically)
+    #[command(subcommand)]
+    Daemon(DaemonCommands),
+}
+
+#[derive(Subcommand)]
+pub enum DaemonCommands {
+    /// Show whether the daemon is running
+    Status,
+
+    /// Stop the daemon
+    Stop,
+
+    /// Run the daemon in the foreground
+    Run,
 }
 
 #[derive(Subcommand)]
@@ -105,3 +259,34 @@ pub enum TemplateCommands {
         name: String,
     },
 }
+
+#[derive(Subcommand)]
+pub enum ConfigCommands {
+    /// Show all settings and where they come from
+    List,
+
+    /// Show the value of a setting
+    Get {
+        /// Setting key
+        key: String,
+    },
+
+    /// Change a setting (workspace config if inside a workspace)
+    Set {
+        /// Setting key
+        key: String,
+
+        /// New value (comma-separated for default_tags)
+        value: String,
+
+        /// Write to the global config instead of the workspace config
+        #[arg(short, long)]
+        global: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
@@ -0,0 +1,63 @@
+use crate::config::{self, Settings, KEYS};
+use crate::error::Result;
+use crate::workspace;
+use colored::Colorize;
+
+/// Show every setting with its effective value and origin
+pub fn list() -> Result<()> {
+    let settings = Settings::load()?;
+
+    for key in KEYS {
+        let value = settings.get(key)?;
+        let source = settings
+            .source(key)
+            .map(|s| s.to_string())
+            .unwrap_or_default();
+
+        match value {
+            Some(v) => println!(
+                "{} = {}  {}",
+                key.bold(),
+                v.cyan(),
+                format!("({source})").dimmed()
+            ),
+            None => println!("{} = {}", key.bold(), "(未設定)".dimmed()),
+        }
+    }
+
+    Ok(())
+}
+
+/// Print the effective value of a single setting
+pub fn get(key: &str) -> Result<()> {
+    let settings = Settings::load()?;
+
+    if let Some(value) = settings.get(key)? {
+        println!("{value}");
+    }
+
+    Ok(())
+}
+
+/// Write a setting to the workspace config, or the global config with `--global`
+pub fn set(key: &str, value: &str, global: bool) -> Result<()> {
+    // The Native Messaging host only reads the global config
+    let global = global || key == "workspace";
+    let path = if global || !workspace::is_in_workspace() {
+        config::global_config_path()?
+    } else {
+        config::workspace_config_path()?
+    };
+
+    config::set_value(&path, key, value)?;
+
+    println!(
+        "{} {} を {} に設定しました ({})",
+        "✓".green(),
+        key.bold(),
+        value.cyan(),
+        path.display().to_string().dimmed()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,107 @@
+//! `noet daemon`: manage the background connection to the extension
+
+use crate::error::Result;
+
+#[cfg(unix)]
+use crate::daemon;
+#[cfg(not(unix))]
+use crate::error::NoetError;
+#[cfg(unix)]
+use crate::extension_client::ExtensionClient;
+#[cfg(unix)]
+use colored::Colorize;
+
+/// Run the daemon in the foreground
+#[cfg(unix)]
+pub async fn run() -> Result<()> {
+    daemon::run().await
+}
+
+/// Show whether the daemon is running and what it is doing
+#[cfg(unix)]
+pub async fn status() -> Result<()> {
+    let Some(client) = ExtensionClient::connect_running_daemon().await? else {
+        println!("{} デーモンは起動していません", "•".dimmed());
+        return Ok(());
+    };
+
+    let status = client.daemon_status().await?;
+    let extension = if status.extension_connected {
+        "接続中".green()
+    } else {
+        "未接続".yellow()
+    };
+
+    println!("{} デーモンは起動しています", "✓".green());
+    println!("  PID:          {}", status.pid);
+    println!("  稼働時間:     {}", format_uptime(status.uptime_secs));
+    match status.port {
+        Some(port) => println!("  ポート:       {port}"),
+        None => println!(
+            "  ポート:       {}",
+            "なし (Native Messaging 経由のみ)".yellow()
+        ),
+    }
+    println!("  拡張機能:     {extension}");
+    println!("  接続中の CLI: {}", status.clients.saturating_sub(1));
+    println!("  処理中:       {}", status.pending);
+    println!("  処理済み:     {}", status.requests.saturating_sub(1));
+    println!("  ソケット:     {}", daemon::socket_path()?.display());
+    println!("  ログ:         {}", daemon::log_path()?.display());
+
+    Ok(())
+}
+
+/// Stop the daemon if it is running
+#[cfg(unix)]
+pub async fn stop() -> Result<()> {
+    let Some(client) = ExtensionClient::connect_running_daemon().await? else {
+        println!("{} デーモンは起動していません", "•".dimmed());
+        return Ok(());
+    };
+
+    client.stop_daemon().await?;
+    println!("{} デーモンを停止しました", "✓".green());
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub async fn run() -> Result<()> {
+    Err(unsupported())
+}
+
+#[cfg(not(unix))]
+pub async fn status() -> Result<()> {
+    Err(unsupported())
+}
+
+#[cfg(not(unix))]
+pub async fn stop() -> Result<()> {
+    Err(unsupported())
+}
+
+#[cfg(not(unix))]
+fn unsupported() -> NoetError {
+    NoetError::InvalidInput("デーモンは Unix 系 OS でのみ利用できます".to_string())
+}
+
+#[cfg(unix)]
+fn format_uptime(secs: u64) -> String {
+    match secs {
+        s if s < 60 => format!("{s}秒"),
+        s if s < 3600 => format!("{}分{}秒", s / 60, s % 60),
+        s => format!("{}時間{}分", s / 3600, s % 3600 / 60),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_uptime() {
+        assert_eq!(format_uptime(42), "42秒");
+        assert_eq!(format_uptime(125), "2分5秒");
+        assert_eq!(format_uptime(7380), "2時間3分");
+    }
+}
@@ -0,0 +1,697 @@
+//! `noet doctor`: check everything noet depends on and say how to fix what's broken
+
+use crate::browser::{self, Browser};
+use crate::commands::{setup, template};
+use crate::config::{self, Settings};
+use crate::error::{NoetError, Result};
+use crate::extension_auth;
+use crate::extension_client::ExtensionClient;
+use crate::{bridge, workspace};
+use colored::Colorize;
+use serde::Serialize;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Lines of the daemon log kept in the bundle
+#[cfg(unix)]
+const LOG_TAIL_LINES: usize = 200;
+
+/// What replaces redacted values
+const REDACTED: &str = "[REDACTED]";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Outcome {
+    Pass,
+    /// Works, but not the way it should
+    Warn,
+    Fail,
+    /// Couldn't be checked because an earlier check failed
+    Skip,
+}
+
+/// Result of one check
+#[derive(Debug, Clone, Serialize)]
+struct Check {
+    name: String,
+    outcome: Outcome,
+    detail: String,
+    /// What to do about a warning or failure
+    fix: Option<String>,
+}
+
+impl Check {
+    fn pass(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self::new(name, Outcome::Pass, detail, None)
+    }
+
+    fn warn(name: impl Into<String>, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self::new(name, Outcome::Warn, detail, Some(fix.into()))
+    }
+
+    fn fail(name: impl Into<String>, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self::new(name, Outcome::Fail, detail, Some(fix.into()))
+    }
+
+    fn skip(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self::new(name, Outcome::Skip, detail, None)
+    }
+
+    fn new(
+        name: impl Into<String>,
+        outcome: Outcome,
+        detail: impl Into<String>,
+        fix: Option<String>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            outcome,
+            detail: detail.into(),
+            fix,
+        }
+    }
+
+    fn print(&self) {
+        let mark = match self.outcome {
+            Outcome::Pass => "✓".green(),
+            Outcome::Warn => "!".yellow(),
+            Outcome::Fail => "✗".red(),
+            Outcome::Skip => "-".dimmed(),
+        };
+        println!("{mark} {}: {}", self.name.bold(), self.detail);
+        if let Some(fix) = &self.fix {
+            println!("    {} {fix}", "→".cyan());
+        }
+    }
+}
+
+/// Run every check, print the results and optionally write a diagnostic bundle
+pub async fn run(bundle: Option<Option<PathBuf>>) -> Result<()> {
+    let mut checks = Vec::new();
+    let mut report = |check: Check| {
+        check.print();
+        checks.push(check);
+    };
+
+    let settings = match Settings::load() {
+        Ok(settings) => {
+            report(check_config());
+            Some(settings)
+        }
+        Err(e) => {
+            report(Check::fail(
+                "設定",
+                e.to_string(),
+                "`noet config list` で確認し、エラーの行を修正してください",
+            ));
+            None
+        }
+    };
+    report(check_workspace());
+    report(check_templates());
+    report(check_extension_files());
+
+    let home = setup::home_dir()?;
+    let exe = std::env::current_exe()?;
+    let extension_id = settings.as_ref().and_then(|s| s.extension_id.as_deref());
+    for check in check_host_manifests(&home, &exe, extension_id) {
+        report(check);
+    }
+
+    match &settings {
+        Some(settings) => {
+            report(check_port(settings).await);
+            for check in check_extension().await {
+                report(check);
+            }
+        }
+        None => {
+            report(Check::skip(
+                "ポート",
+                "設定を読み込めないため確認できません",
+            ));
+            report(Check::skip(
+                "拡張機能との接続",
+                "設定を読み込めないため確認できません",
+            ));
+            report(Check::skip(
+                "note.com へのログイン",
+                "設定を読み込めないため確認できません",
+            ));
+        }
+    }
+
+    if let Some(path) = bundle {
+        let path = path.unwrap_or_else(default_bundle_path);
+        write_bundle(&path, &checks, settings.as_ref())?;
+        println!();
+        println!(
+            "{} 診断情報を書き出しました: {}",
+            "✓".green(),
+            path.display()
+        );
+        println!("  トークン、拡張機能 ID、ユーザー名、ホームディレクトリは伏せてあります");
+    }
+
+    let failures = checks.iter().filter(|c| c.outcome == Outcome::Fail).count();
+    if failures > 0 {
+        return Err(NoetError::ChecksFailed(failures));
+    }
+    println!();
+    println!("{} 問題は見つかりませんでした", "✓".green());
+    Ok(())
+}
+
+/// Loading the settings already parsed and validated every file, so list them
+fn check_config() -> Check {
+    let files: Vec<String> = [
+        config::global_config_path(),
+        config::workspace_config_path(),
+    ]
+    .into_iter()
+    .flatten()
+    .filter(|path| path.exists())
+    .map(|path| path.display().to_string())
+    .collect();
+    let detail = if files.is_empty() {
+        "設定ファイルはありません (既定値を使用)".to_string()
+    } else {
+        files.join(", ")
+    };
+    Check::pass("設定", detail)
+}
+
+fn check_workspace() -> Check {
+    match workspace::find_workspace_root() {
+        Ok(root) => Check::pass("ワークスペース", root.display().to_string()),
+        Err(_) => Check::warn(
+            "ワークスペース",
+            "カレントディレクトリはワークスペースの外です",
+            "記事を管理するディレクトリで `noet init` を実行してください",
+        ),
+    }
+}
+
+fn check_templates() -> Check {
+    match template::get_template_dir() {
+        Ok(dir) => check_templates_dir(&dir),
+        Err(e) => Check::fail(
+            "テンプレート",
+            e.to_string(),
+            "設定ディレクトリを確認してください",
+        ),
+    }
+}
+
+fn check_templates_dir(dir: &Path) -> Check {
+    if !dir.exists() {
+        return Check::warn(
+            "テンプレート",
+            format!("{} がありません", dir.display()),
+            "`noet template add <NAME>` でテンプレートを作成できます",
+        );
+    }
+    match fs::read_dir(dir) {
+        Ok(entries) => {
+            let count = entries
+                .flatten()
+                .filter(|e| e.path().extension().is_some_and(|ext| ext == "md"))
+                .count();
+            Check::pass("テンプレート", format!("{} ({count} 件)", dir.display()))
+        }
+        Err(e) => Check::fail(
+            "テンプレート",
+            format!("{} を読めません: {e}", dir.display()),
+            "ディレクトリの権限を確認してください",
+        ),
+    }
+}
+
+fn check_extension_files() -> Check {
+    match setup::current_extension_dir() {
+        Ok(dir) => check_extension_dir(&dir, env!("CARGO_PKG_VERSION")),
+        Err(e) => Check::fail(
+            "拡張機能のファイル",
+            e.to_string(),
+            "`noet setup` を実行してください",
+        ),
+    }
+}
+
+fn check_extension_dir(dir: &Path, version: &str) -> Check {
+    const NAME: &str = "拡張機能のファイル";
+    match setup::installed_version(dir) {
+        None => Check::fail(
+            NAME,
+            format!("{} に拡張機能がありません", dir.display()),
+            "`noet setup` を実行してください",
+        ),
+        Some(installed) if installed != version => Check::warn(
+            NAME,
+            format!("{} は {installed} 用です (noet: {version})", dir.display()),
+            "`noet setup --upgrade` で入れ替えてください",
+        ),
+        Some(installed) => Check::pass(NAME, format!("{} ({installed})", dir.display())),
+    }
+}
+
+/// One check per browser that is installed or has a manifest
+fn check_host_manifests(home: &Path, exe: &Path, extension_id: Option<&str>) -> Vec<Check> {
+    let detected = Browser::detect(home);
+    let checks: Vec<Check> = Browser::ALL
+        .into_iter()
+        .filter(|b| detected.contains(b) || b.manifest_path(home).exists())
+        .map(|b| check_host_manifest(b, &b.manifest_path(home), exe, extension_id))
+        .collect();
+
+    if checks.is_empty() {
+        return vec![Check::fail(
+            "Native Messaging",
+            "対応するブラウザが見つかりません",
+            "`noet setup --browser <NAME>` で使っているブラウザを指定してください",
+        )];
+    }
+    checks
+}
+
+fn check_host_manifest(
+    browser: Browser,
+    path: &Path,
+    exe: &Path,
+    extension_id: Option<&str>,
+) -> Check {
+    let name = format!("Native Messaging ({})", browser.name());
+    let setup_again = format!(
+        "`noet setup --browser {}` で書き直してください",
+        browser.name().to_lowercase()
+    );
+
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(_) => {
+            return Check::fail(
+                name,
+                format!("{} がありません", path.display()),
+                setup_again,
+            )
+        }
+    };
+    let manifest: serde_json::Value = match serde_json::from_str(&content) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            return Check::fail(
+                name,
+                format!("{} を読めません: {e}", path.display()),
+                setup_again,
+            )
+        }
+    };
+
+    if manifest["name"] != browser::HOST_NAME || manifest["type"] != "stdio" {
+        return Check::fail(
+            name,
+            format!("{} は noet のものではありません", path.display()),
+            setup_again,
+        );
+    }
+
+    let target = manifest["path"].as_str().unwrap_or_default();
+    if !same_file(Path::new(target), exe) {
+        return Check::fail(
+            name,
+            format!("別の noet を指しています: {target}"),
+            setup_again,
+        );
+    }
+
+    let (key, expected) = if browser.is_firefox() {
+        (
+            "allowed_extensions",
+            browser::FIREFOX_EXTENSION_ID.to_string(),
+        )
+    } else {
+        match extension_id {
+            Some(id) => ("allowed_origins", format!("chrome-extension://{id}/")),
+            None => {
+                let allowed = manifest["allowed_origins"].as_array().map_or(0, Vec::len);
+                if allowed == 0 {
+                    return Check::fail(name, "許可された拡張機能がありません", setup_again);
+                }
+                return Check::warn(
+                    name,
+                    path.display().to_string(),
+                    "extension_id が未設定のため、許可された拡張機能が正しいか確認できません。`noet setup` で設定してください",
+                );
+            }
+        }
+    };
+    let allowed = manifest[key]
+        .as_array()
+        .is_some_and(|ids| ids.iter().any(|id| id == expected.as_str()));
+    if !allowed {
+        return Check::fail(
+            name,
+            format!("{key} に {expected} がありません"),
+            setup_again,
+        );
+    }
+
+    Check::pass(name, path.display().to_string())
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// The bridge port is either free or held by our own daemon
+async fn check_port(settings: &Settings) -> Check {
+    const NAME: &str = "ポート";
+
+    #[cfg(unix)]
+    if let Ok(Some(client)) = ExtensionClient::connect_running_daemon().await {
+        if let Ok(status) = client.daemon_status().await {
+            return match status.port {
+                Some(port) => Check::pass(
+                    NAME,
+                    format!(
+                        "noet デーモン (PID {}) がポート {port} で待ち受けています",
+                        status.pid
+                    ),
+                ),
+                None => Check::warn(
+                    NAME,
+                    format!(
+                        "noet デーモン (PID {}) はポートで待ち受けられず、Native Messaging 経由でのみ接続できます",
+                        status.pid
+                    ),
+                    "TCP ポートが使えない環境なら問題ありません。そうでなければ `noet config set bridge_port <PORT>` で別のポートを指定してください",
+                ),
+            };
+        }
+    }
+
+    let port = settings.bridge_port;
+    match tokio::net::TcpListener::bind(("127.0.0.1", port)).await {
+        Ok(_) => Check::pass(NAME, format!("ポート {port} は空いています")),
+        Err(_) => {
+            let published = bridge::read().ok().flatten();
+            Check::warn(
+                NAME,
+                format!(
+                    "ポート {port} は他のプログラムが使用中です{}",
+                    published
+                        .map(|e| format!(" (前回の noet はポート {} を使用)", e.port))
+                        .unwrap_or_default()
+                ),
+                "noet は近くの空きポートを使いますが、`noet config set bridge_port <PORT>` で変更するとより確実です",
+            )
+        }
+    }
+}
+
+/// Connect to the extension, compare versions and ask whether the user is logged in
+async fn check_extension() -> Vec<Check> {
+    const CONNECTION: &str = "拡張機能との接続";
+    const LOGIN: &str = "note.com へのログイン";
+
+    let client = match ExtensionClient::connect().await {
+        Ok(client) => client,
+        Err(e) => {
+            let fix = e
+                .hint()
+                .unwrap_or("ブラウザを起動し、拡張機能が有効か確認してください");
+            return vec![
+                Check::fail(CONNECTION, e.to_string(), fix),
+                Check::skip(LOGIN, "拡張機能に接続できないため確認できません"),
+            ];
+        }
+    };
+
+    let mut checks = Vec::new();
+    let version = env!("CARGO_PKG_VERSION");
+    match client.ping().await {
+        Ok(info) if info.version == version => checks.push(Check::pass(
+            CONNECTION,
+            format!("拡張機能 {}", info.version),
+        )),
+        Ok(info) => checks.push(Check::fail(
+            CONNECTION,
+            format!(
+                "拡張機能 {} は noet {version} 用ではありません",
+                info.version
+            ),
+            "`noet setup --upgrade` で入れ替え、拡張機能ページで再読み込みしてください",
+        )),
+        Err(e) => checks.push(Check::fail(
+            CONNECTION,
+            e.to_string(),
+            e.hint().unwrap_or("拡張機能を再読み込みしてください"),
+        )),
+    }
+
+    match client.check_auth().await {
+        Ok(auth) if auth.logged_in => checks.push(Check::pass(
+            LOGIN,
+            auth.username
+                .map(|u| format!("{u} としてログイン中"))
+                .unwrap_or_else(|| "ログイン中".to_string()),
+        )),
+        Ok(_) => checks.push(Check::fail(
+            LOGIN,
+            "ログインしていません",
+            "ブラウザで https://note.com/login にアクセスしてログインしてください",
+        )),
+        Err(e) => checks.push(Check::fail(
+            LOGIN,
+            e.to_string(),
+            e.hint().unwrap_or("拡張機能を再読み込みしてください"),
+        )),
+    }
+    checks
+}
+
+fn default_bundle_path() -> PathBuf {
+    let now = jiff::Zoned::now().strftime("%Y%m%d-%H%M%S").to_string();
+    PathBuf::from(format!("noet-doctor-{now}.zip"))
+}
+
+/// Hides secrets and the user's home directory in text going into the bundle
+struct Redactor {
+    secrets: Vec<String>,
+    home: Option<String>,
+}
+
+impl Redactor {
+    fn new(settings: Option<&Settings>) -> Self {
+        let mut secrets: Vec<String> = extension_auth::load_token()
+            .ok()
+            .flatten()
+            .into_iter()
+            .collect();
+        if let Some(settings) = settings {
+            secrets.extend(settings.extension_id.clone());
+            secrets.extend(settings.username.clone());
+        }
+        Self {
+            secrets,
+            home: dirs::home_dir().map(|home| home.display().to_string()),
+        }
+    }
+
+    fn redact(&self, text: &str) -> String {
+        // Home first, since it often contains the user name
+        let mut text = match self.home.as_deref().filter(|h| h.len() > 1) {
+            Some(home) => text.replace(home, "~"),
+            None => text.to_string(),
+        };
+        for secret in self.secrets.iter().filter(|s| !s.is_empty()) {
+            text = text.replace(secret.as_str(), REDACTED);
+        }
+        text
+    }
+}
+
+/// Everything useful for a bug report, with secrets removed, as a zip at `path`
+fn write_bundle(path: &Path, checks: &[Check], settings: Option<&Settings>) -> Result<()> {
+    let redactor = Redactor::new(settings);
+    let mut files: Vec<(String, String)> = Vec::new();
+
+    let report = serde_json::json!({
+        "noet": env!("CARGO_PKG_VERSION"),
+        "protocol": crate::protocol::PROTOCOL_VERSION,
+        "os": std::env::consts::OS,
+        "arch": std::env::consts::ARCH,
+        "created_at": jiff::Zoned::now().to_string(),
+        "checks": checks,
+    });
+    files.push(("report.json".into(), serde_json::to_string_pretty(&report)?));
+
+    for (name, path) in [
+        ("config/global.toml", config::global_config_path().ok()),
+        (
+            "config/workspace.toml",
+            config::workspace_config_path().ok(),
+        ),
+        ("bridge.json", bridge::discovery_path().ok()),
+    ] {
+        if let Some(content) = path.and_then(|p| fs::read_to_string(p).ok()) {
+            files.push((name.into(), content));
+        }
+    }
+
+    if let Ok(home) = setup::home_dir() {
+        for browser in Browser::ALL {
+            if let Ok(content) = fs::read_to_string(browser.manifest_path(&home)) {
+                let name = format!("native-messaging/{}.json", browser.name().to_lowercase());
+                files.push((name, content));
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    if let Some(log) = crate::daemon::log_path()
+        .ok()
+        .and_then(|p| fs::read_to_string(p).ok())
+    {
+        files.push(("daemon.log".into(), tail(&log, LOG_TAIL_LINES)));
+    }
+
+    let mut zip = zip::ZipWriter::new(fs::File::create(path)?);
+    for (name, content) in files {
+        zip.start_file(name, zip::write::SimpleFileOptions::default())
+            .map_err(io::Error::other)?;
+        zip.write_all(redactor.redact(&content).as_bytes())?;
+    }
+    zip.finish().map_err(io::Error::other)?;
+    Ok(())
+}
+
+/// The last `lines` lines of `text`
+#[cfg(unix)]
+fn tail(text: &str, lines: usize) -> String {
+    let all: Vec<&str> = text.lines().collect();
+    all[all.len().saturating_sub(lines)..].join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn write_manifest(browser: Browser, home: &Path, exe: &Path, id: &str) -> PathBuf {
+        let path = browser.manifest_path(home);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let manifest = browser.host_manifest(exe, id);
+        fs::write(&path, serde_json::to_string(&manifest).unwrap()).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_host_manifest_checks() {
+        let home = TempDir::new().unwrap();
+        let exe = home.path().join("noet");
+        fs::write(&exe, "").unwrap();
+        let id = "abcdefghijklmnopabcdefghijklmnop";
+
+        let path = write_manifest(Browser::Chromium, home.path(), &exe, id);
+        let check = |id| check_host_manifest(Browser::Chromium, &path, &exe, id);
+        assert_eq!(check(Some(id)).outcome, Outcome::Pass);
+        assert_eq!(check(None).outcome, Outcome::Warn);
+        assert_eq!(
+            check(Some("ponmlkjihgfedcbaponmlkjihgfedcba")).outcome,
+            Outcome::Fail
+        );
+
+        // Points at another binary
+        let other = home.path().join("old-noet");
+        fs::write(&other, "").unwrap();
+        let check = check_host_manifest(Browser::Chromium, &path, &other, Some(id));
+        assert_eq!(check.outcome, Outcome::Fail);
+        assert!(check.fix.unwrap().contains("--browser chromium"));
+
+        let path = write_manifest(Browser::Firefox, home.path(), &exe, id);
+        let check = check_host_manifest(Browser::Firefox, &path, &exe, Some(id));
+        assert_eq!(check.outcome, Outcome::Pass);
+
+        fs::write(&path, "{").unwrap();
+        let check = check_host_manifest(Browser::Firefox, &path, &exe, Some(id));
+        assert_eq!(check.outcome, Outcome::Fail);
+    }
+
+    #[test]
+    fn test_host_manifests_cover_installed_browsers() {
+        let home = TempDir::new().unwrap();
+        let exe = home.path().join("noet");
+        assert_eq!(
+            check_host_manifests(home.path(), &exe, None)[0].outcome,
+            Outcome::Fail
+        );
+
+        // Brave is installed but was never set up
+        write_manifest(Browser::Firefox, home.path(), &exe, "");
+        fs::create_dir_all(Browser::Brave.manifest_dir(home.path())).unwrap();
+        let checks = check_host_manifests(home.path(), &exe, None);
+        let names: Vec<_> = checks.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(
+            names,
+            ["Native Messaging (Brave)", "Native Messaging (Firefox)"]
+        );
+        assert_eq!(checks[0].outcome, Outcome::Fail);
+    }
+
+    #[test]
+    fn test_extension_dir_check() {
+        let dir = TempDir::new().unwrap();
+        assert_eq!(
+            check_extension_dir(dir.path(), "1.0.0").outcome,
+            Outcome::Fail
+        );
+
+        fs::write(dir.path().join("manifest.json"), r#"{"version": "0.9.0"}"#).unwrap();
+        assert_eq!(
+            check_extension_dir(dir.path(), "1.0.0").outcome,
+            Outcome::Warn
+        );
+        assert_eq!(
+            check_extension_dir(dir.path(), "0.9.0").outcome,
+            Outcome::Pass
+        );
+    }
+
+    #[test]
+    fn test_templates_dir_check() {
+        let dir = TempDir::new().unwrap();
+        assert_eq!(
+            check_templates_dir(&dir.path().join("templates")).outcome,
+            Outcome::Warn
+        );
+
+        fs::write(dir.path().join("blog.md"), "").unwrap();
+        let check = check_templates_dir(dir.path());
+        assert_eq!(check.outcome, Outcome::Pass);
+        assert!(check.detail.ends_with("(1 件)"));
+    }
+
+    #[test]
+    fn test_redact() {
+        let redactor = Redactor {
+            secrets: vec!["s3cret".into(), "kako".into(), String::new()],
+            home: Some("/home/kako".into()),
+        };
+        assert_eq!(
+            redactor.redact("token=s3cret path=/home/kako/.config user: kako"),
+            "token=[REDACTED] path=~/.config user: [REDACTED]"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_tail() {
+        assert_eq!(tail("a\nb\nc", 2), "b\nc");
+        assert_eq!(tail("a", 5), "a");
+    }
+}
@@ -0,0 +1,321 @@
+//! `noet export`: back up articles into a workspace
+
+use crate::commands::extension::{get_article, save_article};
+use crate::commands::sync::record_pull;
+use crate::config::Settings;
+use crate::error::{NoetError, Result};
+use crate::extension_client::ExtensionClient;
+use crate::protocol::ArticleSummary;
+use crate::sync::{self, SyncState};
+use crate::workspace;
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const PROGRESS_FILE: &str = "export-progress.json";
+
+/// Keys finished by an `export --all` run, kept until the run completes so an
+/// interrupted export can pick up where it stopped
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ExportProgress {
+    done: BTreeSet<String>,
+}
+
+impl ExportProgress {
+    fn path(root: &Path) -> PathBuf {
+        root.join(".noet").join(PROGRESS_FILE)
+    }
+
+    fn load(root: &Path) -> Result<Self> {
+        let path = Self::path(root);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    fn mark_done(&mut self, root: &Path, key: &str) -> Result<()> {
+        self.done.insert(key.to_string());
+        fs::write(Self::path(root), serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    fn finish(root: &Path) -> Result<()> {
+        let path = Self::path(root);
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct Summary {
+    exported: usize,
+    unchanged: usize,
+    modified: usize,
+    drafts: usize,
+    failures: Vec<(String, String)>,
+}
+
+/// Export one article, or every article with `all`. Drafts stop an `all` export
+/// unless `skip_drafts` is set.
+pub async fn run(
+    key: Option<&str>,
+    all: bool,
+    skip_drafts: bool,
+    username: Option<&str>,
+    output: Option<&Path>,
+) -> Result<()> {
+    match (key, all) {
+        (Some(key), _) => export_one(key, username, output).await,
+        (None, true) => export_all(skip_drafts, username, output).await,
+        (None, false) => Err(NoetError::InvalidInput(
+            "記事キーを指定するか --all を付けてください".to_string(),
+        )),
+    }
+}
+
+async fn export_one(key: &str, username: Option<&str>, output: Option<&Path>) -> Result<()> {
+    let path = match output {
+        Some(path) => path.to_path_buf(),
+        None => match workspace::find_workspace_root() {
+            Ok(root) => root
+                .join(Settings::load()?.articles_dir)
+                .join(format!("{key}.md")),
+            Err(_) => PathBuf::from(format!("{key}.md")),
+        },
+    };
+
+    get_article(username, key, Some(&path)).await
+}
+
+async fn export_all(
+    skip_drafts: bool,
+    username: Option<&str>,
+    output: Option<&Path>,
+) -> Result<()> {
+    let root = workspace::find_workspace_root()?;
+    let settings = Settings::load()?;
+    let username = settings.require_username(username)?;
+
+    let out_dir = match output {
+        Some(dir) if dir.is_relative() => std::env::current_dir()?.join(dir),
+        Some(dir) => dir.to_path_buf(),
+        None => root.join(&settings.articles_dir),
+    };
+    if !out_dir.starts_with(&root) {
+        return Err(NoetError::InvalidInput(format!(
+            "出力先はワークスペース内のディレクトリを指定してください: {}",
+            out_dir.display()
+        )));
+    }
+
+    let mut state = SyncState::load(&root)?;
+    let mut progress = ExportProgress::load(&root)?;
+    if !progress.done.is_empty() {
+        println!(
+            "{} 前回中断したエクスポートを再開します ({} 件完了済み)",
+            "↻".cyan(),
+            progress.done.len()
+        );
+    }
+
+    println!("{}", "拡張機能に接続中...".cyan());
+    let client = ExtensionClient::connect().await?;
+
+    println!("{}", "記事一覧を取得中...".cyan());
+    let articles = client.list_articles().await?.articles;
+    let total = articles.len();
+
+    if !skip_drafts {
+        check_no_drafts(&articles)?;
+    }
+
+    let mut summary = Summary::default();
+
+    for (i, article) in articles.iter().enumerate() {
+        let counter = format!("[{}/{total}]", i + 1).dimmed();
+        let Some(key) = article.key.as_deref() else {
+            summary
+                .failures
+                .push((article.title.clone(), "記事キーがありません".to_string()));
+            continue;
+        };
+
+        if progress.done.contains(key) {
+            summary.unchanged += 1;
+            continue;
+        }
+
+        if is_draft(article) {
+            println!(
+                "{counter} {} {}",
+                article.title,
+                "(下書き: スキップ)".dimmed()
+            );
+            summary.drafts += 1;
+            continue;
+        }
+
+        let tracked = state.find_by_key(key).map(|(p, t)| (p.clone(), t.clone()));
+        let rel_path = match &tracked {
+            Some((rel_path, _)) => rel_path.clone(),
+            None => sync::relative_path(&root, &out_dir.join(format!("{key}.md"))),
+        };
+        let path = root.join(&rel_path);
+
+        if let (Some((_, tracked)), true) = (&tracked, path.exists()) {
+            let content = fs::read_to_string(&path)?;
+            if sync::content_hash(&content) != tracked.content_hash {
+                println!(
+                    "{counter} {} {}",
+                    rel_path,
+                    "(ローカルに未プッシュの変更: スキップ)".yellow()
+                );
+                summary.modified += 1;
+                continue;
+            }
+            if is_unchanged(tracked.remote_updated.as_deref(), article) {
+                summary.unchanged += 1;
+                progress.mark_done(&root, key)?;
+                continue;
+            }
+        }
+
+        println!("{counter} {} → {}", article.title, rel_path);
+        match save_article(&client, &username, key, &path).await {
+            Ok(fetched) => {
+                record_pull(
+                    &root,
+                    &mut state,
+                    &rel_path,
+                    key,
+                    fetched,
+                    sync::remote_timestamp(article),
+                )?;
+                progress.mark_done(&root, key)?;
+                summary.exported += 1;
+            }
+            Err(e) => {
+                println!("  {} {e}", "✗".red());
+                summary.failures.push((key.to_string(), e.to_string()));
+            }
+        }
+    }
+
+    ExportProgress::finish(&root)?;
+    print_summary(&summary);
+
+    Ok(())
+}
+
+fn is_draft(article: &ArticleSummary) -> bool {
+    article.status == "draft"
+}
+
+/// The public page of a draft can't be fetched, so refuse to leave drafts out
+/// of a backup without being told to
+fn check_no_drafts(articles: &[ArticleSummary]) -> Result<()> {
+    let drafts: Vec<&str> = articles
+        .iter()
+        .filter(|article| is_draft(article))
+        .map(|article| article.title.as_str())
+        .collect();
+    if drafts.is_empty() {
+        return Ok(());
+    }
+
+    println!("{} 下書き:", "!".yellow());
+    for title in &drafts {
+        println!("  • {title}");
+    }
+    Err(NoetError::InvalidInput(format!(
+        "下書きが {} 件あります。下書きは公開ページから取得できないためエクスポートできません。公開済みの記事だけをエクスポートするには --skip-drafts を付けてください",
+        drafts.len()
+    )))
+}
+
+fn is_unchanged(recorded: Option<&str>, article: &ArticleSummary) -> bool {
+    recorded.is_some() && recorded == sync::remote_timestamp(article).as_deref()
+}
+
+fn print_summary(summary: &Summary) {
+    println!();
+    println!(
+        "{} {} 件をエクスポートしました (変更なし: {} 件, ローカル変更あり: {} 件, 下書き: {} 件, 失敗: {} 件)",
+        "✓".green(),
+        summary.exported,
+        summary.unchanged,
+        summary.modified,
+        summary.drafts,
+        summary.failures.len()
+    );
+
+    if !summary.failures.is_empty() {
+        println!();
+        println!("{}", "失敗した記事:".red());
+        for (name, error) in &summary.failures {
+            println!("  • {name}: {error}");
+        }
+        println!(
+            "{}",
+            "もう一度実行すると取得できていない記事を再取得します".dimmed()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_progress_survives_until_finished() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join(".noet")).unwrap();
+
+        let mut progress = ExportProgress::load(root).unwrap();
+        progress.mark_done(root, "n1").unwrap();
+        progress.mark_done(root, "n2").unwrap();
+
+        // An interrupted run leaves the progress behind
+        let resumed = ExportProgress::load(root).unwrap();
+        assert!(resumed.done.contains("n1") && resumed.done.contains("n2"));
+
+        ExportProgress::finish(root).unwrap();
+        assert!(ExportProgress::load(root).unwrap().done.is_empty());
+    }
+
+    #[test]
+    fn test_drafts_need_skip_drafts() {
+        let article = |key: &str, status: &str| ArticleSummary {
+            key: Some(key.to_string()),
+            title: key.to_string(),
+            status: status.to_string(),
+            date: None,
+        };
+
+        assert!(check_no_drafts(&[article("n1", "published")]).is_ok());
+        assert!(matches!(
+            check_no_drafts(&[article("n1", "published"), article("n2", "draft")]),
+            Err(NoetError::InvalidInput(message)) if message.contains("--skip-drafts")
+        ));
+    }
+
+    #[test]
+    fn test_is_unchanged_needs_a_recorded_date() {
+        let article = ArticleSummary {
+            key: Some("n1".to_string()),
+            title: "T".to_string(),
+            status: "published".to_string(),
+            date: Some("2025/01/02".to_string()),
+        };
+        assert!(is_unchanged(Some("2025/01/02"), &article));
+        assert!(!is_unchanged(Some("2025/01/01"), &article));
+        assert!(!is_unchanged(None, &article));
+    }
+}
@@ -1,21 +1,40 @@
 //! Extension-based commands for Note.com operations via browser extension
 
-use crate::error::Result;
+use crate::commands::sync as sync_commands;
+use crate::config::{ArticleStatus, Settings};
+use crate::converters::html_to_md::{self, ConvertOptions};
+use crate::converters::note_markdown::{self, Change};
+use crate::error::{NoetError, Result};
 use crate::extension_client::ExtensionClient;
-use crate::image_handler::{self, ImageData};
+use crate::frontmatter::{self, Document};
+use crate::image_cache::ImageCache;
+use crate::image_handler::{self, LocalImage};
+use crate::image_pipeline::{self, ImageOptions};
+use crate::merge;
+use crate::protocol::{self, ArticleResult, CreateArticleParams, UpdateArticleParams};
+use crate::sync::{self, SyncState};
+use crate::workspace;
 use colored::Colorize;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 /// Check connection to browser extension
 pub async fn ping() -> Result<()> {
     println!("{}", "拡張機能に接続中...".cyan());
 
     let client = ExtensionClient::connect().await?;
-    let version = client.ping().await?;
+    let info = client.ping().await?;
 
     println!("{} 拡張機能と接続しました", "✓".green());
-    println!("  バージョン: {}", version.cyan());
+    println!("  バージョン: {}", info.version.cyan());
+    println!(
+        "  プロトコル: {} (noet: {})",
+        info.protocol_version.as_deref().unwrap_or("不明"),
+        protocol::PROTOCOL_VERSION
+    );
+    if let Some(commands) = &info.commands {
+        println!("  対応コマンド: {}", commands.join(", ").dimmed());
+    }
 
     Ok(())
 }
@@ -57,9 +76,9 @@ pub async fn list_articles() -> Result<()> {
     println!();
 
     for article in result.articles {
-        let status_badge = match article.status.as_deref() {
-            Some("published") => "公開中".green(),
-            Some("draft") => "下書き".yellow(),
+        let status_badge = match article.status.as_str() {
+            "published" => "公開中".green(),
+            "draft" => "下書き".yellow(),
             _ => "不明".dimmed(),
         };
 
@@ -81,96 +100,175 @@ pub async fn list_articles() -> Result<()> {
 }
 
 /// Get article content via extension
-pub async fn get_article(username: &str, key: &str, save_path: Option<&Path>) -> Result<()> {
+pub async fn get_article(
+    username: Option<&str>,
+    key: &str,
+    save_path: Option<&Path>,
+) -> Result<()> {
+    let settings = Settings::load()?;
+    let username = settings.require_username(username)?;
+
     println!("{}", "拡張機能に接続中...".cyan());
 
     let client = ExtensionClient::connect().await?;
 
-    println!("{}", "記事を取得中...".cyan());
-    let article = client.get_article(username, key).await?;
-
-    let html = article
-        .html
-        .as_ref()
-        .ok_or_else(|| crate::error::NoetError::Extension("Article HTML not found".to_string()))?;
-
-    // Convert HTML to markdown
-    let markdown = html2md::parse_html(html);
-
     // If save path is specified, download images and save to file
     if let Some(save_file) = save_path {
-        println!("{}", "画像をダウンロード中...".cyan());
-
-        let (markdown_with_local_paths, header_image) =
-            download_images_and_replace_urls(&markdown, save_file).await?;
-
-        // Create frontmatter
-        let mut frontmatter = format!("---\ntitle: \"{}\"\n", article.title);
-
-        if let Some(tags) = &article.tags {
-            if !tags.is_empty() {
-                frontmatter.push_str(&format!(
-                    "tags: [{}]\n",
-                    tags.iter()
-                        .map(|t| format!("\"{t}\""))
-                        .collect::<Vec<_>>()
-                        .join(", ")
-                ));
+        // Track the file if it is saved inside a workspace
+        let absolute = if save_file.is_relative() {
+            std::env::current_dir()?.join(save_file)
+        } else {
+            save_file.to_path_buf()
+        };
+        let root = workspace::find_workspace_root()
+            .ok()
+            .filter(|root| absolute.starts_with(root));
+
+        // Recorded like `pull` does, so `status` can tell later edits on note.com
+        let remote_updated = match &root {
+            Some(_) => {
+                let articles = client.list_articles().await?.articles;
+                sync::find_remote(&articles, key).and_then(sync::remote_timestamp)
             }
-        }
-
-        if let Some(header_img) = header_image {
-            frontmatter.push_str(&format!("header_image: {header_img}\n"));
-        }
-
-        frontmatter.push_str(&format!("note_key: {key}\n"));
-        frontmatter.push_str("---\n\n");
+            None => None,
+        };
 
-        let full_content = format!("{frontmatter}{markdown_with_local_paths}");
+        let fetched = save_article(&client, &username, key, save_file).await?;
 
-        // Save to file
-        fs::write(save_file, full_content)?;
+        if let Some(root) = root {
+            let mut state = SyncState::load(&root)?;
+            let rel_path = sync::relative_path(&root, &absolute);
+            sync_commands::record_pull(&root, &mut state, &rel_path, key, fetched, remote_updated)?;
+        }
 
         println!(
             "{} ファイルに保存しました: {}",
             "✓".green(),
             save_file.display()
         );
-    } else {
-        // Just display to console
-        println!();
-        println!("{} {}", "タイトル:".cyan(), article.title);
+        return Ok(());
+    }
 
-        if let Some(tags) = &article.tags {
-            if !tags.is_empty() {
-                println!(
-                    "{} {}",
-                    "タグ:".cyan(),
-                    tags.iter()
-                        .map(|t| format!("#{t}"))
-                        .collect::<Vec<_>>()
-                        .join(" ")
-                );
-            }
-        }
+    println!("{}", "記事を取得中...".cyan());
+    let article = client.get_article(&username, key).await?;
+    let markdown = html_to_md::convert_html_to_markdown(&article.html)?;
 
-        if let Some(published_at) = &article.published_at {
-            println!("{} {}", "公開日:".cyan(), published_at);
-        }
+    // Just display to console
+    println!();
+    println!("{} {}", "タイトル:".cyan(), article.title);
 
-        println!();
-        println!("{}", "─".repeat(60).dimmed());
-        println!("{markdown}");
+    if !article.tags.is_empty() {
+        println!(
+            "{} {}",
+            "タグ:".cyan(),
+            article
+                .tags
+                .iter()
+                .map(|t| format!("#{t}"))
+                .collect::<Vec<_>>()
+                .join(" ")
+        );
+    }
+
+    if let Some(published_at) = &article.published_at {
+        println!("{} {}", "公開日:".cyan(), published_at);
     }
 
+    println!();
+    println!("{}", "─".repeat(60).dimmed());
+    println!("{markdown}");
+
     Ok(())
 }
 
-/// Download images from Note.com URLs and replace with local paths
+/// An article fetched from note.com, converted to a local document
+pub(crate) struct FetchedArticle {
+    pub document: Document,
+    /// `sync::body_hash` of the converted body, before image URLs are rewritten
+    pub body_hash: String,
+    /// Images downloaded next to the file, with the URL each came from
+    pub images: Vec<(PathBuf, String)>,
+}
+
+/// Hash of the current remote body, for detecting edits made on note.com
+pub(crate) async fn remote_body_hash(
+    client: &ExtensionClient,
+    username: &str,
+    key: &str,
+) -> Result<String> {
+    let article = client.get_article(username, key).await?;
+    let markdown = html_to_md::convert_html_to_markdown(&article.html)?;
+    Ok(sync::body_hash(&markdown))
+}
+
+/// Fetch an article and convert it to a document for `save_file`, downloading
+/// its images next to it. The document itself is not written.
+pub(crate) async fn fetch_article(
+    client: &ExtensionClient,
+    username: &str,
+    key: &str,
+    save_file: &Path,
+) -> Result<FetchedArticle> {
+    // Keep frontmatter keys and heading levels from an existing file
+    let existing = if save_file.exists() {
+        Document::read(save_file).ok()
+    } else {
+        None
+    };
+    let options = existing
+        .as_ref()
+        .map(|doc| ConvertOptions::from_markdown(&doc.body))
+        .unwrap_or_default();
+
+    println!("{}", "記事を取得中...".cyan());
+    let article = client.get_article(username, key).await?;
+    let html = &article.html;
+    let markdown = html_to_md::convert_with_options(html, &options);
+
+    println!("{}", "画像をダウンロード中...".cyan());
+
+    if let Some(parent) = save_file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let (markdown_with_local_paths, header_image, images) =
+        download_images_and_replace_urls(&markdown, save_file).await?;
+
+    let mut frontmatter = existing.map(|doc| doc.frontmatter).unwrap_or_default();
+    frontmatter.title = Some(article.title.clone());
+    frontmatter.tags = article.tags.clone();
+    frontmatter.header_image = header_image;
+    frontmatter.note_key = Some(key.to_string());
+
+    Ok(FetchedArticle {
+        document: Document::new(frontmatter, markdown_with_local_paths),
+        // Without heading hints, to match `remote_body_hash`
+        body_hash: sync::body_hash(&html_to_md::convert_html_to_markdown(html)?),
+        images,
+    })
+}
+
+/// Fetch an article and save it as Markdown with frontmatter and local images
+pub(crate) async fn save_article(
+    client: &ExtensionClient,
+    username: &str,
+    key: &str,
+    save_file: &Path,
+) -> Result<FetchedArticle> {
+    let fetched = fetch_article(client, username, key, save_file).await?;
+
+    // Save to file
+    fetched.document.write(save_file)?;
+
+    Ok(fetched)
+}
+
+/// Download images from Note.com URLs and replace with local paths.
+/// Also returns the header image and each body image downloaded with its URL.
 async fn download_images_and_replace_urls(
     markdown: &str,
     save_file: &Path,
-) -> Result<(String, Option<String>)> {
+) -> Result<(String, Option<String>, Vec<(PathBuf, String)>)> {
     use regex::Regex;
 
     let base_dir = save_file.parent().unwrap_or_else(|| Path::new("."));
@@ -183,6 +281,7 @@ async fn download_images_and_replace_urls(
 
     let mut modified_markdown = markdown.to_string();
     let mut header_image: Option<String> = None;
+    let mut downloaded = Vec::new();
 
     // Pattern for ![caption](url)
     let img_re = Regex::new(r"!\[([^\]]*)\]\((https://[^)]+)\)").unwrap();
@@ -204,6 +303,7 @@ async fn download_images_and_replace_urls(
             modified_markdown = modified_markdown.replace(&old_pattern, &new_pattern);
 
             println!("  {} → {}", url.dimmed(), relative_path);
+            downloaded.push((local_path, url.to_string()));
         }
     }
 
@@ -221,7 +321,7 @@ async fn download_images_and_replace_urls(
         }
     }
 
-    Ok((modified_markdown, header_image))
+    Ok((modified_markdown, header_image, downloaded))
 }
 
 /// Download a single image from URL
@@ -235,12 +335,12 @@ async fn download_image(url: &str, images_dir: &Path) -> Result<std::path::PathB
     // Download image
     let response = reqwest::get(url)
         .await
-        .map_err(|e| crate::error::NoetError::Network(format!("Failed to download image: {e}")))?;
+        .map_err(|e| NoetError::Network(format!("Failed to download image: {e}")))?;
 
     let bytes = response
         .bytes()
         .await
-        .map_err(|e| crate::error::NoetError::Network(format!("Failed to read image data: {e}")))?;
+        .map_err(|e| NoetError::Network(format!("Failed to read image data: {e}")))?;
 
     // Save to file
     fs::write(&file_path, bytes)?;
@@ -248,49 +348,102 @@ async fn download_image(url: &str, images_dir: &Path) -> Result<std::path::PathB
     Ok(file_path)
 }
 
-/// Create article via extension
-pub async fn create_article(file: &Path, draft: bool) -> Result<()> {
-    // Read the markdown file
-    let content = fs::read_to_string(file)?;
+/// A Markdown file ready to be sent to the extension
+pub(crate) struct PreparedArticle {
+    pub title: String,
+    pub body: String,
+    pub tags: Vec<String>,
+    pub draft: bool,
+    pub note_key: Option<String>,
+    /// Read and converted one at a time as they are sent
+    pub images: Vec<LocalImage>,
+    pub header_image: Option<LocalImage>,
+    pub image_options: ImageOptions,
+    /// Directory image references are relative to
+    pub base_dir: PathBuf,
+    /// Workspace whose image cache applies, if the file is in one
+    pub workspace_root: Option<PathBuf>,
+}
 
-    // Parse frontmatter and body
-    let (title, body, tags, header_image_path) = parse_markdown_file(&content);
+impl PreparedArticle {
+    fn has_images(&self) -> bool {
+        !self.images.is_empty() || self.header_image.is_some()
+    }
+}
 
-    // Process images from markdown
-    let images = image_handler::process_images(file, &body)?;
+/// Read a Markdown file, resolve its settings and find its images
+pub(crate) fn prepare_article(
+    file: &Path,
+    settings: &Settings,
+    draft: Option<bool>,
+) -> Result<PreparedArticle> {
+    // Read the markdown file and parse frontmatter
+    let mut document = Document::read(file)?;
+    let title = document.take_title();
+    let Document { frontmatter, body } = document;
+
+    // Line of the trimmed body in the file, for the rewrite report
+    let first_line = frontmatter::trimmed_body_line(&fs::read_to_string(file)?, &body);
+    let body = body.trim();
+    let draft = settings.resolve_draft(draft.or(frontmatter.status.map(ArticleStatus::is_draft)));
+
+    if merge::has_conflict_markers(body) {
+        return Err(NoetError::Conflict(format!(
+            "{} に未解決の競合マーカーがあります。編集してから再実行してください",
+            file.display()
+        )));
+    }
+
+    let transformed = note_markdown::transform(body, &settings.transform_options());
+    print_changes(file, first_line, &transformed.changes);
+    let base_dir = file
+        .parent()
+        .ok_or_else(|| NoetError::InvalidInput("Cannot determine base directory".to_string()))?
+        .to_path_buf();
+
+    // Images uploaded before are referenced by their note.com URL instead of sent again
+    let workspace_root = std::env::current_dir()?
+        .join(&base_dir)
+        .canonicalize()
+        .ok()
+        .and_then(|dir| workspace::find_workspace_root_from(&dir).ok());
+    let body = match &workspace_root {
+        Some(root) => {
+            let (body, reused) = ImageCache::load(root)?.rewrite(&transformed.body, &base_dir);
+            if reused > 0 {
+                println!(
+                    "{} アップロード済みの画像を {reused} 枚再利用します",
+                    "✓".green()
+                );
+            }
+            body
+        }
+        None => transformed.body,
+    };
+
+    // Default tags only apply to articles that have never been published
+    let tags = if frontmatter.tags.is_empty() && frontmatter.note_key.is_none() {
+        settings.default_tags.clone()
+    } else {
+        frontmatter.tags
+    };
+
+    let images = image_handler::find_images(file, &body)?;
 
     // Process header image if specified
-    let header_image = if let Some(path_str) = header_image_path {
+    let header_image = if let Some(path_str) = frontmatter.header_image {
         let header_path = if Path::new(&path_str).is_absolute() {
             std::path::PathBuf::from(&path_str)
         } else {
-            file.parent()
-                .ok_or_else(|| {
-                    crate::error::NoetError::InvalidInput(
-                        "Cannot determine base directory".to_string(),
-                    )
-                })?
-                .join(&path_str)
+            base_dir.join(&path_str)
         };
 
         if header_path.exists() {
-            let (mime_type, base64_data) = image_handler::read_image_as_base64(&header_path)?;
-            let filename = header_path
-                .file_name()
-                .and_then(|s| s.to_str())
-                .ok_or_else(|| {
-                    crate::error::NoetError::InvalidInput(
-                        "Invalid header image filename".to_string(),
-                    )
-                })?
-                .to_string();
-
-            Some(ImageData {
-                local_path: path_str.clone(),
-                filename,
+            image_pipeline::check(&header_path)?;
+            Some(LocalImage {
+                path: header_path,
+                local_path: path_str,
                 caption: String::new(),
-                mime_type,
-                data: base64_data,
             })
         } else {
             eprintln!("Warning: Header image not found: {}", header_path.display());
@@ -300,205 +453,271 @@ pub async fn create_article(file: &Path, draft: bool) -> Result<()> {
         None
     };
 
-    println!("{}", "拡張機能に接続中...".cyan());
+    Ok(PreparedArticle {
+        title,
+        body,
+        tags,
+        draft,
+        note_key: frontmatter.note_key,
+        images,
+        header_image,
+        image_options: settings.image_options(),
+        base_dir,
+        workspace_root,
+    })
+}
 
-    let client = ExtensionClient::connect().await?;
+/// Add the images the extension uploaded to the workspace's image cache
+pub(crate) fn remember_uploaded_images(
+    article: &PreparedArticle,
+    result: &ArticleResult,
+) -> Result<()> {
+    let (Some(root), Some(uploaded)) = (&article.workspace_root, &result.uploaded_images) else {
+        return Ok(());
+    };
+    if uploaded.is_empty() {
+        return Ok(());
+    }
+
+    let mut cache = ImageCache::load(root)?;
+    for image in uploaded {
+        if let Some(path) = image_handler::local_image_path(&article.base_dir, &image.local_path) {
+            // Gone since it was read, so there is nothing to match later anyway
+            let _ = cache.record(&path, &image.note_url);
+        }
+    }
+    cache.save(root)
+}
+
+/// Report what was rewritten for the note.com editor
+fn print_changes(file: &Path, first_line: usize, changes: &[Change]) {
+    if changes.is_empty() {
+        return;
+    }
+
+    println!(
+        "{} note.com で表示できない記法を {} 箇所変換しました:",
+        "!".yellow(),
+        changes.len()
+    );
+    for change in changes {
+        println!(
+            "  {}:{} {}: {} → {}",
+            file.display(),
+            first_line + change.line - 1,
+            change.kind,
+            snippet(&change.before),
+            snippet(&change.after).green()
+        );
+    }
+}
 
-    let mode = if draft { "下書き" } else { "公開" };
+/// Shorten a rewrite for the one-line report
+fn snippet(text: &str) -> String {
+    const MAX_CHARS: usize = 40;
+    let text = text.replace('\n', " ");
+    if text.chars().count() > MAX_CHARS {
+        format!("{}…", text.chars().take(MAX_CHARS).collect::<String>())
+    } else {
+        text
+    }
+}
+
+/// Create (no key) or update (with key) an article
+pub(crate) async fn send_article(
+    client: &ExtensionClient,
+    article: &PreparedArticle,
+    key: Option<&str>,
+) -> Result<ArticleResult> {
+    let local: Vec<&LocalImage> = article.images.iter().chain(&article.header_image).collect();
+    let mut images = client.upload_images(&local, &article.image_options).await?;
+    let header_image = article.header_image.as_ref().and_then(|_| images.pop());
+
+    match key {
+        None => {
+            let params = CreateArticleParams {
+                title: article.title.clone(),
+                body: article.body.clone(),
+                tags: article.tags.clone(),
+                draft: article.draft,
+                images,
+                header_image,
+            };
+            client.create_article(&params).await
+        }
+        Some(key) => {
+            let params = UpdateArticleParams {
+                key: key.to_string(),
+                title: article.title.clone(),
+                body: article.body.clone(),
+                tags: Some(article.tags.clone()),
+                draft: article.draft,
+                images,
+                header_image,
+            };
+            client.update_article(&params).await
+        }
+    }
+}
 
-    if !images.is_empty() || header_image.is_some() {
-        let img_count = images.len();
-        let has_header = if header_image.is_some() {
+/// Print the progress line shown before sending an article
+fn print_sending(article: &PreparedArticle, action: &str) {
+    if article.has_images() {
+        let img_count = article.images.len();
+        let has_header = if article.header_image.is_some() {
             " (見出し画像あり)"
         } else {
             ""
         };
         println!(
             "{}",
-            format!("記事を{mode}として投稿中... (画像: {img_count}枚{has_header})").cyan()
+            format!("記事を{action}... (画像: {img_count}枚{has_header})").cyan()
         );
     } else {
-        println!("{}", format!("記事を{mode}として投稿中...").cyan());
+        println!("{}", format!("記事を{action}...").cyan());
     }
+}
 
-    let result = if !images.is_empty() || header_image.is_some() {
-        client
-            .create_article_with_images(&title, &body, &tags, draft, &images, header_image.as_ref())
-            .await?
-    } else {
-        client.create_article(&title, &body, &tags, draft).await?
-    };
+/// Print the result of create/update. Returns whether it succeeded.
+pub(crate) fn print_send_result(result: &ArticleResult, mode: &str, failure: &str) -> bool {
+    if !result.success {
+        let error = result.error.as_deref().unwrap_or("不明なエラー");
+        println!("{} {failure}に失敗しました: {error}", "✗".red());
+        return false;
+    }
 
-    if result
-        .get("success")
-        .and_then(|v| v.as_bool())
-        .unwrap_or(false)
-    {
-        println!("{} 記事を{}しました", "✓".green(), mode);
+    println!("{} 記事を{}しました", "✓".green(), mode);
 
-        if let Some(url) = result.get("url").and_then(|v| v.as_str()) {
-            println!("  URL: {}", url.cyan());
-        }
+    if let Some(url) = &result.url {
+        println!("  URL: {}", url.cyan());
+    }
 
-        if let Some(status) = result.get("status").and_then(|v| v.as_str()) {
-            println!("  ステータス: {status}");
-        }
+    if let Some(status) = &result.status {
+        println!("  ステータス: {status}");
+    }
 
-        // Show uploaded images
-        if let Some(uploaded_images) = result.get("uploaded_images").and_then(|v| v.as_array()) {
-            if !uploaded_images.is_empty() {
-                println!("  アップロードされた画像:");
-                for img in uploaded_images {
-                    if let (Some(local_path), Some(note_url)) = (
-                        img.get("local_path").and_then(|v| v.as_str()),
-                        img.get("note_url").and_then(|v| v.as_str()),
-                    ) {
-                        println!("    {} → {}", local_path, note_url.dimmed());
-                    }
-                }
-            }
+    // Show uploaded images
+    if let Some(uploaded_images) = result.uploaded_images.as_ref().filter(|i| !i.is_empty()) {
+        println!("  アップロードされた画像:");
+        for img in uploaded_images {
+            println!("    {} → {}", img.local_path, img.note_url.dimmed());
         }
+    }
 
-        if let Some(header_url) = result.get("header_image_url").and_then(|v| v.as_str()) {
-            println!("  見出し画像: {}", header_url.dimmed());
-        }
-    } else {
-        let error = result
-            .get("error")
-            .and_then(|v| v.as_str())
-            .unwrap_or("不明なエラー");
-        println!("{} 投稿に失敗しました: {error}", "✗".red());
+    if let Some(header_url) = &result.header_image_url {
+        println!("  見出し画像: {}", header_url.dimmed());
     }
 
-    Ok(())
+    true
 }
 
-/// Update article via extension
-pub async fn update_article(key: &str, file: &Path, draft: bool) -> Result<()> {
-    // Read the markdown file
-    let content = fs::read_to_string(file)?;
+/// Extract the article key from a create result (`/n/<key>` or `/notes/<key>/edit`)
+pub(crate) fn key_from_result(result: &ArticleResult) -> Option<String> {
+    if let Some(key) = &result.key {
+        return Some(key.clone());
+    }
 
-    // Parse frontmatter and body
-    let (title, body, tags, header_image_path) = parse_markdown_file(&content);
+    let url = result.url.as_deref()?;
+    let re = regex::Regex::new(r"/(?:n|notes)/(n[0-9a-zA-Z]+)").unwrap();
+    re.captures(url).map(|cap| cap[1].to_string())
+}
 
-    // Process images from markdown
-    let images = image_handler::process_images(file, &body)?;
+/// Create article via extension
+pub async fn create_article(file: &Path, draft: Option<bool>) -> Result<()> {
+    let settings = Settings::load()?;
+    let article = prepare_article(file, &settings, draft)?;
 
-    // Process header image if specified
-    let header_image = if let Some(path_str) = header_image_path {
-        let header_path = if Path::new(&path_str).is_absolute() {
-            std::path::PathBuf::from(&path_str)
-        } else {
-            file.parent()
-                .ok_or_else(|| {
-                    crate::error::NoetError::InvalidInput(
-                        "Cannot determine base directory".to_string(),
-                    )
-                })?
-                .join(&path_str)
-        };
+    println!("{}", "拡張機能に接続中...".cyan());
 
-        if header_path.exists() {
-            let (mime_type, base64_data) = image_handler::read_image_as_base64(&header_path)?;
-            let filename = header_path
-                .file_name()
-                .and_then(|s| s.to_str())
-                .ok_or_else(|| {
-                    crate::error::NoetError::InvalidInput(
-                        "Invalid header image filename".to_string(),
-                    )
-                })?
-                .to_string();
-
-            Some(ImageData {
-                local_path: path_str.clone(),
-                filename,
-                caption: String::new(),
-                mime_type,
-                data: base64_data,
-            })
-        } else {
-            eprintln!("Warning: Header image not found: {}", header_path.display());
-            None
+    let client = ExtensionClient::connect().await?;
+
+    let mode = if article.draft { "下書き" } else { "公開" };
+    print_sending(&article, &format!("{mode}として投稿中"));
+
+    let result = send_article(&client, &article, None).await?;
+    if print_send_result(&result, mode, "投稿") {
+        remember_uploaded_images(&article, &result)?;
+    }
+
+    Ok(())
+}
+
+/// Update article via extension
+pub async fn update_article(
+    key: Option<&str>,
+    file: &Path,
+    draft: Option<bool>,
+    force: bool,
+) -> Result<()> {
+    let settings = Settings::load()?;
+    let article = prepare_article(file, &settings, draft)?;
+
+    let key = key
+        .map(String::from)
+        .or_else(|| article.note_key.clone())
+        .ok_or_else(|| {
+            NoetError::InvalidInput(format!(
+                "記事キーがありません。--key を指定するか {} の frontmatter に note_key を書いてください",
+                file.display()
+            ))
+        })?;
+
+    // Workspace file this update belongs to, if any
+    let tracking = match workspace::find_workspace_root() {
+        Ok(root) => {
+            let path = if file.is_relative() {
+                std::env::current_dir()?.join(file)
+            } else {
+                file.to_path_buf()
+            };
+            path.starts_with(&root)
+                .then(|| (sync::relative_path(&root, &path), root))
         }
-    } else {
-        None
+        Err(_) => None,
     };
 
     println!("{}", "拡張機能に接続中...".cyan());
 
     let client = ExtensionClient::connect().await?;
 
-    let mode = if draft { "下書き保存" } else { "更新" };
-
-    if !images.is_empty() || header_image.is_some() {
-        let img_count = images.len();
-        let has_header = if header_image.is_some() {
-            " (見出し画像あり)"
-        } else {
-            ""
-        };
-        println!(
-            "{}",
-            format!("記事を{mode}中... (画像: {img_count}枚{has_header})").cyan()
-        );
-    } else {
-        println!("{}", format!("記事を{mode}中...").cyan());
-    }
-
-    let result = if !images.is_empty() || header_image.is_some() {
-        client
-            .update_article_with_images(
-                key,
-                &title,
-                &body,
-                Some(&tags),
-                draft,
-                &images,
-                header_image.as_ref(),
+    if let Some((_, root)) = &tracking {
+        let state = SyncState::load(root)?;
+        if let (Some((_, tracked)), false) = (state.find_by_key(&key), force) {
+            let remote = client.list_articles().await?.articles;
+            sync_commands::ensure_remote_unchanged(
+                &client,
+                tracked,
+                &remote,
+                settings.username.as_deref(),
             )
-            .await?
+            .await?;
+        }
+    }
+
+    let mode = if article.draft {
+        "下書き保存"
     } else {
-        client
-            .update_article(key, &title, &body, Some(&tags), draft)
-            .await?
+        "更新"
     };
+    print_sending(&article, &format!("{mode}中"));
 
-    if result
-        .get("success")
-        .and_then(|v| v.as_bool())
-        .unwrap_or(false)
-    {
-        println!("{} 記事を{}しました", "✓".green(), mode);
-
-        if let Some(status) = result.get("status").and_then(|v| v.as_str()) {
-            println!("  ステータス: {status}");
-        }
-
-        // Show uploaded images
-        if let Some(uploaded_images) = result.get("uploaded_images").and_then(|v| v.as_array()) {
-            if !uploaded_images.is_empty() {
-                println!("  アップロードされた画像:");
-                for img in uploaded_images {
-                    if let (Some(local_path), Some(note_url)) = (
-                        img.get("local_path").and_then(|v| v.as_str()),
-                        img.get("note_url").and_then(|v| v.as_str()),
-                    ) {
-                        println!("    {} → {}", local_path, note_url.dimmed());
-                    }
-                }
-            }
-        }
+    let result = send_article(&client, &article, Some(&key)).await?;
+    let success = print_send_result(&result, mode, "更新");
+    if success {
+        remember_uploaded_images(&article, &result)?;
+    }
 
-        if let Some(header_url) = result.get("header_image_url").and_then(|v| v.as_str()) {
-            println!("  見出し画像: {}", header_url.dimmed());
-        }
-    } else {
-        let error = result
-            .get("error")
-            .and_then(|v| v.as_str())
-            .unwrap_or("不明なエラー");
-        println!("{} 更新に失敗しました: {error}", "✗".red());
+    if let (true, Some((rel_path, root))) = (success, &tracking) {
+        let mut state = SyncState::load(root)?;
+        sync_commands::record_push(root, &mut state, rel_path, &key)?;
+        sync_commands::refresh_remote_state(
+            &client,
+            root,
+            &mut state,
+            &[key],
+            settings.username.as_deref(),
+        )
+        .await?;
     }
 
     Ok(())
@@ -513,81 +732,43 @@ pub async fn delete_article(key: &str) -> Result<()> {
     println!("{}", "記事を削除中...".cyan());
     let result = client.delete_article(key).await?;
 
-    if result
-        .get("success")
-        .and_then(|v| v.as_bool())
-        .unwrap_or(false)
-    {
+    if result.success {
         println!("{} 記事を削除しました", "✓".green());
     } else {
-        let error = result
-            .get("error")
-            .and_then(|v| v.as_str())
-            .unwrap_or("不明なエラー");
+        let error = result.error.as_deref().unwrap_or("不明なエラー");
         println!("{} 削除に失敗しました: {}", "✗".red(), error);
     }
 
     Ok(())
 }
 
-/// Parse a markdown file with frontmatter
-/// Returns (title, body, tags, header_image)
-fn parse_markdown_file(content: &str) -> (String, String, Vec<String>, Option<String>) {
-    let mut title = String::new();
-    let mut tags: Vec<String> = Vec::new();
-    let mut header_image: Option<String> = None;
-    let mut body = content.to_string();
-
-    // Check for YAML frontmatter
-    if let Some(rest) = content.strip_prefix("---") {
-        if let Some(end) = rest.find("---") {
-            let frontmatter = &rest[..end];
-            body = rest[end + 3..].trim().to_string();
-
-            // Parse frontmatter
-            for line in frontmatter.lines() {
-                let line = line.trim();
-                if let Some(value) = line.strip_prefix("title:") {
-                    title = value
-                        .trim()
-                        .trim_matches('"')
-                        .trim_matches('\'')
-                        .to_string();
-                } else if let Some(value) = line.strip_prefix("tags:") {
-                    // tags: [tag1, tag2] or tags: tag1, tag2
-                    let value = value.trim().trim_matches('[').trim_matches(']');
-                    tags = value
-                        .split(',')
-                        .map(|s| s.trim().trim_matches('"').trim_matches('\'').to_string())
-                        .filter(|s| !s.is_empty())
-                        .collect();
-                } else if let Some(value) = line.strip_prefix("header_image:") {
-                    // header_image: path/to/image.jpg
-                    let value = value
-                        .trim()
-                        .trim_matches('"')
-                        .trim_matches('\'')
-                        .to_string();
-                    if !value.is_empty() {
-                        header_image = Some(value);
-                    }
-                }
-            }
-        }
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    // If no title in frontmatter, try to extract from first H1
-    if title.is_empty() {
-        for line in body.lines() {
-            let line = line.trim();
-            if let Some(h1) = line.strip_prefix("# ") {
-                title = h1.to_string();
-                // Remove the H1 from body
-                body = body.replacen(line, "", 1).trim().to_string();
-                break;
-            }
-        }
+    fn result(value: serde_json::Value) -> ArticleResult {
+        serde_json::from_value(value).unwrap()
     }
 
-    (title, body, tags, header_image)
+    #[test]
+    fn test_key_from_result() {
+        let published = result(
+            serde_json::json!({"success": true, "url": "https://note.com/user/n/n1a2b3c4d5e6f"}),
+        );
+        assert_eq!(
+            key_from_result(&published).as_deref(),
+            Some("n1a2b3c4d5e6f")
+        );
+
+        let draft = result(
+            serde_json::json!({"success": true, "url": "https://editor.note.com/notes/n9f8e7d6c5b4a/edit/"}),
+        );
+        assert_eq!(key_from_result(&draft).as_deref(), Some("n9f8e7d6c5b4a"));
+
+        let explicit = result(serde_json::json!({"success": true, "key": "nabc"}));
+        assert_eq!(key_from_result(&explicit).as_deref(), Some("nabc"));
+
+        let none = result(serde_json::json!({"success": true, "url": "https://note.com/notes"}));
+        assert_eq!(key_from_result(&none), None);
+    }
 }
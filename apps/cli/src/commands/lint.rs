@@ -0,0 +1,105 @@
+use crate::cli::OutputFormat;
+use crate::config::Settings;
+use crate::error::{NoetError, Result};
+use crate::lint::{self, Diagnostic, Severity};
+use crate::sync;
+use crate::workspace;
+use colored::Colorize;
+use std::fs;
+use std::path::PathBuf;
+
+/// Lint articles and fail if any problem should block publishing
+pub fn run(paths: &[PathBuf], format: OutputFormat, strict: bool) -> Result<()> {
+    let settings = Settings::load()?;
+    let files = collect_files(paths, &settings)?;
+
+    let mut diagnostics = Vec::new();
+    for file in &files {
+        let content = fs::read_to_string(file)?;
+        diagnostics.extend(lint::lint(file, &content, &settings.transform_options()));
+    }
+
+    match format {
+        OutputFormat::Text => print_text(&diagnostics, files.len()),
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&diagnostics)?),
+    }
+
+    let failures = diagnostics
+        .iter()
+        .filter(|d| strict || d.severity == Severity::Error)
+        .count();
+    if failures > 0 {
+        return Err(NoetError::LintFailed(failures));
+    }
+
+    Ok(())
+}
+
+/// Markdown files named on the command line, or the workspace articles
+fn collect_files(paths: &[PathBuf], settings: &Settings) -> Result<Vec<PathBuf>> {
+    let default_dir;
+    let paths = if paths.is_empty() {
+        let root = workspace::find_workspace_root().map_err(|_| {
+            NoetError::InvalidInput(
+                "ファイルを指定するか、ワークスペース内で実行してください".to_string(),
+            )
+        })?;
+        default_dir = [root.join(&settings.articles_dir)];
+        &default_dir[..]
+    } else {
+        paths
+    };
+
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let mut found = Vec::new();
+            sync::collect_markdown(path, &mut found)?;
+            found.sort();
+            files.extend(found);
+        } else if path.exists() {
+            files.push(path.clone());
+        } else {
+            return Err(NoetError::FileNotFound(path.display().to_string()));
+        }
+    }
+
+    Ok(files)
+}
+
+fn print_text(diagnostics: &[Diagnostic], file_count: usize) {
+    for d in diagnostics {
+        let severity = match d.severity {
+            Severity::Error => d.severity.to_string().red().bold(),
+            Severity::Warning => d.severity.to_string().yellow(),
+        };
+        println!(
+            "{}:{}: {severity}: {} {}",
+            d.path,
+            d.line,
+            d.message,
+            format!("[{}]", d.rule).dimmed()
+        );
+    }
+
+    let errors = count(diagnostics, Severity::Error);
+    let warnings = count(diagnostics, Severity::Warning);
+
+    if diagnostics.is_empty() {
+        println!("{} {file_count} ファイルに問題はありません", "✓".green());
+    } else {
+        println!();
+        println!(
+            "{file_count} ファイル: エラー {} 件, 警告 {} 件",
+            errors.to_string().red(),
+            warnings.to_string().yellow()
+        );
+    }
+}
+
+fn count(diagnostics: &[Diagnostic], severity: Severity) -> usize {
+    diagnostics
+        .iter()
+        .filter(|d| d.severity == severity)
+        .count()
+}
@@ -1,4 +1,11 @@
+pub mod config;
+pub mod daemon;
+pub mod doctor;
+pub mod export;
 pub mod extension;
+pub mod lint;
+pub mod new;
 pub mod setup;
+pub mod sync;
 pub mod template;
 pub mod workspace;
@@ -0,0 +1,178 @@
+//! `noet new`: start an article from a template
+
+use crate::commands::sync::{push_file, record_push, refresh_remote_state};
+use crate::commands::template;
+use crate::config::Settings;
+use crate::editor;
+use crate::error::{NoetError, Result};
+use crate::extension_client::ExtensionClient;
+use crate::sync::{self, SyncState};
+use crate::template::{self as engine, Context, Template, Variable};
+use crate::workspace;
+use colored::Colorize;
+use dialoguer::{Input, Select};
+use std::fs;
+use std::path::Path;
+
+/// File name stem used when nothing usable is left of the title
+const FALLBACK_STEM: &str = "untitled";
+
+/// Create an article in the workspace, optionally as a draft on note.com, and open it
+pub async fn run(
+    title: Option<&str>,
+    template_name: Option<&str>,
+    vars: &[String],
+    create_draft: bool,
+    edit: bool,
+) -> Result<()> {
+    let root = workspace::find_workspace_root()?;
+    let settings = Settings::load()?;
+
+    let title = match title {
+        Some(title) => title.to_string(),
+        None => Input::<String>::new()
+            .with_prompt("記事タイトル")
+            .interact_text()?,
+    };
+    let title = title.trim();
+    let template = choose_template(template_name, &settings)?;
+
+    let stem = match workspace::slugify(title) {
+        stem if stem.is_empty() => FALLBACK_STEM.to_string(),
+        stem => stem,
+    };
+    let mut context = engine::builtins(title, &stem, &engine::today(), &settings, &root);
+    preset_variables(&mut context, &template, vars)?;
+    template.resolve(&mut context, ask)?;
+    let content = template.render(&context)?;
+
+    let dir = root.join(&settings.articles_dir);
+    fs::create_dir_all(&dir)?;
+    let path = workspace::unique_article_path(&dir, &stem);
+    fs::write(&path, content)?;
+
+    let rel_path = sync::relative_path(&root, &path);
+    println!("{} {} を作成しました", "✓".green(), rel_path.bold());
+
+    if create_draft {
+        if let Err(e) = create_remote_draft(&root, &rel_path, &settings).await {
+            println!("{} 下書きを作成できませんでした: {e}", "!".yellow());
+            println!(
+                "  後で {} を実行すると投稿されます",
+                "noet push --draft".cyan()
+            );
+        }
+    }
+
+    if edit {
+        editor::open_in_editor(&path)?;
+    }
+
+    Ok(())
+}
+
+/// The named template, or one picked interactively
+fn choose_template(template_name: Option<&str>, settings: &Settings) -> Result<Template> {
+    if let Some(name) = template_name {
+        return template::load_template(name);
+    }
+
+    let names = template::list_template_names()?;
+    if names.is_empty() {
+        return template::builtin_template(settings);
+    }
+
+    let mut items = names.clone();
+    items.push("(テンプレートなし)".to_string());
+    let selection = Select::new()
+        .with_prompt("テンプレートを選択")
+        .items(&items)
+        .default(0)
+        .interact()?;
+
+    match names.get(selection) {
+        Some(name) => template::load_template(name),
+        None => template::builtin_template(settings),
+    }
+}
+
+/// Apply `--var NAME=VALUE` arguments so those variables aren't asked for
+fn preset_variables(context: &mut Context, template: &Template, vars: &[String]) -> Result<()> {
+    for var in vars {
+        let Some((name, answer)) = var.split_once('=') else {
+            return Err(NoetError::InvalidInput(format!(
+                "--var は NAME=VALUE の形式で指定してください: {var}"
+            )));
+        };
+        let name = name.trim();
+        if engine::BUILTIN_VARIABLES.contains(&name) {
+            return Err(NoetError::InvalidInput(format!(
+                "組み込み変数 {name} は --var で変更できません"
+            )));
+        }
+
+        let value = match template.variables.iter().find(|v| v.name == name) {
+            Some(variable) => engine::value(variable, answer),
+            None => answer.trim().into(),
+        };
+        context.insert(name.to_string(), value);
+    }
+    Ok(())
+}
+
+fn ask(variable: &Variable, default: Option<String>) -> Result<String> {
+    let mut prompt = variable.prompt.clone().unwrap_or(variable.name.clone());
+    if variable.list {
+        prompt.push_str(" (カンマ区切り)");
+    }
+
+    let mut input = Input::<String>::new().with_prompt(prompt).allow_empty(true);
+    if let Some(default) = default {
+        input = input.default(default);
+    }
+    Ok(input.interact_text()?)
+}
+
+/// Post the new file as a draft and start tracking it under the returned key
+async fn create_remote_draft(root: &Path, rel_path: &str, settings: &Settings) -> Result<()> {
+    println!("{}", "拡張機能に接続中...".cyan());
+    let client = ExtensionClient::connect().await?;
+
+    let Some(key) = push_file(&client, root, rel_path, None, settings, Some(true)).await? else {
+        return Ok(());
+    };
+
+    let mut state = SyncState::load(root)?;
+    record_push(root, &mut state, rel_path, &key)?;
+    refresh_remote_state(
+        &client,
+        root,
+        &mut state,
+        &[key],
+        settings.username.as_deref(),
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use minijinja::Value;
+
+    #[test]
+    fn test_preset_variables() {
+        let source = "---\nvariables:\n  - name: topics\n    list: true\n---\n";
+        let template = Template::parse("t", source, None).unwrap();
+        let mut context = Context::new();
+
+        let vars = ["topics=a, b".to_string(), "extra = x ".to_string()];
+        preset_variables(&mut context, &template, &vars).unwrap();
+        assert_eq!(context["topics"], Value::from(vec!["a", "b"]));
+        assert_eq!(context["extra"], Value::from("x"));
+
+        let builtin = ["TITLE=x".to_string()];
+        assert!(preset_variables(&mut context, &template, &builtin).is_err());
+        let malformed = ["topics".to_string()];
+        assert!(preset_variables(&mut context, &template, &malformed).is_err());
+    }
+}
@@ -1,12 +1,22 @@
+use crate::browser::{self, Browser};
+use crate::config;
 use crate::error::{NoetError, Result};
+use crate::extension_auth;
 use colored::Colorize;
+use dialoguer::Input;
+use sha2::{Digest, Sha256};
 use std::fs;
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
 
-/// Extension download URL (GitHub releases)
-const EXTENSION_RELEASE_URL: &str =
-    "https://github.com/kako-jun/noet/releases/latest/download/noet-extension.zip";
+/// GitHub releases, one per version of noet
+const RELEASES_URL: &str = "https://github.com/kako-jun/noet/releases/download";
+
+/// Release asset holding the extension
+const EXTENSION_ASSET: &str = "noet-extension.zip";
+
+/// Release asset listing the SHA-256 of the other assets, in `sha256sum` format
+const CHECKSUMS_ASSET: &str = "SHA256SUMS";
 
 /// Local extension path (for development)
 fn get_local_extension_path() -> Option<PathBuf> {
@@ -31,63 +41,101 @@ fn get_extension_dir() -> Result<PathBuf> {
     Ok(config_dir.join("noet").join("extension"))
 }
 
-/// Get the native messaging manifest directory for the current platform
-fn get_native_manifest_dir() -> Result<PathBuf> {
-    #[cfg(target_os = "linux")]
-    {
-        let home = dirs::home_dir()
-            .ok_or_else(|| NoetError::ConfigError("ホームディレクトリが見つかりません".into()))?;
-        Ok(home
-            .join(".config")
-            .join("google-chrome")
-            .join("NativeMessagingHosts"))
+/// The extension noet uses: the repo's copy in development, otherwise the downloaded one
+pub fn current_extension_dir() -> Result<PathBuf> {
+    match get_local_extension_path() {
+        Some(local_path) => Ok(local_path),
+        None => get_extension_dir(),
     }
+}
 
-    #[cfg(target_os = "macos")]
-    {
-        let home = dirs::home_dir()
-            .ok_or_else(|| NoetError::ConfigError("ホームディレクトリが見つかりません".into()))?;
-        Ok(home
-            .join("Library")
-            .join("Application Support")
-            .join("Google")
-            .join("Chrome")
-            .join("NativeMessagingHosts"))
-    }
+pub fn home_dir() -> Result<PathBuf> {
+    dirs::home_dir()
+        .ok_or_else(|| NoetError::ConfigError("ホームディレクトリが見つかりません".into()))
+}
 
-    #[cfg(target_os = "windows")]
-    {
-        // Windows uses registry, but we can also use a manifest file in AppData
-        let app_data = dirs::config_dir()
-            .ok_or_else(|| NoetError::ConfigError("AppDataディレクトリが見つかりません".into()))?;
-        Ok(app_data.join("noet").join("NativeMessagingHosts"))
+/// `browser` if given, otherwise every browser that has been used on this machine
+fn target_browsers(browser: Option<Browser>) -> Result<Vec<Browser>> {
+    if let Some(browser) = browser {
+        return Ok(vec![browser]);
     }
 
-    #[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
-    {
-        Err(NoetError::ConfigError(
-            "サポートされていないプラットフォームです".into(),
-        ))
+    let detected = Browser::detect(&home_dir()?);
+    if detected.is_empty() {
+        println!(
+            "      {} ブラウザが見つからないため Chrome 用に設定します (--browser で指定できます)",
+            "!".yellow()
+        );
+        return Ok(vec![Browser::Chrome]);
     }
+    let names: Vec<_> = detected.iter().map(|b| b.name()).collect();
+    println!("      検出したブラウザ: {}", names.join(", "));
+    Ok(detected)
 }
 
-/// Download and extract the extension
-async fn download_extension(extension_dir: &PathBuf) -> Result<()> {
-    println!("{}", "[1/4] 拡張機能をダウンロード中...".cyan());
+/// Where an extension comes from
+enum Package {
+    /// A downloaded or local zip
+    Zip(Vec<u8>),
+    /// An already unpacked extension
+    Dir(PathBuf),
+}
 
-    // Create extension directory
-    fs::create_dir_all(extension_dir)?;
+/// URL of an asset of the release for `version`
+fn release_asset_url(version: &str, asset: &str) -> String {
+    format!("{RELEASES_URL}/v{version}/{asset}")
+}
+
+/// Download the extension released with `version` and check it against the
+/// release's checksums
+async fn download_release(version: &str) -> Result<Package> {
+    let bytes = fetch(&release_asset_url(version, EXTENSION_ASSET)).await?;
+    let checksums = fetch(&release_asset_url(version, CHECKSUMS_ASSET)).await?;
+    verify_checksum(
+        &bytes,
+        &String::from_utf8_lossy(&checksums),
+        EXTENSION_ASSET,
+    )?;
+    println!("      {} SHA-256 を確認しました", "✓".green());
+    Ok(Package::Zip(bytes))
+}
+
+/// Read an extension from a zip or a directory for offline installs. A zip is
+/// checked against a `SHA256SUMS` next to it when there is one.
+fn read_package(path: &Path) -> Result<Package> {
+    if path.is_dir() {
+        return Ok(Package::Dir(path.to_path_buf()));
+    }
+    if !path.exists() {
+        return Err(NoetError::FileNotFound(path.display().to_string()));
+    }
 
-    let zip_path = extension_dir.join("noet-extension.zip");
+    let bytes = fs::read(path)?;
+    let checksums_path = path.with_file_name(CHECKSUMS_ASSET);
+    if checksums_path.exists() {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        verify_checksum(&bytes, &fs::read_to_string(&checksums_path)?, &name)?;
+        println!("      {} SHA-256 を確認しました", "✓".green());
+    } else {
+        println!(
+            "      {} {CHECKSUMS_ASSET} が同じフォルダにないため、チェックサムを確認していません",
+            "!".yellow()
+        );
+    }
+    Ok(Package::Zip(bytes))
+}
 
-    // Download using reqwest
-    let response = reqwest::get(EXTENSION_RELEASE_URL)
+async fn fetch(url: &str) -> Result<Vec<u8>> {
+    let response = reqwest::get(url)
         .await
         .map_err(|e| NoetError::Network(format!("ダウンロードに失敗しました: {e}")))?;
 
     if !response.status().is_success() {
         return Err(NoetError::Network(format!(
-            "ダウンロードに失敗しました: HTTP {}",
+            "ダウンロードに失敗しました: HTTP {} ({url})",
             response.status()
         )));
     }
@@ -96,14 +144,34 @@ async fn download_extension(extension_dir: &PathBuf) -> Result<()> {
         .bytes()
         .await
         .map_err(|e| NoetError::Network(format!("データの読み込みに失敗しました: {e}")))?;
+    Ok(bytes.to_vec())
+}
 
-    fs::write(&zip_path, &bytes)?;
+/// Check `bytes` against the entry for `name` in a `sha256sum` style file
+fn verify_checksum(bytes: &[u8], checksums: &str, name: &str) -> Result<()> {
+    let expected = checksums
+        .lines()
+        .find_map(|line| {
+            let (hash, file) = line.trim().split_once(char::is_whitespace)?;
+            // `*` marks binary mode in sha256sum output
+            (file.trim_start().trim_start_matches('*') == name).then(|| hash.to_lowercase())
+        })
+        .ok_or_else(|| NoetError::Integrity(format!("{CHECKSUMS_ASSET} に {name} がありません")))?;
+
+    let actual = format!("{:x}", Sha256::digest(bytes));
+    if actual != expected {
+        return Err(NoetError::Integrity(format!(
+            "{name} の SHA-256 が一致しません (期待値 {expected}, 実際 {actual})。ファイルが壊れているか改ざんされています"
+        )));
+    }
+    Ok(())
+}
 
-    println!("{}", "[2/4] 解凍中...".cyan());
+/// Extract an extension zip into `extension_dir`
+fn unpack_extension(bytes: &[u8], extension_dir: &Path) -> Result<()> {
+    fs::create_dir_all(extension_dir)?;
 
-    // Extract zip
-    let file = fs::File::open(&zip_path)?;
-    let mut archive = zip::ZipArchive::new(file)
+    let mut archive = zip::ZipArchive::new(io::Cursor::new(bytes))
         .map_err(|e| NoetError::ConfigError(format!("ZIPファイルの読み込みに失敗しました: {e}")))?;
 
     for i in 0..archive.len() {
@@ -129,85 +197,220 @@ async fn download_extension(extension_dir: &PathBuf) -> Result<()> {
         }
     }
 
-    // Remove zip file
-    fs::remove_file(&zip_path)?;
-
-    println!("      {} {}", "解凍完了:".green(), extension_dir.display());
-
     Ok(())
 }
 
-/// Configure native messaging host
-fn configure_native_messaging(_extension_dir: &Path) -> Result<()> {
-    println!("{}", "[3/4] Native Messaging を設定中...".cyan());
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
 
-    let manifest_dir = get_native_manifest_dir()?;
-    fs::create_dir_all(&manifest_dir)?;
+/// Version in the manifest of an unpacked extension
+pub fn installed_version(extension_dir: &Path) -> Option<String> {
+    let manifest = fs::read_to_string(extension_dir.join("manifest.json")).ok()?;
+    let manifest: serde_json::Value = serde_json::from_str(&manifest).ok()?;
+    manifest["version"].as_str().map(String::from)
+}
 
-    // Get the path to the noet executable
-    let exe_path = std::env::current_exe()?;
+/// Install `package` into `extension_dir` if it is the extension for `version`.
+/// The old copy stays in place when it isn't, or can't be unpacked.
+fn install_extension(package: &Package, extension_dir: &Path, version: &str) -> Result<()> {
+    let staging = extension_dir.with_file_name("extension.new");
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
 
-    // Create the native messaging manifest
-    let manifest = serde_json::json!({
-        "name": "com.noet.host",
-        "description": "noet Native Messaging Host",
-        "path": exe_path.to_string_lossy(),
-        "type": "stdio",
-        "allowed_origins": [
-            "chrome-extension://noet-extension-id/"  // Will be updated after installation
-        ]
+    let staged = match package {
+        Package::Zip(bytes) => unpack_extension(bytes, &staging),
+        Package::Dir(dir) => copy_dir(dir, &staging),
+    }
+    .and_then(|()| match installed_version(&staging) {
+        Some(found) if found == version => Ok(()),
+        Some(found) => Err(NoetError::VersionMismatch {
+            message: format!("拡張機能 {found} は noet {version} 用ではありません"),
+        }),
+        None => Err(NoetError::ConfigError(
+            "拡張機能に manifest.json (version) がありません".into(),
+        )),
     });
+    if let Err(e) = staged {
+        let _ = fs::remove_dir_all(&staging);
+        return Err(e);
+    }
+
+    if extension_dir.exists() {
+        fs::remove_dir_all(extension_dir)?;
+    }
+    fs::rename(&staging, extension_dir)?;
+    Ok(())
+}
 
-    let manifest_path = manifest_dir.join("com.noet.host.json");
-    let manifest_json = serde_json::to_string_pretty(&manifest)?;
-    fs::write(&manifest_path, &manifest_json)?;
+/// Get the extension for this version of noet from `from`, or from its release
+async fn obtain_extension(from: Option<&Path>, extension_dir: &Path) -> Result<()> {
+    let version = env!("CARGO_PKG_VERSION");
+    let package = match from {
+        Some(path) => {
+            println!(
+                "{}",
+                format!("拡張機能を読み込み中: {}", path.display()).cyan()
+            );
+            read_package(path)?
+        }
+        None => {
+            println!(
+                "{}",
+                format!("拡張機能 v{version} をダウンロード中...").cyan()
+            );
+            download_release(version).await?
+        }
+    };
 
+    println!("{}", "インストール中...".cyan());
+    install_extension(&package, extension_dir, version)?;
     println!(
         "      {} {}",
-        "マニフェスト作成:".green(),
-        manifest_path.display()
+        "インストール先:".green(),
+        extension_dir.display()
     );
+    Ok(())
+}
+
+/// Update the extension to the one matching this version of noet
+pub async fn run_upgrade(from: Option<&Path>) -> Result<()> {
+    let version = env!("CARGO_PKG_VERSION");
+
+    if let (Some(local_path), None) = (get_local_extension_path(), from) {
+        println!(
+            "      {} 開発モード: ローカルの拡張機能を使用しています",
+            "⚙".cyan()
+        );
+        println!("      {}", local_path.display().to_string().dimmed());
+        println!("      拡張機能ページで noet の再読み込みボタンを押してください。");
+        return Ok(());
+    }
+
+    let extension_dir = get_extension_dir()?;
+    match installed_version(&extension_dir) {
+        Some(installed) if installed == version && from.is_none() => {
+            println!("      {} 拡張機能は既に noet {version} 用です", "✓".green());
+            println!(
+                "      ブラウザで古い版が動いている場合は拡張機能ページで再読み込みしてください。"
+            );
+            return Ok(());
+        }
+        Some(installed) => println!("      現在の拡張機能: {installed}"),
+        None => println!("      拡張機能はまだインストールされていません"),
+    }
+
+    obtain_extension(from, &extension_dir).await?;
+
+    println!();
+    println!("      拡張機能ページを開き、noet の再読み込みボタン (↻) を押してください。");
+    println!("      フォルダは同じ場所なので、再インストールやトークンの再設定は不要です。");
+    for browser in Browser::detect(&home_dir()?) {
+        browser.open_extensions_page();
+    }
+
+    Ok(())
+}
 
-    // For Windows, we need to update the registry
+/// Register the native messaging host with `browsers` for the extension with `extension_id`
+fn configure_native_messaging(browsers: &[Browser], extension_id: &str) -> Result<()> {
+    let home = home_dir()?;
+    let exe_path = std::env::current_exe()?;
+
+    for &browser in browsers {
+        let manifest_path = browser.manifest_path(&home);
+        if let Some(dir) = manifest_path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let manifest = browser.host_manifest(&exe_path, extension_id);
+        fs::write(&manifest_path, serde_json::to_string_pretty(&manifest)?)?;
+
+        println!(
+            "      {} {}",
+            format!("マニフェスト作成 ({}):", browser.name()).green(),
+            manifest_path.display()
+        );
+    }
+
+    // On Windows the browsers find the manifests through the registry
     #[cfg(target_os = "windows")]
     {
         println!(
             "      {} Windowsではレジストリの設定が必要です。",
             "注意:".yellow()
         );
-        println!("      管理者権限で以下のコマンドを実行してください:");
+        println!("      以下のコマンドを実行してください:");
         println!();
-        println!(
-            "      reg add \"HKCU\\Software\\Google\\Chrome\\NativeMessagingHosts\\com.noet.host\" /ve /t REG_SZ /d \"{}\" /f",
-            manifest_path.display()
-        );
+        for &browser in browsers {
+            println!(
+                "      reg add \"{}\" /ve /t REG_SZ /d \"{}\" /f",
+                browser.registry_key(),
+                browser.manifest_path(&home).display()
+            );
+        }
     }
 
     Ok(())
 }
 
-/// Show Chrome extension installation instructions
-fn show_installation_instructions(extension_dir: &Path) -> Result<()> {
+/// Show how to load the extension into `browsers`
+fn show_installation_instructions(extension_dir: &Path, browsers: &[Browser]) -> Result<()> {
     println!();
     println!("{}", "━".repeat(60).dimmed());
     println!();
     println!(
         "{}",
-        "[4/4] Chrome に拡張機能をインストールしてください".cyan()
-    );
-    println!();
-    println!("      以下の手順で拡張機能をインストールします:");
-    println!();
-    println!("      {}", "1. Chromeで chrome://extensions を開く".white());
-    println!(
-        "      {}",
-        "2. 右上の「デベロッパーモード」をONにする".white()
-    );
-    println!(
-        "      {}",
-        "3. 「パッケージ化されていない拡張機能を読み込む」をクリック".white()
+        "[3/3] ブラウザに拡張機能をインストールしてください".cyan()
     );
-    println!("      {}", "4. 以下のフォルダを選択:".white());
+
+    if let Some(browser) = browsers.iter().find(|b| !b.is_firefox()) {
+        println!();
+        println!(
+            "      {}:",
+            "Chrome / Chromium / Brave / Edge / Vivaldi".bold()
+        );
+        println!(
+            "      {}",
+            format!("1. {} を開く", browser.extensions_page()).white()
+        );
+        println!(
+            "      {}",
+            "2. 右上の「デベロッパーモード」をONにする".white()
+        );
+        println!(
+            "      {}",
+            "3. 「パッケージ化されていない拡張機能を読み込む」をクリック".white()
+        );
+        println!("      {}", "4. 以下のフォルダを選択:".white());
+    }
+    if browsers.contains(&Browser::Firefox) {
+        println!();
+        println!("      {}:", "Firefox".bold());
+        println!(
+            "      {}",
+            format!("1. {} を開く", Browser::Firefox.extensions_page()).white()
+        );
+        println!(
+            "      {}",
+            "2. 「一時的なアドオンを読み込む」をクリック".white()
+        );
+        println!(
+            "      {}",
+            "3. 以下のフォルダの manifest.json を選択:".white()
+        );
+    }
+
     println!();
     println!(
         "         {}",
@@ -220,6 +423,47 @@ fn show_installation_instructions(extension_dir: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Ask for the ID the extensions page shows, suggesting the one derived from its folder
+fn ask_extension_id(suggested: &str) -> Result<Option<String>> {
+    println!(
+        "      拡張機能ページに表示される noet の {} を確認してください",
+        "ID".bold()
+    );
+    let id = Input::<String>::new()
+        .with_prompt("      拡張機能 ID (空欄でスキップ)")
+        .default(suggested.to_string())
+        .allow_empty(true)
+        .interact_text()?
+        .trim()
+        .to_string();
+    if id.is_empty() {
+        println!(
+            "      {} ID を設定しないと、どの拡張機能からの接続も受け付けます",
+            "!".yellow()
+        );
+        return Ok(None);
+    }
+    Ok(Some(id))
+}
+
+/// Generate the WebSocket token if needed and tell the user how to give it to the extension
+fn show_token() -> Result<()> {
+    let token = extension_auth::ensure_token()?;
+
+    println!();
+    println!("      {}", "認証トークン:".bold());
+    println!("      {}", token.yellow());
+    println!();
+    println!("      拡張機能のオプションページで「CLI から取得」を押すか、");
+    println!("      このトークンを貼り付けて保存してください。");
+    println!(
+        "      {}",
+        format!("(保存先: {})", extension_auth::token_path()?.display()).dimmed()
+    );
+
+    Ok(())
+}
+
 /// Wait for user to press Enter
 fn wait_for_enter(prompt: &str) -> Result<()> {
     print!("{prompt}");
@@ -229,60 +473,35 @@ fn wait_for_enter(prompt: &str) -> Result<()> {
     Ok(())
 }
 
-/// Open Chrome extensions page
-fn open_extensions_page() -> Result<()> {
-    #[cfg(target_os = "linux")]
-    {
-        let _ = std::process::Command::new("xdg-open")
-            .arg("chrome://extensions")
-            .spawn();
-        // xdg-open might not work with chrome:// URLs, try direct chrome
-        let _ = std::process::Command::new("google-chrome")
-            .arg("chrome://extensions")
-            .spawn();
-    }
-
-    #[cfg(target_os = "macos")]
-    {
-        let _ = std::process::Command::new("open")
-            .arg("-a")
-            .arg("Google Chrome")
-            .arg("chrome://extensions")
-            .spawn();
-    }
-
-    #[cfg(target_os = "windows")]
-    {
-        let _ = std::process::Command::new("cmd")
-            .args(["/C", "start", "chrome://extensions"])
-            .spawn();
-    }
-
-    Ok(())
-}
-
-/// Test connection to the extension
-async fn test_extension_connection() -> Result<bool> {
+/// Check that every browser has the host manifest
+async fn test_extension_connection(browsers: &[Browser]) -> Result<bool> {
     println!("{}", "接続テスト中...".cyan());
 
-    // TODO: Implement actual connection test via native messaging
-    // For now, we just check if the manifest exists
-    let manifest_dir = get_native_manifest_dir()?;
-    let manifest_path = manifest_dir.join("com.noet.host.json");
-
-    if manifest_path.exists() {
-        println!(
-            "      {} Native Messaging マニフェストが設定されています",
-            "✓".green()
-        );
-        return Ok(true);
+    // Only the files are checked here; `noet doctor` also connects to the extension
+    let home = home_dir()?;
+    let mut ok = true;
+    for &browser in browsers {
+        if browser.manifest_path(&home).exists() {
+            println!(
+                "      {} {} の Native Messaging マニフェストが設定されています",
+                "✓".green(),
+                browser.name()
+            );
+        } else {
+            println!(
+                "      {} {} の Native Messaging マニフェストがありません",
+                "✗".red(),
+                browser.name()
+            );
+            ok = false;
+        }
     }
 
-    Ok(false)
+    Ok(ok)
 }
 
 /// Run the setup wizard
-pub async fn run_setup() -> Result<()> {
+pub async fn run_setup(from: Option<&Path>, browser: Option<Browser>) -> Result<()> {
     println!();
     println!(
         "{}",
@@ -305,69 +524,89 @@ pub async fn run_setup() -> Result<()> {
     println!();
 
     // Check for local development extension first
-    let extension_dir = if let Some(local_path) = get_local_extension_path() {
-        println!(
-            "      {} 開発モード: ローカルの拡張機能を使用します",
-            "⚙".cyan()
-        );
-        println!("      {}", local_path.display().to_string().dimmed());
-        println!();
-        local_path
-    } else {
-        let ext_dir = get_extension_dir()?;
-
-        // Check if extension is already installed
-        if ext_dir.exists() && ext_dir.join("manifest.json").exists() {
+    let extension_dir = match (get_local_extension_path(), from) {
+        (Some(local_path), None) => {
             println!(
-                "      {} 拡張機能は既にダウンロード済みです: {}",
-                "✓".green(),
-                ext_dir.display()
+                "      {} 開発モード: ローカルの拡張機能を使用します",
+                "⚙".cyan()
             );
+            println!("      {}", local_path.display().to_string().dimmed());
             println!();
-
-            print!("      再ダウンロードしますか？ [y/N]: ");
-            io::stdout().flush()?;
-            let mut input = String::new();
-            io::stdin().read_line(&mut input)?;
-
-            if input.trim().to_lowercase() != "y" {
-                println!();
-                println!("      既存の拡張機能を使用します。");
-            } else {
-                // Remove and re-download
-                fs::remove_dir_all(&ext_dir)?;
-                download_extension(&ext_dir).await?;
+            local_path
+        }
+        _ => {
+            println!("{}", "[1/3] 拡張機能を準備中...".cyan());
+            let ext_dir = get_extension_dir()?;
+
+            // Check if extension is already installed
+            match installed_version(&ext_dir).filter(|_| from.is_none()) {
+                Some(installed) => {
+                    println!(
+                        "      {} 拡張機能 {installed} は既にダウンロード済みです: {}",
+                        "✓".green(),
+                        ext_dir.display()
+                    );
+                    println!();
+
+                    print!("      再ダウンロードしますか？ [y/N]: ");
+                    io::stdout().flush()?;
+                    let mut input = String::new();
+                    io::stdin().read_line(&mut input)?;
+
+                    if input.trim().to_lowercase() != "y" {
+                        println!();
+                        println!("      既存の拡張機能を使用します。");
+                    } else {
+                        obtain_extension(None, &ext_dir).await?;
+                    }
+                }
+                None => obtain_extension(from, &ext_dir).await?,
             }
-        } else {
-            // Download extension
-            download_extension(&ext_dir).await?;
+            ext_dir
         }
-        ext_dir
     };
 
     // Configure native messaging
-    configure_native_messaging(&extension_dir)?;
+    println!("{}", "[2/3] Native Messaging を設定中...".cyan());
+    let browsers = target_browsers(browser)?;
+    let derived_id = browser::unpacked_extension_id(&extension_dir);
+    configure_native_messaging(&browsers, &derived_id)?;
 
     // Show installation instructions
-    show_installation_instructions(&extension_dir)?;
+    show_installation_instructions(&extension_dir, &browsers)?;
 
-    // Open Chrome extensions page
-    print!("      Chromeの拡張機能ページを開きますか？ [Y/n]: ");
+    print!("      拡張機能ページを開きますか？ [Y/n]: ");
     io::stdout().flush()?;
     let mut input = String::new();
     io::stdin().read_line(&mut input)?;
 
     if input.trim().to_lowercase() != "n" {
-        open_extensions_page()?;
+        for browser in &browsers {
+            browser.open_extensions_page();
+        }
     }
 
     // Wait for user to install extension
     println!();
     wait_for_enter("      拡張機能をインストールしたらEnterを押してください...")?;
 
+    // Only this extension may connect to the CLI and the native host.
+    // Firefox's ID is fixed by the extension's manifest.
+    if browsers.iter().any(|b| !b.is_firefox()) {
+        println!();
+        if let Some(id) = ask_extension_id(&derived_id)? {
+            config::set_value(&config::global_config_path()?, "extension_id", &id)?;
+            if id != derived_id {
+                configure_native_messaging(&browsers, &id)?;
+            }
+        }
+    }
+
+    show_token()?;
+
     // Test connection
     println!();
-    let connected = test_extension_connection().await?;
+    let connected = test_extension_connection(&browsers).await?;
 
     if connected {
         println!();
@@ -392,7 +631,8 @@ pub async fn run_setup() -> Result<()> {
         println!();
         println!("      次のステップ:");
         println!("      1. Note.com にログインしてください");
-        println!("      2. `noet list <username>` で記事一覧を取得できます");
+        println!("      2. `noet doctor` で拡張機能との接続を確認できます");
+        println!("      3. `noet list` で記事一覧を取得できます");
         println!();
     } else {
         println!();
@@ -400,8 +640,146 @@ pub async fn run_setup() -> Result<()> {
             "{}",
             "セットアップが完了していない可能性があります。".yellow()
         );
-        println!("      拡張機能のインストールを確認してください。");
+        println!("      `noet doctor` で原因と対処方法を確認してください。");
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::TempDir;
+
+    fn extension_zip(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        for (name, content) in files {
+            zip.start_file(*name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn zip_package(files: &[(&str, &str)]) -> Package {
+        Package::Zip(extension_zip(files))
+    }
+
+    #[test]
+    fn test_install_extension() {
+        let temp_dir = TempDir::new().unwrap();
+        let extension_dir = temp_dir.path().join("extension");
+        fs::create_dir_all(&extension_dir).unwrap();
+        fs::write(
+            extension_dir.join("manifest.json"),
+            r#"{"version":"0.1.0"}"#,
+        )
+        .unwrap();
+        fs::write(extension_dir.join("stale.js"), "").unwrap();
+
+        let package = zip_package(&[
+            ("manifest.json", r#"{"version":"0.2.0"}"#),
+            ("src/background.js", "// new"),
+        ]);
+        install_extension(&package, &extension_dir, "0.2.0").unwrap();
+
+        assert_eq!(installed_version(&extension_dir).as_deref(), Some("0.2.0"));
+        assert!(extension_dir.join("src/background.js").exists());
+        assert!(!extension_dir.join("stale.js").exists());
+        assert!(!temp_dir.path().join("extension.new").exists());
+    }
+
+    #[test]
+    fn test_install_extension_keeps_the_old_copy_when_refused() {
+        let temp_dir = TempDir::new().unwrap();
+        let extension_dir = temp_dir.path().join("extension");
+        fs::create_dir_all(&extension_dir).unwrap();
+        fs::write(
+            extension_dir.join("manifest.json"),
+            r#"{"version":"0.1.0"}"#,
+        )
+        .unwrap();
+
+        let bad_zip = Package::Zip(b"not a zip".to_vec());
+        assert!(install_extension(&bad_zip, &extension_dir, "0.2.0").is_err());
+        let no_manifest = zip_package(&[("README.md", "")]);
+        assert!(install_extension(&no_manifest, &extension_dir, "0.2.0").is_err());
+        let other_version = zip_package(&[("manifest.json", r#"{"version":"0.3.0"}"#)]);
+        assert!(matches!(
+            install_extension(&other_version, &extension_dir, "0.2.0"),
+            Err(NoetError::VersionMismatch { .. })
+        ));
+
+        assert_eq!(installed_version(&extension_dir).as_deref(), Some("0.1.0"));
+        assert!(!temp_dir.path().join("extension.new").exists());
+    }
+
+    #[test]
+    fn test_install_extension_from_a_directory() {
+        let temp_dir = TempDir::new().unwrap();
+        let source = temp_dir.path().join("unpacked");
+        fs::create_dir_all(source.join("src")).unwrap();
+        fs::write(source.join("manifest.json"), r#"{"version":"0.2.0"}"#).unwrap();
+        fs::write(source.join("src/background.js"), "// js").unwrap();
+
+        let extension_dir = temp_dir.path().join("extension");
+        let package = read_package(&source).unwrap();
+        install_extension(&package, &extension_dir, "0.2.0").unwrap();
+        assert!(extension_dir.join("src/background.js").exists());
+    }
+
+    #[test]
+    fn test_bundled_extension_matches_this_version() {
+        // The CLI and the extension are released together, so `setup --from apps/extension`
+        // must accept the copy in this repository
+        let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("../extension");
+        let temp_dir = TempDir::new().unwrap();
+        let extension_dir = temp_dir.path().join("extension");
+        let package = read_package(&source).unwrap();
+        install_extension(&package, &extension_dir, env!("CARGO_PKG_VERSION")).unwrap();
+        assert_eq!(
+            installed_version(&extension_dir).as_deref(),
+            Some(env!("CARGO_PKG_VERSION"))
+        );
+    }
+
+    #[test]
+    fn test_verify_checksum() {
+        let sha = format!("{:x}", Sha256::digest(b"zip"));
+        let sums = format!("{sha}  noet-extension.zip\n0000  noet-linux-amd64.tar.gz\n");
+        assert!(verify_checksum(b"zip", &sums, "noet-extension.zip").is_ok());
+        // Binary mode marker and upper case hex
+        let binary = format!("{} *noet-extension.zip\n", sha.to_uppercase());
+        assert!(verify_checksum(b"zip", &binary, "noet-extension.zip").is_ok());
+
+        assert!(matches!(
+            verify_checksum(b"tampered", &sums, "noet-extension.zip"),
+            Err(NoetError::Integrity(_))
+        ));
+        assert!(verify_checksum(b"zip", &sums, "other.zip").is_err());
+    }
+
+    #[test]
+    fn test_read_package_checks_a_neighbouring_checksums_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let zip = temp_dir.path().join("noet-extension.zip");
+        fs::write(&zip, b"zip").unwrap();
+        assert!(read_package(&zip).is_ok());
+
+        fs::write(
+            temp_dir.path().join("SHA256SUMS"),
+            "0000  noet-extension.zip\n",
+        )
+        .unwrap();
+        assert!(matches!(read_package(&zip), Err(NoetError::Integrity(_))));
+    }
+
+    #[test]
+    fn test_release_url_is_pinned() {
+        assert_eq!(
+            release_asset_url("1.2.3", EXTENSION_ASSET),
+            "https://github.com/kako-jun/noet/releases/download/v1.2.3/noet-extension.zip"
+        );
+    }
+}
@@ -0,0 +1,603 @@
+//! `noet status` / `push` / `pull` / `merge`: keep a workspace in sync with note.com
+
+use crate::commands::extension::{
+    fetch_article, key_from_result, prepare_article, print_send_result, remember_uploaded_images,
+    remote_body_hash, save_article, send_article, FetchedArticle,
+};
+use crate::config::Settings;
+use crate::error::{NoetError, Result};
+use crate::extension_client::ExtensionClient;
+use crate::frontmatter::Document;
+use crate::image_cache::ImageCache;
+use crate::merge;
+use crate::protocol::ArticleSummary;
+use crate::sync::{self, FileStatus, LocalFile, StatusEntry, SyncState, TrackedArticle};
+use crate::workspace;
+use colored::{ColoredString, Colorize};
+use std::fs;
+use std::path::Path;
+
+fn status_label(status: FileStatus) -> ColoredString {
+    match status {
+        FileStatus::New => "新規".green(),
+        FileStatus::Modified => "変更".yellow(),
+        FileStatus::RemoteChanged => "リモート変更".cyan(),
+        FileStatus::Conflict => "競合".red().bold(),
+        FileStatus::Deleted => "削除".red(),
+        FileStatus::RemoteOnly => "未取得".dimmed(),
+        FileStatus::InSync => "同期済み".dimmed(),
+    }
+}
+
+async fn fetch_remote(client: &ExtensionClient) -> Result<Vec<ArticleSummary>> {
+    println!("{}", "記事一覧を取得中...".cyan());
+    Ok(client.list_articles().await?.articles)
+}
+
+/// Show what changed locally and remotely since the last sync
+pub async fn status(offline: bool) -> Result<()> {
+    let root = workspace::find_workspace_root()?;
+    let settings = Settings::load()?;
+    let state = SyncState::load(&root)?;
+    let files = sync::scan_local_files(&root, &settings.articles_dir, &state)?;
+
+    let remote = if offline {
+        None
+    } else {
+        println!("{}", "拡張機能に接続中...".cyan());
+        let client = ExtensionClient::connect().await?;
+        Some(fetch_remote(&client).await?)
+    };
+
+    let entries = sync::compute_status(&state, &files, remote.as_deref());
+    print_status(&entries);
+
+    if offline {
+        println!(
+            "\n{}",
+            "(オフライン: リモートの変更は確認していません)".dimmed()
+        );
+    }
+
+    Ok(())
+}
+
+fn print_status(entries: &[StatusEntry]) {
+    let in_sync = entries
+        .iter()
+        .filter(|e| e.status == FileStatus::InSync)
+        .count();
+    let changed: Vec<_> = entries
+        .iter()
+        .filter(|e| e.status != FileStatus::InSync)
+        .collect();
+
+    println!();
+    if changed.is_empty() {
+        println!("{} すべての記事が同期済みです ({in_sync}件)", "✓".green());
+        return;
+    }
+
+    for entry in &changed {
+        let name = entry
+            .path
+            .clone()
+            .or_else(|| entry.title.clone())
+            .unwrap_or_default();
+        let key = entry.note_key.as_deref().unwrap_or("-");
+        println!(
+            "  [{}] {} {}",
+            status_label(entry.status),
+            name,
+            key.dimmed()
+        );
+    }
+
+    println!();
+    println!(
+        "{} 件の変更, {} 件が同期済み",
+        changed.len().to_string().cyan(),
+        in_sync
+    );
+}
+
+/// Publish new and modified files
+pub async fn push(draft: Option<bool>, force: bool) -> Result<()> {
+    let root = workspace::find_workspace_root()?;
+    let settings = Settings::load()?;
+    let mut state = SyncState::load(&root)?;
+    let files = sync::scan_local_files(&root, &settings.articles_dir, &state)?;
+
+    let pending = sync::compute_status(&state, &files, None)
+        .into_iter()
+        .filter(|e| matches!(e.status, FileStatus::New | FileStatus::Modified))
+        .count();
+    if pending == 0 {
+        println!("{} プッシュする変更はありません", "✓".green());
+        return Ok(());
+    }
+
+    println!("{}", "拡張機能に接続中...".cyan());
+    let client = ExtensionClient::connect().await?;
+    let remote = fetch_remote(&client).await?;
+
+    let mut pushed = Vec::new();
+    let mut failed = 0;
+
+    for entry in sync::compute_status(&state, &files, Some(&remote)) {
+        let Some(rel_path) = entry.path.as_deref() else {
+            continue;
+        };
+
+        match entry.status {
+            FileStatus::New | FileStatus::Modified => {}
+            FileStatus::RemoteChanged | FileStatus::Conflict if force => {}
+            FileStatus::RemoteChanged => {
+                println!(
+                    "{} {}: リモートで変更されています。先に `noet pull` を実行してください",
+                    "!".yellow(),
+                    rel_path
+                );
+                continue;
+            }
+            FileStatus::Conflict => {
+                println!(
+                    "{} {}: ローカルとリモートの両方で変更されています。`noet merge {}` で取り込んでください",
+                    "!".yellow(),
+                    rel_path,
+                    rel_path
+                );
+                continue;
+            }
+            _ => continue,
+        }
+
+        // The list date may not move for every edit; compare the body as well
+        if let (FileStatus::Modified, false) = (entry.status, force) {
+            if let Some(tracked) = state.articles.get(rel_path) {
+                if let Err(e) =
+                    ensure_remote_unchanged(&client, tracked, &remote, settings.username.as_deref())
+                        .await
+                {
+                    println!("{} {}: {e}", "!".yellow(), rel_path);
+                    continue;
+                }
+            }
+        }
+
+        println!();
+        println!("{} {}", status_label(entry.status), rel_path.bold());
+
+        match push_file(
+            &client,
+            &root,
+            rel_path,
+            entry.note_key.as_deref(),
+            &settings,
+            draft,
+        )
+        .await
+        {
+            Ok(Some(key)) => {
+                record_push(&root, &mut state, rel_path, &key)?;
+                pushed.push(key);
+            }
+            Ok(None) => failed += 1,
+            Err(e) => {
+                println!("{} {}: {e}", "✗".red(), rel_path);
+                failed += 1;
+            }
+        }
+    }
+
+    refresh_remote_state(
+        &client,
+        &root,
+        &mut state,
+        &pushed,
+        settings.username.as_deref(),
+    )
+    .await?;
+
+    println!();
+    println!(
+        "{} {} 件をプッシュしました (失敗: {failed} 件)",
+        "✓".green(),
+        pushed.len()
+    );
+
+    Ok(())
+}
+
+/// Fail with `NoetError::Conflict` if the article was edited on note.com since
+/// `tracked` was recorded. Checks the list date first, then the body hash when
+/// one was recorded and a username is known.
+pub(crate) async fn ensure_remote_unchanged(
+    client: &ExtensionClient,
+    tracked: &TrackedArticle,
+    remote: &[ArticleSummary],
+    username: Option<&str>,
+) -> Result<()> {
+    let key = &tracked.note_key;
+    let current = sync::find_remote(remote, key).and_then(sync::remote_timestamp);
+
+    let mut changed = matches!(
+        (&tracked.remote_updated, &current),
+        (Some(recorded), Some(current)) if recorded != current
+    );
+
+    if !changed {
+        if let (Some(expected), Some(username)) = (&tracked.remote_hash, username) {
+            // Drafts can't be fetched by key; the date check is all we have then
+            match remote_body_hash(client, username, key).await {
+                Ok(hash) => changed = &hash != expected,
+                Err(e) => log::debug!("Could not fetch {key} to compare bodies: {e}"),
+            }
+        }
+    }
+
+    if changed {
+        return Err(NoetError::Conflict(format!(
+            "{key} は前回の同期以降に note.com で編集されています。`noet merge` で取り込むか --force で上書きしてください"
+        )));
+    }
+
+    Ok(())
+}
+
+/// Record a successful push of `rel_path` as `key`
+pub(crate) fn record_push(
+    root: &Path,
+    state: &mut SyncState,
+    rel_path: &str,
+    key: &str,
+) -> Result<()> {
+    let content = fs::read_to_string(root.join(rel_path))?;
+    state.record(rel_path, key, &content, None);
+    state.save(root)?;
+    sync::save_base(root, key, &content)
+}
+
+/// Record that `rel_path` was just written from the remote article `key`
+pub(crate) fn record_pull(
+    root: &Path,
+    state: &mut SyncState,
+    rel_path: &str,
+    key: &str,
+    fetched: FetchedArticle,
+    remote_updated: Option<String>,
+) -> Result<()> {
+    // Downloaded images are already on note.com, so pushing them back needs no upload
+    if !fetched.images.is_empty() {
+        let mut cache = ImageCache::load(root)?;
+        for (path, url) in &fetched.images {
+            cache.record(path, url)?;
+        }
+        cache.save(root)?;
+    }
+
+    let content = fetched.document.to_markdown()?;
+    state
+        .record(rel_path, key, &content, remote_updated)
+        .remote_hash = Some(fetched.body_hash);
+    state.save(root)?;
+    sync::save_base(root, key, &content)
+}
+
+/// Remember the remote timestamps and bodies produced by our own pushes, so the
+/// next push can tell them from edits made on note.com
+pub(crate) async fn refresh_remote_state(
+    client: &ExtensionClient,
+    root: &Path,
+    state: &mut SyncState,
+    pushed: &[String],
+    username: Option<&str>,
+) -> Result<()> {
+    if pushed.is_empty() {
+        return Ok(());
+    }
+
+    let remote = fetch_remote(client).await?;
+    for tracked in state.articles.values_mut() {
+        if !pushed.contains(&tracked.note_key) {
+            continue;
+        }
+        tracked.remote_updated =
+            sync::find_remote(&remote, &tracked.note_key).and_then(sync::remote_timestamp);
+        if let Some(username) = username {
+            // Drafts can't be fetched by key, which leaves the date check
+            match remote_body_hash(client, username, &tracked.note_key).await {
+                Ok(hash) => tracked.remote_hash = Some(hash),
+                Err(e) => log::debug!("Could not fetch {} after pushing: {e}", tracked.note_key),
+            }
+        }
+    }
+    state.save(root)
+}
+
+/// Create or update one file. Returns the note key on success.
+pub(crate) async fn push_file(
+    client: &ExtensionClient,
+    root: &Path,
+    rel_path: &str,
+    note_key: Option<&str>,
+    settings: &Settings,
+    draft: Option<bool>,
+) -> Result<Option<String>> {
+    let path = root.join(rel_path);
+    let article = prepare_article(&path, settings, draft)?;
+    let result = send_article(client, &article, note_key).await?;
+
+    let (mode, failure) = match (note_key, article.draft) {
+        (None, true) => ("下書き", "投稿"),
+        (None, false) => ("公開", "投稿"),
+        (Some(_), true) => ("下書き保存", "更新"),
+        (Some(_), false) => ("更新", "更新"),
+    };
+
+    if !print_send_result(&result, mode, failure) {
+        return Ok(None);
+    }
+    remember_uploaded_images(&article, &result)?;
+
+    if let Some(key) = note_key {
+        return Ok(Some(key.to_string()));
+    }
+
+    // Link the new article to the file so the next push updates it
+    let Some(key) = key_from_result(&result) else {
+        println!(
+            "{} 記事キーを取得できませんでした。frontmatter に note_key を追加してください",
+            "!".yellow()
+        );
+        return Ok(None);
+    };
+
+    let mut document = Document::read(&path)?;
+    document.frontmatter.note_key = Some(key.clone());
+    document.write(&path)?;
+
+    Ok(Some(key))
+}
+
+/// Refresh local files from note.com
+pub async fn pull(username: Option<&str>) -> Result<()> {
+    let root = workspace::find_workspace_root()?;
+    let settings = Settings::load()?;
+    let username = settings.require_username(username)?;
+    let mut state = SyncState::load(&root)?;
+    let files = sync::scan_local_files(&root, &settings.articles_dir, &state)?;
+
+    println!("{}", "拡張機能に接続中...".cyan());
+    let client = ExtensionClient::connect().await?;
+    let remote = fetch_remote(&client).await?;
+
+    let mut updated = 0;
+    let mut skipped = 0;
+    let mut failed = 0;
+
+    for entry in sync::compute_status(&state, &files, Some(&remote)) {
+        let Some(key) = entry.note_key.clone() else {
+            continue;
+        };
+        let Some(article) = sync::find_remote(&remote, &key) else {
+            continue;
+        };
+
+        match entry.status {
+            FileStatus::RemoteChanged | FileStatus::RemoteOnly => {}
+            FileStatus::Modified => {
+                println!(
+                    "{} {}: ローカルに未プッシュの変更があるためスキップしました",
+                    "!".yellow(),
+                    entry.path.as_deref().unwrap_or(&key)
+                );
+                skipped += 1;
+                continue;
+            }
+            FileStatus::Conflict => {
+                println!(
+                    "{} {}: ローカルとリモートの両方で変更されています。`noet merge` で取り込んでください",
+                    "!".yellow(),
+                    entry.path.as_deref().unwrap_or(&key)
+                );
+                skipped += 1;
+                continue;
+            }
+            _ => continue,
+        }
+
+        let rel_path = entry
+            .path
+            .clone()
+            .unwrap_or_else(|| pull_path(&settings.articles_dir, &key, &files));
+
+        println!();
+        println!("{} {}", status_label(entry.status), rel_path.bold());
+
+        match save_article(&client, &username, &key, &root.join(&rel_path)).await {
+            Ok(fetched) => {
+                record_pull(
+                    &root,
+                    &mut state,
+                    &rel_path,
+                    &key,
+                    fetched,
+                    sync::remote_timestamp(article),
+                )?;
+                println!("{} {}", "✓".green(), rel_path);
+                updated += 1;
+            }
+            Err(e) => {
+                println!("{} {}: {e}", "✗".red(), rel_path);
+                failed += 1;
+            }
+        }
+    }
+
+    println!();
+    println!(
+        "{} {updated} 件を更新しました (スキップ: {skipped} 件, 失敗: {failed} 件)",
+        "✓".green()
+    );
+
+    Ok(())
+}
+
+/// Three-way merge remote edits into `file`, using the content at the last sync as base
+pub async fn merge(file: &Path, username: Option<&str>) -> Result<()> {
+    let root = workspace::find_workspace_root()?;
+    let settings = Settings::load()?;
+    let username = settings.require_username(username)?;
+    let mut state = SyncState::load(&root)?;
+
+    let path = if file.is_relative() {
+        std::env::current_dir()?.join(file)
+    } else {
+        file.to_path_buf()
+    };
+    let rel_path = sync::relative_path(&root, &path);
+
+    let key = state
+        .articles
+        .get(&rel_path)
+        .map(|tracked| tracked.note_key.clone())
+        .ok_or_else(|| {
+            NoetError::InvalidInput(format!(
+                "{rel_path} は同期されていません。`noet push` か `noet pull` を先に実行してください"
+            ))
+        })?;
+
+    let base = sync::load_base(&root, &key)?.ok_or_else(|| {
+        NoetError::InvalidInput(format!(
+            "{rel_path} の前回同期時の内容がありません。`noet pull` で取得し直してください"
+        ))
+    })?;
+
+    let local = fs::read_to_string(&path)?;
+    if merge::has_conflict_markers(&local) {
+        return Err(NoetError::Conflict(format!(
+            "{rel_path} に未解決の競合マーカーがあります。編集してから再実行してください"
+        )));
+    }
+
+    println!("{}", "拡張機能に接続中...".cyan());
+    let client = ExtensionClient::connect().await?;
+    let remote = fetch_remote(&client).await?;
+
+    let fetched = fetch_article(&client, &username, &key, &path).await?;
+    let remote_content = fetched.document.to_markdown()?;
+    let result = merge::merge(&base, &local, &remote_content);
+
+    fs::write(&path, &result.text)?;
+
+    // The remote version is the new common ancestor
+    state
+        .record(
+            &rel_path,
+            &key,
+            &remote_content,
+            sync::find_remote(&remote, &key).and_then(sync::remote_timestamp),
+        )
+        .remote_hash = Some(fetched.body_hash);
+    state.save(&root)?;
+    sync::save_base(&root, &key, &remote_content)?;
+
+    println!();
+    if result.is_clean() {
+        println!(
+            "{} マージしました。内容を確認して `noet push` してください: {}",
+            "✓".green(),
+            rel_path
+        );
+    } else {
+        println!(
+            "{} {} 箇所で競合しました: {}",
+            "!".yellow(),
+            result.conflicts,
+            rel_path
+        );
+        println!(
+            "  {} と {} の間を編集してから `noet push` してください",
+            merge::MARKER_LOCAL.dimmed(),
+            merge::MARKER_REMOTE.dimmed()
+        );
+    }
+
+    Ok(())
+}
+
+/// Where to save a remote article that has no local file yet
+fn pull_path(articles_dir: &str, key: &str, files: &[LocalFile]) -> String {
+    let rel_path = format!("{}/{key}.md", articles_dir.trim_end_matches('/'));
+    if files.iter().any(|f| f.rel_path == rel_path) {
+        format!("{}/{key}.remote.md", articles_dir.trim_end_matches('/'))
+    } else {
+        rel_path
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use tempfile::TempDir;
+
+    const DATE: &str = "2025-01-02T03:04:05+09:00";
+
+    fn listed() -> Vec<ArticleSummary> {
+        serde_json::from_value(json!([
+            { "key": "n1", "title": "t", "status": "published", "date": DATE }
+        ]))
+        .unwrap()
+    }
+
+    /// A client for an extension whose copy of n1 has `html` as its body
+    fn extension(replies: usize, html: &'static str) -> ExtensionClient {
+        ExtensionClient::fake(replies, move |_, request: &Value| {
+            let data = match request["command"].as_str() {
+                Some("list_articles") => json!({ "articles": listed(), "count": 1 }),
+                Some("get_article") => {
+                    json!({ "title": "t", "html": html, "tags": [], "published_at": null })
+                }
+                other => panic!("unexpected {other:?}"),
+            };
+            json!({ "id": request["id"], "status": "success", "data": data })
+        })
+    }
+
+    #[tokio::test]
+    async fn test_edits_after_a_push_are_caught_when_the_date_stays() {
+        let root = TempDir::new().unwrap();
+        fs::create_dir_all(root.path().join("articles")).unwrap();
+        fs::write(root.path().join("articles/a.md"), "pushed\n").unwrap();
+
+        let mut state = SyncState::default();
+        record_push(root.path(), &mut state, "articles/a.md", "n1").unwrap();
+        refresh_remote_state(
+            &extension(2, "<p>pushed</p>"),
+            root.path(),
+            &mut state,
+            &["n1".to_string()],
+            Some("alice"),
+        )
+        .await
+        .unwrap();
+
+        let tracked = &state.articles["articles/a.md"];
+        assert_eq!(tracked.remote_updated.as_deref(), Some(DATE));
+        assert!(tracked.remote_hash.is_some());
+
+        let unchanged = extension(1, "<p>pushed</p>");
+        ensure_remote_unchanged(&unchanged, tracked, &listed(), Some("alice"))
+            .await
+            .unwrap();
+
+        // note.com keeps the list date for some edits
+        let edited = extension(1, "<p>edited on note.com</p>");
+        assert!(matches!(
+            ensure_remote_unchanged(&edited, tracked, &listed(), Some("alice")).await,
+            Err(NoetError::Conflict(_))
+        ));
+    }
+}
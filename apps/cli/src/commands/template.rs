@@ -1,18 +1,12 @@
+use crate::config::{self, Settings};
 use crate::error::{NoetError, Result};
+use crate::template::Template;
 use crate::workspace;
 use colored::Colorize;
 use dialoguer::{Confirm, Editor};
 use std::fs;
 use std::path::PathBuf;
 
-/// Get the config directory for noet
-fn get_config_dir() -> Result<PathBuf> {
-    let config_dir = dirs::config_dir()
-        .ok_or_else(|| NoetError::ConfigError("設定ディレクトリが見つかりません".to_string()))?
-        .join("noet");
-    Ok(config_dir)
-}
-
 /// Helper function to get markdown filenames from a directory
 fn list_markdown_files_in_dir(dir: &PathBuf) -> Result<Vec<String>> {
     if !dir.exists() {
@@ -82,22 +76,13 @@ pub fn add_template(name: &str) -> Result<()> {
         }
     }
 
-    let default_content = r#"---
-title: {{TITLE}}
-status: draft
-tags:
----
-
-# {{TITLE}}
-
-記事の内容をここに書いてください...
-"#;
+    let default_content = default_template_content(&Settings::load()?);
 
     // Open editor for user to customize the template
-    let content = if let Some(edited) = Editor::new().edit(default_content)? {
+    let content = if let Some(edited) = Editor::new().edit(&default_content)? {
         edited
     } else {
-        default_content.to_string()
+        default_content
     };
 
     fs::write(&template_path, content)?;
@@ -112,6 +97,31 @@ tags:
     Ok(())
 }
 
+/// Initial content for a new template, pre-filled from config defaults
+fn default_template_content(settings: &Settings) -> String {
+    let tags = if settings.default_tags.is_empty() {
+        String::new()
+    } else {
+        format!(" [{}]", settings.default_tags.join(", "))
+    };
+
+    format!(
+        r#"---
+title: {{{{ TITLE | tojson }}}}
+status: {}
+tags:{tags}
+---
+
+# {{{{TITLE}}}}
+
+記事の内容をここに書いてください...
+"#,
+        settings
+            .default_status
+            .unwrap_or(config::ArticleStatus::Draft)
+    )
+}
+
 pub fn show_template(name: &str) -> Result<()> {
     let template_path = get_template_path(name)?;
 
@@ -160,37 +170,42 @@ pub fn remove_template(name: &str) -> Result<()> {
     Ok(())
 }
 
-#[allow(dead_code)]
-pub fn load_template(name: &str, title: &str) -> Result<String> {
-    let template_path = get_template_path(name)?;
+/// Load a template from the templates directory
+pub fn load_template(name: &str) -> Result<Template> {
+    let template_dir = get_template_dir()?;
+    let template_path = template_dir.join(format!("{name}.md"));
 
     if !template_path.exists() {
         return Err(NoetError::FileNotFound(format!(
-            "Template '{name}' not found. Use 'noet template list' to see available templates."
+            "テンプレート '{name}' が見つかりません。`noet template list` で一覧を確認してください"
         )));
     }
 
     let content = fs::read_to_string(&template_path)?;
+    Template::parse(&format!("{name}.md"), &content, Some(&template_dir))
+}
 
-    // Replace placeholders
-    let content = content.replace("{{TITLE}}", title);
-
-    Ok(content)
+/// The template used when none is chosen
+pub fn builtin_template(settings: &Settings) -> Result<Template> {
+    Template::parse(
+        "(テンプレートなし)",
+        &default_template_content(settings),
+        None,
+    )
 }
 
-#[allow(dead_code)]
 pub fn list_template_names() -> Result<Vec<String>> {
     let template_dir = get_template_dir()?;
     list_markdown_files_in_dir(&template_dir)
 }
 
-fn get_template_dir() -> Result<PathBuf> {
+pub fn get_template_dir() -> Result<PathBuf> {
     // Try workspace templates first
     if workspace::is_in_workspace() {
         Ok(workspace::get_templates_dir()?)
     } else {
         // Fallback to global config directory
-        let config_dir = get_config_dir()?;
+        let config_dir = config::global_config_dir()?;
         Ok(config_dir.join("templates"))
     }
 }
@@ -204,24 +219,55 @@ fn get_template_path(name: &str) -> Result<PathBuf> {
 mod tests {
     use super::*;
     use std::fs;
+    use std::path::Path;
     use tempfile::TempDir;
 
     #[test]
-    fn test_load_template_with_title() {
-        let template_content = r#"---
-title: {{TITLE}}
-status: draft
----
+    fn test_builtin_template_renders_title() {
+        let template = builtin_template(&Settings::default()).unwrap();
+        let context = crate::template::builtins(
+            "Test Article",
+            "test-article",
+            "2025-01-02",
+            &Settings::default(),
+            Path::new("/tmp/blog"),
+        );
+        let result = template.render(&context).unwrap();
 
-# {{TITLE}}
+        assert!(result.contains("title: \"Test Article\""));
+        assert!(result.contains("# Test Article"));
+        assert!(!result.contains("{{"));
+    }
 
-Content here"#;
+    #[test]
+    fn test_builtin_template_quotes_title() {
+        let template = builtin_template(&Settings::default()).unwrap();
+        let title = "Rust: 所有権 #1 \"入門\" - 'まとめ'";
+        let context = crate::template::builtins(
+            title,
+            "rust",
+            "2025-01-02",
+            &Settings::default(),
+            Path::new("/tmp/blog"),
+        );
+        let result = template.render(&context).unwrap();
 
-        let result = template_content.replace("{{TITLE}}", "Test Article");
+        let document = crate::frontmatter::Document::parse(&result).unwrap();
+        assert_eq!(document.frontmatter.title.as_deref(), Some(title));
+    }
 
-        assert!(result.contains("title: Test Article"));
-        assert!(result.contains("# Test Article"));
-        assert!(!result.contains("{{TITLE}}"));
+    #[test]
+    fn test_default_template_content_uses_config() {
+        let mut settings = Settings::default();
+        let content = default_template_content(&settings);
+        assert!(content.contains("title: {{ TITLE | tojson }}"));
+        assert!(content.contains("status: draft\ntags:\n"));
+
+        settings.default_status = Some(config::ArticleStatus::Published);
+        settings.default_tags = vec!["rust".to_string(), "cli".to_string()];
+        let content = default_template_content(&settings);
+        assert!(content.contains("status: published"));
+        assert!(content.contains("tags: [rust, cli]"));
     }
 
     #[test]
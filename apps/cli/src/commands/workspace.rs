@@ -14,6 +14,7 @@ pub async fn init(path: Option<PathBuf>) -> Result<()> {
 
     println!("\n作成されたファイル:");
     println!("  • {} - 設定ディレクトリ", ".noet/".dimmed());
+    println!("  • {} - 記事ディレクトリ", "articles/".dimmed());
     println!("  • {} - テンプレートディレクトリ", "templates/".dimmed());
     println!("  • {} - .gitignore を更新", ".gitignore".dimmed());
 
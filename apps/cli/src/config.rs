@@ -0,0 +1,903 @@
+//! Layered configuration
+//!
+//! Settings are resolved in this order (later wins):
+//! 1. Built-in defaults
+//! 2. Global config (`~/.config/noet/config.toml`)
+//! 3. Workspace config (`.noet/config.toml`)
+//! 4. Environment variables (`NOET_*`)
+//! 5. Command line flags (applied by each command)
+
+use crate::converters::note_markdown::{InlineCodeStyle, TableStyle, TransformOptions};
+use crate::error::{NoetError, Result};
+use crate::image_pipeline::{self, ImageOptions};
+use crate::workspace;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const CONFIG_FILE: &str = "config.toml";
+const DEFAULT_ARTICLES_DIR: &str = "articles";
+const DEFAULT_BRIDGE_PORT: u16 = 9876;
+const DEFAULT_CONNECT_TIMEOUT: u64 = 30;
+const DEFAULT_COMMAND_TIMEOUT: u64 = 60;
+
+/// All keys that can be set in a config file
+pub const KEYS: &[&str] = &[
+    "default_status",
+    "default_tags",
+    "editor",
+    "username",
+    "articles_dir",
+    "inline_code",
+    "table_style",
+    "extension_id",
+    "bridge_port",
+    "connect_timeout",
+    "command_timeout",
+    "reconnect",
+    "workspace",
+    "image_max_width",
+    "image_quality",
+    "strip_image_metadata",
+];
+
+/// Article status used when a command doesn't specify one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArticleStatus {
+    Draft,
+    Published,
+}
+
+impl ArticleStatus {
+    pub fn is_draft(self) -> bool {
+        self == ArticleStatus::Draft
+    }
+}
+
+impl fmt::Display for ArticleStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArticleStatus::Draft => write!(f, "draft"),
+            ArticleStatus::Published => write!(f, "published"),
+        }
+    }
+}
+
+impl std::str::FromStr for ArticleStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim() {
+            "draft" => Ok(ArticleStatus::Draft),
+            "published" => Ok(ArticleStatus::Published),
+            other => Err(format!("'{other}' は無効な値です (draft または published)")),
+        }
+    }
+}
+
+/// A single config file layer. Every field is optional so layers can be merged.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub default_status: Option<ArticleStatus>,
+    pub default_tags: Option<Vec<String>>,
+    pub editor: Option<String>,
+    pub username: Option<String>,
+    pub articles_dir: Option<String>,
+    pub inline_code: Option<InlineCodeStyle>,
+    pub table_style: Option<TableStyle>,
+    pub extension_id: Option<String>,
+    pub bridge_port: Option<u16>,
+    pub connect_timeout: Option<u64>,
+    pub command_timeout: Option<u64>,
+    pub reconnect: Option<bool>,
+    pub workspace: Option<PathBuf>,
+    pub image_max_width: Option<u32>,
+    pub image_quality: Option<u8>,
+    pub strip_image_metadata: Option<bool>,
+    /// No longer used; still accepted so older config files load
+    #[serde(default, skip_serializing)]
+    pub base_url: Option<String>,
+}
+
+/// Where a resolved value came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    File(PathBuf),
+    Env(&'static str),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File(path) => write!(f, "{}", path.display()),
+            Source::Env(var) => write!(f, "${var}"),
+        }
+    }
+}
+
+/// Fully resolved settings
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub default_status: Option<ArticleStatus>,
+    pub default_tags: Vec<String>,
+    pub editor: Option<String>,
+    pub username: Option<String>,
+    /// Directory for article files, relative to the workspace root
+    pub articles_dir: String,
+    /// How inline code is rewritten before publishing
+    pub inline_code: InlineCodeStyle,
+    /// How tables are rewritten before publishing
+    pub table_style: TableStyle,
+    /// ID of the installed browser extension, checked against the WebSocket `Origin`
+    pub extension_id: Option<String>,
+    /// Port the extension connects to, or the first one tried if it's taken
+    pub bridge_port: u16,
+    /// Seconds a command waits for the extension to connect
+    pub connect_timeout: u64,
+    /// Seconds a command waits for the extension to answer
+    pub command_timeout: u64,
+    /// Resend read-only commands after reconnecting when the connection drops
+    pub reconnect: bool,
+    /// Workspace the extension reads and writes through the Native Messaging host
+    pub workspace: Option<PathBuf>,
+    /// Images wider than this many pixels are scaled down before upload; 0 keeps them
+    pub image_max_width: u32,
+    /// JPEG quality images are recompressed at
+    pub image_quality: u8,
+    /// Remove EXIF/XMP metadata such as GPS positions from images before upload
+    pub strip_image_metadata: bool,
+    sources: Vec<(&'static str, Source)>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            default_status: None,
+            default_tags: Vec::new(),
+            editor: None,
+            username: None,
+            articles_dir: DEFAULT_ARTICLES_DIR.to_string(),
+            inline_code: InlineCodeStyle::default(),
+            table_style: TableStyle::default(),
+            extension_id: None,
+            bridge_port: DEFAULT_BRIDGE_PORT,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            command_timeout: DEFAULT_COMMAND_TIMEOUT,
+            reconnect: true,
+            workspace: None,
+            image_max_width: image_pipeline::DEFAULT_MAX_WIDTH,
+            image_quality: image_pipeline::DEFAULT_QUALITY,
+            strip_image_metadata: true,
+            sources: KEYS.iter().map(|k| (*k, Source::Default)).collect(),
+        }
+    }
+}
+
+impl Config {
+    /// Parse and validate a config file
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        Self::parse(&content, path)
+    }
+
+    fn parse(content: &str, path: &Path) -> Result<Self> {
+        let config: Config = toml::from_str(content).map_err(|e| {
+            let location = match e.span() {
+                Some(span) => format!("{}:{}", path.display(), line_of(content, span.start)),
+                None => path.display().to_string(),
+            };
+            NoetError::ConfigError(format!("{location}: {}", e.message().trim()))
+        })?;
+
+        config.validate().map_err(|(key, msg)| {
+            let location = match key_line(content, key) {
+                Some(line) => format!("{}:{line}", path.display()),
+                None => path.display().to_string(),
+            };
+            NoetError::ConfigError(format!("{location}: {key}: {msg}"))
+        })?;
+
+        if config.base_url.is_some() {
+            let line = key_line(content, "base_url").unwrap_or(1);
+            log::warn!(
+                "{}:{line}: base_url は使われなくなりました。削除してください",
+                path.display()
+            );
+        }
+
+        Ok(config)
+    }
+
+    /// Check values that parse fine but make no sense
+    fn validate(&self) -> std::result::Result<(), (&'static str, String)> {
+        if let Some(tags) = &self.default_tags {
+            if tags.iter().any(|t| t.trim().is_empty()) {
+                return Err(("default_tags", "空のタグは指定できません".to_string()));
+            }
+        }
+        if let Some(editor) = &self.editor {
+            if editor.trim().is_empty() {
+                return Err(("editor", "エディタコマンドが空です".to_string()));
+            }
+        }
+        if let Some(id) = &self.extension_id {
+            if id.trim().is_empty()
+                || !id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_@.{}".contains(c))
+            {
+                return Err((
+                    "extension_id",
+                    format!("'{id}' は拡張機能の ID ではありません"),
+                ));
+            }
+        }
+        for (key, secs) in [
+            ("connect_timeout", self.connect_timeout),
+            ("command_timeout", self.command_timeout),
+        ] {
+            if secs == Some(0) {
+                return Err((key, "1 秒以上を指定してください".to_string()));
+            }
+        }
+        if let Some(dir) = &self.articles_dir {
+            if dir.trim().is_empty() || Path::new(dir).is_absolute() || dir.contains("..") {
+                return Err((
+                    "articles_dir",
+                    format!("'{dir}' はワークスペース内の相対パスではありません"),
+                ));
+            }
+        }
+        if let Some(dir) = &self.workspace {
+            if !dir.is_absolute() {
+                return Err((
+                    "workspace",
+                    format!("'{}' は絶対パスではありません", dir.display()),
+                ));
+            }
+        }
+        if let Some(quality) = self.image_quality {
+            if !(1..=100).contains(&quality) {
+                return Err(("image_quality", format!("{quality} は範囲外です (1〜100)")));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Settings {
+    /// Whether to save as draft, preferring an explicit `--draft`/`--publish` choice.
+    /// Without either, commands publish as they always have.
+    pub fn resolve_draft(&self, cli_value: Option<bool>) -> bool {
+        cli_value
+            .or(self.default_status.map(ArticleStatus::is_draft))
+            .unwrap_or(false)
+    }
+
+    /// Load settings for the current directory
+    pub fn load() -> Result<Self> {
+        let global = global_config_path().ok();
+        let workspace = workspace_config_path().ok();
+        Self::load_from(global.as_deref(), workspace.as_deref(), |k| {
+            env::var(k).ok()
+        })
+    }
+
+    /// Load settings shared by every workspace, as the daemon does
+    pub fn load_global() -> Result<Self> {
+        let global = global_config_path().ok();
+        Self::load_from(global.as_deref(), None, |k| env::var(k).ok())
+    }
+
+    /// Load settings from explicit file locations and an environment lookup
+    pub fn load_from(
+        global: Option<&Path>,
+        workspace: Option<&Path>,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self> {
+        let mut settings = Settings::default();
+
+        for path in [global, workspace].into_iter().flatten() {
+            if path.exists() {
+                let layer = Config::from_file(path)?;
+                settings.apply(layer, &Source::File(path.to_path_buf()));
+            }
+        }
+
+        settings.apply_env(env)?;
+        Ok(settings)
+    }
+
+    fn apply(&mut self, layer: Config, source: &Source) {
+        if let Some(v) = layer.default_status {
+            self.default_status = Some(v);
+            self.set_source("default_status", source);
+        }
+        if let Some(v) = layer.default_tags {
+            self.default_tags = v;
+            self.set_source("default_tags", source);
+        }
+        if let Some(v) = layer.editor {
+            self.editor = Some(v);
+            self.set_source("editor", source);
+        }
+        if let Some(v) = layer.username {
+            self.username = Some(v);
+            self.set_source("username", source);
+        }
+        if let Some(v) = layer.articles_dir {
+            self.articles_dir = v;
+            self.set_source("articles_dir", source);
+        }
+        if let Some(v) = layer.inline_code {
+            self.inline_code = v;
+            self.set_source("inline_code", source);
+        }
+        if let Some(v) = layer.table_style {
+            self.table_style = v;
+            self.set_source("table_style", source);
+        }
+        if let Some(v) = layer.extension_id {
+            self.extension_id = Some(v);
+            self.set_source("extension_id", source);
+        }
+        if let Some(v) = layer.bridge_port {
+            self.bridge_port = v;
+            self.set_source("bridge_port", source);
+        }
+        if let Some(v) = layer.connect_timeout {
+            self.connect_timeout = v;
+            self.set_source("connect_timeout", source);
+        }
+        if let Some(v) = layer.command_timeout {
+            self.command_timeout = v;
+            self.set_source("command_timeout", source);
+        }
+        if let Some(v) = layer.reconnect {
+            self.reconnect = v;
+            self.set_source("reconnect", source);
+        }
+        if let Some(v) = layer.workspace {
+            self.workspace = Some(v);
+            self.set_source("workspace", source);
+        }
+        if let Some(v) = layer.image_max_width {
+            self.image_max_width = v;
+            self.set_source("image_max_width", source);
+        }
+        if let Some(v) = layer.image_quality {
+            self.image_quality = v;
+            self.set_source("image_quality", source);
+        }
+        if let Some(v) = layer.strip_image_metadata {
+            self.strip_image_metadata = v;
+            self.set_source("strip_image_metadata", source);
+        }
+    }
+
+    fn apply_env(&mut self, env: impl Fn(&str) -> Option<String>) -> Result<()> {
+        for key in KEYS {
+            let var = env_var(key).ok_or_else(|| unknown_key(key))?;
+            let Some(value) = env(var) else {
+                continue;
+            };
+
+            let mut layer = Config::default();
+            match *key {
+                "default_status" => layer.default_status = Some(parse_env(var, &value)?),
+                "default_tags" => layer.default_tags = Some(split_list(&value)),
+                "editor" => layer.editor = Some(value),
+                "username" => layer.username = Some(value),
+                "articles_dir" => layer.articles_dir = Some(value),
+                "inline_code" => layer.inline_code = Some(parse_env(var, &value)?),
+                "table_style" => layer.table_style = Some(parse_env(var, &value)?),
+                "extension_id" => layer.extension_id = Some(value),
+                "bridge_port" => layer.bridge_port = Some(parse_env(var, &value)?),
+                "connect_timeout" => layer.connect_timeout = Some(parse_env(var, &value)?),
+                "command_timeout" => layer.command_timeout = Some(parse_env(var, &value)?),
+                "reconnect" => layer.reconnect = Some(parse_env(var, &value)?),
+                "workspace" => layer.workspace = Some(PathBuf::from(value)),
+                "image_max_width" => layer.image_max_width = Some(parse_env(var, &value)?),
+                "image_quality" => layer.image_quality = Some(parse_env(var, &value)?),
+                "strip_image_metadata" => {
+                    layer.strip_image_metadata = Some(parse_env(var, &value)?)
+                }
+                _ => return Err(unknown_key(key)),
+            }
+
+            layer
+                .validate()
+                .map_err(|(_, msg)| NoetError::ConfigError(format!("${var}: {msg}")))?;
+            self.apply(layer, &Source::Env(var));
+        }
+
+        Ok(())
+    }
+
+    fn set_source(&mut self, key: &'static str, source: &Source) {
+        if let Some(entry) = self.sources.iter_mut().find(|(k, _)| *k == key) {
+            entry.1 = source.clone();
+        }
+    }
+
+    /// Where the value of `key` came from
+    pub fn source(&self, key: &str) -> Option<&Source> {
+        self.sources.iter().find(|(k, _)| *k == key).map(|(_, s)| s)
+    }
+
+    /// Display value of a key, or None if unset
+    pub fn get(&self, key: &str) -> Result<Option<String>> {
+        let value = match key {
+            "default_status" => self.default_status.map(|s| s.to_string()),
+            "default_tags" => Some(self.default_tags.join(", ")).filter(|t| !t.is_empty()),
+            "editor" => self.editor.clone(),
+            "username" => self.username.clone(),
+            "articles_dir" => Some(self.articles_dir.clone()),
+            "inline_code" => Some(self.inline_code.to_string()),
+            "table_style" => Some(self.table_style.to_string()),
+            "extension_id" => self.extension_id.clone(),
+            "bridge_port" => Some(self.bridge_port.to_string()),
+            "connect_timeout" => Some(self.connect_timeout.to_string()),
+            "command_timeout" => Some(self.command_timeout.to_string()),
+            "reconnect" => Some(self.reconnect.to_string()),
+            "workspace" => self.workspace.as_ref().map(|p| p.display().to_string()),
+            "image_max_width" => Some(self.image_max_width.to_string()),
+            "image_quality" => Some(self.image_quality.to_string()),
+            "strip_image_metadata" => Some(self.strip_image_metadata.to_string()),
+            _ => return Err(unknown_key(key)),
+        };
+        Ok(value)
+    }
+
+    /// Options for rewriting Markdown before publishing
+    pub fn transform_options(&self) -> TransformOptions {
+        TransformOptions {
+            inline_code: self.inline_code,
+            table: self.table_style,
+        }
+    }
+
+    /// Options for preparing images before upload
+    pub fn image_options(&self) -> ImageOptions {
+        ImageOptions {
+            max_width: self.image_max_width,
+            quality: self.image_quality,
+            strip_metadata: self.strip_image_metadata,
+        }
+    }
+
+    /// Resolve the username, preferring an explicit value from the command line
+    pub fn require_username(&self, cli_value: Option<&str>) -> Result<String> {
+        cli_value
+            .map(String::from)
+            .or_else(|| self.username.clone())
+            .ok_or_else(|| {
+                NoetError::ConfigError(
+                    "ユーザー名が指定されていません。--username を指定するか `noet config set username <NAME>` を実行してください".to_string(),
+                )
+            })
+    }
+}
+
+/// Write a single key to a config file, keeping comments and formatting intact
+pub fn set_value(path: &Path, key: &str, value: &str) -> Result<()> {
+    if !KEYS.contains(&key) {
+        return Err(unknown_key(key));
+    }
+
+    let content = if path.exists() {
+        fs::read_to_string(path)?
+    } else {
+        String::new()
+    };
+
+    let mut doc: toml_edit::DocumentMut = content
+        .parse()
+        .map_err(|e| NoetError::ConfigError(format!("{}: {e}", path.display())))?;
+
+    let item = match key {
+        "default_tags" => {
+            let mut array = toml_edit::Array::new();
+            for tag in split_list(value) {
+                array.push(tag);
+            }
+            toml_edit::value(array)
+        }
+        "default_status" => toml_edit::value(parse_value::<ArticleStatus>(key, value)?),
+        "inline_code" => toml_edit::value(parse_value::<InlineCodeStyle>(key, value)?),
+        "table_style" => toml_edit::value(parse_value::<TableStyle>(key, value)?),
+        "bridge_port" => toml_edit::value(i64::from(parse_number::<u16>(key, value)?)),
+        "reconnect" | "strip_image_metadata" => {
+            toml_edit::value(value.trim().parse::<bool>().map_err(|_| {
+                NoetError::InvalidInput(format!(
+                    "{key}: '{value}' は true または false で指定してください"
+                ))
+            })?)
+        }
+        "image_max_width" => toml_edit::value(i64::from(parse_number::<u32>(key, value)?)),
+        "image_quality" => toml_edit::value(i64::from(parse_number::<u8>(key, value)?)),
+        "connect_timeout" | "command_timeout" => {
+            let secs = parse_number::<u64>(key, value)?;
+            toml_edit::value(
+                i64::try_from(secs).map_err(|e| NoetError::InvalidInput(format!("{key}: {e}")))?,
+            )
+        }
+        // Relative to where the command runs, since the host starts elsewhere
+        "workspace" => {
+            let dir = env::current_dir()?.join(value.trim());
+            let dir = fs::canonicalize(&dir).map_err(|_| {
+                NoetError::InvalidInput(format!("{key}: '{value}' が見つかりません"))
+            })?;
+            toml_edit::value(dir.to_string_lossy().into_owned())
+        }
+        _ => toml_edit::value(value),
+    };
+    doc[key] = item;
+
+    let new_content = doc.to_string();
+
+    // Validate before touching the file
+    Config::parse(&new_content, path)?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, new_content)?;
+
+    Ok(())
+}
+
+/// Get the global config directory (`~/.config/noet`)
+pub fn global_config_dir() -> Result<PathBuf> {
+    let config_dir = dirs::config_dir()
+        .ok_or_else(|| NoetError::ConfigError("設定ディレクトリが見つかりません".to_string()))?
+        .join("noet");
+    Ok(config_dir)
+}
+
+/// Path to the global config file
+pub fn global_config_path() -> Result<PathBuf> {
+    Ok(global_config_dir()?.join(CONFIG_FILE))
+}
+
+/// Path to the config file of the current workspace
+pub fn workspace_config_path() -> Result<PathBuf> {
+    Ok(workspace::get_workspace_dir()?.join(CONFIG_FILE))
+}
+
+/// Environment variable overriding `key`
+fn env_var(key: &str) -> Option<&'static str> {
+    let var = match key {
+        "default_status" => "NOET_DEFAULT_STATUS",
+        "default_tags" => "NOET_DEFAULT_TAGS",
+        "editor" => "NOET_EDITOR",
+        "username" => "NOET_USERNAME",
+        "articles_dir" => "NOET_ARTICLES_DIR",
+        "inline_code" => "NOET_INLINE_CODE",
+        "table_style" => "NOET_TABLE_STYLE",
+        "extension_id" => "NOET_EXTENSION_ID",
+        "bridge_port" => "NOET_BRIDGE_PORT",
+        "connect_timeout" => "NOET_CONNECT_TIMEOUT",
+        "command_timeout" => "NOET_COMMAND_TIMEOUT",
+        "reconnect" => "NOET_RECONNECT",
+        "workspace" => "NOET_WORKSPACE",
+        "image_max_width" => "NOET_IMAGE_MAX_WIDTH",
+        "image_quality" => "NOET_IMAGE_QUALITY",
+        "strip_image_metadata" => "NOET_STRIP_IMAGE_METADATA",
+        _ => return None,
+    };
+    Some(var)
+}
+
+/// Parse an enum value given on the command line, normalized for writing
+fn parse_value<T>(key: &str, value: &str) -> Result<String>
+where
+    T: std::str::FromStr<Err = String> + fmt::Display,
+{
+    let parsed: T = value
+        .parse()
+        .map_err(|e| NoetError::InvalidInput(format!("{key}: {e}")))?;
+    Ok(parsed.to_string())
+}
+
+fn parse_number<T>(key: &str, value: &str) -> Result<T>
+where
+    T: std::str::FromStr,
+{
+    value
+        .trim()
+        .parse()
+        .map_err(|_| NoetError::InvalidInput(format!("{key}: '{value}' は数値ではありません")))
+}
+
+fn parse_env<T>(var: &str, value: &str) -> Result<T>
+where
+    T: std::str::FromStr,
+    T::Err: fmt::Display,
+{
+    value
+        .trim()
+        .parse()
+        .map_err(|e| NoetError::ConfigError(format!("${var}: {e}")))
+}
+
+fn unknown_key(key: &str) -> NoetError {
+    NoetError::InvalidInput(format!(
+        "不明な設定キー: {key} (有効なキー: {})",
+        KEYS.join(", ")
+    ))
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// 1-based line number of a byte offset
+fn line_of(content: &str, offset: usize) -> usize {
+    content[..offset.min(content.len())].matches('\n').count() + 1
+}
+
+/// 1-based line number of the line that assigns `key`
+fn key_line(content: &str, key: &str) -> Option<usize> {
+    content
+        .lines()
+        .position(|line| {
+            line.trim_start()
+                .strip_prefix(key)
+                .is_some_and(|rest| rest.trim_start().starts_with('='))
+        })
+        .map(|i| i + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn no_env(_: &str) -> Option<String> {
+        None
+    }
+
+    #[test]
+    fn test_defaults_without_files() {
+        let settings = Settings::load_from(None, None, no_env).unwrap();
+        assert_eq!(settings.default_status, None);
+        assert_eq!(settings.articles_dir, "articles");
+        assert_eq!(settings.source("editor"), Some(&Source::Default));
+    }
+
+    #[test]
+    fn test_resolve_draft() {
+        let mut settings = Settings::load_from(None, None, no_env).unwrap();
+        assert!(!settings.resolve_draft(None));
+        assert!(settings.resolve_draft(Some(true)));
+
+        settings.default_status = Some(ArticleStatus::Draft);
+        assert!(settings.resolve_draft(None));
+        assert!(!settings.resolve_draft(Some(false)));
+    }
+
+    #[test]
+    fn test_workspace_overrides_global() {
+        let temp_dir = TempDir::new().unwrap();
+        let global = temp_dir.path().join("global.toml");
+        let local = temp_dir.path().join("local.toml");
+        fs::write(&global, "username = \"global-user\"\neditor = \"vim\"\n").unwrap();
+        fs::write(&local, "username = \"local-user\"\n").unwrap();
+
+        let settings = Settings::load_from(Some(&global), Some(&local), no_env).unwrap();
+
+        assert_eq!(settings.username.as_deref(), Some("local-user"));
+        assert_eq!(settings.editor.as_deref(), Some("vim"));
+        assert_eq!(settings.source("username"), Some(&Source::File(local)));
+        assert_eq!(settings.source("editor"), Some(&Source::File(global)));
+    }
+
+    #[test]
+    fn test_env_overrides_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let local = temp_dir.path().join("local.toml");
+        fs::write(&local, "default_tags = [\"a\"]\n").unwrap();
+
+        let settings = Settings::load_from(None, Some(&local), |k| match k {
+            "NOET_DEFAULT_TAGS" => Some("rust, cli".to_string()),
+            _ => None,
+        })
+        .unwrap();
+
+        assert_eq!(settings.default_tags, vec!["rust", "cli"]);
+        assert_eq!(
+            settings.source("default_tags"),
+            Some(&Source::Env("NOET_DEFAULT_TAGS"))
+        );
+    }
+
+    #[test]
+    fn test_every_key_has_its_own_env_var() {
+        let vars: Vec<&str> = KEYS.iter().map(|key| env_var(key).unwrap()).collect();
+        for (i, var) in vars.iter().enumerate() {
+            assert!(!vars[..i].contains(var), "{var} is used twice");
+        }
+        assert_eq!(env_var("usernmae"), None);
+
+        let settings = Settings::load_from(None, None, |k| match k {
+            "NOET_ARTICLES_DIR" => Some("posts".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        assert_eq!(settings.articles_dir, "posts");
+        assert_eq!(
+            settings.source("articles_dir"),
+            Some(&Source::Env("NOET_ARTICLES_DIR"))
+        );
+    }
+
+    #[test]
+    fn test_base_url_is_accepted_and_ignored() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("config.toml");
+        fs::write(
+            &path,
+            "base_url = \"https://note.com\"\nusername = \"alice\"\n",
+        )
+        .unwrap();
+
+        let settings = Settings::load_from(None, Some(&path), no_env).unwrap();
+        assert_eq!(settings.username.as_deref(), Some("alice"));
+        assert!(settings.get("base_url").is_err());
+    }
+
+    #[test]
+    fn test_default_workspace_config_parses() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = workspace::init_workspace(Some(temp_dir.path().to_path_buf())).unwrap();
+        let config = Config::from_file(&root.join(".noet/config.toml")).unwrap();
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn test_unknown_key_error_points_to_line() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("config.toml");
+        fs::write(&path, "editor = \"vim\"\nusernmae = \"typo\"\n").unwrap();
+
+        let err = Config::from_file(&path).unwrap_err().to_string();
+        assert!(err.contains(&format!("{}:2", path.display())), "{err}");
+        assert!(err.contains("usernmae"), "{err}");
+    }
+
+    #[test]
+    fn test_invalid_value_error_points_to_key() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("config.toml");
+        fs::write(&path, "# comment\nextension_id = \"not an id\"\n").unwrap();
+
+        let err = Config::from_file(&path).unwrap_err().to_string();
+        assert!(
+            err.contains(&format!("{}:2: extension_id", path.display())),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_set_value_keeps_comments() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("config.toml");
+        fs::write(&path, "# my settings\neditor = \"vim\"\n").unwrap();
+
+        set_value(&path, "default_tags", "rust, cli").unwrap();
+        set_value(&path, "editor", "code -w").unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with("# my settings"));
+
+        let config = Config::from_file(&path).unwrap();
+        assert_eq!(config.editor.as_deref(), Some("code -w"));
+        assert_eq!(
+            config.default_tags,
+            Some(vec!["rust".to_string(), "cli".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_set_value_rejects_unknown_key() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("config.toml");
+        assert!(set_value(&path, "color", "red").is_err());
+        assert!(set_value(&path, "default_status", "public").is_err());
+        assert!(set_value(&path, "inline_code", "italic").is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_bridge_settings() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("config.toml");
+
+        let settings = Settings::load_from(None, None, no_env).unwrap();
+        assert_eq!(settings.bridge_port, 9876);
+        assert_eq!(settings.command_timeout, 60);
+
+        set_value(&path, "bridge_port", "19876").unwrap();
+        set_value(&path, "command_timeout", "120").unwrap();
+        assert!(set_value(&path, "bridge_port", "70000").is_err());
+        assert!(set_value(&path, "connect_timeout", "0").is_err());
+
+        let settings = Settings::load_from(Some(&path), None, |k| match k {
+            "NOET_CONNECT_TIMEOUT" => Some("5".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        assert_eq!(settings.bridge_port, 19876);
+        assert_eq!(settings.command_timeout, 120);
+        assert_eq!(settings.connect_timeout, 5);
+
+        let err = Settings::load_from(None, None, |k| {
+            (k == "NOET_BRIDGE_PORT").then(|| "abc".to_string())
+        })
+        .unwrap_err();
+        assert!(err.to_string().contains("NOET_BRIDGE_PORT"), "{err}");
+
+        assert!(settings.reconnect);
+        set_value(&path, "reconnect", "false").unwrap();
+        assert!(set_value(&path, "reconnect", "no").is_err());
+        let settings = Settings::load_from(Some(&path), None, no_env).unwrap();
+        assert!(!settings.reconnect);
+    }
+
+    #[test]
+    fn test_workspace_is_stored_absolute() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("config.toml");
+        let root = temp_dir.path().join("blog");
+        fs::create_dir(&root).unwrap();
+
+        set_value(&path, "workspace", &root.to_string_lossy()).unwrap();
+        let settings = Settings::load_from(Some(&path), None, no_env).unwrap();
+        assert_eq!(settings.workspace, Some(fs::canonicalize(&root).unwrap()));
+
+        assert!(set_value(&path, "workspace", "/nonexistent/blog").is_err());
+        let err = Settings::load_from(None, None, |k| {
+            (k == "NOET_WORKSPACE").then(|| "blog".to_string())
+        })
+        .unwrap_err();
+        assert!(err.to_string().contains("NOET_WORKSPACE"), "{err}");
+    }
+
+    #[test]
+    fn test_image_settings() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("config.toml");
+
+        let settings = Settings::load_from(None, None, no_env).unwrap();
+        assert_eq!(settings.image_options(), ImageOptions::default());
+
+        set_value(&path, "image_max_width", "1280").unwrap();
+        set_value(&path, "strip_image_metadata", "false").unwrap();
+        assert!(set_value(&path, "image_quality", "0").is_err());
+        assert!(set_value(&path, "image_quality", "101").is_err());
+
+        let settings = Settings::load_from(Some(&path), None, |k| {
+            (k == "NOET_IMAGE_QUALITY").then(|| "70".to_string())
+        })
+        .unwrap();
+        assert_eq!(
+            settings.image_options(),
+            ImageOptions {
+                max_width: 1280,
+                quality: 70,
+                strip_metadata: false,
+            }
+        );
+    }
+}
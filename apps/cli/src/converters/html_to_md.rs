@@ -1,44 +1,427 @@
+use crate::converters::note_markdown;
 use crate::error::Result;
+use html5ever::tendril::TendrilSink;
+use html5ever::{parse_document, ParseOpts};
+use markup5ever_rcdom::{Handle, NodeData, RcDom};
+use std::collections::HashMap;
+
+/// Options for [`convert_with_options`]
+#[derive(Debug, Clone, Default)]
+pub struct ConvertOptions {
+    /// Heading text → level used in the local file. note.com renders `#` as h2 and
+    /// `####` as h3, so the HTML alone can't tell which level the author wrote.
+    pub heading_levels: HashMap<String, usize>,
+}
+
+impl ConvertOptions {
+    /// Remember the heading levels of an existing local version of the article
+    pub fn from_markdown(markdown: &str) -> Self {
+        let heading_levels = note_markdown::headings(markdown).into_iter().collect();
+        Self { heading_levels }
+    }
+}
 
 /// Convert Note.com HTML to Markdown
 ///
 /// Note.com returns article body in HTML format with specific structure:
-/// - Images use `data-src` attribute (lazy loading)
-/// - Links are wrapped in `<figure>` tags
+/// - Images use `data-src` attribute (lazy loading) and sit in `<figure>` with
+///   an optional `<figcaption>`
+/// - Link cards and embeds are `<figure embedded-service="...">`
 /// - Paragraphs contain `name` and `id` attributes (UUIDs)
 ///
-/// This function converts the HTML to standard Markdown format.
-#[allow(dead_code)]
+/// This function converts the HTML to the Markdown dialect noet publishes, so a
+/// pulled article can be pushed back unchanged.
 pub fn convert_html_to_markdown(html: &str) -> Result<String> {
-    // Use html2md for basic conversion
-    let markdown = html2md::parse_html(html);
+    Ok(convert_with_options(html, &ConvertOptions::default()))
+}
+
+/// Convert Note.com HTML to Markdown, restoring heading levels from `options`
+pub fn convert_with_options(html: &str, options: &ConvertOptions) -> String {
+    let dom = parse_document(RcDom::default(), ParseOpts::default()).one(html);
+    // Dropping the document clears the whole tree, so keep `dom` alive
+    let body = find_element(&dom.document, "body").unwrap_or_else(|| dom.document.clone());
+
+    let converter = Converter { options };
+    converter.blocks(&body).join("\n\n")
+}
+
+struct Converter<'a> {
+    options: &'a ConvertOptions,
+}
+
+impl Converter<'_> {
+    /// Block-level Markdown of the children of `node`
+    fn blocks(&self, node: &Handle) -> Vec<String> {
+        let mut blocks = Vec::new();
+        let mut inline = String::new();
+
+        for child in node.children.borrow().iter() {
+            if is_inline(child) {
+                inline.push_str(&self.inline(child));
+                continue;
+            }
+
+            push_paragraph(&mut blocks, &mut inline);
+            blocks.extend(self.block(child));
+        }
+
+        push_paragraph(&mut blocks, &mut inline);
+        blocks
+    }
+
+    fn block(&self, node: &Handle) -> Option<String> {
+        let tag = tag_name(node)?;
+
+        let markdown = match tag.as_str() {
+            "p" => escape_line_starts(&paragraph(&self.inline_children(node))),
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let text = paragraph(&self.inline_children(node)).replace('\n', " ");
+                let html_level: usize = tag[1..].parse().unwrap_or(2);
+                let level = self
+                    .options
+                    .heading_levels
+                    .get(&text)
+                    .copied()
+                    .filter(|level| (*level).clamp(2, 3) == html_level)
+                    .unwrap_or(html_level);
+                format!("{} {text}", "#".repeat(level))
+            }
+            "pre" => code_block(node),
+            "blockquote" => self
+                .blocks(node)
+                .join("\n")
+                .lines()
+                .map(|line| format!("> {line}").trim_end().to_string())
+                .collect::<Vec<_>>()
+                .join("\n"),
+            "ul" | "ol" => self.list(node, tag == "ol"),
+            "hr" => "---".to_string(),
+            "figure" => self.figure(node),
+            "img" => image(node, None),
+            "iframe" => attr(node, "src").map(embed_url).unwrap_or_default(),
+            "script" | "style" | "noscript" | "button" => String::new(),
+            _ => self.blocks(node).join("\n\n"),
+        };
+
+        (!markdown.trim().is_empty()).then_some(markdown)
+    }
+
+    fn list(&self, node: &Handle, ordered: bool) -> String {
+        let items = node
+            .children
+            .borrow()
+            .iter()
+            .filter(|child| tag_name(child).as_deref() == Some("li"))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| {
+                let marker = if ordered {
+                    format!("{}. ", i + 1)
+                } else {
+                    "- ".to_string()
+                };
+                let indent = " ".repeat(marker.len());
+                let content = self.blocks(item).join("\n");
 
-    // Post-process to handle Note.com specific quirks
-    let markdown = post_process_markdown(&markdown);
+                content
+                    .lines()
+                    .enumerate()
+                    .map(|(n, line)| match n {
+                        0 => format!("{marker}{line}"),
+                        _ if line.is_empty() => String::new(),
+                        _ => format!("{indent}{line}"),
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Images become `![caption](url)`; link cards and embeds become bare URLs,
+    /// which the editor turns back into cards when pasted
+    fn figure(&self, node: &Handle) -> String {
+        if attr(node, "embedded-service").is_some() {
+            let url = attr(node, "data-src")
+                .or_else(|| find_element(node, "iframe").and_then(|f| attr(&f, "src")))
+                .or_else(|| find_element(node, "a").and_then(|a| attr(&a, "href")));
+            if let Some(url) = url {
+                return embed_url(url);
+            }
+        }
+
+        if let Some(img) = find_element(node, "img") {
+            let caption = find_element(node, "figcaption")
+                .map(|c| paragraph(&self.inline_children(&c)).replace('\n', " "))
+                .filter(|c| !c.is_empty());
+            return image(&img, caption);
+        }
+
+        if let Some(iframe) = find_element(node, "iframe") {
+            if let Some(src) = attr(&iframe, "src") {
+                return embed_url(src);
+            }
+        }
+
+        self.blocks(node).join("\n\n")
+    }
+
+    fn inline_children(&self, node: &Handle) -> String {
+        node.children
+            .borrow()
+            .iter()
+            .map(|child| self.inline(child))
+            .collect()
+    }
+
+    fn inline(&self, node: &Handle) -> String {
+        match &node.data {
+            NodeData::Text { contents } => escape_text(&collapse_whitespace(&contents.borrow())),
+            NodeData::Element { .. } => {
+                let tag = tag_name(node).unwrap_or_default();
+                match tag.as_str() {
+                    "br" => "\n".to_string(),
+                    "strong" | "b" => wrap("**", &self.inline_children(node)),
+                    "em" | "i" => wrap("*", &self.inline_children(node)),
+                    "s" | "del" | "strike" => wrap("~~", &self.inline_children(node)),
+                    "code" => wrap("`", &text_content(node)),
+                    "img" => image(node, None),
+                    "a" => {
+                        let text = self.inline_children(node);
+                        match attr(node, "href") {
+                            Some(href) if text.trim().is_empty() || text.trim() == href => href,
+                            Some(href) => format!("[{}]({href})", text.trim()),
+                            None => text,
+                        }
+                    }
+                    _ => self.inline_children(node),
+                }
+            }
+            _ => String::new(),
+        }
+    }
+}
 
-    Ok(markdown)
+fn push_paragraph(blocks: &mut Vec<String>, inline: &mut String) {
+    let text = escape_line_starts(&paragraph(inline));
+    if !text.is_empty() {
+        blocks.push(text);
+    }
+    inline.clear();
+}
+
+/// Trim each line of inline content and drop empty lines at the ends
+fn paragraph(inline: &str) -> String {
+    inline
+        .lines()
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim_matches('\n')
+        .to_string()
 }
 
-/// Post-process markdown to clean up Note.com specific artifacts
-#[allow(dead_code)]
-fn post_process_markdown(markdown: &str) -> String {
-    let mut result = markdown.to_string();
+/// Escape characters that would otherwise read as inline Markdown. Bare URLs are
+/// left alone, as the editor links them as written.
+fn escape_text(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
 
-    // Remove extra newlines (html2md sometimes adds too many)
-    while result.contains("\n\n\n") {
-        result = result.replace("\n\n\n", "\n\n");
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("http://") || rest.starts_with("https://") {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            out.push_str(&rest[..end]);
+            rest = &rest[end..];
+            continue;
+        }
+        if matches!(c, '\\' | '*' | '_' | '`' | '[' | ']') {
+            out.push('\\');
+        }
+        out.push(c);
+        rest = &rest[c.len_utf8()..];
     }
 
-    // Clean up link formatting from <figure> tags
-    // html2md might not handle these perfectly
-    result = result.trim().to_string();
+    out
+}
 
-    result
+/// Escape the start of lines that would otherwise become headings, quotes,
+/// lists or rules
+fn escape_line_starts(text: &str) -> String {
+    text.lines()
+        .map(|line| match block_marker_end(line) {
+            Some(at) => format!("{}\\{}", &line[..at], &line[at..]),
+            None => line.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Where to put the backslash if `line` starts with block Markdown
+fn block_marker_end(line: &str) -> Option<usize> {
+    let ends_marker = |rest: &str| rest.is_empty() || rest.starts_with([' ', '\t']);
+
+    let hashes = line.chars().take_while(|c| *c == '#').count();
+    if (1..=6).contains(&hashes) && ends_marker(&line[hashes..]) {
+        return Some(0);
+    }
+    if line.starts_with('>') {
+        return Some(0);
+    }
+    if line.starts_with(['-', '+']) && ends_marker(&line[1..]) {
+        return Some(0);
+    }
+    if line.starts_with('-') && line.chars().all(|c| c == '-' || c == ' ') {
+        return Some(0);
+    }
+
+    let digits = line.chars().take_while(char::is_ascii_digit).count();
+    let rest = &line[digits..];
+    ((1..=9).contains(&digits) && rest.starts_with(['.', ')']) && ends_marker(&rest[1..]))
+        .then_some(digits)
+}
+
+/// The URL to paste for an embed. A tweet's iframe points at Twitter's widget
+/// page, which the editor can't embed again.
+fn embed_url(url: String) -> String {
+    let tweet_id = url
+        .strip_prefix("https://platform.twitter.com/embed/Tweet.html?")
+        .and_then(|query| query.split('&').find_map(|p| p.strip_prefix("id=")));
+    match tweet_id {
+        Some(id) => format!("https://twitter.com/i/status/{id}"),
+        None => url,
+    }
+}
+
+fn code_block(node: &Handle) -> String {
+    let language = find_element(node, "code")
+        .and_then(|code| attr(&code, "class"))
+        .and_then(|class| {
+            class.split_whitespace().find_map(|c| {
+                c.strip_prefix("language-")
+                    .or_else(|| c.strip_prefix("lang-"))
+                    .map(String::from)
+            })
+        })
+        .or_else(|| attr(node, "data-lang"))
+        .unwrap_or_default();
+
+    let code = text_content(node);
+    format!("```{language}\n{}\n```", code.trim_end_matches('\n'))
+}
+
+fn image(node: &Handle, caption: Option<String>) -> String {
+    // `src` is a placeholder until the lazy loader swaps in `data-src`
+    let src = attr(node, "data-src")
+        .or_else(|| attr(node, "src").filter(|s| !s.starts_with("data:")))
+        .unwrap_or_default();
+    let alt = caption.or_else(|| attr(node, "alt")).unwrap_or_default();
+    format!("![{alt}]({src})")
+}
+
+/// Wrap inline content in a marker, keeping surrounding spaces outside
+fn wrap(marker: &str, inner: &str) -> String {
+    let trimmed = inner.trim();
+    if trimmed.is_empty() {
+        return inner.to_string();
+    }
+    let leading = &inner[..inner.len() - inner.trim_start().len()];
+    let trailing = &inner[inner.trim_end().len()..];
+    format!("{leading}{marker}{trimmed}{marker}{trailing}")
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut in_space = false;
+    for c in text.chars() {
+        if c.is_whitespace() && c != '\u{3000}' {
+            if !in_space {
+                out.push(' ');
+            }
+            in_space = true;
+        } else {
+            out.push(c);
+            in_space = false;
+        }
+    }
+    out
+}
+
+/// Raw text of a node, with `<br>` as newlines
+fn text_content(node: &Handle) -> String {
+    match &node.data {
+        NodeData::Text { contents } => contents.borrow().to_string(),
+        NodeData::Element { .. } if tag_name(node).as_deref() == Some("br") => "\n".to_string(),
+        _ => node.children.borrow().iter().map(text_content).collect(),
+    }
+}
+
+fn is_inline(node: &Handle) -> bool {
+    match &node.data {
+        NodeData::Text { .. } => true,
+        NodeData::Element { .. } => matches!(
+            tag_name(node).as_deref(),
+            Some(
+                "a" | "b"
+                    | "br"
+                    | "code"
+                    | "del"
+                    | "em"
+                    | "i"
+                    | "s"
+                    | "span"
+                    | "strike"
+                    | "strong"
+                    | "sub"
+                    | "sup"
+                    | "u"
+            )
+        ),
+        _ => false,
+    }
+}
+
+fn tag_name(node: &Handle) -> Option<String> {
+    match &node.data {
+        NodeData::Element { name, .. } => Some(name.local.to_string()),
+        _ => None,
+    }
+}
+
+fn attr(node: &Handle, name: &str) -> Option<String> {
+    match &node.data {
+        NodeData::Element { attrs, .. } => attrs
+            .borrow()
+            .iter()
+            .find(|a| &*a.name.local == name)
+            .map(|a| a.value.to_string())
+            .filter(|v| !v.is_empty()),
+        _ => None,
+    }
+}
+
+/// First descendant element with the given tag
+fn find_element(node: &Handle, tag: &str) -> Option<Handle> {
+    for child in node.children.borrow().iter() {
+        if tag_name(child).as_deref() == Some(tag) {
+            return Some(child.clone());
+        }
+        if let Some(found) = find_element(child, tag) {
+            return Some(found);
+        }
+    }
+    None
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::converters::note_markdown::{transform, ChangeKind, TransformOptions};
+    use std::fs;
+    use std::path::Path;
 
     #[test]
     fn test_simple_paragraph() {
@@ -75,4 +458,64 @@ mod tests {
         let md = convert_html_to_markdown(html).unwrap();
         assert!(md.contains("**bold**") || md.contains("bold"));
     }
+
+    #[test]
+    fn test_heading_levels_from_local_file() {
+        let html = r#"<h2 name="a" id="a">はじめに</h2><h3>詳細</h3><h2>まとめ</h2>"#;
+        let options = ConvertOptions::from_markdown("# はじめに\n\n#### 詳細\n\n### まとめ\n");
+        assert_eq!(
+            convert_with_options(html, &options),
+            "# はじめに\n\n#### 詳細\n\n## まとめ"
+        );
+    }
+
+    /// Stand-in for the image download done by `noet get --save`
+    fn localize_images(markdown: &str) -> String {
+        markdown.replace("](https://assets.st-note.com/img/", "](./images/")
+    }
+
+    /// Each `tests/fixtures/note_html/<name>.html` is an article body as the
+    /// extension scrapes it, and `<name>.md` the Markdown noet should pull.
+    /// Pushing that Markdown must not need any rewriting, so pull then push
+    /// leaves the article unchanged. The editor gets the body without escapes,
+    /// as in `<name>.sent.md` when there are any; only the escaped block markers
+    /// among them can't survive that and are reported.
+    #[test]
+    fn test_fixtures_round_trip() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/note_html");
+        let mut checked = 0;
+
+        for entry in fs::read_dir(&dir).unwrap() {
+            let html_path = entry.unwrap().path();
+            if html_path.extension().is_none_or(|ext| ext != "html") {
+                continue;
+            }
+
+            let html = fs::read_to_string(&html_path).unwrap();
+            let expected = fs::read_to_string(html_path.with_extension("md")).unwrap();
+            let pulled = convert_html_to_markdown(&html).unwrap();
+            assert_eq!(pulled, expected.trim_end(), "pull: {}", html_path.display());
+
+            let local = localize_images(&pulled);
+            let pushed = transform(&local, &TransformOptions::default());
+            let rewrites: Vec<_> = pushed
+                .changes
+                .iter()
+                .filter(|change| change.kind != ChangeKind::Escape)
+                .collect();
+            assert!(
+                rewrites.is_empty(),
+                "push rewrites: {}: {rewrites:?}",
+                html_path.display()
+            );
+            let sent = fs::read_to_string(html_path.with_extension("sent.md"))
+                .map(|sent| sent.trim_end().to_string())
+                .unwrap_or(local);
+            assert_eq!(pushed.body, sent, "push: {}", html_path.display());
+
+            checked += 1;
+        }
+
+        assert!(checked >= 3, "no fixtures found in {}", dir.display());
+    }
 }
@@ -1,4 +1,2 @@
 pub mod html_to_md;
-
-#[allow(unused_imports)]
-pub use html_to_md::convert_html_to_markdown;
+pub mod note_markdown;
@@ -0,0 +1,894 @@
+//! Rewrite Markdown into the subset the note.com editor understands
+//!
+//! The editor receives the body as pasted text and converts it with its own
+//! ProseMirror rules (see `docs/MARKDOWN_SUPPORT_TEST.md`). Italics, inline code,
+//! tables and remote images are not recognized and end up as literal Markdown, so
+//! they are rewritten into supported forms here. Every such rewrite is recorded as
+//! a [`Change`] so the user can see what will look different on note.com.
+//!
+//! The editor has no escapes either: a pasted `\*` stays a backslash and a star.
+//! Backslash escapes, such as the ones `noet get` writes, are removed so the text
+//! reads as intended. A line that only escaped a heading, quote, list or rule
+//! marker becomes that block in the editor, which is reported as a change.
+
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::Range;
+use unicode_width::UnicodeWidthStr;
+
+/// How inline code is rewritten
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InlineCodeStyle {
+    /// `**code**`
+    #[default]
+    Bold,
+    /// `「code」`
+    Quote,
+    /// `code`
+    Plain,
+}
+
+impl fmt::Display for InlineCodeStyle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InlineCodeStyle::Bold => write!(f, "bold"),
+            InlineCodeStyle::Quote => write!(f, "quote"),
+            InlineCodeStyle::Plain => write!(f, "plain"),
+        }
+    }
+}
+
+impl std::str::FromStr for InlineCodeStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim() {
+            "bold" => Ok(InlineCodeStyle::Bold),
+            "quote" => Ok(InlineCodeStyle::Quote),
+            "plain" => Ok(InlineCodeStyle::Plain),
+            other => Err(format!(
+                "'{other}' は無効な値です (bold, quote または plain)"
+            )),
+        }
+    }
+}
+
+/// How tables are rewritten
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TableStyle {
+    /// Aligned columns in a code block
+    #[default]
+    Code,
+    /// One bullet per row
+    List,
+}
+
+impl fmt::Display for TableStyle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TableStyle::Code => write!(f, "code"),
+            TableStyle::List => write!(f, "list"),
+        }
+    }
+}
+
+impl std::str::FromStr for TableStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim() {
+            "code" => Ok(TableStyle::Code),
+            "list" => Ok(TableStyle::List),
+            other => Err(format!("'{other}' は無効な値です (code または list)")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TransformOptions {
+    pub inline_code: InlineCodeStyle,
+    pub table: TableStyle,
+}
+
+/// Kind of lossy rewrite
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Heading,
+    Italic,
+    InlineCode,
+    Table,
+    Image,
+    /// An escaped block marker at the start of a line
+    Escape,
+}
+
+impl fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            ChangeKind::Heading => "見出し",
+            ChangeKind::Italic => "斜体",
+            ChangeKind::InlineCode => "インラインコード",
+            ChangeKind::Table => "表",
+            ChangeKind::Image => "画像",
+            ChangeKind::Escape => "エスケープ",
+        };
+        write!(f, "{label}")
+    }
+}
+
+/// One rewrite made for note.com
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    /// 1-based line number in the body
+    pub line: usize,
+    pub kind: ChangeKind,
+    pub before: String,
+    pub after: String,
+}
+
+/// Transformed body plus the rewrites that were made
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transformed {
+    pub body: String,
+    pub changes: Vec<Change>,
+}
+
+/// Rewrite `body` for the note.com editor
+///
+/// The body is parsed as CommonMark, and only the parts that need rewriting are
+/// replaced in the source, so everything else reaches the editor as written.
+pub fn transform(body: &str, options: &TransformOptions) -> Transformed {
+    let mut rewriter = Rewriter::new(body, options);
+    for (event, range) in Parser::new_ext(body, parser_options()).into_offset_iter() {
+        rewriter.event(event, range);
+    }
+    rewriter.finish()
+}
+
+/// Headings of a body with their level, as `(text, level)`
+pub fn headings(body: &str) -> Vec<(String, usize)> {
+    let mut headings = Vec::new();
+    let mut current: Option<(usize, Range<usize>)> = None;
+
+    for (event, range) in Parser::new_ext(body, parser_options()).into_offset_iter() {
+        match event {
+            Event::Start(Tag::Heading { level, .. }) => {
+                current = Some((level as usize, range.end..range.end));
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                if let Some((level, content)) = current.take() {
+                    let text = body.get(content).unwrap_or("").trim();
+                    headings.push((text.to_string(), level));
+                }
+            }
+            _ => {
+                if let Some((_, content)) = &mut current {
+                    *content = extend(content, &range);
+                }
+            }
+        }
+    }
+
+    headings
+}
+
+/// An image reference outside code
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageRef {
+    /// 1-based line number in the body
+    pub line: usize,
+    pub alt: String,
+    pub url: String,
+}
+
+/// Images referenced in a body
+pub fn images(body: &str) -> Vec<ImageRef> {
+    let lines = LineIndex::new(body);
+    let mut images = Vec::new();
+    let mut current: Option<ImageRef> = None;
+
+    for (event, range) in Parser::new_ext(body, parser_options()).into_offset_iter() {
+        match event {
+            Event::Start(Tag::Image { dest_url, .. }) => {
+                current = Some(ImageRef {
+                    line: lines.line(range.start),
+                    alt: String::new(),
+                    url: dest_url.to_string(),
+                });
+            }
+            Event::End(TagEnd::Image) => images.extend(current.take()),
+            Event::Text(text) | Event::Code(text) => {
+                if let Some(image) = &mut current {
+                    image.alt.push_str(&text);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    images
+}
+
+/// Whether an image reference points to a file that will be uploaded
+pub fn is_local_image(url: &str) -> bool {
+    !(url.starts_with("http://") || url.starts_with("https://") || url.starts_with("//"))
+}
+
+fn parser_options() -> Options {
+    Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH
+}
+
+/// Smallest range covering both, treating an empty `range` as unset
+fn extend(range: &Range<usize>, other: &Range<usize>) -> Range<usize> {
+    if range.is_empty() {
+        other.clone()
+    } else {
+        range.start.min(other.start)..range.end.max(other.end)
+    }
+}
+
+/// Line numbers of byte offsets
+struct LineIndex {
+    starts: Vec<usize>,
+}
+
+impl LineIndex {
+    fn new(text: &str) -> Self {
+        let starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self { starts }
+    }
+
+    /// 1-based line containing `offset`
+    fn line(&self, offset: usize) -> usize {
+        self.starts.partition_point(|&start| start <= offset)
+    }
+
+    /// Offset where the line containing `offset` starts
+    fn start_of(&self, offset: usize) -> usize {
+        self.starts[self.line(offset) - 1]
+    }
+}
+
+/// Replacement of a byte range of the body
+#[derive(Debug)]
+struct Edit {
+    range: Range<usize>,
+    text: String,
+}
+
+/// `text[range]` with the edits inside it applied
+fn splice(text: &str, range: Range<usize>, edits: &[Edit]) -> String {
+    let mut inside: Vec<&Edit> = edits
+        .iter()
+        .filter(|edit| edit.range.start >= range.start && edit.range.end <= range.end)
+        .collect();
+    inside.sort_by_key(|edit| edit.range.start);
+
+    let mut out = String::new();
+    let mut at = range.start;
+    for edit in inside {
+        if edit.range.start < at {
+            continue;
+        }
+        out.push_str(&text[at..edit.range.start]);
+        out.push_str(&edit.text);
+        at = edit.range.end;
+    }
+    out.push_str(&text[at..range.end]);
+    out
+}
+
+/// A table being collected; its cells' edits are kept apart from the body's
+struct Table {
+    range: Range<usize>,
+    /// Each cell's trimmed source range and its text without Markdown
+    rows: Vec<Vec<(Range<usize>, String)>>,
+    edits: Vec<Edit>,
+    changes: Vec<Change>,
+}
+
+/// An image being collected
+struct Image {
+    range: Range<usize>,
+    url: String,
+    alt: String,
+}
+
+/// Walks the parser's events and records the edits to make
+struct Rewriter<'a> {
+    body: &'a str,
+    options: &'a TransformOptions,
+    lines: LineIndex,
+    edits: Vec<Edit>,
+    changes: Vec<Change>,
+    /// Lines whose escaped block marker is dropped, as content ranges
+    escaped_lines: Vec<Range<usize>>,
+    /// Level, whole range and content range of the heading being read
+    heading: Option<(usize, Range<usize>, Range<usize>)>,
+    emphasis: Vec<Range<usize>>,
+    table: Option<Table>,
+    image: Option<Image>,
+    in_code_block: bool,
+    /// End of the last text or code span, so a backslash ending one isn't taken
+    /// for an escape
+    text_end: usize,
+}
+
+impl<'a> Rewriter<'a> {
+    fn new(body: &'a str, options: &'a TransformOptions) -> Self {
+        Self {
+            body,
+            options,
+            lines: LineIndex::new(body),
+            edits: Vec::new(),
+            changes: Vec::new(),
+            escaped_lines: Vec::new(),
+            heading: None,
+            emphasis: Vec::new(),
+            table: None,
+            image: None,
+            in_code_block: false,
+            text_end: 0,
+        }
+    }
+
+    fn event(&mut self, event: Event<'a>, range: Range<usize>) {
+        if let Some((_, _, content)) = &mut self.heading {
+            if !matches!(event, Event::End(TagEnd::Heading(_))) {
+                *content = extend(content, &range);
+            }
+        }
+
+        if let Some(image) = &mut self.image {
+            match event {
+                Event::Text(text) | Event::Code(text) => image.alt.push_str(&text),
+                Event::End(TagEnd::Image) => self.end_image(),
+                _ => {}
+            }
+            return;
+        }
+
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => self.in_code_block = true,
+            Event::End(TagEnd::CodeBlock) => self.in_code_block = false,
+            _ if self.in_code_block => {}
+
+            Event::Start(Tag::Heading { level, .. }) => {
+                self.heading = Some((level as usize, range.clone(), range.end..range.end));
+            }
+            Event::End(TagEnd::Heading(_)) => self.end_heading(),
+
+            Event::Start(Tag::Table(_)) => {
+                // Cells are rewritten on their own and the table replaced as a whole
+                self.table = Some(Table {
+                    range,
+                    rows: Vec::new(),
+                    edits: std::mem::take(&mut self.edits),
+                    changes: std::mem::take(&mut self.changes),
+                });
+            }
+            Event::End(TagEnd::Table) => self.end_table(),
+            Event::Start(Tag::TableHead | Tag::TableRow) => self.table_mut().rows.push(Vec::new()),
+            Event::Start(Tag::TableCell) => {
+                let source = &self.body[range.clone()];
+                let start = range.start + (source.len() - source.trim_start().len());
+                let cell = start..start + source.trim().len();
+                if let Some(row) = self.table_mut().rows.last_mut() {
+                    row.push((cell, String::new()));
+                }
+            }
+
+            Event::Start(Tag::Emphasis) => {
+                self.edit(range.start..range.start + 1, "");
+                self.emphasis.push(range);
+            }
+            Event::End(TagEnd::Emphasis) => self.end_emphasis(),
+
+            Event::Start(Tag::Image { dest_url, .. }) => {
+                self.image = Some(Image {
+                    range,
+                    url: dest_url.to_string(),
+                    alt: String::new(),
+                });
+            }
+
+            Event::Code(code) => {
+                self.cell_text(&code);
+                self.code(&code, range.clone());
+                self.text_end = range.end;
+            }
+            Event::Text(text) => {
+                self.cell_text(&text);
+                self.text(range.clone());
+                self.text_end = range.end;
+            }
+            Event::SoftBreak | Event::HardBreak => self.cell_text(" "),
+            _ => {}
+        }
+    }
+
+    fn table_mut(&mut self) -> &mut Table {
+        self.table.as_mut().expect("inside a table")
+    }
+
+    /// Add to the plain text of the current table cell
+    fn cell_text(&mut self, text: &str) {
+        if let Some(cell) = self
+            .table
+            .as_mut()
+            .and_then(|table| table.rows.last_mut())
+            .and_then(|row| row.last_mut())
+        {
+            cell.1.push_str(text);
+        }
+    }
+
+    fn edit(&mut self, range: Range<usize>, text: impl Into<String>) {
+        self.edits.push(Edit {
+            range,
+            text: text.into(),
+        });
+    }
+
+    fn change(&mut self, offset: usize, kind: ChangeKind, before: String, after: String) {
+        self.changes.push(Change {
+            line: self.lines.line(offset),
+            kind,
+            before,
+            after,
+        });
+    }
+
+    /// Drop the backslash of an escape just before the text
+    fn text(&mut self, range: Range<usize>) {
+        let Some(backslash) = range.start.checked_sub(1) else {
+            return;
+        };
+        let escaped = self.body[range.start..]
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_punctuation());
+        if backslash < self.text_end || self.body.as_bytes()[backslash] != b'\\' || !escaped {
+            return;
+        }
+        self.edit(backslash..range.start, "");
+
+        // Without the backslash the line may start a heading, quote or list
+        let line_start = self.lines.start_of(backslash);
+        let prefix = &self.body[line_start..backslash];
+        let number = prefix.trim_end_matches(|c: char| c.is_ascii_digit());
+        if number.chars().all(|c| c.is_whitespace() || c == '>') {
+            let start = line_start + number.len();
+            let end = self.body[start..]
+                .find('\n')
+                .map_or(self.body.len(), |i| start + i);
+            if escapes_block_marker(&self.body[start..end]) {
+                self.escaped_lines.push(start..end);
+            }
+        }
+    }
+
+    fn code(&mut self, code: &str, range: Range<usize>) {
+        let after = match self.options.inline_code {
+            InlineCodeStyle::Bold if !code.contains("**") => format!("**{code}**"),
+            InlineCodeStyle::Bold | InlineCodeStyle::Quote => format!("「{code}」"),
+            InlineCodeStyle::Plain => code.to_string(),
+        };
+        self.change(
+            range.start,
+            ChangeKind::InlineCode,
+            format!("`{code}`"),
+            after.clone(),
+        );
+        self.edit(range, after);
+    }
+
+    fn end_emphasis(&mut self) {
+        let Some(range) = self.emphasis.pop() else {
+            return;
+        };
+        let close = range.end - 1;
+        let after = splice(self.body, range.start + 1..close, &self.edits);
+        self.edit(close..range.end, "");
+        self.change(
+            range.start,
+            ChangeKind::Italic,
+            self.body[range.clone()].to_string(),
+            after,
+        );
+    }
+
+    fn end_image(&mut self) {
+        let Some(Image { range, url, alt }) = self.image.take() else {
+            return;
+        };
+        self.cell_text(if alt.is_empty() { &url } else { &alt });
+        self.text_end = range.end;
+        // Local images are uploaded through the editor by the extension
+        if is_local_image(&url) {
+            return;
+        }
+
+        let after = if alt.is_empty() {
+            url.clone()
+        } else {
+            format!("[{alt}]({url})")
+        };
+        self.change(
+            range.start,
+            ChangeKind::Image,
+            self.body[range.clone()].to_string(),
+            after.clone(),
+        );
+        self.edit(range, after);
+    }
+
+    fn end_heading(&mut self) {
+        let Some((level, range, content)) = self.heading.take() else {
+            return;
+        };
+        // The editor only has h2 and h3
+        let new_level = level.clamp(2, 3);
+        if new_level == level {
+            return;
+        }
+
+        let source = &self.body[range.clone()];
+        let marker = source.trim_start();
+        if marker.starts_with('#') {
+            let start = range.start + (source.len() - marker.len());
+            self.edit(start..start + level, "#".repeat(new_level));
+        } else {
+            // Setext: `===` under the text becomes `---`
+            let underline = source.trim_end();
+            let end = range.start + underline.len();
+            let start = range.start + underline.trim_end_matches('=').len();
+            self.edit(start..end, "-".repeat(end - start));
+        }
+
+        let text = splice(self.body, content, &self.edits);
+        let text = text.trim();
+        self.change(
+            range.start,
+            ChangeKind::Heading,
+            format!("{} {text}", "#".repeat(level)),
+            format!("{} {text}", "#".repeat(new_level)),
+        );
+    }
+
+    fn end_table(&mut self) {
+        let Some(mut table) = self.table.take() else {
+            return;
+        };
+        std::mem::swap(&mut self.edits, &mut table.edits);
+        std::mem::swap(&mut self.changes, &mut table.changes);
+        let Table {
+            range,
+            rows,
+            edits: cell_edits,
+            changes: cell_changes,
+        } = table;
+        if rows.is_empty() {
+            return;
+        }
+
+        let (rendered, after) = match self.options.table {
+            TableStyle::Code => {
+                let cells = rows
+                    .iter()
+                    .map(|row| row.iter().map(|(_, text)| text.clone()).collect())
+                    .collect::<Vec<Vec<String>>>();
+                (table_as_code(&cells), "コードブロック")
+            }
+            TableStyle::List => {
+                let line = self.lines.line(range.start);
+                self.changes.extend(
+                    cell_changes
+                        .into_iter()
+                        .map(|change| Change { line, ..change }),
+                );
+                (table_as_list(self.body, &rows, &cell_edits), "箇条書き")
+            }
+        };
+        self.change(
+            range.start,
+            ChangeKind::Table,
+            format!("{}行 × {}列", rows.len(), rows[0].len()),
+            after.to_string(),
+        );
+
+        // Lines after the first keep the quote markers of the lines they replace
+        let line_start = self.lines.start_of(range.start);
+        let continuation: String = self.body[line_start..range.start]
+            .chars()
+            .map(|c| if c == '>' { '>' } else { ' ' })
+            .collect();
+        let rendered = rendered
+            .lines()
+            .enumerate()
+            .map(|(i, line)| match i {
+                0 => line.to_string(),
+                _ => format!("{continuation}{line}").trim_end().to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let end = range.start + self.body[range.clone()].trim_end_matches('\n').len();
+        self.edit(range.start..end, rendered);
+    }
+
+    fn finish(mut self) -> Transformed {
+        for line in std::mem::take(&mut self.escaped_lines) {
+            let after = splice(self.body, line.clone(), &self.edits);
+            self.change(
+                line.start,
+                ChangeKind::Escape,
+                self.body[line].to_string(),
+                after,
+            );
+        }
+        self.changes.sort_by_key(|change| change.line);
+
+        let body = splice(self.body, 0..self.body.len(), &self.edits);
+        Transformed {
+            body: body.trim_end_matches('\n').to_string(),
+            changes: self.changes,
+        }
+    }
+}
+
+/// Whether a line starts with an escaped `#`, `>`, `-`, `+` or `1.`, which the
+/// editor turns into a block once the backslash is gone
+fn escapes_block_marker(line: &str) -> bool {
+    let digits = line.chars().take_while(char::is_ascii_digit).count();
+    let markers: &[char] = if digits == 0 {
+        &['#', '>', '-', '+']
+    } else {
+        &['.', ')']
+    };
+    let Some(rest) = line[digits..].strip_prefix('\\') else {
+        return false;
+    };
+    let Some(marker) = rest.chars().next().filter(|c| markers.contains(c)) else {
+        return false;
+    };
+    let after = match marker {
+        '#' => rest[1..].trim_start_matches('#'),
+        _ => &rest[1..],
+    };
+    marker == '>'
+        || after.is_empty()
+        || after.starts_with([' ', '\t'])
+        || (marker == '-' && rest.chars().all(|c| c == '-' || c == ' '))
+}
+
+fn table_as_code(rows: &[Vec<String>]) -> String {
+    let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+    let widths: Vec<usize> = (0..columns)
+        .map(|c| {
+            rows.iter()
+                .filter_map(|row| row.get(c))
+                .map(|cell| cell.width())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    let mut lines = vec!["```".to_string()];
+    for (i, row) in rows.iter().enumerate() {
+        let cells: Vec<String> = (0..columns)
+            .map(|c| {
+                let cell = row.get(c).map(String::as_str).unwrap_or("");
+                format!("{cell}{}", " ".repeat(widths[c] - cell.width()))
+            })
+            .collect();
+        lines.push(cells.join(" | ").trim_end().to_string());
+        if i == 0 {
+            let rule: Vec<String> = widths.iter().map(|w| "-".repeat(*w)).collect();
+            lines.push(rule.join("-+-"));
+        }
+    }
+    lines.push("```".to_string());
+    lines.join("\n")
+}
+
+/// One bullet per row, each cell rewritten like the rest of the body
+fn table_as_list(body: &str, rows: &[Vec<(Range<usize>, String)>], edits: &[Edit]) -> String {
+    let header = &rows[0];
+    rows[1..]
+        .iter()
+        .map(|row| {
+            let cells: Vec<String> = row
+                .iter()
+                .enumerate()
+                .map(|(c, (range, _))| {
+                    let cell = splice(body, range.clone(), edits);
+                    match header.get(c).filter(|(_, name)| !name.is_empty()) {
+                        Some((_, name)) => format!("{name}: {cell}"),
+                        None => cell,
+                    }
+                })
+                .collect();
+            format!("- {}", cells.join(" / "))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(body: &str) -> Transformed {
+        transform(body, &TransformOptions::default())
+    }
+
+    #[test]
+    fn test_supported_markdown_is_untouched() {
+        let body = "## 見出し\n\n**太字** と ~~取り消し~~ と [リンク](https://example.com/a_b_c)\n\n- item\n1. one\n> quote\n\n---\n\n***\n";
+        let result = run(body);
+        assert_eq!(result.body, body.trim_end());
+        assert!(result.changes.is_empty());
+    }
+
+    #[test]
+    fn test_italic_becomes_plain_text() {
+        let result = run("これは *強調* と _強調_ です。snake_case_name はそのまま");
+        assert_eq!(
+            result.body,
+            "これは 強調 と 強調 です。snake_case_name はそのまま"
+        );
+        assert_eq!(result.changes.len(), 2);
+        assert_eq!(result.changes[0].kind, ChangeKind::Italic);
+    }
+
+    #[test]
+    fn test_bold_with_nested_italic() {
+        let result = run("**太字 *斜体* 太字**");
+        assert_eq!(result.body, "**太字 斜体 太字**");
+    }
+
+    #[test]
+    fn test_inline_code_styles() {
+        let body = "`cargo build` を実行";
+        assert_eq!(run(body).body, "**cargo build** を実行");
+
+        let quote = TransformOptions {
+            inline_code: InlineCodeStyle::Quote,
+            ..Default::default()
+        };
+        assert_eq!(transform(body, &quote).body, "「cargo build」 を実行");
+
+        let change = &run(body).changes[0];
+        assert_eq!(change.kind, ChangeKind::InlineCode);
+        assert_eq!(change.before, "`cargo build`");
+    }
+
+    #[test]
+    fn test_code_block_is_untouched() {
+        let body = "```rust\nlet x = `a` * b * c;\n# not a heading\n```\n後の `code`";
+        let result = run(body);
+        assert!(result
+            .body
+            .starts_with("```rust\nlet x = `a` * b * c;\n# not a heading\n```\n"));
+        assert_eq!(result.changes.len(), 1);
+        assert_eq!(result.changes[0].line, 5);
+    }
+
+    #[test]
+    fn test_headings_are_clamped() {
+        let result = run("# Top\n\n#### Deep\n\n#hashtag");
+        assert_eq!(result.body, "## Top\n\n### Deep\n\n#hashtag");
+        let lines: Vec<usize> = result.changes.iter().map(|c| c.line).collect();
+        assert_eq!(lines, vec![1, 3]);
+    }
+
+    #[test]
+    fn test_table_as_code_block() {
+        let body = "前\n\n| 名前 | 値 |\n|:---|---:|\n| `a` | 1 |\n| 長い名前 | 22 |\n\n後";
+        let result = run(body);
+        assert_eq!(
+            result.body,
+            "前\n\n```\n名前     | 値\n---------+---\na        | 1\n長い名前 | 22\n```\n\n後"
+        );
+        assert_eq!(result.changes.len(), 1);
+        assert_eq!(result.changes[0].kind, ChangeKind::Table);
+        assert_eq!(result.changes[0].line, 3);
+    }
+
+    #[test]
+    fn test_table_as_list() {
+        let options = TransformOptions {
+            table: TableStyle::List,
+            ..Default::default()
+        };
+        let result = transform("| 名前 | 値 |\n|---|---|\n| a | 1 |\n| b | 2 |", &options);
+        assert_eq!(result.body, "- 名前: a / 値: 1\n- 名前: b / 値: 2");
+    }
+
+    #[test]
+    fn test_remote_image_becomes_link() {
+        let result = run("![図](https://example.com/a.png)\n\n![](./images/local.png)\n\n![](https://example.com/b.png)");
+        assert_eq!(
+            result.body,
+            "[図](https://example.com/a.png)\n\n![](./images/local.png)\n\nhttps://example.com/b.png"
+        );
+        assert_eq!(result.changes.len(), 2);
+    }
+
+    #[test]
+    fn test_escapes_are_removed() {
+        let result = run("2\\*3 と \\\\ と \\`a\\` と C:\\Users\n\n\\# 見出しではない\n\n1\\. 番号でもない\n\n\\#タグ\n\n```\n\\* code\n```");
+        assert_eq!(
+            result.body,
+            "2*3 と \\ と `a` と C:\\Users\n\n# 見出しではない\n\n1. 番号でもない\n\n#タグ\n\n```\n\\* code\n```"
+        );
+        let escapes: Vec<usize> = result
+            .changes
+            .iter()
+            .filter(|c| c.kind == ChangeKind::Escape)
+            .map(|c| c.line)
+            .collect();
+        assert_eq!(escapes, vec![3, 5]);
+    }
+
+    #[test]
+    fn test_nested_lists() {
+        let result = run("- item\n  - nested *斜体*\n    1. deeper `code`\n- last");
+        assert_eq!(
+            result.body,
+            "- item\n  - nested 斜体\n    1. deeper **code**\n- last"
+        );
+        let kinds: Vec<(usize, ChangeKind)> =
+            result.changes.iter().map(|c| (c.line, c.kind)).collect();
+        assert_eq!(
+            kinds,
+            vec![(2, ChangeKind::Italic), (3, ChangeKind::InlineCode)]
+        );
+    }
+
+    #[test]
+    fn test_table_inside_blockquote() {
+        let result = run("> 前\n>\n> | a | b |\n> |---|---|\n> | 1 | 2 |\n\n後");
+        assert_eq!(
+            result.body,
+            "> 前\n>\n> ```\n> a | b\n> --+--\n> 1 | 2\n> ```\n\n後"
+        );
+        assert_eq!(result.changes.len(), 1);
+        assert_eq!(result.changes[0].line, 3);
+    }
+
+    #[test]
+    fn test_indented_code_fence_is_untouched() {
+        let body = "- 手順\n\n  ```sh\n  echo *x* `c`\n  # comment\n  ```\n\n  *後*";
+        let result = run(body);
+        assert_eq!(
+            result.body,
+            "- 手順\n\n  ```sh\n  echo *x* `c`\n  # comment\n  ```\n\n  後"
+        );
+        assert_eq!(result.changes.len(), 1);
+        assert_eq!(result.changes[0].line, 8);
+    }
+
+    #[test]
+    fn test_headings_and_images() {
+        let body =
+            "# T\n\nSetext\n---\n\n```\n# code\n![](a.png)\n```\n\n| ![表](b.png) |\n|---|\n| x |";
+        assert_eq!(
+            headings(body),
+            vec![("T".to_string(), 1), ("Setext".to_string(), 2)]
+        );
+        assert_eq!(
+            images(body),
+            vec![ImageRef {
+                line: 11,
+                alt: "表".to_string(),
+                url: "b.png".to_string(),
+            }]
+        );
+    }
+}
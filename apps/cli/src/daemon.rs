@@ -0,0 +1,698 @@
+//! Background daemon that owns the WebSocket connection to the browser extension
+//!
+//! The daemon listens on localhost for the extension, and on a Unix domain socket
+//! for noet commands. Commands send the same JSON requests as the WebSocket protocol,
+//! one per line; the daemon forwards them to the extension and routes each response
+//! back by its `id`, so several commands can share the connection at once.
+//! It is started on first use and exits after a while without clients. Port and
+//! connect timeout come from the global config, read when the daemon starts.
+//!
+//! The extension can also reach the daemon through the Native Messaging host,
+//! which connects to the command socket and says [`ATTACH_COMMAND`] first. That
+//! works without the WebSocket port, so the daemon keeps running if it can't listen.
+
+use crate::bridge::{self, Disconnect};
+use crate::config::{self, Settings};
+use crate::error::{NoetError, Result};
+use crate::extension_auth::{self, AcceptError, Policy};
+use crate::protocol::{Request, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::fs::TryLockError;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tokio::sync::{mpsc, watch, Mutex, Notify};
+use tokio::time::{sleep, timeout};
+
+const SOCKET_FILE: &str = "daemon.sock";
+/// Held by the running daemon, so two starting at once can't both take the socket
+const LOCK_FILE: &str = "daemon.lock";
+const LOG_FILE: &str = "daemon.log";
+
+/// The daemon exits after this long without clients or requests in flight
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// How long a command waits for a freshly spawned daemon to listen
+const SPAWN_WAIT: Duration = Duration::from_secs(5);
+
+/// Commands answered by the daemon itself instead of the extension
+pub const STATUS_COMMAND: &str = "daemon_status";
+pub const STOP_COMMAND: &str = "daemon_stop";
+
+/// First line of a Native Messaging host relaying the extension over the command socket
+pub const ATTACH_COMMAND: &str = "attach_extension";
+
+/// Reply to `daemon_status`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DaemonStatus {
+    pub pid: u32,
+    /// Port the extension connects to, if the daemon could listen
+    pub port: Option<u16>,
+    pub uptime_secs: u64,
+    pub extension_connected: bool,
+    pub clients: usize,
+    pub pending: usize,
+    pub requests: u64,
+}
+
+/// Path of the socket commands connect to
+pub fn socket_path() -> Result<PathBuf> {
+    let dir = match dirs::runtime_dir() {
+        Some(dir) => dir.join("noet"),
+        None => config::global_config_dir()?,
+    };
+    Ok(dir.join(SOCKET_FILE))
+}
+
+/// Take the daemon lock next to the socket, or fail if another daemon holds it.
+/// The lock lasts as long as the returned file is open.
+fn lock(socket_path: &Path) -> Result<fs::File> {
+    let path = socket_path.with_file_name(LOCK_FILE);
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(TryLockError::WouldBlock) => Err(NoetError::Network(
+            "noet デーモンは既に起動しています".to_string(),
+        )),
+        Err(TryLockError::Error(e)) => Err(e.into()),
+    }
+}
+
+/// Where a spawned daemon writes its output
+pub fn log_path() -> Result<PathBuf> {
+    Ok(config::global_config_dir()?.join(LOG_FILE))
+}
+
+/// Connect to a running daemon
+pub async fn connect() -> Result<Option<UnixStream>> {
+    match UnixStream::connect(socket_path()?).await {
+        Ok(stream) => Ok(Some(stream)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Connect to the daemon, starting it first if needed
+pub async fn connect_or_spawn() -> Result<UnixStream> {
+    if let Some(stream) = connect().await? {
+        return Ok(stream);
+    }
+
+    spawn()?;
+
+    let started = Instant::now();
+    while started.elapsed() < SPAWN_WAIT {
+        sleep(Duration::from_millis(50)).await;
+        if let Some(stream) = connect().await? {
+            return Ok(stream);
+        }
+    }
+
+    Err(NoetError::Network(format!(
+        "noet デーモンを起動できませんでした。ログを確認してください: {}",
+        log_path()?.display()
+    )))
+}
+
+/// Start `noet daemon run` detached from this terminal
+fn spawn() -> Result<()> {
+    use std::os::unix::process::CommandExt;
+
+    let log_path = log_path()?;
+    if let Some(dir) = log_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let log = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)?;
+
+    log::debug!("Starting daemon, logging to {}", log_path.display());
+    Command::new(std::env::current_exe()?)
+        .args(["daemon", "run"])
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log)
+        // Keep Ctrl-C in the terminal from reaching the daemon
+        .process_group(0)
+        .spawn()?;
+
+    Ok(())
+}
+
+/// Writer of an extension connection, numbered so replies can be failed when it goes away
+#[derive(Clone)]
+struct Extension {
+    connection: u64,
+    writer: mpsc::Sender<String>,
+    /// Through the Native Messaging host rather than the WebSocket
+    relayed: bool,
+}
+
+/// A forwarded request waiting for the extension's reply
+struct Pending {
+    client: mpsc::Sender<String>,
+    /// Number of the client connection that sent it
+    origin: u64,
+    /// Number of the extension connection it went out on
+    connection: u64,
+}
+
+/// State shared by the connection tasks
+struct Shared {
+    /// The current extension connection
+    extension: watch::Sender<Option<Extension>>,
+    /// Request id → the client that sent it
+    pending: Mutex<HashMap<String, Pending>>,
+    connections: AtomicU64,
+    /// Numbers of the connected noet commands. Locked before `pending`.
+    clients: Mutex<HashSet<u64>>,
+    requests: AtomicU64,
+    /// Why the last extension connection was refused, for commands left waiting
+    last_rejection: Mutex<Option<String>>,
+    last_activity: Mutex<Instant>,
+    /// How long a request waits for the extension to connect
+    connect_timeout: Duration,
+    port: Option<u16>,
+    started: Instant,
+    shutdown: Notify,
+}
+
+impl Shared {
+    fn new(connect_timeout: Duration, port: Option<u16>) -> Self {
+        Self {
+            extension: watch::channel(None).0,
+            pending: Mutex::new(HashMap::new()),
+            connections: AtomicU64::new(0),
+            clients: Mutex::new(HashSet::new()),
+            requests: AtomicU64::new(0),
+            last_rejection: Mutex::new(None),
+            last_activity: Mutex::new(Instant::now()),
+            connect_timeout,
+            port,
+            started: Instant::now(),
+            shutdown: Notify::new(),
+        }
+    }
+
+    async fn touch(&self) {
+        *self.last_activity.lock().await = Instant::now();
+    }
+
+    async fn status(&self) -> DaemonStatus {
+        let extension_connected = self.extension.borrow().is_some();
+        DaemonStatus {
+            pid: std::process::id(),
+            port: self.port,
+            uptime_secs: self.started.elapsed().as_secs(),
+            extension_connected,
+            clients: self.clients.lock().await.len(),
+            pending: self.pending.lock().await.len(),
+            requests: self.requests.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Whether a command is connected or a request is still on its way
+    async fn busy(&self) -> bool {
+        !self.clients.lock().await.is_empty() || !self.pending.lock().await.is_empty()
+    }
+}
+
+/// Run the daemon in the foreground until stopped or idle
+pub async fn run() -> Result<()> {
+    let settings = Settings::load_global()?;
+
+    let socket_path = socket_path()?;
+    if let Some(dir) = socket_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // Held until the daemon exits; probing and replacing the socket happen under it
+    let _lock = lock(&socket_path)?;
+    if UnixStream::connect(&socket_path).await.is_ok() {
+        return Err(NoetError::Network(
+            "noet デーモンは既に起動しています".to_string(),
+        ));
+    }
+    // Left behind by a daemon that didn't shut down cleanly
+    let _ = fs::remove_file(&socket_path);
+    let commands = UnixListener::bind(&socket_path)?;
+
+    let websocket = match bridge::bind(settings.bridge_port).await {
+        Ok(listener) => Some(listener),
+        Err(e) => {
+            log::warn!("{e}; only the Native Messaging host can connect");
+            None
+        }
+    };
+    let port = match &websocket {
+        Some(listener) => Some(listener.local_addr()?.port()),
+        None => None,
+    };
+    let published = port.map(bridge::publish).transpose()?;
+
+    match port {
+        Some(port) => log::info!(
+            "Listening on ws://127.0.0.1:{port} and {}",
+            socket_path.display()
+        ),
+        None => log::info!("Listening on {}", socket_path.display()),
+    }
+
+    let shared = Arc::new(Shared::new(
+        Duration::from_secs(settings.connect_timeout),
+        port,
+    ));
+
+    tokio::spawn(watch_idle(shared.clone()));
+
+    loop {
+        tokio::select! {
+            accepted = accept_websocket(websocket.as_ref()) => match accepted {
+                Ok((stream, addr)) => {
+                    log::debug!("Connection from {addr}");
+                    tokio::spawn(serve_extension(shared.clone(), stream));
+                }
+                Err(e) => log::warn!("Failed to accept extension connection: {e}"),
+            },
+            accepted = commands.accept() => match accepted {
+                Ok((stream, _)) => {
+                    tokio::spawn(serve_client(shared.clone(), stream));
+                }
+                Err(e) => log::warn!("Failed to accept client connection: {e}"),
+            },
+            _ = shared.shutdown.notified() => break,
+        }
+    }
+
+    let _ = fs::remove_file(&socket_path);
+    drop(published);
+    log::info!("Daemon stopped");
+    Ok(())
+}
+
+async fn accept_websocket(
+    listener: Option<&TcpListener>,
+) -> std::io::Result<(TcpStream, std::net::SocketAddr)> {
+    match listener {
+        Some(listener) => listener.accept().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn watch_idle(shared: Arc<Shared>) {
+    loop {
+        sleep(Duration::from_secs(10)).await;
+        if !shared.busy().await && shared.last_activity.lock().await.elapsed() >= IDLE_TIMEOUT {
+            log::info!("Idle for {}s, shutting down", IDLE_TIMEOUT.as_secs());
+            shared.shutdown.notify_one();
+            return;
+        }
+    }
+}
+
+/// Relay one extension connection. A newer connection replaces it.
+async fn serve_extension(shared: Arc<Shared>, stream: TcpStream) {
+    // Re-read every time so a new token from `noet setup` applies right away
+    let policy = match Policy::load() {
+        Ok(policy) => policy,
+        Err(e) => {
+            log::warn!("Could not load the extension token: {e}");
+            return;
+        }
+    };
+    let ws_stream = match extension_auth::accept(stream, &policy).await {
+        Ok(ws_stream) => ws_stream,
+        Err(AcceptError::Rejected(reason)) => {
+            log::warn!("Rejected connection: {reason}");
+            *shared.last_rejection.lock().await = Some(reason);
+            return;
+        }
+        Err(AcceptError::Handshake(e)) => {
+            log::warn!("WebSocket handshake failed: {e}");
+            return;
+        }
+    };
+    // The extension stops using the WebSocket once the host relays for it
+    if shared
+        .extension
+        .borrow()
+        .as_ref()
+        .is_some_and(|e| e.relayed)
+    {
+        log::info!(
+            "Extension is connected through the Native Messaging host, closing the WebSocket"
+        );
+        return;
+    }
+    log::info!("Extension connected");
+
+    let (tx, outgoing) = mpsc::channel::<String>(32);
+    let (incoming_tx, incoming) = mpsc::channel::<String>(32);
+    let pump = tokio::spawn(bridge::pump(ws_stream, outgoing, incoming_tx));
+    let connection = route_replies(&shared, tx, incoming, false).await;
+
+    let reason = match pump.await {
+        Ok(reason) => reason,
+        Err(e) => Disconnect::Error(e.to_string()),
+    };
+    detach(&shared, connection, reason).await;
+}
+
+/// Relay the extension through a Native Messaging host, one message per line
+async fn serve_host(
+    shared: Arc<Shared>,
+    mut lines: Lines<BufReader<OwnedReadHalf>>,
+    write: OwnedWriteHalf,
+) {
+    log::info!("Extension connected through the Native Messaging host");
+
+    let (tx, outgoing) = mpsc::channel::<String>(32);
+    let (incoming_tx, incoming) = mpsc::channel::<String>(32);
+    // Closes the socket once the connection is replaced or forgotten
+    tokio::spawn(write_lines(write, outgoing));
+    tokio::spawn(async move {
+        while let Ok(Some(line)) = lines.next_line().await {
+            if incoming_tx.send(line).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let connection = route_replies(&shared, tx, incoming, true).await;
+    detach(&shared, connection, Disconnect::Closed).await;
+}
+
+/// Make `writer` the extension connection and pass the replies it brings to
+/// the clients waiting for them, until it closes. Returns its number.
+async fn route_replies(
+    shared: &Shared,
+    writer: mpsc::Sender<String>,
+    mut incoming: mpsc::Receiver<String>,
+    relayed: bool,
+) -> u64 {
+    *shared.last_rejection.lock().await = None;
+    let connection = shared.connections.fetch_add(1, Ordering::Relaxed);
+    // Dropping the previous writer closes the connection it replaces
+    shared.extension.send_replace(Some(Extension {
+        connection,
+        writer,
+        relayed,
+    }));
+
+    while let Some(text) = incoming.recv().await {
+        let Some(id) = message_id(&text) else {
+            continue;
+        };
+        let pending = shared.pending.lock().await.remove(&id);
+        if let Some(pending) = pending {
+            let _ = pending.client.send(text).await;
+        }
+    }
+    connection
+}
+
+/// Forget a closed extension connection and fail the requests sent over it
+async fn detach(shared: &Shared, connection: u64, reason: Disconnect) {
+    log::info!("Extension disconnected: {reason:?}");
+    shared.extension.send_if_modified(|current| {
+        // Only forget it if it hasn't been replaced already
+        if current.as_ref().is_some_and(|e| e.connection == connection) {
+            *current = None;
+            true
+        } else {
+            false
+        }
+    });
+
+    // Requests sent over this connection will never be answered
+    let lost: Vec<(String, Pending)> = {
+        let mut pending = shared.pending.lock().await;
+        let ids: Vec<String> = pending
+            .iter()
+            .filter(|(_, p)| p.connection == connection)
+            .map(|(id, _)| id.clone())
+            .collect();
+        ids.into_iter()
+            .filter_map(|id| pending.remove(&id).map(|p| (id, p)))
+            .collect()
+    };
+    for (id, pending) in lost {
+        let reply = error(
+            &id,
+            bridge::DISCONNECTED,
+            &format!("拡張機能との接続が切れました ({reason})"),
+        );
+        let _ = pending.client.send(reply).await;
+    }
+}
+
+/// Write each message as a line until the channel closes
+async fn write_lines(mut write: OwnedWriteHalf, mut messages: mpsc::Receiver<String>) {
+    while let Some(mut msg) = messages.recv().await {
+        msg.push('\n');
+        if write.write_all(msg.as_bytes()).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Handle one noet command connection, or a Native Messaging host attaching
+async fn serve_client(shared: Arc<Shared>, stream: UnixStream) {
+    let (read, write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    let Ok(Some(first)) = lines.next_line().await else {
+        return;
+    };
+    if serde_json::from_str::<Request>(&first).is_ok_and(|r| r.command == ATTACH_COMMAND) {
+        serve_host(shared, lines, write).await;
+        return;
+    }
+
+    let origin = shared.connections.fetch_add(1, Ordering::Relaxed);
+    shared.clients.lock().await.insert(origin);
+    shared.touch().await;
+
+    let (tx, rx) = mpsc::channel::<String>(32);
+    let writer = tokio::spawn(write_lines(write, rx));
+
+    let mut line = Some(first);
+    while let Some(text) = line {
+        if !text.trim().is_empty() {
+            shared.touch().await;
+            shared.requests.fetch_add(1, Ordering::Relaxed);
+            tokio::spawn(handle_request(shared.clone(), text, origin, tx.clone()));
+        }
+        line = lines.next_line().await.ok().flatten();
+    }
+
+    // Nobody is left to read the replies to its requests
+    {
+        let mut clients = shared.clients.lock().await;
+        clients.remove(&origin);
+        shared
+            .pending
+            .lock()
+            .await
+            .retain(|_, p| p.origin != origin);
+    }
+    drop(tx);
+    let _ = writer.await;
+    shared.touch().await;
+}
+
+async fn handle_request(
+    shared: Arc<Shared>,
+    line: String,
+    origin: u64,
+    client: mpsc::Sender<String>,
+) {
+    let request: Request = match serde_json::from_str(&line) {
+        Ok(request) => request,
+        Err(e) => {
+            log::warn!("Invalid request from client: {e}");
+            let _ = client.send(invalid_request(&line, &e)).await;
+            return;
+        }
+    };
+    let id = request.id;
+
+    let reply = match request.command.as_str() {
+        STATUS_COMMAND => success(&id, json!(shared.status().await)),
+        STOP_COMMAND => {
+            log::info!("Stop requested");
+            shared.shutdown.notify_one();
+            success(&id, json!({}))
+        }
+        _ => match forward(&shared, &id, line, origin, client.clone()).await {
+            Ok(()) => return,
+            Err((code, message)) => error(&id, code, &message),
+        },
+    };
+
+    let _ = client.send(reply).await;
+}
+
+/// Send a request to the extension, waiting for it to connect if needed
+async fn forward(
+    shared: &Shared,
+    id: &str,
+    line: String,
+    origin: u64,
+    client: mpsc::Sender<String>,
+) -> std::result::Result<(), (&'static str, String)> {
+    let mut extension = shared.extension.subscribe();
+    let current = match timeout(shared.connect_timeout, extension.wait_for(Option::is_some)).await {
+        Ok(Ok(current)) => current.clone(),
+        Ok(Err(_)) => return Err((bridge::NOT_CONNECTED, "デーモンが終了しました".to_string())),
+        Err(_) => None,
+    };
+    let Some(Extension {
+        connection, writer, ..
+    }) = current
+    else {
+        return Err(match shared.last_rejection.lock().await.clone() {
+            Some(reason) => (bridge::UNAUTHORIZED, reason),
+            None => (
+                bridge::NOT_CONNECTED,
+                format!(
+                    "{} 秒待ちましたが拡張機能が接続しませんでした",
+                    shared.connect_timeout.as_secs()
+                ),
+            ),
+        });
+    };
+
+    {
+        // The client may have gone while the extension was connecting
+        let clients = shared.clients.lock().await;
+        if !clients.contains(&origin) {
+            return Ok(());
+        }
+        shared.pending.lock().await.insert(
+            id.to_string(),
+            Pending {
+                client,
+                origin,
+                connection,
+            },
+        );
+    }
+    if writer.send(line).await.is_err() {
+        shared.pending.lock().await.remove(id);
+        return Err((
+            bridge::DISCONNECTED,
+            "拡張機能との接続が切れました".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn message_id(text: &str) -> Option<String> {
+    let value: Value = serde_json::from_str(text).ok()?;
+    value.get("id")?.as_str().map(String::from)
+}
+
+/// Answer a line that isn't a request, so the client doesn't wait for its timeout
+fn invalid_request(line: &str, e: &serde_json::Error) -> String {
+    let id = message_id(line).unwrap_or_default();
+    error(
+        &id,
+        "INVALID_PARAMS",
+        &format!("リクエストを解釈できません: {e}"),
+    )
+}
+
+fn success(id: &str, data: Value) -> String {
+    serde_json::to_string(&Response::success(id, data)).unwrap_or_default()
+}
+
+fn error(id: &str, code: &str, message: &str) -> String {
+    serde_json::to_string(&Response::error(id, code, message)).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_id() {
+        assert_eq!(
+            message_id(r#"{"id":"abc","status":"success"}"#).as_deref(),
+            Some("abc")
+        );
+        assert_eq!(message_id(r#"{"status":"success"}"#), None);
+        assert_eq!(message_id("not json"), None);
+    }
+
+    #[test]
+    fn test_invalid_requests_get_an_answer() {
+        let line = r#"{"id":"abc","params":{}}"#;
+        let e = serde_json::from_str::<Request>(line).unwrap_err();
+        let reply: Value = serde_json::from_str(&invalid_request(line, &e)).unwrap();
+        assert_eq!(reply["id"], "abc");
+        assert_eq!(reply["error"]["code"], "INVALID_PARAMS");
+    }
+
+    #[test]
+    fn test_only_one_daemon_holds_the_lock() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let socket = dir.path().join(SOCKET_FILE);
+
+        let held = lock(&socket).unwrap();
+        assert!(lock(&socket).is_err());
+        drop(held);
+        assert!(lock(&socket).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_requests_of_a_client_that_left_are_forgotten() {
+        let shared = Arc::new(Shared::new(Duration::from_secs(5), None));
+        let (writer, mut sent) = mpsc::channel(32);
+        shared.extension.send_replace(Some(Extension {
+            connection: 0,
+            writer,
+            relayed: false,
+        }));
+
+        let (client, stream) = UnixStream::pair().unwrap();
+        let serving = tokio::spawn(serve_client(shared.clone(), stream));
+        let (read, mut write) = client.into_split();
+        write
+            .write_all(b"{\"id\":\"1\",\"command\":\"list_articles\"}\n")
+            .await
+            .unwrap();
+
+        // The extension gets the request but never answers
+        sent.recv().await.unwrap();
+        assert_eq!(shared.status().await.pending, 1);
+
+        drop((read, write));
+        timeout(Duration::from_secs(5), serving)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!shared.busy().await);
+    }
+
+    #[test]
+    fn test_replies_match_the_response_envelope() {
+        let reply: Value = serde_json::from_str(&error("1", "NOT_CONNECTED", "x")).unwrap();
+        assert_eq!(reply["status"], "error");
+        assert_eq!(reply["error"]["code"], "NOT_CONNECTED");
+
+        let reply: Value = serde_json::from_str(&success("1", json!({"a": 1}))).unwrap();
+        assert_eq!(reply["id"], "1");
+        assert_eq!(reply["data"]["a"], 1);
+    }
+}
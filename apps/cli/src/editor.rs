@@ -1,12 +1,17 @@
+use crate::config::Settings;
 use crate::error::{NoetError, Result};
 use std::env;
 use std::path::Path;
 use std::process::Command;
 
-/// Get the editor command from environment variables or platform default
-#[allow(dead_code)]
+/// Get the editor command from config, environment variables or platform default
 pub fn get_editor() -> Result<String> {
-    // 1. Check environment variables
+    // 1. Check config (`editor` key or $NOET_EDITOR)
+    if let Some(editor) = Settings::load()?.editor {
+        return Ok(editor);
+    }
+
+    // 2. Check environment variables
     if let Ok(editor) = env::var("VISUAL") {
         return Ok(editor);
     }
@@ -14,7 +19,7 @@ pub fn get_editor() -> Result<String> {
         return Ok(editor);
     }
 
-    // 2. Platform defaults
+    // 3. Platform defaults
     #[cfg(target_os = "windows")]
     return Ok("notepad".to_string());
 
@@ -26,7 +31,6 @@ pub fn get_editor() -> Result<String> {
 }
 
 /// Parse editor command handling quoted arguments
-#[allow(dead_code)]
 fn parse_editor_command(cmd: &str) -> Vec<String> {
     let mut args = Vec::new();
     let mut current_arg = String::new();
@@ -57,7 +61,6 @@ fn parse_editor_command(cmd: &str) -> Vec<String> {
 }
 
 /// Open a file in the configured editor
-#[allow(dead_code)]
 pub fn open_in_editor<P: AsRef<Path>>(filepath: P) -> Result<()> {
     let editor_cmd = get_editor()?;
     let parts = parse_editor_command(&editor_cmd);
@@ -1,3 +1,4 @@
+use crate::bridge;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -14,6 +15,9 @@ pub enum NoetError {
     #[error("シリアライズエラー: {0}")]
     SerdeError(#[from] serde_json::Error),
 
+    #[error("YAMLエラー: {0}")]
+    YamlError(#[from] serde_yaml::Error),
+
     #[error("対話型入力エラー: {0}")]
     DialoguerError(#[from] dialoguer::Error),
 
@@ -23,8 +27,151 @@ pub enum NoetError {
     #[error("拡張機能エラー: {0}")]
     Extension(String),
 
+    /// The connection to the extension dropped before it answered
+    #[error("接続エラー: {0}")]
+    ExtensionDisconnected(String),
+
+    /// The extension never connected
+    #[error("拡張機能に接続できません: {message}")]
+    ExtensionNotFound { message: String },
+
+    /// The extension connected without a valid token or origin
+    #[error("拡張機能の認証に失敗しました: {message}")]
+    Unauthorized { message: String },
+
+    #[error("拡張機能のバージョンが一致しません: {message}")]
+    VersionMismatch { message: String },
+
+    #[error("タイムアウト: {message}")]
+    Timeout { message: String },
+
+    #[error("note.com にログインしていません: {message}")]
+    NotLoggedIn { message: String },
+
+    #[error("見つかりません: {message}")]
+    NotFound { message: String },
+
+    #[error("権限がありません: {message}")]
+    PermissionDenied { message: String },
+
+    #[error("パラメータが不正です: {message}")]
+    InvalidParams { message: String },
+
+    /// The extension couldn't drive a note.com page
+    #[error("ページ操作に失敗しました: {message}")]
+    DomError { message: String },
+
     #[error("無効な入力: {0}")]
     InvalidInput(String),
+
+    #[error("テンプレートエラー: {0}")]
+    Template(String),
+
+    #[error("競合: {0}")]
+    Conflict(String),
+
+    /// A download didn't match its published checksum
+    #[error("整合性エラー: {0}")]
+    Integrity(String),
+
+    #[error("{0} 件の問題が見つかりました")]
+    LintFailed(usize),
+
+    /// `noet doctor` found problems
+    #[error("{0} 件のチェックに失敗しました")]
+    ChecksFailed(usize),
+
+    #[error("frontmatter エラー ({location}行目): {message}")]
+    Frontmatter { location: String, message: String },
+}
+
+impl NoetError {
+    /// Error for a code from the `error_codes` section of `protocol.yaml`
+    pub fn from_protocol(code: &str, message: String) -> Self {
+        match code {
+            "NOT_LOGGED_IN" => NoetError::NotLoggedIn { message },
+            "NOT_FOUND" => NoetError::NotFound { message },
+            "PERMISSION_DENIED" => NoetError::PermissionDenied { message },
+            "NETWORK_ERROR" => NoetError::Network(message),
+            "TIMEOUT" => NoetError::Timeout { message },
+            "INVALID_PARAMS" => NoetError::InvalidParams { message },
+            "VERSION_MISMATCH" => NoetError::VersionMismatch { message },
+            "DOM_ERROR" => NoetError::DomError { message },
+            bridge::DISCONNECTED => NoetError::ExtensionDisconnected(message),
+            bridge::NOT_CONNECTED | "EXTENSION_NOT_FOUND" => {
+                NoetError::ExtensionNotFound { message }
+            }
+            bridge::UNAUTHORIZED => NoetError::Unauthorized { message },
+            _ => NoetError::Extension(format!("{code}: {message}")),
+        }
+    }
+
+    /// Process exit code, so scripts can tell failures apart.
+    /// Keep in sync with the table in README.md.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            NoetError::IoError(_)
+            | NoetError::SerdeError(_)
+            | NoetError::YamlError(_)
+            | NoetError::DialoguerError(_) => 1,
+            NoetError::InvalidInput(_) => 2,
+            NoetError::ConfigError(_) => 3,
+            NoetError::FileNotFound(_) => 4,
+            NoetError::Template(_) => 5,
+            NoetError::Frontmatter { .. } => 6,
+            NoetError::LintFailed(_) => 7,
+            NoetError::Conflict(_) => 8,
+            NoetError::Integrity(_) => 9,
+            NoetError::Network(_) => 10,
+            NoetError::Timeout { .. } => 11,
+            NoetError::ExtensionDisconnected(_) => 12,
+            NoetError::ExtensionNotFound { .. } => 13,
+            NoetError::Unauthorized { .. } => 14,
+            NoetError::VersionMismatch { .. } => 15,
+            NoetError::ChecksFailed(_) => 16,
+            NoetError::NotLoggedIn { .. } => 20,
+            NoetError::NotFound { .. } => 21,
+            NoetError::PermissionDenied { .. } => 22,
+            NoetError::InvalidParams { .. } => 23,
+            NoetError::DomError { .. } => 24,
+            NoetError::Extension(_) => 25,
+        }
+    }
+
+    /// What the user can do about it
+    pub fn hint(&self) -> Option<&'static str> {
+        let hint = match self {
+            NoetError::Timeout { .. } => {
+                "`noet config set command_timeout <秒>` で待ち時間を延長できます"
+            }
+            NoetError::ExtensionDisconnected(_) => {
+                "ブラウザが起動していて拡張機能が有効になっているか確認してください"
+            }
+            NoetError::ExtensionNotFound { .. } => {
+                "ブラウザを起動し、拡張機能がインストールされているか確認してください。未インストールなら `noet setup` を実行してください"
+            }
+            NoetError::Unauthorized { .. } => {
+                "拡張機能のオプションページでトークンを設定し直してください。トークンがなければ `noet setup` で作成されます"
+            }
+            NoetError::VersionMismatch { .. } => {
+                "noet と同じバージョンの拡張機能が必要です。`noet setup --upgrade` で入れ替えられます"
+            }
+            NoetError::NotLoggedIn { .. } => {
+                "ブラウザで https://note.com/login を開いてログインしてください"
+            }
+            NoetError::NotFound { .. } => {
+                "記事キーとユーザー名を確認してください。`noet list` で自分の記事を一覧できます"
+            }
+            NoetError::PermissionDenied { .. } => {
+                "ログイン中のアカウントで操作できる記事か確認してください"
+            }
+            NoetError::DomError { .. } => {
+                "note.com の画面が変わった可能性があります。拡張機能を最新版に更新してください"
+            }
+            _ => return None,
+        };
+        Some(hint)
+    }
 }
 
 pub type Result<T> = std::result::Result<T, NoetError>;
@@ -51,6 +198,88 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_frontmatter_error_display() {
+        let error = NoetError::Frontmatter {
+            location: "article.md:3".to_string(),
+            message: "invalid type".to_string(),
+        };
+        assert_eq!(
+            error.to_string(),
+            "frontmatter エラー (article.md:3行目): invalid type"
+        );
+    }
+
+    #[test]
+    fn test_from_protocol() {
+        let error = NoetError::from_protocol("NOT_LOGGED_IN", "login required".to_string());
+        assert!(
+            matches!(error, NoetError::NotLoggedIn { ref message } if message == "login required")
+        );
+        assert!(error.hint().unwrap().contains("https://note.com/login"));
+
+        assert!(matches!(
+            NoetError::from_protocol("DISCONNECTED", String::new()),
+            NoetError::ExtensionDisconnected(_)
+        ));
+        assert_eq!(
+            NoetError::from_protocol("SOMETHING_NEW", "x".to_string()).to_string(),
+            "拡張機能エラー: SOMETHING_NEW: x"
+        );
+    }
+
+    #[test]
+    fn test_every_spec_error_code_has_a_variant() {
+        let spec: serde_yaml::Value =
+            serde_yaml::from_str(include_str!("../../../protocol.yaml")).unwrap();
+        let codes = spec["error_codes"].as_mapping().unwrap();
+        assert!(codes.len() > 10);
+
+        for code in codes.keys().filter_map(|k| k.as_str()) {
+            let error = NoetError::from_protocol(code, String::new());
+            match code {
+                "UNKNOWN" => assert!(matches!(error, NoetError::Extension(_))),
+                _ => assert!(!matches!(error, NoetError::Extension(_)), "{code}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_exit_codes_are_distinct() {
+        let text = || String::new();
+        let errors = [
+            NoetError::InvalidInput(text()),
+            NoetError::ConfigError(text()),
+            NoetError::FileNotFound(text()),
+            NoetError::Template(text()),
+            NoetError::Frontmatter {
+                location: text(),
+                message: text(),
+            },
+            NoetError::LintFailed(1),
+            NoetError::Conflict(text()),
+            NoetError::Integrity(text()),
+            NoetError::Network(text()),
+            NoetError::Timeout { message: text() },
+            NoetError::ExtensionDisconnected(text()),
+            NoetError::ExtensionNotFound { message: text() },
+            NoetError::Unauthorized { message: text() },
+            NoetError::VersionMismatch { message: text() },
+            NoetError::ChecksFailed(1),
+            NoetError::NotLoggedIn { message: text() },
+            NoetError::NotFound { message: text() },
+            NoetError::PermissionDenied { message: text() },
+            NoetError::InvalidParams { message: text() },
+            NoetError::DomError { message: text() },
+            NoetError::Extension(text()),
+        ];
+        let mut exit_codes: Vec<i32> = errors.iter().map(NoetError::exit_code).collect();
+        exit_codes.sort_unstable();
+        exit_codes.dedup();
+        assert_eq!(exit_codes.len(), errors.len());
+        assert!(!exit_codes.contains(&0) && !exit_codes.contains(&1));
+    }
+
     #[test]
     fn test_extension_error_display() {
         let error = NoetError::Extension("接続に失敗しました".to_string());
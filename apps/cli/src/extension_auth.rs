@@ -0,0 +1,277 @@
+//! Authentication of the browser extension on the localhost WebSocket
+//!
+//! Any local process, or any web page open in the browser, can connect to localhost.
+//! The extension proves itself with a per-install token generated by `noet setup`,
+//! sent as `?token=` because browsers can't set headers on a WebSocket, and its
+//! `Origin` must be the installed extension.
+
+use crate::config::{self, Settings};
+use crate::error::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::{accept_hdr_async, WebSocketStream};
+
+const TOKEN_FILE: &str = "extension-token";
+
+/// Origin schemes used by extensions
+const EXTENSION_SCHEMES: [&str; 2] = ["chrome-extension://", "moz-extension://"];
+
+/// Path of the token shared with the extension
+pub fn token_path() -> Result<PathBuf> {
+    Ok(config::global_config_dir()?.join(TOKEN_FILE))
+}
+
+/// The token, if `noet setup` has generated one
+pub fn load_token() -> Result<Option<String>> {
+    let path = token_path()?;
+    if !path.exists() {
+        return Ok(None);
+    }
+    let token = fs::read_to_string(path)?.trim().to_string();
+    Ok(Some(token).filter(|t| !t.is_empty()))
+}
+
+/// The token, generated and saved on first use
+pub fn ensure_token() -> Result<String> {
+    if let Some(token) = load_token()? {
+        return Ok(token);
+    }
+
+    let path = token_path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let token = generate_token();
+    write_private(&path, &token)?;
+    Ok(token)
+}
+
+fn generate_token() -> String {
+    format!(
+        "{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    )
+}
+
+/// Write a file only the user can read, without it ever being readable by others
+#[cfg(unix)]
+fn write_private(path: &Path, contents: &str) -> Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    // The mode only applies to a new file; an empty one left behind keeps its own
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(contents.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, contents: &str) -> Result<()> {
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+/// What a connecting peer must present
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    pub token: Option<String>,
+    pub extension_id: Option<String>,
+}
+
+impl Policy {
+    /// Read the token and the extension ID from the global config
+    pub fn load() -> Result<Self> {
+        let settings = Settings::load_global()?;
+        Ok(Self {
+            token: load_token()?,
+            extension_id: settings.extension_id,
+        })
+    }
+
+    /// Check the `Origin` header and the query string of a handshake request
+    pub fn check(
+        &self,
+        origin: Option<&str>,
+        query: Option<&str>,
+    ) -> std::result::Result<(), String> {
+        let Some(expected) = &self.token else {
+            return Err("認証トークンがありません。`noet setup` を実行してください".to_string());
+        };
+
+        let origin = origin.unwrap_or_default().trim_end_matches('/');
+        let allowed = match &self.extension_id {
+            Some(id) => EXTENSION_SCHEMES
+                .iter()
+                .any(|scheme| origin.strip_prefix(scheme) == Some(id.as_str())),
+            None => EXTENSION_SCHEMES
+                .iter()
+                .any(|scheme| origin.starts_with(scheme)),
+        };
+        if !allowed {
+            let origin = if origin.is_empty() {
+                "(なし)"
+            } else {
+                origin
+            };
+            return Err(format!(
+                "拡張機能以外からの接続を拒否しました (Origin: {origin})"
+            ));
+        }
+
+        let token = query
+            .unwrap_or_default()
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("token="));
+        match token {
+            Some(token) if constant_time_eq(token.as_bytes(), expected.as_bytes()) => Ok(()),
+            Some(_) => Err(
+                "認証トークンが一致しません。拡張機能のオプションページでトークンを設定し直してください"
+                    .to_string(),
+            ),
+            None => Err(
+                "拡張機能が認証トークンを送信していません。拡張機能のオプションページでトークンを設定してください"
+                    .to_string(),
+            ),
+        }
+    }
+}
+
+/// Why a connection was not accepted
+#[derive(Debug)]
+pub enum AcceptError {
+    /// The peer failed the policy check
+    Rejected(String),
+    /// The peer didn't speak WebSocket
+    Handshake(String),
+}
+
+/// Complete the WebSocket handshake if the peer passes `policy`
+pub async fn accept<S>(
+    stream: S,
+    policy: &Policy,
+) -> std::result::Result<WebSocketStream<S>, AcceptError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut rejection = None;
+    // The callback's signature is fixed by tungstenite
+    #[allow(clippy::result_large_err)]
+    let callback = |request: &Request, response: Response| {
+        let origin = request
+            .headers()
+            .get("origin")
+            .and_then(|v| v.to_str().ok());
+        match policy.check(origin, request.uri().query()) {
+            Ok(()) => Ok(response),
+            Err(reason) => {
+                let mut error = ErrorResponse::new(Some(reason.clone()));
+                *error.status_mut() = StatusCode::FORBIDDEN;
+                rejection = Some(reason);
+                Err(error)
+            }
+        }
+    };
+
+    let result = accept_hdr_async(stream, callback).await;
+    result.map_err(|e| match rejection {
+        Some(reason) => AcceptError::Rejected(reason),
+        None => AcceptError::Handshake(e.to_string()),
+    })
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "abcdefghijklmnopabcdefghijklmnop";
+
+    fn policy(extension_id: Option<&str>) -> Policy {
+        Policy {
+            token: Some("secret".to_string()),
+            extension_id: extension_id.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_accepts_the_extension_with_the_token() {
+        let origin = format!("chrome-extension://{ID}");
+        assert!(policy(Some(ID))
+            .check(Some(&origin), Some("token=secret"))
+            .is_ok());
+        assert!(policy(None)
+            .check(Some("moz-extension://other/"), Some("a=1&token=secret"))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_rejects_other_origins() {
+        let policy = policy(Some(ID));
+        for origin in [
+            Some("https://evil.example"),
+            Some("chrome-extension://someoneelse"),
+            None,
+        ] {
+            assert!(policy.check(origin, Some("token=secret")).is_err());
+        }
+
+        // Without a configured ID any extension passes, but web pages don't
+        assert!(super::Policy {
+            extension_id: None,
+            ..policy
+        }
+        .check(Some("http://localhost:3000"), Some("token=secret"))
+        .is_err());
+    }
+
+    #[test]
+    fn test_rejects_missing_or_wrong_token() {
+        let origin = format!("chrome-extension://{ID}");
+        let policy = policy(Some(ID));
+        assert!(policy.check(Some(&origin), None).is_err());
+        assert!(policy.check(Some(&origin), Some("token=secreT")).is_err());
+        assert!(policy.check(Some(&origin), Some("token=secret2")).is_err());
+
+        let unconfigured = Policy::default();
+        assert!(unconfigured
+            .check(Some(&origin), Some("token=secret"))
+            .unwrap_err()
+            .contains("noet setup"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_token_file_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join(TOKEN_FILE);
+        fs::write(&path, "").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        write_private(&path, "secret").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "secret");
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn test_generated_tokens_differ() {
+        let token = generate_token();
+        assert_eq!(token.len(), 64);
+        assert_ne!(token, generate_token());
+    }
+}
@@ -1,382 +1,851 @@
 //! Extension client for communicating with browser extension via WebSocket
 //!
-//! The CLI starts a WebSocket server on localhost:9876
-//! The browser extension connects to it and executes commands
+//! A WebSocket server on localhost (see [`crate::bridge`]) waits for the browser
+//! extension to connect and run commands. On Unix the server lives in the `noet daemon`
+//! process and commands reach it through the daemon's socket; elsewhere each command
+//! starts the server itself.
+//!
+//! When the connection drops, requests in flight fail right away. Read-only commands
+//! are sent again once the extension is back, unless `reconnect` is turned off.
+//!
+//! Each connection starts with a `ping`, comparing the extension's protocol version
+//! with ours and learning which commands it handles.
+//!
+//! Images go ahead of the article that uses them, read and converted one at a time
+//! and sent in chunks of [`protocol::IMAGE_CHUNK_CHARS`] acknowledged one at a time,
+//! so neither memory nor any single message holds them all.
 
+use crate::bridge;
+use crate::config::Settings;
 use crate::error::{NoetError, Result};
-use futures_util::{SinkExt, StreamExt};
-use serde::{Deserialize, Serialize};
+use crate::image_handler::{ImageData, LocalImage};
+use crate::image_pipeline::{self, ImageOptions, ProcessedImage};
+use crate::protocol::{
+    self, Article, ArticleList, ArticleResult, AuthStatus, CheckAuth, Command, Compatibility,
+    CreateArticle, CreateArticleParams, DebugModeParams, DeleteArticle, DeleteResult, GetArticle,
+    GetArticleParams, KeyParams, ListArticles, NoParams, Ping, PingData, Request, Response,
+    SetDebugMode, Status, UpdateArticle, UpdateArticleParams, UploadImageChunk,
+    UploadImageChunkParams, PROTOCOL_VERSION,
+};
+use base64::{engine::general_purpose, Engine as _};
+use colored::Colorize;
+use serde_json::Value;
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::{mpsc, oneshot, Mutex};
 use tokio::time::timeout;
-use tokio_tungstenite::{accept_async, tungstenite::Message, WebSocketStream};
-use uuid::Uuid;
-
-const WEBSOCKET_PORT: u16 = 9876;
-const COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
-
-/// Request sent to extension
-#[derive(Debug, Clone, Serialize)]
-pub struct ExtensionRequest {
-    pub id: String,
-    pub command: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub params: Option<serde_json::Value>,
+
+#[cfg(unix)]
+use crate::daemon::{self, DaemonStatus};
+#[cfg(not(unix))]
+use crate::extension_auth::{self, AcceptError, Policy};
+#[cfg(unix)]
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+#[cfg(unix)]
+use tokio::net::UnixStream;
+#[cfg(not(unix))]
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(not(unix))]
+use tokio_tungstenite::WebSocketStream;
+
+/// How many times a command is resent after the connection drops
+const MAX_RETRIES: usize = 2;
+
+/// How many times an image chunk is resent when its acknowledgement doesn't come
+const MAX_CHUNK_RETRIES: usize = 3;
+
+/// How long to wait for a chunk to be acknowledged before resending it: a share
+/// of the command timeout, so a chunk with all its resends takes no longer than
+/// a command may
+fn chunk_timeout(command_timeout: Duration) -> Duration {
+    command_timeout / (MAX_CHUNK_RETRIES as u32 + 1)
 }
 
-/// Response from extension
-#[derive(Debug, Clone, Deserialize)]
-pub struct ExtensionResponse {
-    pub id: String,
-    pub status: String,
-    #[serde(default)]
-    pub data: Option<serde_json::Value>,
-    #[serde(default)]
-    pub error: Option<ExtensionError>,
+/// Answer for a request whose connection went away
+fn disconnected(id: String, message: &str) -> Response {
+    Response::error(id, bridge::DISCONNECTED, message)
 }
 
-#[derive(Debug, Clone, Deserialize)]
-pub struct ExtensionError {
-    pub code: String,
-    pub message: String,
+/// Pending request waiting for response
+type PendingRequest = oneshot::Sender<Response>;
+
+type PendingMap = Arc<Mutex<HashMap<String, PendingRequest>>>;
+
+/// One connection requests travel over
+struct Link {
+    /// Sender for outgoing messages, closed once the connection is gone
+    tx: mpsc::Sender<String>,
+    /// Pending requests waiting for responses
+    pending: PendingMap,
+}
+
+impl Link {
+    /// Link over a connection to the daemon
+    #[cfg(unix)]
+    fn daemon(stream: UnixStream) -> Self {
+        let (read, mut write) = stream.into_split();
+
+        let (tx, mut rx) = mpsc::channel::<String>(32);
+        let pending: PendingMap = Arc::new(Mutex::new(HashMap::new()));
+        let pending_clone = pending.clone();
+
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(read).lines();
+            loop {
+                tokio::select! {
+                    line = lines.next_line() => match line {
+                        Ok(Some(line)) => resolve(&pending_clone, &line).await,
+                        _ => break,
+                    },
+                    msg = rx.recv() => match msg {
+                        Some(mut msg) => {
+                            msg.push('\n');
+                            if write.write_all(msg.as_bytes()).await.is_err() {
+                                break;
+                            }
+                        }
+                        None => break,
+                    },
+                }
+            }
+            rx.close();
+            fail_pending(&pending_clone, "noet デーモンとの接続が切れました").await;
+        });
+
+        Self { tx, pending }
+    }
+
+    /// Link over a WebSocket connection from the extension
+    #[cfg(not(unix))]
+    fn websocket(ws_stream: WebSocketStream<TcpStream>) -> Self {
+        let (tx, outgoing) = mpsc::channel::<String>(32);
+        let (incoming_tx, mut incoming) = mpsc::channel::<String>(32);
+        let pending: PendingMap = Arc::new(Mutex::new(HashMap::new()));
+        let pending_clone = pending.clone();
+
+        let pump = tokio::spawn(bridge::pump(ws_stream, outgoing, incoming_tx));
+        tokio::spawn(async move {
+            while let Some(text) = incoming.recv().await {
+                resolve(&pending_clone, &text).await;
+            }
+            let reason = match pump.await {
+                Ok(reason) => reason.to_string(),
+                Err(e) => e.to_string(),
+            };
+            fail_pending(
+                &pending_clone,
+                &format!("拡張機能との接続が切れました ({reason})"),
+            )
+            .await;
+        });
+
+        Self { tx, pending }
+    }
 }
 
-/// Article data from extension
-#[derive(Debug, Clone, Deserialize)]
-pub struct ArticleData {
-    pub key: Option<String>,
-    pub title: String,
-    pub html: Option<String>,
-    pub tags: Option<Vec<String>>,
-    pub status: Option<String>,
-    pub date: Option<String>,
-    pub published_at: Option<String>,
+/// Hand a response to the request waiting for it
+async fn resolve(pending: &PendingMap, text: &str) {
+    if let Ok(response) = serde_json::from_str::<Response>(text) {
+        if let Some(sender) = pending.lock().await.remove(&response.id) {
+            let _ = sender.send(response);
+        }
+    }
 }
 
-/// Article list response
-#[derive(Debug, Clone, Deserialize)]
-pub struct ArticleListData {
-    pub articles: Vec<ArticleData>,
-    pub count: usize,
+/// Answer every request still waiting on a connection that has gone away
+async fn fail_pending(pending: &PendingMap, message: &str) {
+    for (id, sender) in pending.lock().await.drain() {
+        let _ = sender.send(disconnected(id, message));
+    }
 }
 
-/// Auth status response
-#[derive(Debug, Clone, Deserialize)]
-pub struct AuthStatusData {
-    pub logged_in: bool,
-    pub username: Option<String>,
+/// WebSocket server the extension connects to when there is no daemon
+#[cfg(not(unix))]
+struct Server {
+    listener: TcpListener,
+    policy: Policy,
+    connect_timeout: Duration,
+    /// Keeps the endpoint published while the server runs
+    _published: bridge::Published,
 }
 
-/// Pending request waiting for response
-type PendingRequest = oneshot::Sender<ExtensionResponse>;
+#[cfg(not(unix))]
+impl Server {
+    /// Wait for an authenticated connection; other peers are turned away
+    async fn accept(&self) -> Result<WebSocketStream<TcpStream>> {
+        println!("ブラウザ拡張機能からの接続を待っています...");
+
+        let mut rejection = None;
+        let waited = timeout(self.connect_timeout, async {
+            loop {
+                let (stream, addr) = match self.listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        return Err(NoetError::Network(format!(
+                            "Failed to accept connection: {e}"
+                        )))
+                    }
+                };
+                match extension_auth::accept(stream, &self.policy).await {
+                    Ok(ws_stream) => return Ok((ws_stream, addr)),
+                    Err(AcceptError::Rejected(reason)) => {
+                        println!("{reason}");
+                        rejection = Some(reason);
+                    }
+                    Err(AcceptError::Handshake(e)) => {
+                        log::warn!("WebSocket handshake failed: {e}");
+                    }
+                }
+            }
+        })
+        .await;
+
+        let (ws_stream, addr) = match waited {
+            Ok(result) => result?,
+            Err(_) => {
+                return Err(match rejection {
+                    Some(message) => NoetError::Unauthorized { message },
+                    None => NoetError::ExtensionNotFound {
+                        message: format!(
+                            "{} 秒待ちましたが拡張機能が接続しませんでした",
+                            self.connect_timeout.as_secs()
+                        ),
+                    },
+                })
+            }
+        };
+
+        println!("拡張機能が接続しました: {addr}");
+        Ok(ws_stream)
+    }
+}
 
 /// Extension client that communicates via WebSocket
 pub struct ExtensionClient {
-    /// Sender for outgoing messages
-    tx: mpsc::Sender<String>,
-    /// Pending requests waiting for responses
-    pending: Arc<Mutex<HashMap<String, PendingRequest>>>,
+    link: Mutex<Link>,
+    /// How long to wait for each response
+    command_timeout: Duration,
+    /// How long to wait for an image chunk's acknowledgement
+    chunk_timeout: Duration,
+    /// Whether to resend idempotent commands after the connection drops
+    reconnect: bool,
+    /// Commands the extension said it handles; None if it didn't say
+    supported: std::sync::Mutex<Option<Vec<String>>>,
+    #[cfg(not(unix))]
+    server: Server,
 }
 
 impl ExtensionClient {
-    /// Connect to a running extension server or start waiting for connection
+    /// Connect through the daemon, starting it if it isn't running
+    #[cfg(unix)]
     pub async fn connect() -> Result<Self> {
-        let listener = TcpListener::bind(format!("127.0.0.1:{WEBSOCKET_PORT}"))
-            .await
-            .map_err(|e| NoetError::Network(format!("Failed to bind WebSocket server: {e}")))?;
+        let stream = daemon::connect_or_spawn().await?;
+        let client = Self::from_daemon(stream, &Settings::load()?);
+        client.handshake().await?;
+        Ok(client)
+    }
 
-        println!("WebSocket サーバーを起動しました (ws://127.0.0.1:{WEBSOCKET_PORT})");
-        println!("ブラウザ拡張機能からの接続を待っています...");
+    /// Connect to a daemon that is already running
+    #[cfg(unix)]
+    pub async fn connect_running_daemon() -> Result<Option<Self>> {
+        let settings = Settings::load()?;
+        Ok(daemon::connect()
+            .await?
+            .map(|stream| Self::from_daemon(stream, &settings)))
+    }
 
-        // Wait for connection with timeout
-        let (stream, addr) = timeout(Duration::from_secs(30), listener.accept())
-            .await
-            .map_err(|_| {
-                NoetError::Network("拡張機能からの接続がタイムアウトしました。拡張機能がインストールされていることを確認してください。".into())
-            })?
-            .map_err(|e| NoetError::Network(format!("Failed to accept connection: {e}")))?;
+    /// Create client from a connection to the daemon
+    #[cfg(unix)]
+    fn from_daemon(stream: UnixStream, settings: &Settings) -> Self {
+        Self {
+            link: Mutex::new(Link::daemon(stream)),
+            command_timeout: Duration::from_secs(settings.command_timeout),
+            chunk_timeout: chunk_timeout(Duration::from_secs(settings.command_timeout)),
+            reconnect: settings.reconnect,
+            supported: std::sync::Mutex::new(None),
+        }
+    }
 
-        println!("拡張機能が接続しました: {addr}");
+    /// Start a WebSocket server and wait for the extension to connect
+    #[cfg(not(unix))]
+    pub async fn connect() -> Result<Self> {
+        let settings = Settings::load()?;
+        let listener = bridge::bind(settings.bridge_port).await?;
+        let port = listener.local_addr()?.port();
+
+        let server = Server {
+            listener,
+            policy: Policy::load()?,
+            connect_timeout: Duration::from_secs(settings.connect_timeout),
+            _published: bridge::publish(port)?,
+        };
+        println!("WebSocket サーバーを起動しました (ws://127.0.0.1:{port})");
+        let ws_stream = server.accept().await?;
+
+        let client = Self {
+            link: Mutex::new(Link::websocket(ws_stream)),
+            command_timeout: Duration::from_secs(settings.command_timeout),
+            chunk_timeout: chunk_timeout(Duration::from_secs(settings.command_timeout)),
+            reconnect: settings.reconnect,
+            supported: std::sync::Mutex::new(None),
+            server,
+        };
+        client.handshake().await?;
+        Ok(client)
+    }
 
-        let ws_stream = accept_async(stream)
-            .await
-            .map_err(|e| NoetError::Network(format!("WebSocket handshake failed: {e}")))?;
+    /// Check that the extension speaks our protocol and note what it supports
+    async fn handshake(&self) -> Result<()> {
+        let info = self.call::<Ping>(&NoParams {}).await?;
+        let theirs = info.protocol_version.as_deref();
+
+        match protocol::compatibility(theirs) {
+            Compatibility::Same => {}
+            Compatibility::OlderExtension => println!(
+                "{} 拡張機能 {} のプロトコル ({}) は noet ({PROTOCOL_VERSION}) より古いため、使えないコマンドがあるかもしれません。`noet setup --upgrade` で更新してください",
+                "!".yellow(),
+                info.version,
+                theirs.unwrap_or("不明")
+            ),
+            Compatibility::NewerExtension => println!(
+                "{} 拡張機能 {} のプロトコル ({}) は noet ({PROTOCOL_VERSION}) より新しいため、noet の更新をおすすめします",
+                "!".yellow(),
+                info.version,
+                theirs.unwrap_or("不明")
+            ),
+            Compatibility::Incompatible => {
+                return Err(NoetError::VersionMismatch {
+                    message: format!(
+                        "noet はプロトコル {PROTOCOL_VERSION}、拡張機能 {} はプロトコル {} を使っています",
+                        info.version,
+                        theirs.unwrap_or("不明")
+                    ),
+                })
+            }
+        }
 
-        Self::from_stream(ws_stream).await
+        *self.supported.lock().unwrap() = info.commands;
+        Ok(())
     }
 
-    /// Create client from existing WebSocket stream
-    async fn from_stream(ws_stream: WebSocketStream<TcpStream>) -> Result<Self> {
-        let (mut write, mut read) = ws_stream.split();
+    /// Replace the link if its connection is gone
+    async fn reconnect_link(&self) -> Result<()> {
+        let mut link = self.link.lock().await;
+        if !link.tx.is_closed() {
+            // The daemon is still there and waits for the extension to come back
+            return Ok(());
+        }
 
-        let (tx, mut rx) = mpsc::channel::<String>(32);
-        let pending: Arc<Mutex<HashMap<String, PendingRequest>>> =
-            Arc::new(Mutex::new(HashMap::new()));
-        let pending_clone = pending.clone();
+        #[cfg(unix)]
+        {
+            *link = Link::daemon(daemon::connect_or_spawn().await?);
+        }
+        #[cfg(not(unix))]
+        {
+            *link = Link::websocket(self.server.accept().await?);
+        }
+        Ok(())
+    }
 
-        // Spawn writer task
-        tokio::spawn(async move {
-            while let Some(msg) = rx.recv().await {
-                if write.send(Message::Text(msg)).await.is_err() {
-                    break;
-                }
-            }
-        });
+    /// Whether the extension said it handles `command`
+    fn supports(&self, command: &str) -> bool {
+        self.supported
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|supported| supported.iter().any(|name| name == command))
+    }
 
-        // Spawn reader task
-        tokio::spawn(async move {
-            while let Some(msg) = read.next().await {
-                match msg {
-                    Ok(Message::Text(text)) => {
-                        if let Ok(response) = serde_json::from_str::<ExtensionResponse>(&text) {
-                            let mut pending = pending_clone.lock().await;
-                            if let Some(sender) = pending.remove(&response.id) {
-                                let _ = sender.send(response);
-                            }
-                        }
-                    }
-                    Ok(Message::Close(_)) => break,
-                    Err(_) => break,
-                    _ => {}
-                }
+    /// Send a command to the extension and decode what it returns
+    pub async fn call<C: Command>(&self, params: &C::Params) -> Result<C::Returns> {
+        let retries = if self.reconnect && C::IDEMPOTENT {
+            MAX_RETRIES
+        } else {
+            0
+        };
+        self.call_within::<C>(params, self.command_timeout, retries)
+            .await
+    }
+
+    /// [`Self::call`] waiting `wait` for the answer and resending up to `retries`
+    /// times after the connection drops
+    async fn call_within<C: Command>(
+        &self,
+        params: &C::Params,
+        wait: Duration,
+        retries: usize,
+    ) -> Result<C::Returns> {
+        if let Some(supported) = self.supported.lock().unwrap().as_ref() {
+            if !supported.iter().any(|name| name == C::NAME) {
+                return Err(NoetError::VersionMismatch {
+                    message: format!("拡張機能が {} に対応していません", C::NAME),
+                });
             }
-        });
+        }
+
+        let params = serde_json::to_value(params)?;
+        let response = self.send_command(C::NAME, params, wait, retries).await?;
 
-        Ok(Self { tx, pending })
+        serde_json::from_value(response.data.unwrap_or_default())
+            .map_err(|e| NoetError::Extension(format!("{} の応答を解釈できません: {e}", C::NAME)))
     }
 
-    /// Send a command to the extension and wait for response
+    /// Send a command and wait `wait` for the response, resending it up to
+    /// `retries` times if the connection drops
     async fn send_command(
         &self,
         command: &str,
-        params: Option<serde_json::Value>,
-    ) -> Result<ExtensionResponse> {
-        let id = Uuid::new_v4().to_string();
-
-        let request = ExtensionRequest {
-            id: id.clone(),
-            command: command.to_string(),
-            params,
+        params: Value,
+        wait: Duration,
+        retries: usize,
+    ) -> Result<Response> {
+        let mut attempt = 0;
+        loop {
+            match self.send_once(command, params.clone(), wait).await {
+                Err(NoetError::ExtensionDisconnected(reason)) if attempt < retries => {
+                    attempt += 1;
+                    println!("{} {reason}。再接続して再送します...", "!".yellow());
+                    self.reconnect_link().await?;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn send_once(&self, command: &str, params: Value, wait: Duration) -> Result<Response> {
+        let request = Request::new(command, params);
+        let id = request.id.clone();
+
+        let (tx, pending) = {
+            let link = self.link.lock().await;
+            (link.tx.clone(), link.pending.clone())
         };
 
         let (response_tx, response_rx) = oneshot::channel();
 
         // Register pending request
-        {
-            let mut pending = self.pending.lock().await;
-            pending.insert(id.clone(), response_tx);
-        }
+        pending.lock().await.insert(id.clone(), response_tx);
 
         // Send request
         let json = serde_json::to_string(&request)?;
-        self.tx
-            .send(json)
-            .await
-            .map_err(|e| NoetError::Network(format!("Failed to send message: {e}")))?;
+        if tx.send(json).await.is_err() {
+            pending.lock().await.remove(&id);
+            return Err(NoetError::ExtensionDisconnected(
+                "拡張機能との接続が切れています".into(),
+            ));
+        }
 
         // Wait for response with timeout
-        let response = timeout(COMMAND_TIMEOUT, response_rx)
-            .await
-            .map_err(|_| NoetError::Network("コマンドがタイムアウトしました".into()))?
-            .map_err(|_| NoetError::Network("レスポンスの受信に失敗しました".into()))?;
+        let response = match timeout(wait, response_rx).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(_)) => {
+                return Err(NoetError::ExtensionDisconnected(
+                    "レスポンスを受信する前に接続が切れました".into(),
+                ))
+            }
+            Err(_) => {
+                pending.lock().await.remove(&id);
+                return Err(NoetError::Timeout {
+                    message: format!("コマンドが {} 秒以内に完了しませんでした", wait.as_secs()),
+                });
+            }
+        };
 
         // Check for error
-        if response.status == "error" {
-            if let Some(err) = &response.error {
-                return Err(NoetError::Extension(format!(
-                    "{}: {}",
-                    err.code, err.message
-                )));
-            }
+        if response.status == Status::Error {
+            let err = response.error.unwrap_or_else(|| protocol::ErrorBody {
+                code: "UNKNOWN".to_string(),
+                message: "エラーの詳細がありません".to_string(),
+            });
+            return Err(NoetError::from_protocol(&err.code, err.message));
         }
 
         Ok(response)
     }
 
     /// Ping the extension to check connection
-    pub async fn ping(&self) -> Result<String> {
-        let response = self.send_command("ping", None).await?;
+    pub async fn ping(&self) -> Result<PingData> {
+        self.call::<Ping>(&NoParams {}).await
+    }
 
-        let version = response
-            .data
-            .and_then(|d| d.get("version").and_then(|v| v.as_str()).map(String::from))
-            .unwrap_or_else(|| "unknown".to_string());
+    /// Check authentication status
+    pub async fn check_auth(&self) -> Result<AuthStatus> {
+        self.call::<CheckAuth>(&NoParams {}).await
+    }
 
-        Ok(version)
+    /// List articles
+    pub async fn list_articles(&self) -> Result<ArticleList> {
+        self.call::<ListArticles>(&NoParams {}).await
     }
 
-    /// Check authentication status
-    pub async fn check_auth(&self) -> Result<AuthStatusData> {
-        let response = self.send_command("check_auth", None).await?;
+    /// Get a single article
+    pub async fn get_article(&self, username: &str, key: &str) -> Result<Article> {
+        let params = GetArticleParams {
+            username: username.to_string(),
+            key: key.to_string(),
+        };
+        self.call::<GetArticle>(&params).await
+    }
 
-        let data = response
-            .data
-            .ok_or_else(|| NoetError::Extension("No data in response".into()))?;
+    /// Create a new article
+    pub async fn create_article(&self, params: &CreateArticleParams) -> Result<ArticleResult> {
+        self.call::<CreateArticle>(params).await
+    }
 
-        serde_json::from_value(data)
-            .map_err(|e| NoetError::Extension(format!("Failed to parse auth data: {e}")))
+    /// Update an existing article
+    pub async fn update_article(&self, params: &UpdateArticleParams) -> Result<ArticleResult> {
+        self.call::<UpdateArticle>(params).await
     }
 
-    /// List articles
-    pub async fn list_articles(&self) -> Result<ArticleListData> {
-        let response = self.send_command("list_articles", None).await?;
+    /// Read and convert each image, then send it ahead of the article with
+    /// `upload_image_chunk`, one image at a time. The returned images refer to
+    /// their uploads; extensions that can't take chunks get the data inline.
+    pub async fn upload_images(
+        &self,
+        images: &[&LocalImage],
+        options: &ImageOptions,
+    ) -> Result<Vec<ImageData>> {
+        let chunked = self.supports(UploadImageChunk::NAME);
+        let count = images.len();
+
+        let mut sent = Vec::with_capacity(count);
+        for (n, image) in images.iter().enumerate() {
+            let processed = image_pipeline::process(&image.path, options)?;
+            println!("{} {}", "✓".green(), processed.report);
+
+            let mut data = ImageData {
+                local_path: image.local_path.clone(),
+                filename: processed.filename.clone(),
+                caption: image.caption.clone(),
+                mime_type: processed.mime_type.to_string(),
+                data: None,
+                upload_id: None,
+            };
+            if chunked && !processed.bytes.is_empty() {
+                let (upload_id, chunks) = self.upload_image(&processed).await?;
+                println!(
+                    "{} 画像を送信しました ({}/{count}): {} ({}, {chunks} チャンク)",
+                    "✓".green(),
+                    n + 1,
+                    processed.filename,
+                    image_pipeline::format_size(processed.bytes.len() as u64),
+                );
+                data.upload_id = Some(upload_id);
+            } else {
+                data.data = Some(general_purpose::STANDARD.encode(&processed.bytes));
+            }
+            sent.push(data);
+        }
+        Ok(sent)
+    }
 
-        let data = response
-            .data
-            .ok_or_else(|| NoetError::Extension("No data in response".into()))?;
+    /// Send one image in chunks, each encoded from its slice of the bytes.
+    /// Returns the upload and how many chunks it took.
+    async fn upload_image(&self, image: &ProcessedImage) -> Result<(String, usize)> {
+        // Whole groups of 3 bytes encode to 4 characters without padding, so the
+        // chunks' base64 joins up into the image's
+        let chunk_bytes = protocol::IMAGE_CHUNK_CHARS / 4 * 3;
+        let upload_id = uuid::Uuid::new_v4().to_string();
+        let total = image.bytes.len().div_ceil(chunk_bytes);
+
+        let size = image_pipeline::format_size(image.bytes.len() as u64);
+        let mut sent = 0;
+        for (index, chunk) in image.bytes.chunks(chunk_bytes).enumerate() {
+            let params = UploadImageChunkParams {
+                upload_id: upload_id.clone(),
+                index,
+                total,
+                data: general_purpose::STANDARD.encode(chunk),
+            };
+            self.send_chunk(&image.filename, &params).await?;
+
+            sent += chunk.len();
+            let progress = format!(
+                "{}: チャンク {}/{total} ({} / {size})",
+                image.filename,
+                index + 1,
+                image_pipeline::format_size(sent as u64)
+            );
+            println!("      {}", progress.dimmed());
+        }
+        Ok((upload_id, total))
+    }
 
-        serde_json::from_value(data)
-            .map_err(|e| NoetError::Extension(format!("Failed to parse article list: {e}")))
+    /// Send one chunk, resending it if the acknowledgement doesn't come in time or
+    /// the connection drops
+    async fn send_chunk(&self, filename: &str, params: &UploadImageChunkParams) -> Result<()> {
+        let mut attempt = 0;
+        loop {
+            let reason = match self
+                .call_within::<UploadImageChunk>(params, self.chunk_timeout, 0)
+                .await
+            {
+                Ok(ack) if ack.upload_id == params.upload_id && ack.index == params.index => {
+                    return Ok(())
+                }
+                Ok(ack) => {
+                    return Err(NoetError::Extension(format!(
+                        "{filename} のチャンク {}/{} に別のチャンク ({}) の応答が返りました",
+                        params.index + 1,
+                        params.total,
+                        ack.index + 1
+                    )))
+                }
+                Err(NoetError::Timeout { message }) if attempt < MAX_CHUNK_RETRIES => message,
+                Err(NoetError::ExtensionDisconnected(reason))
+                    if self.reconnect && attempt < MAX_CHUNK_RETRIES =>
+                {
+                    self.reconnect_link().await?;
+                    reason
+                }
+                Err(e) => return Err(e),
+            };
+
+            attempt += 1;
+            println!(
+                "{} {filename} のチャンク {}/{} を再送します ({reason})",
+                "!".yellow(),
+                params.index + 1,
+                params.total
+            );
+        }
     }
 
-    /// Get a single article
-    pub async fn get_article(&self, username: &str, key: &str) -> Result<ArticleData> {
-        let params = serde_json::json!({
-            "username": username,
-            "key": key
-        });
+    /// Delete an article
+    pub async fn delete_article(&self, key: &str) -> Result<DeleteResult> {
+        let params = KeyParams {
+            key: key.to_string(),
+        };
+        self.call::<DeleteArticle>(&params).await
+    }
 
-        let response = self.send_command("get_article", Some(params)).await?;
+    /// Ask the daemon about itself
+    #[cfg(unix)]
+    pub async fn daemon_status(&self) -> Result<DaemonStatus> {
+        let response = self
+            .send_command(daemon::STATUS_COMMAND, Value::Null, self.command_timeout, 0)
+            .await?;
 
         let data = response
             .data
             .ok_or_else(|| NoetError::Extension("No data in response".into()))?;
 
         serde_json::from_value(data)
-            .map_err(|e| NoetError::Extension(format!("Failed to parse article: {e}")))
+            .map_err(|e| NoetError::Extension(format!("Failed to parse daemon status: {e}")))
     }
 
-    /// Create a new article
-    pub async fn create_article(
-        &self,
-        title: &str,
-        body: &str,
-        tags: &[String],
-        draft: bool,
-    ) -> Result<serde_json::Value> {
-        let params = serde_json::json!({
-            "title": title,
-            "body": body,
-            "tags": tags,
-            "draft": draft
-        });
-
-        let response = self.send_command("create_article", Some(params)).await?;
+    /// Ask the daemon to shut down
+    #[cfg(unix)]
+    pub async fn stop_daemon(&self) -> Result<()> {
+        self.send_command(daemon::STOP_COMMAND, Value::Null, self.command_timeout, 0)
+            .await?;
+        Ok(())
+    }
 
-        response
-            .data
-            .ok_or_else(|| NoetError::Extension("No data in response".into()))
+    /// Set debug mode
+    #[allow(dead_code)]
+    pub async fn set_debug_mode(&self, enabled: bool) -> Result<()> {
+        self.call::<SetDebugMode>(&DebugModeParams { enabled })
+            .await?;
+        Ok(())
     }
+}
 
-    /// Create a new article with images
-    pub async fn create_article_with_images(
-        &self,
-        title: &str,
-        body: &str,
-        tags: &[String],
-        draft: bool,
-        images: &[crate::image_handler::ImageData],
-        header_image: Option<&crate::image_handler::ImageData>,
-    ) -> Result<serde_json::Value> {
-        let params = serde_json::json!({
-            "title": title,
-            "body": body,
-            "tags": tags,
-            "draft": draft,
-            "images": images,
-            "header_image": header_image
+#[cfg(all(test, unix))]
+impl ExtensionClient {
+    /// A client whose daemon answers each request line with `reply(n, request)`,
+    /// then hangs up
+    pub(crate) fn fake(
+        replies: usize,
+        reply: impl Fn(usize, &Value) -> Value + Send + 'static,
+    ) -> Self {
+        let (client, daemon) = UnixStream::pair().unwrap();
+        tokio::spawn(async move {
+            let (read, mut write) = daemon.into_split();
+            let mut lines = BufReader::new(read).lines();
+            for n in 0..replies {
+                let Ok(Some(line)) = lines.next_line().await else {
+                    return;
+                };
+                let request: Value = serde_json::from_str(&line).unwrap();
+                let mut response = reply(n, &request).to_string();
+                response.push('\n');
+                write.write_all(response.as_bytes()).await.unwrap();
+            }
         });
+        Self::from_daemon(client, &Settings::default())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn disconnected(request: &Value) -> Value {
+        json!({
+            "id": request["id"],
+            "status": "error",
+            "error": { "code": "DISCONNECTED", "message": "拡張機能との接続が切れました" }
+        })
+    }
 
-        let response = self.send_command("create_article", Some(params)).await?;
+    #[tokio::test]
+    async fn test_requests_fail_as_soon_as_the_connection_drops() {
+        let client = ExtensionClient::fake(0, |_, _| unreachable!());
 
-        response
-            .data
-            .ok_or_else(|| NoetError::Extension("No data in response".into()))
+        let result = timeout(Duration::from_secs(5), client.delete_article("n1"))
+            .await
+            .expect("should not wait for the command timeout");
+        assert!(matches!(result, Err(NoetError::ExtensionDisconnected(_))));
     }
 
-    /// Update an existing article
-    pub async fn update_article(
-        &self,
-        key: &str,
-        title: &str,
-        body: &str,
-        tags: Option<&[String]>,
-        draft: bool,
-    ) -> Result<serde_json::Value> {
-        let mut params = serde_json::json!({
-            "key": key,
-            "title": title,
-            "body": body,
-            "draft": draft
+    #[tokio::test]
+    async fn test_idempotent_commands_are_resent() {
+        let client = ExtensionClient::fake(2, |n, request| match n {
+            0 => disconnected(request),
+            _ => json!({ "id": request["id"], "status": "success", "data": { "version": "1.0" } }),
         });
+        assert_eq!(client.ping().await.unwrap().version, "1.0");
+    }
 
-        if let Some(t) = tags {
-            params["tags"] = serde_json::json!(t);
-        }
+    #[tokio::test]
+    async fn test_other_commands_are_not_resent() {
+        let client = ExtensionClient::fake(2, |n, request| match n {
+            0 => disconnected(request),
+            _ => panic!("sent twice"),
+        });
+        assert!(matches!(
+            client.delete_article("n1").await,
+            Err(NoetError::ExtensionDisconnected(_))
+        ));
+    }
 
-        let response = self.send_command("update_article", Some(params)).await?;
+    fn pong(request: &Value, protocol_version: &str) -> Value {
+        json!({
+            "id": request["id"],
+            "status": "success",
+            "data": {
+                "version": "9.9.9",
+                "protocol_version": protocol_version,
+                "commands": ["ping", "list_articles"]
+            }
+        })
+    }
 
-        response
-            .data
-            .ok_or_else(|| NoetError::Extension("No data in response".into()))
+    #[tokio::test]
+    async fn test_handshake_refuses_another_major_version() {
+        let client = ExtensionClient::fake(1, |_, request| pong(request, "2.0"));
+        assert!(matches!(
+            client.handshake().await,
+            Err(NoetError::VersionMismatch { .. })
+        ));
     }
 
-    /// Update an existing article with images
-    #[allow(clippy::too_many_arguments)]
-    pub async fn update_article_with_images(
-        &self,
-        key: &str,
-        title: &str,
-        body: &str,
-        tags: Option<&[String]>,
-        draft: bool,
-        images: &[crate::image_handler::ImageData],
-        header_image: Option<&crate::image_handler::ImageData>,
-    ) -> Result<serde_json::Value> {
-        let mut params = serde_json::json!({
-            "key": key,
-            "title": title,
-            "body": body,
-            "draft": draft,
-            "images": images,
-            "header_image": header_image
-        });
+    #[tokio::test]
+    async fn test_chunk_timeout_follows_the_command_timeout() {
+        let client = ExtensionClient::fake(0, |_, _| unreachable!());
+        assert_eq!(client.command_timeout, Duration::from_secs(60));
+        // Four tries of 15 seconds fit in one command timeout
+        assert_eq!(client.chunk_timeout, Duration::from_secs(15));
+    }
 
-        if let Some(t) = tags {
-            params["tags"] = serde_json::json!(t);
+    /// A GIF, which is sent as it is
+    fn gif(dir: &tempfile::TempDir, bytes: &[u8]) -> LocalImage {
+        let path = dir.path().join("a.gif");
+        std::fs::write(&path, bytes).unwrap();
+        LocalImage {
+            path,
+            local_path: "./a.gif".to_string(),
+            caption: String::new(),
         }
-
-        let response = self.send_command("update_article", Some(params)).await?;
-
-        response
-            .data
-            .ok_or_else(|| NoetError::Extension("No data in response".into()))
     }
 
-    /// Delete an article
-    pub async fn delete_article(&self, key: &str) -> Result<serde_json::Value> {
-        let params = serde_json::json!({
-            "key": key
+    #[tokio::test]
+    async fn test_images_are_sent_in_chunks() {
+        let received = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = received.clone();
+        let mut client = ExtensionClient::fake(5, move |n, request| {
+            let params = &request["params"];
+            log.lock().unwrap().push(params.clone());
+            // The extension drops away during the first chunk, and the
+            // acknowledgement of the second gets lost
+            let id = match n {
+                0 => return disconnected(request),
+                2 => json!("lost"),
+                _ => request["id"].clone(),
+            };
+            json!({
+                "id": id,
+                "status": "success",
+                "data": { "upload_id": params["upload_id"], "index": params["index"], "received": 1 }
+            })
         });
+        client.chunk_timeout = Duration::from_secs(1);
+        *client.supported.lock().unwrap() = Some(vec![UploadImageChunk::NAME.to_string()]);
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let bytes: Vec<u8> = (0..protocol::IMAGE_CHUNK_CHARS / 4 * 3 * 2 + 5)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let image = gif(&dir, &bytes);
+        let sent = client
+            .upload_images(&[&image], &ImageOptions::default())
+            .await
+            .unwrap();
+
+        let chunks = received.lock().unwrap().clone();
+        let indexes: Vec<_> = chunks
+            .iter()
+            .map(|c| c["index"].as_u64().unwrap())
+            .collect();
+        assert_eq!(
+            indexes,
+            vec![0, 0, 1, 1, 2],
+            "unacknowledged chunks are resent"
+        );
+        assert!(chunks.iter().all(|c| c["total"] == 3));
+        assert_eq!(chunks[0], chunks[1]);
+        assert_eq!(chunks[2], chunks[3]);
+        assert!(chunks
+            .iter()
+            .all(|c| c["data"].as_str().unwrap().len() <= protocol::IMAGE_CHUNK_CHARS));
+        let joined: String = [&chunks[1], &chunks[3], &chunks[4]]
+            .iter()
+            .map(|c| c["data"].as_str().unwrap())
+            .collect();
+        assert_eq!(general_purpose::STANDARD.decode(joined).unwrap(), bytes);
+
+        assert_eq!(sent.len(), 1);
+        assert_eq!(
+            sent[0].upload_id.as_deref(),
+            chunks[0]["upload_id"].as_str()
+        );
+        assert_eq!(sent[0].data, None);
+    }
 
-        let response = self.send_command("delete_article", Some(params)).await?;
-
-        response
-            .data
-            .ok_or_else(|| NoetError::Extension("No data in response".into()))
+    #[tokio::test]
+    async fn test_images_stay_inline_for_older_extensions() {
+        let client = ExtensionClient::fake(0, |_, _| unreachable!());
+        let dir = tempfile::TempDir::new().unwrap();
+        let image = gif(&dir, b"ABC");
+        let sent = client
+            .upload_images(&[&image], &ImageOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(sent[0].data.as_deref(), Some("QUJD"));
+        assert_eq!(sent[0].upload_id, None);
     }
 
-    /// Set debug mode
-    #[allow(dead_code)]
-    pub async fn set_debug_mode(&self, enabled: bool) -> Result<()> {
-        let params = serde_json::json!({
-            "enabled": enabled
-        });
+    #[tokio::test]
+    async fn test_unsupported_commands_are_not_sent() {
+        let client = ExtensionClient::fake(1, |_, request| pong(request, PROTOCOL_VERSION));
+        client.handshake().await.unwrap();
 
-        self.send_command("set_debug_mode", Some(params)).await?;
-        Ok(())
+        let result = timeout(Duration::from_secs(5), client.delete_article("n1"))
+            .await
+            .expect("should be refused without asking the extension");
+        assert!(matches!(result, Err(NoetError::VersionMismatch { .. })));
     }
 }
@@ -0,0 +1,342 @@
+//! YAML frontmatter for article Markdown files
+//!
+//! ```text
+//! ---
+//! title: "記事タイトル"
+//! tags: [rust, cli]
+//! header_image: ./images/header.png
+//! note_key: n1234567890ab
+//! ---
+//!
+//! 本文...
+//! ```
+//!
+//! Keys that noet doesn't know about are kept as-is, so files written by other
+//! tools survive a pull/push round trip.
+
+use crate::config::ArticleStatus;
+use crate::error::{NoetError, Result};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fs;
+use std::path::Path;
+
+const DELIMITER: &str = "---";
+
+/// Known frontmatter keys plus any extra ones
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Frontmatter {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+
+    #[serde(
+        default,
+        deserialize_with = "deserialize_tags",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub tags: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<ArticleStatus>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub header_image: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note_key: Option<String>,
+
+    /// Unknown keys, kept in their original order
+    #[serde(flatten)]
+    pub extra: serde_yaml::Mapping,
+}
+
+/// A Markdown file split into frontmatter and body
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Document {
+    pub frontmatter: Frontmatter,
+    pub body: String,
+}
+
+impl Document {
+    pub fn new(frontmatter: Frontmatter, body: impl Into<String>) -> Self {
+        Self {
+            frontmatter,
+            body: body.into(),
+        }
+    }
+
+    /// Read and parse a Markdown file. Errors include the file path and line.
+    pub fn read(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        Self::parse(&content).map_err(|e| match e {
+            NoetError::Frontmatter { location, message } => NoetError::Frontmatter {
+                location: format!("{}:{location}", path.display()),
+                message,
+            },
+            other => other,
+        })
+    }
+
+    /// Parse Markdown content. A file without frontmatter is all body.
+    pub fn parse(content: &str) -> Result<Self> {
+        let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+
+        let Some((yaml, body)) = split(content)? else {
+            return Ok(Self::new(
+                Frontmatter::default(),
+                content.trim_start_matches('\n'),
+            ));
+        };
+
+        let frontmatter = if yaml.trim().is_empty() {
+            Frontmatter::default()
+        } else {
+            serde_yaml::from_str(yaml).map_err(|e| {
+                // +1 for the opening delimiter line
+                let line = e.location().map(|l| l.line() + 1).unwrap_or(1);
+                NoetError::Frontmatter {
+                    location: line.to_string(),
+                    message: strip_location(&e.to_string()),
+                }
+            })?
+        };
+
+        Ok(Self::new(frontmatter, body.trim_start_matches('\n')))
+    }
+
+    /// Title from frontmatter, or the first `# ` heading of the body (which is then
+    /// removed from the body)
+    pub fn take_title(&mut self) -> String {
+        if let Some(title) = &self.frontmatter.title {
+            return title.clone();
+        }
+
+        let Some(line) = self
+            .body
+            .lines()
+            .find(|line| line.trim().starts_with("# "))
+            .map(String::from)
+        else {
+            return String::new();
+        };
+
+        let title = line.trim().trim_start_matches("# ").trim().to_string();
+        // Leave the line empty so line numbers in the body stay valid
+        self.body = self.body.replacen(&line, "", 1);
+        title
+    }
+
+    /// Render back to a Markdown file
+    pub fn to_markdown(&self) -> Result<String> {
+        let yaml = serde_yaml::to_string(&self.frontmatter)?;
+        let yaml = if yaml.trim() == "{}" { "" } else { &yaml };
+        Ok(format!("{DELIMITER}\n{yaml}{DELIMITER}\n\n{}", self.body))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_markdown()?)?;
+        Ok(())
+    }
+}
+
+/// 1-based line of `content` on which the body returned by [`Document::parse`] starts
+pub fn body_line(content: &str) -> usize {
+    let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+    let body = match split(content) {
+        Ok(Some((_, body))) => body,
+        _ => content,
+    };
+    let offset = content.len() - body.trim_start_matches('\n').len();
+    content[..offset].matches('\n').count() + 1
+}
+
+/// 1-based line of `content` on which `body.trim()` starts, where `body` is the
+/// body parsed from `content` (possibly after [`Document::take_title`])
+pub fn trimmed_body_line(content: &str, body: &str) -> usize {
+    let leading = &body[..body.len() - body.trim_start().len()];
+    body_line(content) + leading.matches('\n').count()
+}
+
+/// Split `---\n<yaml>\n---\n<body>`. Returns None if there is no frontmatter.
+fn split(content: &str) -> Result<Option<(&str, &str)>> {
+    let mut lines = content.split_inclusive('\n');
+
+    match lines.next() {
+        Some(first) if first.trim_end() == DELIMITER => {}
+        _ => return Ok(None),
+    }
+
+    let yaml_start = DELIMITER.len() + line_ending_len(content, DELIMITER.len());
+    let mut offset = yaml_start;
+
+    for line in lines {
+        let trimmed = line.trim_end();
+        if trimmed == DELIMITER || trimmed == "..." {
+            let yaml = &content[yaml_start..offset];
+            let body = &content[offset + line.len()..];
+            return Ok(Some((yaml, body)));
+        }
+        offset += line.len();
+    }
+
+    Err(NoetError::Frontmatter {
+        location: "1".to_string(),
+        message: "frontmatter の終わり (---) が見つかりません".to_string(),
+    })
+}
+
+fn line_ending_len(content: &str, at: usize) -> usize {
+    let rest = &content[at..];
+    if rest.starts_with("\r\n") {
+        2
+    } else if rest.starts_with('\n') {
+        1
+    } else {
+        0
+    }
+}
+
+/// serde_yaml appends " at line X column Y"; we report the line ourselves
+fn strip_location(message: &str) -> String {
+    match message.find(" at line ") {
+        Some(i) => message[..i].to_string(),
+        None => message.to_string(),
+    }
+}
+
+/// Accept `tags: [a, b]`, a block list, `tags: a, b` or an empty `tags:`
+fn deserialize_tags<'de, D>(deserializer: D) -> std::result::Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Tags {
+        List(Vec<String>),
+        Csv(String),
+    }
+
+    let tags = match Option::<Tags>::deserialize(deserializer)? {
+        None => Vec::new(),
+        Some(Tags::List(list)) => list,
+        Some(Tags::Csv(csv)) => csv.split(',').map(String::from).collect(),
+    };
+
+    Ok(tags
+        .into_iter()
+        .map(|t| t.trim().trim_start_matches('#').to_string())
+        .filter(|t| !t.is_empty())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_basic() {
+        let doc =
+            Document::parse("---\ntitle: Hello\ntags: [rust, cli]\n---\n\nBody text\n").unwrap();
+        assert_eq!(doc.frontmatter.title.as_deref(), Some("Hello"));
+        assert_eq!(doc.frontmatter.tags, vec!["rust", "cli"]);
+        assert_eq!(doc.body, "Body text\n");
+    }
+
+    #[test]
+    fn test_parse_block_list_and_colons() {
+        let content = r##"---
+title: "Rust: 入門 --- 第1回"
+tags:
+  - rust
+  - "#プログラミング"
+header_image: ./images/header.png
+---
+本文
+"##;
+        let doc = Document::parse(content).unwrap();
+        assert_eq!(
+            doc.frontmatter.title.as_deref(),
+            Some("Rust: 入門 --- 第1回")
+        );
+        assert_eq!(doc.frontmatter.tags, vec!["rust", "プログラミング"]);
+        assert_eq!(
+            doc.frontmatter.header_image.as_deref(),
+            Some("./images/header.png")
+        );
+        assert_eq!(doc.body, "本文\n");
+    }
+
+    #[test]
+    fn test_parse_csv_and_empty_tags() {
+        let doc = Document::parse("---\ntags: a, b\n---\n").unwrap();
+        assert_eq!(doc.frontmatter.tags, vec!["a", "b"]);
+
+        let doc = Document::parse("---\ntitle: x\ntags:\n---\n").unwrap();
+        assert!(doc.frontmatter.tags.is_empty());
+    }
+
+    #[test]
+    fn test_unknown_keys_survive_round_trip() {
+        let content = "---\ntitle: T\nseries: rust-intro\ncustom:\n  a: 1\n---\n\nBody\n";
+        let doc = Document::parse(content).unwrap();
+        assert!(doc.frontmatter.extra.contains_key("series"));
+
+        let rendered = doc.to_markdown().unwrap();
+        assert_eq!(Document::parse(&rendered).unwrap(), doc);
+        assert!(rendered.contains("series: rust-intro"));
+    }
+
+    #[test]
+    fn test_round_trip_escapes_title() {
+        let doc = Document::new(
+            Frontmatter {
+                title: Some("He said: \"yes\" --- #1".to_string()),
+                tags: vec!["a: b".to_string()],
+                note_key: Some("n123".to_string()),
+                ..Default::default()
+            },
+            "# Heading\n\nText\n",
+        );
+
+        let rendered = doc.to_markdown().unwrap();
+        assert_eq!(Document::parse(&rendered).unwrap(), doc);
+    }
+
+    #[test]
+    fn test_no_frontmatter() {
+        let doc = Document::parse("# Title\n\nBody").unwrap();
+        assert_eq!(doc.frontmatter, Frontmatter::default());
+        assert_eq!(doc.body, "# Title\n\nBody");
+    }
+
+    #[test]
+    fn test_error_reports_file_line() {
+        let err = Document::parse("---\ntitle: ok\nstatus: publish\n---\nbody").unwrap_err();
+        match err {
+            NoetError::Frontmatter { location, .. } => assert_eq!(location, "3"),
+            other => panic!("unexpected error: {other}"),
+        }
+    }
+
+    #[test]
+    fn test_unterminated_frontmatter() {
+        assert!(Document::parse("---\ntitle: x\n\nbody").is_err());
+    }
+
+    #[test]
+    fn test_body_line() {
+        assert_eq!(body_line("# Title\n"), 1);
+        assert_eq!(body_line("---\ntitle: x\n---\n\nBody\n"), 5);
+        assert_eq!(body_line("---\n---\nBody"), 3);
+    }
+
+    #[test]
+    fn test_take_title_from_h1() {
+        let mut doc = Document::parse("intro\n# My Title\n\nBody").unwrap();
+        assert_eq!(doc.take_title(), "My Title");
+        assert_eq!(doc.body, "intro\n\n\nBody");
+
+        let mut doc = Document::parse("---\ntitle: FM\n---\n# H1\n").unwrap();
+        assert_eq!(doc.take_title(), "FM");
+        assert_eq!(doc.body, "# H1\n");
+    }
+}
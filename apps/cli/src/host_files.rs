@@ -0,0 +1,552 @@
+//! Workspace files the Native Messaging host serves to the extension
+//!
+//! Only the workspace set with `noet config set workspace` is reachable, and the
+//! paths the extension sends are relative to its root. A path that would leave
+//! the root, by `..`, an absolute path or a symlink, is refused, as are hidden
+//! entries such as `.noet/`.
+
+use crate::commands::extension::FetchedArticle;
+use crate::commands::sync as sync_commands;
+use crate::config::{self, ArticleStatus, Settings};
+use crate::converters::html_to_md::{self, ConvertOptions};
+use crate::converters::note_markdown;
+use crate::error::{NoetError, Result};
+use crate::frontmatter::Document;
+use crate::image_cache::ImageCache;
+use crate::image_handler;
+use crate::image_pipeline;
+use crate::merge;
+use crate::native_messaging::MAX_MESSAGE_BYTES;
+use crate::protocol::{
+    RecordPushParams, WorkspaceArticle, WorkspaceArticleEntry, WorkspaceArticleList,
+    WorkspaceImage, WorkspaceImageRef, WriteWorkspaceArticleParams, WrittenArticle,
+};
+use crate::sync::{self, SyncState};
+use crate::workspace;
+use base64::{engine::general_purpose, Engine as _};
+use std::env;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// The configured workspace, with its own settings applied
+pub struct HostWorkspace {
+    root: PathBuf,
+    settings: Settings,
+}
+
+impl HostWorkspace {
+    /// Open the workspace named by the global `workspace` setting
+    pub fn open() -> Result<Self> {
+        let root = Settings::load_global()?.workspace.ok_or_else(|| {
+            NoetError::ConfigError(
+                "ワークスペースが設定されていません。ワークスペースで `noet config set workspace .` を実行してください"
+                    .to_string(),
+            )
+        })?;
+        if !workspace::is_workspace_root(&root) {
+            return Err(NoetError::ConfigError(format!(
+                "{} は noet ワークスペースではありません",
+                root.display()
+            )));
+        }
+
+        let global = config::global_config_path().ok();
+        let settings = Settings::load_from(
+            global.as_deref(),
+            Some(&workspace::config_path(&root)),
+            |k| env::var(k).ok(),
+        )?;
+        Self::new(&root, settings)
+    }
+
+    fn new(root: &Path, settings: Settings) -> Result<Self> {
+        Ok(Self {
+            root: fs::canonicalize(root)?,
+            settings,
+        })
+    }
+
+    /// Absolute path of `rel`, which must stay inside the workspace
+    fn resolve(&self, rel: &str) -> Result<PathBuf> {
+        let outside = || NoetError::PermissionDenied {
+            message: format!("{rel} はワークスペースの外です"),
+        };
+
+        let rel_path = Path::new(rel);
+        let allowed = |component: Component| match component {
+            Component::Normal(name) => !name.to_string_lossy().starts_with('.'),
+            Component::CurDir => true,
+            _ => false,
+        };
+        if rel.trim().is_empty() || !rel_path.components().all(allowed) {
+            return Err(outside());
+        }
+
+        // A symlink can still lead out, so check where the deepest existing part really is
+        let path = self.root.join(rel_path);
+        let mut existing = path.as_path();
+        while existing.symlink_metadata().is_err() {
+            existing = existing.parent().ok_or_else(outside)?;
+        }
+        let real = fs::canonicalize(existing).map_err(|_| outside())?;
+        if !real.starts_with(&self.root) {
+            return Err(outside());
+        }
+
+        Ok(path)
+    }
+
+    /// Like [`resolve`](Self::resolve), for a file that must exist
+    fn resolve_file(&self, rel: &str) -> Result<PathBuf> {
+        let path = self.resolve(rel)?;
+        if !path.is_file() {
+            return Err(NoetError::FileNotFound(rel.to_string()));
+        }
+        Ok(path)
+    }
+
+    fn relative(&self, path: &Path) -> String {
+        sync::relative_path(&self.root, path)
+    }
+
+    /// Every article file, as `noet status` sees them
+    pub fn list_articles(&self) -> Result<WorkspaceArticleList> {
+        let state = SyncState::load(&self.root)?;
+        let files = sync::scan_local_files(&self.root, &self.settings.articles_dir, &state)?;
+
+        let articles = files
+            .into_iter()
+            .map(|file| {
+                let mut document = Document::parse(&file.content).unwrap_or_default();
+                let title = Some(document.take_title()).filter(|t| !t.is_empty());
+                WorkspaceArticleEntry {
+                    path: file.rel_path,
+                    title,
+                    note_key: file.note_key,
+                }
+            })
+            .collect();
+
+        Ok(WorkspaceArticleList {
+            root: self.root.display().to_string(),
+            articles,
+        })
+    }
+
+    /// Read an article the way `noet push` prepares it
+    pub fn read_article(&self, rel: &str) -> Result<WorkspaceArticle> {
+        let path = self.resolve_file(rel)?;
+        if path.extension().is_none_or(|ext| ext != "md") {
+            return Err(NoetError::InvalidInput(format!(
+                "{rel} は Markdown ファイルではありません"
+            )));
+        }
+
+        let mut document = Document::read(&path)?;
+        let title = document.take_title();
+        let Document { frontmatter, body } = document;
+
+        if merge::has_conflict_markers(&body) {
+            return Err(NoetError::Conflict(format!(
+                "{rel} に未解決の競合マーカーがあります"
+            )));
+        }
+
+        let body = note_markdown::transform(body.trim(), &self.settings.transform_options()).body;
+        let base_dir = path.parent().unwrap_or(&self.root);
+        let (body, _) = ImageCache::load(&self.root)?.rewrite(&body, base_dir);
+        let draft = self
+            .settings
+            .resolve_draft(frontmatter.status.map(ArticleStatus::is_draft));
+        let tags = if frontmatter.tags.is_empty() && frontmatter.note_key.is_none() {
+            self.settings.default_tags.clone()
+        } else {
+            frontmatter.tags
+        };
+
+        let image = |reference: &str, caption: &str| {
+            image_handler::local_image_path(base_dir, reference).map(|image_path| {
+                WorkspaceImageRef {
+                    reference: reference.to_string(),
+                    path: self.relative(&image_path),
+                    caption: caption.to_string(),
+                }
+            })
+        };
+        let images = image_handler::extract_image_references(&body)
+            .iter()
+            .filter_map(|r| image(&r.path, &r.caption))
+            .collect();
+        let header_image = frontmatter
+            .header_image
+            .as_deref()
+            .and_then(|reference| image(reference, ""));
+
+        Ok(WorkspaceArticle {
+            path: self.relative(&path),
+            title,
+            body,
+            tags,
+            draft,
+            note_key: frontmatter.note_key,
+            images,
+            header_image,
+        })
+    }
+
+    /// Read an image through the upload pipeline as base64, if the reply fits
+    /// in a Native Messaging message
+    pub fn read_image(&self, rel: &str) -> Result<WorkspaceImage> {
+        let path = self.resolve_file(rel)?;
+        let processed = image_pipeline::process(&path, &self.settings.image_options())?;
+        let data = general_purpose::STANDARD.encode(&processed.bytes);
+
+        // Leave room for the rest of the reply
+        let limit = MAX_MESSAGE_BYTES - 1024;
+        if data.len() > limit {
+            return Err(NoetError::InvalidInput(format!(
+                "{rel} は大きすぎます (Base64 で {} KB、上限 {} KB)",
+                data.len() / 1024,
+                limit / 1024
+            )));
+        }
+
+        Ok(WorkspaceImage {
+            path: self.relative(&path),
+            filename: processed.filename,
+            mime_type: processed.mime_type.to_string(),
+            data,
+        })
+    }
+
+    /// Save an article fetched by the extension, as `noet pull` would
+    pub fn write_article(&self, params: WriteWorkspaceArticleParams) -> Result<WrittenArticle> {
+        let WriteWorkspaceArticleParams {
+            key,
+            title,
+            html,
+            tags,
+            overwrite,
+        } = params;
+        check_key(&key)?;
+        let mut state = SyncState::load(&self.root)?;
+
+        let tracked = state
+            .find_by_key(&key)
+            .map(|(rel_path, tracked)| (rel_path.clone(), tracked.content_hash.clone()))
+            .filter(|(rel_path, _)| self.root.join(rel_path).is_file());
+
+        let (path, created) = match tracked {
+            Some((rel_path, synced_hash)) => {
+                let path = self.resolve(&rel_path)?;
+                let changed = sync::content_hash(&fs::read_to_string(&path)?) != synced_hash;
+                if changed && !overwrite {
+                    return Err(NoetError::Conflict(format!(
+                        "{rel_path} にプッシュしていない変更があります"
+                    )));
+                }
+                (path, false)
+            }
+            None => {
+                let dir = self.resolve(&self.settings.articles_dir)?;
+                (workspace::unique_article_path(&dir, &key), true)
+            }
+        };
+
+        // Keep frontmatter keys and heading levels from an existing file
+        let existing = if path.exists() {
+            Document::read(&path).ok()
+        } else {
+            None
+        };
+        let options = existing
+            .as_ref()
+            .map(|doc| ConvertOptions::from_markdown(&doc.body))
+            .unwrap_or_default();
+
+        let mut frontmatter = existing.map(|doc| doc.frontmatter).unwrap_or_default();
+        frontmatter.title = Some(title);
+        frontmatter.tags = tags;
+        frontmatter.note_key = Some(key.clone());
+
+        let fetched = FetchedArticle {
+            document: Document::new(
+                frontmatter,
+                html_to_md::convert_with_options(&html, &options),
+            ),
+            body_hash: sync::body_hash(&html_to_md::convert_html_to_markdown(&html)?),
+            images: Vec::new(),
+        };
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fetched.document.write(&path)?;
+
+        let rel_path = self.relative(&path);
+        sync_commands::record_pull(&self.root, &mut state, &rel_path, &key, fetched, None)?;
+
+        Ok(WrittenArticle {
+            path: rel_path,
+            created,
+        })
+    }
+
+    /// Link a file to the article the extension just published it as, and
+    /// remember the images it uploaded
+    pub fn record_push(&self, params: RecordPushParams) -> Result<WrittenArticle> {
+        let RecordPushParams {
+            path: rel,
+            key,
+            uploaded_images,
+        } = params;
+        check_key(&key)?;
+        let path = self.resolve_file(&rel)?;
+        let key = key.as_str();
+
+        if !uploaded_images.is_empty() {
+            let base_dir = path.parent().unwrap_or(&self.root);
+            let mut cache = ImageCache::load(&self.root)?;
+            for image in uploaded_images {
+                let image_path = self.relative(&base_dir.join(&image.local_path));
+                if let Ok(image_path) = self.resolve_file(&image_path) {
+                    cache.record(&image_path, &image.note_url)?;
+                }
+            }
+            cache.save(&self.root)?;
+        }
+
+        let mut document = Document::read(&path)?;
+        if document.frontmatter.note_key.as_deref() != Some(key) {
+            document.frontmatter.note_key = Some(key.to_string());
+            document.write(&path)?;
+        }
+
+        let rel_path = self.relative(&path);
+        let mut state = SyncState::load(&self.root)?;
+        sync_commands::record_push(&self.root, &mut state, &rel_path, key)?;
+
+        Ok(WrittenArticle {
+            path: rel_path,
+            created: false,
+        })
+    }
+}
+
+/// Refuse anything but a note.com article key (`n` and letters or digits), as
+/// keys become file names
+fn check_key(key: &str) -> Result<()> {
+    let valid = key.len() > 1
+        && key.starts_with('n')
+        && key[1..].chars().all(|c| c.is_ascii_alphanumeric());
+    if valid {
+        Ok(())
+    } else {
+        Err(NoetError::InvalidInput(format!(
+            "'{key}' は記事キーではありません"
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::UploadedImage;
+    use tempfile::TempDir;
+
+    fn workspace() -> (TempDir, HostWorkspace) {
+        let dir = TempDir::new().unwrap();
+        workspace::init_workspace(Some(dir.path().to_path_buf())).unwrap();
+        let host = HostWorkspace::new(dir.path(), Settings::default()).unwrap();
+        (dir, host)
+    }
+
+    #[test]
+    fn test_paths_stay_in_the_workspace() {
+        let (dir, host) = workspace();
+        fs::write(dir.path().join("articles/a.md"), "# A\n").unwrap();
+
+        assert!(host.resolve("articles/a.md").is_ok());
+        assert!(host.resolve("articles/new/b.md").is_ok());
+
+        for rel in [
+            "",
+            "../secret.md",
+            "articles/../../secret.md",
+            "/etc/passwd",
+            ".noet/state.json",
+            "articles/.hidden.md",
+        ] {
+            let err = host.resolve(rel).unwrap_err();
+            assert!(
+                matches!(err, NoetError::PermissionDenied { .. }),
+                "{rel}: {err}"
+            );
+        }
+        assert!(matches!(
+            host.resolve_file("articles/missing.md").unwrap_err(),
+            NoetError::FileNotFound(_)
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks_out_of_the_workspace_are_refused() {
+        let (dir, host) = workspace();
+        let outside = TempDir::new().unwrap();
+        fs::write(outside.path().join("secret.md"), "secret").unwrap();
+        std::os::unix::fs::symlink(outside.path(), dir.path().join("articles/link")).unwrap();
+        std::os::unix::fs::symlink(
+            outside.path().join("gone.md"),
+            dir.path().join("articles/dangling.md"),
+        )
+        .unwrap();
+
+        for rel in [
+            "articles/link/secret.md",
+            "articles/link/new.md",
+            "articles/dangling.md",
+        ] {
+            assert!(host.resolve(rel).is_err(), "{rel}");
+        }
+    }
+
+    #[test]
+    fn test_read_article_and_images() {
+        let (dir, host) = workspace();
+        fs::create_dir(dir.path().join("articles/images")).unwrap();
+        image::RgbImage::new(4, 4)
+            .save(dir.path().join("articles/images/a.png"))
+            .unwrap();
+        image::RgbImage::new(4, 4)
+            .save(dir.path().join("articles/images/scan.bmp"))
+            .unwrap();
+        fs::write(
+            dir.path().join("articles/post.md"),
+            "---\ntitle: 投稿\nheader_image: ./images/a.png\n---\n\n本文\n\n![図](./images/a.png)\n![外部](https://example.com/b.png)\n",
+        )
+        .unwrap();
+
+        let list = host.list_articles().unwrap();
+        assert_eq!(
+            list.articles,
+            vec![WorkspaceArticleEntry {
+                path: "articles/post.md".to_string(),
+                title: Some("投稿".to_string()),
+                note_key: None,
+            }]
+        );
+
+        let article = host.read_article("articles/post.md").unwrap();
+        assert_eq!(article.title, "投稿");
+        assert!(article.body.starts_with("本文"));
+        let image = WorkspaceImageRef {
+            reference: "./images/a.png".to_string(),
+            path: "articles/images/a.png".to_string(),
+            caption: "図".to_string(),
+        };
+        assert_eq!(article.images, vec![image]);
+        assert_eq!(article.header_image.unwrap().path, "articles/images/a.png");
+
+        let image = host.read_image("articles/images/a.png").unwrap();
+        assert_eq!(image.mime_type, "image/png");
+        assert_eq!(image.filename, "a.png");
+        // Converted on the way out, like uploads from the CLI
+        let image = host.read_image("articles/images/scan.bmp").unwrap();
+        assert_eq!(image.mime_type, "image/jpeg");
+        assert_eq!(image.filename, "scan.jpg");
+        assert!(host.read_article("articles/images/a.png").is_err());
+    }
+
+    #[test]
+    fn test_traversal_keys_are_refused() {
+        let (dir, host) = workspace();
+        fs::write(dir.path().join("articles/new.md"), "# 新規\n").unwrap();
+
+        for key in ["../../x", "n1/../../x", "nabc.md", "", "n"] {
+            let written = host.write_article(WriteWorkspaceArticleParams {
+                key: key.to_string(),
+                title: "T".to_string(),
+                html: "<p>本文</p>".to_string(),
+                tags: Vec::new(),
+                overwrite: true,
+            });
+            assert!(matches!(written, Err(NoetError::InvalidInput(_))), "{key}");
+
+            let recorded = host.record_push(RecordPushParams {
+                path: "articles/new.md".to_string(),
+                key: key.to_string(),
+                uploaded_images: Vec::new(),
+            });
+            assert!(matches!(recorded, Err(NoetError::InvalidInput(_))), "{key}");
+        }
+
+        assert!(!dir.path().parent().unwrap().join("x.md").exists());
+        assert!(!dir.path().join(".noet/base").exists());
+        assert_eq!(
+            fs::read_to_string(dir.path().join("articles/new.md")).unwrap(),
+            "# 新規\n"
+        );
+    }
+
+    #[test]
+    fn test_write_and_record_push() {
+        let (dir, host) = workspace();
+        let params = |overwrite| WriteWorkspaceArticleParams {
+            key: "n1234567890ab".to_string(),
+            title: "取得".to_string(),
+            html: "<p>本文</p>".to_string(),
+            tags: vec!["rust".to_string()],
+            overwrite,
+        };
+
+        let written = host.write_article(params(false)).unwrap();
+        assert_eq!(
+            written,
+            WrittenArticle {
+                path: "articles/n1234567890ab.md".to_string(),
+                created: true
+            }
+        );
+        let document = Document::read(&dir.path().join(&written.path)).unwrap();
+        assert_eq!(document.frontmatter.title.as_deref(), Some("取得"));
+        assert_eq!(document.body.trim(), "本文");
+
+        // Written again in place while unchanged, refused once edited locally
+        assert!(!host.write_article(params(false)).unwrap().created);
+        fs::write(dir.path().join(&written.path), "編集").unwrap();
+        assert!(matches!(
+            host.write_article(params(false)).unwrap_err(),
+            NoetError::Conflict(_)
+        ));
+        assert!(host.write_article(params(true)).is_ok());
+
+        fs::write(dir.path().join("articles/new.md"), "# 新規\n").unwrap();
+        fs::write(dir.path().join("articles/a.png"), b"png").unwrap();
+        let params = RecordPushParams {
+            path: "articles/new.md".to_string(),
+            key: "nabcdef".to_string(),
+            uploaded_images: vec![UploadedImage {
+                local_path: "./a.png".to_string(),
+                note_url: "https://assets.st-note.com/a.png".to_string(),
+            }],
+        };
+        host.record_push(params).unwrap();
+        let document = Document::read(&dir.path().join("articles/new.md")).unwrap();
+        assert_eq!(document.frontmatter.note_key.as_deref(), Some("nabcdef"));
+        let state = SyncState::load(dir.path()).unwrap();
+        assert_eq!(state.find_by_key("nabcdef").unwrap().0, "articles/new.md");
+
+        // The uploaded image is sent by URL from now on
+        fs::write(
+            dir.path().join("articles/new.md"),
+            "# 新規\n\n![](./a.png)\n",
+        )
+        .unwrap();
+        let article = host.read_article("articles/new.md").unwrap();
+        assert!(article.images.is_empty());
+        assert!(article
+            .body
+            .contains("![](https://assets.st-note.com/a.png)"));
+    }
+}
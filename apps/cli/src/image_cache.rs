@@ -0,0 +1,142 @@
+//! Images already on note.com
+//!
+//! `.noet/images.json` maps the SHA-256 of an image file to the URL note.com
+//! serves it from, recorded when an article's images are uploaded or downloaded
+//! by a pull. Before an article is sent, references to local files with a known
+//! URL are rewritten to it, so only new or changed images are uploaded.
+
+use crate::error::Result;
+use crate::image_handler;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const CACHE_FILE: &str = "images.json";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CachedImage {
+    pub note_url: String,
+    /// Unix time it was recorded
+    pub recorded_at: u64,
+}
+
+/// The whole cache, keyed by content hash
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ImageCache {
+    #[serde(default)]
+    pub images: BTreeMap<String, CachedImage>,
+}
+
+impl ImageCache {
+    fn path(workspace_root: &Path) -> PathBuf {
+        workspace_root.join(".noet").join(CACHE_FILE)
+    }
+
+    /// Load the cache, or an empty one if nothing has been uploaded yet
+    pub fn load(workspace_root: &Path) -> Result<Self> {
+        let path = Self::path(workspace_root);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    pub fn save(&self, workspace_root: &Path) -> Result<()> {
+        let path = Self::path(workspace_root);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Remember that the image file at `path` is served from `note_url`
+    pub fn record(&mut self, path: &Path, note_url: &str) -> Result<()> {
+        self.images.insert(
+            file_hash(path)?,
+            CachedImage {
+                note_url: note_url.to_string(),
+                recorded_at: now(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Rewrite references to local images that are already on note.com.
+    /// Returns the new body and how many references were rewritten.
+    pub fn rewrite(&self, body: &str, base_dir: &Path) -> (String, usize) {
+        let mut out = body.to_string();
+        let mut rewritten = 0;
+
+        for reference in image_handler::extract_image_references(body) {
+            let Some(path) = image_handler::local_image_path(base_dir, &reference.path) else {
+                continue;
+            };
+            let Some(cached) = file_hash(&path).ok().and_then(|h| self.images.get(&h)) else {
+                continue;
+            };
+
+            let before = format!("![{}]({})", reference.caption, reference.path);
+            let after = format!("![{}]({})", reference.caption, cached.note_url);
+            if out.contains(&before) {
+                out = out.replace(&before, &after);
+                rewritten += 1;
+            }
+        }
+
+        (out, rewritten)
+    }
+}
+
+/// Hex SHA-256 of a file's content
+pub fn file_hash(path: &Path) -> Result<String> {
+    Ok(format!("{:x}", Sha256::digest(fs::read(path)?)))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_rewrite_uploaded_images() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir(dir.path().join("images")).unwrap();
+        fs::write(dir.path().join("images/old.png"), b"old").unwrap();
+        fs::write(dir.path().join("images/new.png"), b"new").unwrap();
+
+        let mut cache = ImageCache::default();
+        cache
+            .record(
+                &dir.path().join("images/old.png"),
+                "https://assets.st-note.com/img/old.png",
+            )
+            .unwrap();
+        cache.save(dir.path()).unwrap();
+        let cache = ImageCache::load(dir.path()).unwrap();
+
+        let body = "![図1](./images/old.png)\n\n![図2](./images/new.png)\n\n![](./images/old.png)";
+        let (body, rewritten) = cache.rewrite(body, dir.path());
+        assert_eq!(rewritten, 2);
+        assert_eq!(
+            body,
+            "![図1](https://assets.st-note.com/img/old.png)\n\n![図2](./images/new.png)\n\n![](https://assets.st-note.com/img/old.png)"
+        );
+
+        // A changed file no longer matches
+        fs::write(dir.path().join("images/old.png"), b"edited").unwrap();
+        let (_, rewritten) = cache.rewrite("![](./images/old.png)", dir.path());
+        assert_eq!(rewritten, 0);
+    }
+}
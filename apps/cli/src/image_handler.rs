@@ -1,10 +1,9 @@
 //! Image handling for articles
 
 use crate::error::{NoetError, Result};
-use base64::{engine::general_purpose, Engine as _};
+use crate::image_pipeline;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::fs;
 use std::path::{Path, PathBuf};
 
 /// Image data to be uploaded
@@ -14,7 +13,21 @@ pub struct ImageData {
     pub filename: String,
     pub caption: String,
     pub mime_type: String,
-    pub data: String, // base64 encoded
+    /// Base64 encoded; absent when the image was sent with `upload_image_chunk`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+    /// Upload that holds the data instead
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload_id: Option<String>,
+}
+
+/// A local image an article refers to, read only when it is sent
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalImage {
+    pub path: PathBuf,
+    /// Reference as written in the Markdown
+    pub local_path: String,
+    pub caption: String,
 }
 
 /// Parsed image reference from Markdown
@@ -37,34 +50,29 @@ pub fn extract_image_references(markdown: &str) -> Vec<ImageReference> {
         .collect()
 }
 
-/// Read image file and convert to base64
-pub fn read_image_as_base64(path: &Path) -> Result<(String, String)> {
-    // Read file as bytes
-    let bytes = fs::read(path)?;
-
-    // Determine MIME type from extension
-    let mime_type = match path.extension().and_then(|s| s.to_str()) {
-        Some("jpg") | Some("jpeg") => "image/jpeg",
-        Some("png") => "image/png",
-        Some("gif") => "image/gif",
-        Some("webp") => "image/webp",
-        _ => {
-            return Err(NoetError::InvalidInput(format!(
-                "Unsupported image format: {:?}",
-                path.extension()
-            )))
-        }
-    };
+/// Resolve an image reference to a local file, or None if it is already hosted
+pub fn local_image_path(base_dir: &Path, reference: &str) -> Option<PathBuf> {
+    // Skip URLs (http://, https://)
+    if reference.starts_with("http://") || reference.starts_with("https://") {
+        return None;
+    }
 
-    // Encode to base64
-    let base64_data = general_purpose::STANDARD.encode(&bytes);
+    // Skip already uploaded images (assets.st-note.com)
+    if reference.contains("st-note.com") {
+        return None;
+    }
 
-    Ok((mime_type.to_string(), base64_data))
+    // Resolve relative path
+    if Path::new(reference).is_absolute() {
+        Some(PathBuf::from(reference))
+    } else {
+        Some(base_dir.join(reference))
+    }
 }
 
-/// Process images from Markdown file
-/// Returns list of ImageData for upload
-pub fn process_images(markdown_path: &Path, markdown: &str) -> Result<Vec<ImageData>> {
+/// Find the local images a Markdown file refers to. Missing files are warned
+/// about and left out; formats that can't be uploaded are an error.
+pub fn find_images(markdown_path: &Path, markdown: &str) -> Result<Vec<LocalImage>> {
     let references = extract_image_references(markdown);
     let mut images = Vec::new();
 
@@ -73,21 +81,8 @@ pub fn process_images(markdown_path: &Path, markdown: &str) -> Result<Vec<ImageD
         .ok_or_else(|| NoetError::InvalidInput("Cannot determine base directory".to_string()))?;
 
     for ref_data in references {
-        // Skip URLs (http://, https://)
-        if ref_data.path.starts_with("http://") || ref_data.path.starts_with("https://") {
-            continue;
-        }
-
-        // Skip already uploaded images (assets.st-note.com)
-        if ref_data.path.contains("st-note.com") {
+        let Some(image_path) = local_image_path(base_dir, &ref_data.path) else {
             continue;
-        }
-
-        // Resolve relative path
-        let image_path = if Path::new(&ref_data.path).is_absolute() {
-            PathBuf::from(&ref_data.path)
-        } else {
-            base_dir.join(&ref_data.path)
         };
 
         // Check if file exists
@@ -100,21 +95,11 @@ pub fn process_images(markdown_path: &Path, markdown: &str) -> Result<Vec<ImageD
             continue;
         }
 
-        // Read and encode image
-        let (mime_type, base64_data) = read_image_as_base64(&image_path)?;
-
-        let filename = image_path
-            .file_name()
-            .and_then(|s| s.to_str())
-            .ok_or_else(|| NoetError::InvalidInput("Invalid filename".to_string()))?
-            .to_string();
-
-        images.push(ImageData {
-            local_path: ref_data.path.clone(),
-            filename,
-            caption: ref_data.caption.clone(),
-            mime_type,
-            data: base64_data,
+        image_pipeline::check(&image_path)?;
+        images.push(LocalImage {
+            path: image_path,
+            local_path: ref_data.path,
+            caption: ref_data.caption,
         });
     }
 
@@ -163,7 +148,16 @@ Some text.
         let refs = extract_image_references(markdown);
         assert_eq!(refs.len(), 3);
 
-        // process_images should skip URLs
-        // (This would need a temporary file to test fully)
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(dir.path().join("local.jpg"), b"").unwrap();
+        let images = find_images(&dir.path().join("article.md"), markdown).unwrap();
+        assert_eq!(
+            images,
+            vec![LocalImage {
+                path: dir.path().join("./local.jpg"),
+                local_path: "./local.jpg".to_string(),
+                caption: "Local".to_string(),
+            }]
+        );
     }
 }
@@ -0,0 +1,630 @@
+//! Preparing images for upload
+//!
+//! Every local image goes through here before it is sent to note.com. Images
+//! wider than `image_max_width` are scaled down, JPEG is recompressed at
+//! `image_quality` when that makes it smaller, and EXIF/XMP metadata (including
+//! GPS positions) is removed unless `strip_image_metadata` is off. BMP and TIFF
+//! become JPEG, or PNG if they have transparency, and SVG is rendered to PNG.
+//!
+//! HEIC and AVIF are rejected with a request to convert them first: there is no
+//! pure-Rust decoder for them.
+
+use crate::error::{NoetError, Result};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::{CompressionType, FilterType as PngFilter, PngEncoder};
+use image::imageops::FilterType;
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder, ImageReader, RgbaImage};
+use resvg::{tiny_skia, usvg};
+use std::fmt;
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+
+pub const DEFAULT_MAX_WIDTH: u32 = 1920;
+pub const DEFAULT_QUALITY: u8 = 85;
+
+/// How images are prepared before upload
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageOptions {
+    /// Images wider than this are scaled down; 0 keeps the original size
+    pub max_width: u32,
+    /// JPEG quality, 1-100
+    pub quality: u8,
+    /// Remove EXIF/XMP metadata such as GPS positions. When off, files that
+    /// need no resizing or conversion are uploaded byte for byte.
+    pub strip_metadata: bool,
+}
+
+impl Default for ImageOptions {
+    fn default() -> Self {
+        Self {
+            max_width: DEFAULT_MAX_WIDTH,
+            quality: DEFAULT_QUALITY,
+            strip_metadata: true,
+        }
+    }
+}
+
+/// An image ready to be uploaded
+#[derive(Debug, Clone)]
+pub struct ProcessedImage {
+    /// File name to upload as, with the extension of the output format
+    pub filename: String,
+    pub mime_type: &'static str,
+    pub bytes: Vec<u8>,
+    pub report: ImageReport,
+}
+
+/// What happened to an image, for the size-before/after line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageReport {
+    pub name: String,
+    pub original_size: u64,
+    pub size: u64,
+    /// Dimensions before and after, if the image was scaled down
+    pub resized: Option<((u32, u32), (u32, u32))>,
+    /// Source and output format, if the image was converted
+    pub converted: Option<(&'static str, &'static str)>,
+    pub metadata_stripped: bool,
+}
+
+impl fmt::Display for ImageReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} → {}",
+            self.name,
+            format_size(self.original_size),
+            format_size(self.size)
+        )?;
+
+        let mut notes = Vec::new();
+        if let Some(((w0, h0), (w1, h1))) = self.resized {
+            notes.push(format!("{w0}×{h0} → {w1}×{h1}"));
+        }
+        if let Some((from, to)) = self.converted {
+            notes.push(format!("{from} → {to}"));
+        }
+        if self.metadata_stripped {
+            notes.push("メタデータを削除".to_string());
+        }
+        if !notes.is_empty() {
+            write!(f, " ({})", notes.join("、"))?;
+        }
+        Ok(())
+    }
+}
+
+/// How a file is handled, by extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Jpeg,
+    Png,
+    Webp,
+    /// Uploaded as is, so animations survive
+    Gif,
+    /// Decoded and converted to JPEG or PNG
+    Raster(&'static str),
+    Svg,
+}
+
+fn kind(path: &Path) -> Result<Kind> {
+    let ext = path
+        .extension()
+        .and_then(|s| s.to_str())
+        .map(str::to_ascii_lowercase)
+        .unwrap_or_default();
+
+    match ext.as_str() {
+        "jpg" | "jpeg" => Ok(Kind::Jpeg),
+        "png" => Ok(Kind::Png),
+        "webp" => Ok(Kind::Webp),
+        "gif" => Ok(Kind::Gif),
+        "bmp" => Ok(Kind::Raster("BMP")),
+        "tif" | "tiff" => Ok(Kind::Raster("TIFF")),
+        "svg" => Ok(Kind::Svg),
+        "heic" | "heif" | "avif" => Err(NoetError::InvalidInput(format!(
+            "{} は {} のためアップロードできません。先に JPEG か PNG に変換してください",
+            path.display(),
+            ext.to_ascii_uppercase()
+        ))),
+        _ => Err(NoetError::InvalidInput(format!(
+            "対応していない画像形式です: {} (jpg, png, gif, webp, bmp, tiff, svg)",
+            path.display()
+        ))),
+    }
+}
+
+/// Fail with the reason if an image can't be uploaded, directly or after conversion
+pub fn check(path: &Path) -> Result<()> {
+    kind(path).map(|_| ())
+}
+
+/// Read an image and prepare it for upload
+pub fn process(path: &Path, options: &ImageOptions) -> Result<ProcessedImage> {
+    let kind = kind(path)?;
+    let original = fs::read(path)?;
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or_else(|| NoetError::InvalidInput("Invalid filename".to_string()))?;
+    let invalid = |e: &dyn fmt::Display| {
+        NoetError::InvalidInput(format!(
+            "{} を画像として読み込めません: {e}",
+            path.display()
+        ))
+    };
+
+    let mut report = ImageReport {
+        name: name.clone(),
+        original_size: original.len() as u64,
+        size: original.len() as u64,
+        resized: None,
+        converted: None,
+        metadata_stripped: false,
+    };
+
+    if kind == Kind::Gif {
+        return Ok(ProcessedImage {
+            filename: name,
+            mime_type: "image/gif",
+            bytes: original,
+            report,
+        });
+    }
+
+    let (mut img, orientation) = match kind {
+        Kind::Svg => (
+            render_svg(&original, options.max_width).map_err(|e| invalid(&e))?,
+            Orientation::NoTransforms,
+        ),
+        _ => decode(&original).map_err(|e| invalid(&e))?,
+    };
+
+    let (width, height) = (img.width(), img.height());
+    if options.max_width > 0 && width > options.max_width {
+        img = img.resize(options.max_width, u32::MAX, FilterType::Lanczos3);
+        report.resized = Some(((width, height), (img.width(), img.height())));
+    }
+
+    let source = match kind {
+        Kind::Jpeg => "JPEG",
+        Kind::Png => "PNG",
+        Kind::Webp => "WebP",
+        Kind::Raster(format) => format,
+        _ => "SVG",
+    };
+    let format = match kind {
+        Kind::Jpeg => "JPEG",
+        Kind::Png | Kind::Svg => "PNG",
+        _ if has_transparency(&img) => "PNG",
+        _ => "JPEG",
+    };
+    let mut bytes = encode(&img, format, options.quality).map_err(|e| invalid(&e))?;
+
+    // Keep the original encoding when nothing forces a re-encode and it isn't larger
+    let unchanged = matches!(kind, Kind::Jpeg | Kind::Png | Kind::Webp)
+        && report.resized.is_none()
+        && orientation == Orientation::NoTransforms;
+    let kept = match (unchanged, options.strip_metadata) {
+        (false, _) => None,
+        (true, true) => strip_metadata(kind, &original).filter(|kept| kept.len() <= bytes.len()),
+        // Recompressing would lose the metadata that was asked to be kept
+        (true, false) => Some(original.clone()),
+    };
+    let format = match kept {
+        Some(kept) => {
+            report.metadata_stripped = options.strip_metadata && kept.len() < original.len();
+            bytes = kept;
+            source
+        }
+        // Decoding and encoding again leaves every piece of metadata behind
+        None => {
+            report.metadata_stripped = has_metadata(kind, &original);
+            format
+        }
+    };
+
+    if source != format {
+        report.converted = Some((source, format));
+    }
+    report.size = bytes.len() as u64;
+
+    let (extension, mime_type) = match format {
+        "JPEG" => ("jpg", "image/jpeg"),
+        "PNG" => ("png", "image/png"),
+        _ => ("webp", "image/webp"),
+    };
+    let filename = if report.converted.is_some() {
+        Path::new(&name)
+            .with_extension(extension)
+            .to_string_lossy()
+            .into_owned()
+    } else {
+        name
+    };
+
+    Ok(ProcessedImage {
+        filename,
+        mime_type,
+        bytes,
+        report,
+    })
+}
+
+/// Decode a raster image, turned the way its EXIF orientation says
+fn decode(bytes: &[u8]) -> image::ImageResult<(DynamicImage, Orientation)> {
+    let mut decoder = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()?
+        .into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut img = DynamicImage::from_decoder(decoder)?;
+    img.apply_orientation(orientation);
+    Ok((img, orientation))
+}
+
+/// Render an SVG at its own size, or narrower if it exceeds `max_width`
+fn render_svg(bytes: &[u8], max_width: u32) -> std::result::Result<DynamicImage, String> {
+    let mut options = usvg::Options::default();
+    options.fontdb_mut().load_system_fonts();
+    let tree = usvg::Tree::from_data(bytes, &options).map_err(|e| e.to_string())?;
+
+    let size = tree.size();
+    // Render at the final width so shapes stay sharp instead of being resized afterwards
+    let scale = match max_width {
+        0 => 1.0,
+        max => (max as f32 / size.width()).min(1.0),
+    };
+    let width = (size.width() * scale).round().max(1.0) as u32;
+    let height = (size.height() * scale).round().max(1.0) as u32;
+    let mut pixmap = tiny_skia::Pixmap::new(width, height)
+        .ok_or_else(|| format!("サイズが不正です ({width}×{height})"))?;
+    resvg::render(
+        &tree,
+        tiny_skia::Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+
+    let pixels = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|p| {
+            let c = p.demultiply();
+            [c.red(), c.green(), c.blue(), c.alpha()]
+        })
+        .collect();
+    RgbaImage::from_raw(width, height, pixels)
+        .map(DynamicImage::ImageRgba8)
+        .ok_or_else(|| "描画に失敗しました".to_string())
+}
+
+fn has_transparency(img: &DynamicImage) -> bool {
+    img.color().has_alpha() && img.to_rgba8().pixels().any(|p| p.0[3] < u8::MAX)
+}
+
+fn encode(img: &DynamicImage, format: &str, quality: u8) -> image::ImageResult<Vec<u8>> {
+    let mut bytes = Vec::new();
+    if format == "JPEG" {
+        let encoder = JpegEncoder::new_with_quality(&mut bytes, quality.clamp(1, 100));
+        DynamicImage::ImageRgb8(img.to_rgb8()).write_with_encoder(encoder)?;
+    } else {
+        let encoder =
+            PngEncoder::new_with_quality(&mut bytes, CompressionType::Best, PngFilter::Adaptive);
+        img.write_with_encoder(encoder)?;
+    }
+    Ok(bytes)
+}
+
+fn has_metadata(kind: Kind, bytes: &[u8]) -> bool {
+    strip_metadata(kind, bytes).is_some_and(|stripped| stripped.len() < bytes.len())
+}
+
+/// The file without EXIF/XMP/text metadata, leaving the pixels untouched.
+/// None if the format can't be stripped or the file doesn't parse.
+fn strip_metadata(kind: Kind, bytes: &[u8]) -> Option<Vec<u8>> {
+    match kind {
+        Kind::Jpeg => strip_jpeg(bytes),
+        Kind::Png => strip_png(bytes),
+        Kind::Webp => strip_webp(bytes),
+        _ => None,
+    }
+}
+
+/// Drop APP1 (EXIF, XMP), APP13 (IPTC) and comment segments; ICC profiles stay
+fn strip_jpeg(bytes: &[u8]) -> Option<Vec<u8>> {
+    if !bytes.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut out = bytes[..2].to_vec();
+    let mut i = 2;
+    loop {
+        let marker = *bytes.get(i + 1)?;
+        if bytes[i] != 0xFF {
+            return None;
+        }
+        // Entropy-coded data follows the start of scan; keep everything from here
+        if marker == 0xDA {
+            out.extend_from_slice(&bytes[i..]);
+            return Some(out);
+        }
+        let len = usize::from(u16::from_be_bytes([*bytes.get(i + 2)?, *bytes.get(i + 3)?]));
+        let end = i + 2 + len;
+        let segment = bytes.get(i..end)?;
+        if !matches!(marker, 0xE1 | 0xED | 0xFE) {
+            out.extend_from_slice(segment);
+        }
+        i = end;
+    }
+}
+
+/// Drop eXIf, text and timestamp chunks
+fn strip_png(bytes: &[u8]) -> Option<Vec<u8>> {
+    const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+    if !bytes.starts_with(SIGNATURE) {
+        return None;
+    }
+    let mut out = SIGNATURE.to_vec();
+    let mut i = SIGNATURE.len();
+    while i < bytes.len() {
+        let len = u32::from_be_bytes(bytes.get(i..i + 4)?.try_into().ok()?) as usize;
+        let chunk_type = bytes.get(i + 4..i + 8)?;
+        let end = i + 12 + len;
+        let chunk = bytes.get(i..end)?;
+        if !matches!(chunk_type, b"eXIf" | b"tEXt" | b"zTXt" | b"iTXt" | b"tIME") {
+            out.extend_from_slice(chunk);
+        }
+        i = end;
+    }
+    Some(out)
+}
+
+/// Drop EXIF and XMP chunks and clear their flags in the VP8X header
+fn strip_webp(bytes: &[u8]) -> Option<Vec<u8>> {
+    if bytes.len() < 12 || &bytes[..4] != b"RIFF" || &bytes[8..12] != b"WEBP" {
+        return None;
+    }
+    let mut out = bytes[..12].to_vec();
+    let mut i = 12;
+    while i < bytes.len() {
+        let fourcc = bytes.get(i..i + 4)?;
+        let len = u32::from_le_bytes(bytes.get(i + 4..i + 8)?.try_into().ok()?) as usize;
+        // Chunks are padded to an even length
+        let end = (i + 8 + len + (len & 1)).min(bytes.len());
+        let chunk = bytes.get(i..end)?;
+        match fourcc {
+            b"EXIF" | b"XMP " => {}
+            b"VP8X" => {
+                let start = out.len();
+                out.extend_from_slice(chunk);
+                *out.get_mut(start + 8)? &= !(0x08 | 0x04);
+            }
+            _ => out.extend_from_slice(chunk),
+        }
+        i = end;
+    }
+    let riff_size = u32::try_from(out.len() - 8).ok()?;
+    out[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Some(out)
+}
+
+/// Size in B, KB or MB
+pub fn format_size(bytes: u64) -> String {
+    if bytes >= 1024 * 1024 {
+        format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+    } else if bytes >= 1024 {
+        format!("{} KB", bytes / 1024)
+    } else {
+        format!("{bytes} B")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage, Rgba};
+    use tempfile::TempDir;
+
+    /// A photo-like gradient, so JPEG doesn't compress it to nothing
+    fn photo(width: u32, height: u32) -> RgbImage {
+        RgbImage::from_fn(width, height, |x, y| {
+            Rgb([
+                (x * 7 % 256) as u8,
+                (y * 5 % 256) as u8,
+                ((x + y) % 256) as u8,
+            ])
+        })
+    }
+
+    const GPS_EXIF: &[u8] = b"Exif\0\0MM\0\x2a\0\0\0\x08GPSLatitude=35.6812";
+
+    /// A JPEG at quality 100 with an APP1 segment holding `exif`
+    fn jpeg_with_exif(width: u32, height: u32, exif: &[u8]) -> Vec<u8> {
+        let mut plain = Vec::new();
+        JpegEncoder::new_with_quality(&mut plain, 100)
+            .encode_image(&photo(width, height))
+            .unwrap();
+        let mut bytes = plain[..2].to_vec();
+        bytes.extend_from_slice(&[0xFF, 0xE1]);
+        bytes.extend_from_slice(&((exif.len() + 2) as u16).to_be_bytes());
+        bytes.extend_from_slice(exif);
+        bytes.extend_from_slice(&plain[2..]);
+        bytes
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|w| w == needle)
+    }
+
+    #[test]
+    fn test_large_jpeg_is_resized_and_stripped() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("photo.JPG");
+        fs::write(&path, jpeg_with_exif(800, 600, GPS_EXIF)).unwrap();
+
+        let options = ImageOptions {
+            max_width: 400,
+            ..ImageOptions::default()
+        };
+        let processed = process(&path, &options).unwrap();
+        assert_eq!(processed.filename, "photo.JPG");
+        assert_eq!(processed.mime_type, "image/jpeg");
+        assert!(!contains(&processed.bytes, b"GPSLatitude"));
+        assert!(processed.report.size < processed.report.original_size);
+        assert_eq!(processed.report.resized, Some(((800, 600), (400, 300))));
+        assert!(processed.report.metadata_stripped);
+
+        let img = image::load_from_memory(&processed.bytes).unwrap();
+        assert_eq!((img.width(), img.height()), (400, 300));
+    }
+
+    #[test]
+    fn test_metadata_kept_when_disabled() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("photo.jpg");
+        fs::write(&path, jpeg_with_exif(64, 48, GPS_EXIF)).unwrap();
+
+        let options = ImageOptions {
+            quality: 100,
+            strip_metadata: false,
+            ..ImageOptions::default()
+        };
+        let processed = process(&path, &options).unwrap();
+        assert!(contains(&processed.bytes, b"GPSLatitude"));
+        assert!(!processed.report.metadata_stripped);
+
+        // Stripped either way, whether or not recompressing pays off
+        let stripped = process(
+            &path,
+            &ImageOptions {
+                quality: 100,
+                ..ImageOptions::default()
+            },
+        )
+        .unwrap();
+        assert!(!contains(&stripped.bytes, b"GPSLatitude"));
+        assert!(stripped.report.metadata_stripped);
+        assert_eq!(stripped.report.resized, None);
+    }
+
+    #[test]
+    fn test_exif_orientation_is_applied() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("portrait.jpg");
+        // Orientation 6: the camera was turned 90° clockwise
+        let exif = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01\0\x06\0\0\0\0\0\0";
+        fs::write(&path, jpeg_with_exif(40, 20, exif)).unwrap();
+
+        let processed = process(&path, &ImageOptions::default()).unwrap();
+        let img = image::load_from_memory(&processed.bytes).unwrap();
+        assert_eq!((img.width(), img.height()), (20, 40));
+        assert!(processed.report.metadata_stripped);
+    }
+
+    #[test]
+    fn test_png_text_chunks_are_removed() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("shot.png");
+        let mut png = Vec::new();
+        RgbaImage::from_pixel(8, 8, Rgba([0, 128, 255, 255]))
+            .write_with_encoder(PngEncoder::new(&mut png))
+            .unwrap();
+        // Insert a tEXt chunk after IHDR (the CRC isn't checked by the stripper)
+        let mut with_text = png[..33].to_vec();
+        let text = b"Author\0somebody";
+        with_text.extend_from_slice(&(text.len() as u32).to_be_bytes());
+        with_text.extend_from_slice(b"tEXt");
+        with_text.extend_from_slice(text);
+        with_text.extend_from_slice(&[0; 4]);
+        with_text.extend_from_slice(&png[33..]);
+
+        assert_eq!(strip_png(&with_text).unwrap(), png);
+
+        fs::write(&path, &png).unwrap();
+        let processed = process(&path, &ImageOptions::default()).unwrap();
+        assert_eq!(processed.mime_type, "image/png");
+        assert!(!processed.report.metadata_stripped);
+        assert_eq!(processed.report.converted, None);
+    }
+
+    #[test]
+    fn test_conversions() {
+        let dir = TempDir::new().unwrap();
+
+        let bmp = dir.path().join("scan.bmp");
+        DynamicImage::ImageRgb8(photo(32, 16)).save(&bmp).unwrap();
+        let processed = process(&bmp, &ImageOptions::default()).unwrap();
+        assert_eq!(processed.filename, "scan.jpg");
+        assert_eq!(processed.mime_type, "image/jpeg");
+        assert_eq!(processed.report.converted, Some(("BMP", "JPEG")));
+
+        // Transparency survives as PNG
+        let tiff = dir.path().join("logo.tiff");
+        RgbaImage::from_fn(16, 16, |x, _| {
+            Rgba([255, 0, 0, if x < 8 { 0 } else { 255 }])
+        })
+        .save(&tiff)
+        .unwrap();
+        let processed = process(&tiff, &ImageOptions::default()).unwrap();
+        assert_eq!(processed.filename, "logo.png");
+        assert_eq!(processed.report.converted, Some(("TIFF", "PNG")));
+
+        let svg = dir.path().join("diagram.svg");
+        fs::write(
+            &svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="200" height="100"><rect width="200" height="100" fill="red"/></svg>"#,
+        )
+        .unwrap();
+        let options = ImageOptions {
+            max_width: 100,
+            ..ImageOptions::default()
+        };
+        let processed = process(&svg, &options).unwrap();
+        assert_eq!(processed.filename, "diagram.png");
+        let img = image::load_from_memory(&processed.bytes)
+            .unwrap()
+            .to_rgba8();
+        assert_eq!(img.dimensions(), (100, 50));
+        assert_eq!(img.get_pixel(50, 25), &Rgba([255, 0, 0, 255]));
+
+        let heic = dir.path().join("IMG_0001.HEIC");
+        fs::write(&heic, b"heic").unwrap();
+        let err = process(&heic, &ImageOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("JPEG か PNG に変換"), "{err}");
+        assert!(check(&heic).is_err());
+    }
+
+    #[test]
+    fn test_webp_metadata_chunks() {
+        let mut riff = b"RIFF\0\0\0\0WEBP".to_vec();
+        riff.extend_from_slice(b"VP8X\x0a\0\0\0\x0c\0\0\0\0\0\0\0\0\0");
+        riff.extend_from_slice(b"VP8L\x03\0\0\0abc\0");
+        riff.extend_from_slice(b"EXIF\x04\0\0\0gps!");
+        let size = (riff.len() - 8) as u32;
+        riff[4..8].copy_from_slice(&size.to_le_bytes());
+
+        let stripped = strip_webp(&riff).unwrap();
+        assert!(!contains(&stripped, b"EXIF"));
+        assert_eq!(stripped[20], 0, "EXIF and XMP flags cleared");
+        assert_eq!(
+            u32::from_le_bytes(stripped[4..8].try_into().unwrap()) as usize,
+            stripped.len() - 8
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let report = ImageReport {
+            name: "IMG_0001.bmp".to_string(),
+            original_size: 4 * 1024 * 1024 + 200 * 1024,
+            size: 318 * 1024,
+            resized: Some(((4032, 3024), (1920, 1440))),
+            converted: Some(("BMP", "JPEG")),
+            metadata_stripped: false,
+        };
+        assert_eq!(
+            report.to_string(),
+            "IMG_0001.bmp: 4.2 MB → 318 KB (4032×3024 → 1920×1440、BMP → JPEG)"
+        );
+    }
+}
@@ -0,0 +1,321 @@
+//! Static checks for article files before they are published
+//!
+//! Reports Markdown the note.com editor would render differently (the rewrites made
+//! by `converters::note_markdown`), plus editorial problems such as a missing title,
+//! duplicate tags or images that would not be uploaded.
+
+use crate::converters::note_markdown::{self, ChangeKind, ImageRef, TransformOptions};
+use crate::error::NoetError;
+use crate::frontmatter::{self, Document};
+use crate::image_handler;
+use crate::image_pipeline;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+
+/// Longest title accepted by `noet lint`
+pub const MAX_TITLE_CHARS: usize = 140;
+
+/// Most hashtags an article may have
+pub const MAX_TAGS: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A problem found at a line of a file
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diagnostic {
+    pub path: String,
+    pub line: usize,
+    pub severity: Severity,
+    pub rule: &'static str,
+    pub message: String,
+}
+
+/// Check one article. `path` is used for messages and to resolve local images.
+pub fn lint(path: &Path, content: &str, options: &TransformOptions) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut report = |line: usize, severity: Severity, rule: &'static str, message: String| {
+        diagnostics.push(Diagnostic {
+            path: path.display().to_string(),
+            line,
+            severity,
+            rule,
+            message,
+        });
+    };
+
+    let mut document = match Document::parse(content) {
+        Ok(document) => document,
+        Err(NoetError::Frontmatter { location, message }) => {
+            let line = location.parse().unwrap_or(1);
+            report(line, Severity::Error, "frontmatter", message);
+            return diagnostics;
+        }
+        Err(e) => {
+            report(1, Severity::Error, "frontmatter", e.to_string());
+            return diagnostics;
+        }
+    };
+
+    // Title, as `prepare_article` resolves it
+    let title_line = key_line(content, "title").or_else(|| h1_line(content));
+    let title = document.take_title();
+    let title_chars = title.chars().count();
+    if title.trim().is_empty() {
+        report(
+            1,
+            Severity::Error,
+            "title-missing",
+            "タイトルがありません。frontmatter の title か `# ` 見出しを書いてください".to_string(),
+        );
+    } else if title_chars > MAX_TITLE_CHARS {
+        report(
+            title_line.unwrap_or(1),
+            Severity::Error,
+            "title-too-long",
+            format!("タイトルが長すぎます ({title_chars}文字, 最大 {MAX_TITLE_CHARS}文字)"),
+        );
+    }
+
+    // Tags
+    let tags = &document.frontmatter.tags;
+    let tags_line = key_line(content, "tags").unwrap_or(1);
+    let mut seen = HashSet::new();
+    for tag in tags {
+        if !seen.insert(tag.to_lowercase()) {
+            report(
+                tags_line,
+                Severity::Warning,
+                "tag-duplicate",
+                format!("タグ #{tag} が重複しています"),
+            );
+        }
+    }
+    if tags.len() > MAX_TAGS {
+        report(
+            tags_line,
+            Severity::Error,
+            "tag-too-many",
+            format!("タグが多すぎます ({}個, 最大 {MAX_TAGS}個)", tags.len()),
+        );
+    }
+
+    let first_line = frontmatter::trimmed_body_line(content, &document.body);
+    let body = document.body.trim();
+
+    // Constructs the editor doesn't understand
+    for change in note_markdown::transform(body, options).changes {
+        let (rule, message) = match change.kind {
+            ChangeKind::Heading => (
+                "heading-level",
+                format!(
+                    "note.com の見出しは h2 と h3 だけです。`{}` に変換されます",
+                    change.after
+                ),
+            ),
+            ChangeKind::Italic => (
+                "italic",
+                "斜体は表示されません。通常テキストに変換されます".to_string(),
+            ),
+            ChangeKind::InlineCode => (
+                "inline-code",
+                format!(
+                    "インラインコードは表示されません。{} に変換されます",
+                    change.after
+                ),
+            ),
+            ChangeKind::Table => (
+                "table",
+                format!("表は表示されません。{}に変換されます", change.after),
+            ),
+            ChangeKind::Image => (
+                "remote-image",
+                "URL の画像は表示されません。リンクに変換されます".to_string(),
+            ),
+            ChangeKind::Escape => (
+                "escaped-marker",
+                format!(
+                    "エディタはエスケープを解釈しません。`{}` として貼り付けられます",
+                    change.after
+                ),
+            ),
+        };
+        report(
+            first_line + change.line - 1,
+            Severity::Warning,
+            rule,
+            message,
+        );
+    }
+
+    // Images
+    let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+    for ImageRef { line, alt, url } in note_markdown::images(body) {
+        let line = first_line + line - 1;
+        if alt.trim().is_empty() {
+            report(
+                line,
+                Severity::Warning,
+                "image-alt-empty",
+                format!("画像 {url} に代替テキストがありません"),
+            );
+        }
+
+        let Some(image_path) = image_handler::local_image_path(base_dir, &url) else {
+            continue;
+        };
+        if !image_path.exists() {
+            report(
+                line,
+                Severity::Error,
+                "image-not-found",
+                format!(
+                    "画像ファイルが見つかりません: {} (アップロードされません)",
+                    image_path.display()
+                ),
+            );
+        } else if let Err(e) = image_pipeline::check(&image_path) {
+            let message = match e {
+                NoetError::InvalidInput(message) => message,
+                other => other.to_string(),
+            };
+            report(line, Severity::Error, "image-format", message);
+        }
+    }
+
+    diagnostics.sort_by_key(|d| d.line);
+    diagnostics
+}
+
+/// 1-based line of a top-level frontmatter key
+fn key_line(content: &str, key: &str) -> Option<usize> {
+    let mut lines = content.lines().enumerate();
+    if lines
+        .next()
+        .map(|(_, l)| l.trim_start_matches('\u{feff}').trim_end())
+        != Some("---")
+    {
+        return None;
+    }
+
+    lines
+        .take_while(|(_, line)| !matches!(line.trim_end(), "---" | "..."))
+        .find(|(_, line)| {
+            line.strip_prefix(key)
+                .is_some_and(|rest| rest.trim_start().starts_with(':'))
+        })
+        .map(|(i, _)| i + 1)
+}
+
+/// 1-based line of the first `# ` heading of the body
+fn h1_line(content: &str) -> Option<usize> {
+    let start = frontmatter::body_line(content);
+    content
+        .lines()
+        .enumerate()
+        .skip(start - 1)
+        .find(|(_, line)| line.trim().starts_with("# "))
+        .map(|(i, _)| i + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn rules(diagnostics: &[Diagnostic]) -> Vec<(usize, &'static str)> {
+        diagnostics.iter().map(|d| (d.line, d.rule)).collect()
+    }
+
+    fn run(content: &str) -> Vec<Diagnostic> {
+        lint(
+            Path::new("article.md"),
+            content,
+            &TransformOptions::default(),
+        )
+    }
+
+    #[test]
+    fn test_clean_article() {
+        let content = "---\ntitle: タイトル\ntags: [rust]\n---\n\n## 見出し\n\n**太字** の本文\n";
+        assert!(run(content).is_empty());
+    }
+
+    #[test]
+    fn test_unsupported_markdown_lines() {
+        let content = "---\ntitle: T\n---\n\n*斜体*\n\n`code`\n\n| a | b |\n|---|---|\n| 1 | 2 |\n\n#### 深い\n";
+        assert_eq!(
+            rules(&run(content)),
+            vec![
+                (5, "italic"),
+                (7, "inline-code"),
+                (9, "table"),
+                (13, "heading-level"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_title_checks() {
+        assert_eq!(rules(&run("本文だけ\n")), vec![(1, "title-missing")]);
+
+        // The H1 is the title, not a heading to demote
+        assert!(run("# タイトル\n\n本文\n").is_empty());
+
+        let long = format!("---\ntags: [a]\ntitle: {}\n---\n", "あ".repeat(141));
+        assert_eq!(rules(&run(&long)), vec![(3, "title-too-long")]);
+    }
+
+    #[test]
+    fn test_tag_checks() {
+        let content = "---\ntitle: T\ntags: [Rust, rust, a, b, c, d, e, f, g, h, i]\n---\n";
+        let diagnostics = run(content);
+        assert_eq!(
+            rules(&diagnostics),
+            vec![(3, "tag-duplicate"), (3, "tag-too-many")]
+        );
+        assert_eq!(diagnostics[1].severity, Severity::Error);
+    }
+
+    #[test]
+    fn test_image_checks() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("article.md");
+        fs::write(temp_dir.path().join("ok.png"), b"png").unwrap();
+        fs::write(temp_dir.path().join("scan.bmp"), b"bmp").unwrap();
+        fs::write(temp_dir.path().join("photo.heic"), b"heic").unwrap();
+
+        let content = "# T\n\n![図](ok.png)\n![](missing.png)\n![heic](photo.heic)\n![bmp](scan.bmp)\n\n```\n![](ignored.png)\n```\n";
+        let diagnostics = lint(&path, content, &TransformOptions::default());
+        assert_eq!(
+            rules(&diagnostics),
+            vec![
+                (4, "image-alt-empty"),
+                (4, "image-not-found"),
+                (5, "image-format"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_frontmatter_error() {
+        let diagnostics = run("---\ntitle: T\nstatus: publish\n---\n");
+        assert_eq!(rules(&diagnostics), vec![(3, "frontmatter")]);
+    }
+}
@@ -1,30 +1,65 @@
+mod bridge;
+mod browser;
 mod cli;
 mod commands;
+mod config;
 mod converters;
+#[cfg(unix)]
+mod daemon;
 mod editor;
 mod error;
+mod extension_auth;
 mod extension_client;
+mod frontmatter;
+mod host_files;
+mod image_cache;
 mod image_handler;
+mod image_pipeline;
+mod lint;
+mod merge;
 mod native_messaging;
+mod protocol;
+mod sync;
+mod template;
 mod workspace;
 
 use clap::Parser;
-use cli::{Cli, Commands, TemplateCommands};
+use cli::{Cli, Commands, ConfigCommands, DaemonCommands, TemplateCommands};
 use colored::Colorize;
+use std::path::PathBuf;
 
 #[tokio::main]
 async fn main() {
-    env_logger::init();
+    // The browser starts the host with arguments of its own
+    if native_messaging::launched_by_browser(&std::env::args().collect::<Vec<_>>()) {
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+        if let Err(e) = native_messaging::run().await {
+            log::error!("{e}");
+            std::process::exit(e.exit_code());
+        }
+        return;
+    }
+
+    let cli = Cli::parse();
 
-    if let Err(e) = run().await {
+    // The daemon's output goes to its log file, so say what it's doing by default
+    let default_level = match cli.command {
+        Some(Commands::Daemon(DaemonCommands::Run)) => "info",
+        _ => "warn",
+    };
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(default_level))
+        .init();
+
+    if let Err(e) = run(cli).await {
         eprintln!("{} {}", "エラー:".red().bold(), e);
-        std::process::exit(1);
+        if let Some(hint) = e.hint() {
+            eprintln!("{} {}", "ヒント:".yellow(), hint);
+        }
+        std::process::exit(e.exit_code());
     }
 }
 
-async fn run() -> error::Result<()> {
-    let cli = Cli::parse();
-
+async fn run(cli: Cli) -> error::Result<()> {
     // Run as Native Messaging host if flag is set
     if cli.native_messaging {
         return native_messaging::run().await;
@@ -44,18 +79,47 @@ async fn run() -> error::Result<()> {
     };
 
     match command {
-        Commands::Setup => {
-            commands::setup::run_setup().await?;
+        Commands::Setup {
+            upgrade,
+            from,
+            browser,
+        } => {
+            if upgrade {
+                commands::setup::run_upgrade(from.as_deref()).await?;
+            } else {
+                commands::setup::run_setup(from.as_deref(), browser).await?;
+            }
         }
 
         Commands::Init { path } => {
             commands::workspace::init(path).await?;
         }
 
+        Commands::New {
+            title,
+            template,
+            vars,
+            draft,
+            no_edit,
+        } => {
+            commands::new::run(
+                title.as_deref(),
+                template.as_deref(),
+                &vars,
+                draft,
+                !no_edit,
+            )
+            .await?;
+        }
+
         Commands::Ping => {
             commands::extension::ping().await?;
         }
 
+        Commands::Doctor { bundle } => {
+            commands::doctor::run(bundle).await?;
+        }
+
         Commands::Auth => {
             commands::extension::check_auth().await?;
         }
@@ -69,15 +133,78 @@ async fn run() -> error::Result<()> {
             key,
             save,
         } => {
-            commands::extension::get_article(&username, &key, save.as_deref()).await?;
+            commands::extension::get_article(username.as_deref(), &key, save.as_deref()).await?;
+        }
+
+        Commands::Export {
+            key,
+            all,
+            skip_drafts,
+            username,
+            output,
+        } => {
+            commands::export::run(
+                key.as_deref(),
+                all,
+                skip_drafts,
+                username.as_deref(),
+                output.as_deref(),
+            )
+            .await?;
+        }
+
+        Commands::Create {
+            file,
+            draft,
+            publish,
+        } => {
+            commands::extension::create_article(&file, draft_flag(draft, publish)).await?;
+        }
+
+        Commands::Update {
+            key,
+            file,
+            legacy_file,
+            draft,
+            publish,
+            force,
+        } => {
+            let (key, file) = update_target(key, file, legacy_file)?;
+            commands::extension::update_article(
+                key.as_deref(),
+                &file,
+                draft_flag(draft, publish),
+                force,
+            )
+            .await?;
+        }
+
+        Commands::Status { offline } => {
+            commands::sync::status(offline).await?;
+        }
+
+        Commands::Push {
+            draft,
+            publish,
+            force,
+        } => {
+            commands::sync::push(draft_flag(draft, publish), force).await?;
+        }
+
+        Commands::Pull { username } => {
+            commands::sync::pull(username.as_deref()).await?;
         }
 
-        Commands::Create { file, draft } => {
-            commands::extension::create_article(&file, draft).await?;
+        Commands::Merge { file, username } => {
+            commands::sync::merge(&file, username.as_deref()).await?;
         }
 
-        Commands::Update { key, file, draft } => {
-            commands::extension::update_article(&key, &file, draft).await?;
+        Commands::Lint {
+            paths,
+            format,
+            strict,
+        } => {
+            commands::lint::run(&paths, format, strict)?;
         }
 
         Commands::Delete { key } => {
@@ -98,7 +225,64 @@ async fn run() -> error::Result<()> {
                 commands::template::remove_template(&name)?;
             }
         },
+
+        Commands::Config(config_cmd) => match config_cmd {
+            ConfigCommands::List => {
+                commands::config::list()?;
+            }
+            ConfigCommands::Get { key } => {
+                commands::config::get(&key)?;
+            }
+            ConfigCommands::Set { key, value, global } => {
+                commands::config::set(&key, &value, global)?;
+            }
+        },
+
+        Commands::Daemon(daemon_cmd) => match daemon_cmd {
+            DaemonCommands::Status => {
+                commands::daemon::status().await?;
+            }
+            DaemonCommands::Stop => {
+                commands::daemon::stop().await?;
+            }
+            DaemonCommands::Run => {
+                commands::daemon::run().await?;
+            }
+        },
     }
 
     Ok(())
 }
+
+/// Key and file of `update`, accepting the old `update <KEY> <FILE>` form with a warning
+fn update_target(
+    key: Option<String>,
+    file: PathBuf,
+    legacy_file: Option<PathBuf>,
+) -> error::Result<(Option<String>, PathBuf)> {
+    let Some(legacy_file) = legacy_file else {
+        return Ok((key, file));
+    };
+    if key.is_some() {
+        return Err(error::NoetError::InvalidInput(
+            "記事キーは --key か最初の引数のどちらかで指定してください".to_string(),
+        ));
+    }
+
+    let key = file.to_string_lossy().into_owned();
+    println!(
+        "{} `noet update <KEY> <FILE>` は非推奨です。`noet update {} --key {key}` を使ってください",
+        "!".yellow(),
+        legacy_file.display()
+    );
+    Ok((Some(key), legacy_file))
+}
+
+/// Turn `--draft` / `--publish` into an explicit choice, or None to use config
+fn draft_flag(draft: bool, publish: bool) -> Option<bool> {
+    match (draft, publish) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    }
+}
@@ -0,0 +1,194 @@
+//! Line-based three-way merge (diff3)
+//!
+//! Used by `noet merge` to combine local edits with changes made in the note.com
+//! editor, starting from the version recorded at the last sync.
+
+pub const MARKER_LOCAL: &str = "<<<<<<< local";
+pub const MARKER_SEPARATOR: &str = "=======";
+pub const MARKER_REMOTE: &str = ">>>>>>> note.com";
+
+/// Result of a three-way merge
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergeResult {
+    pub text: String,
+    /// Number of conflict blocks written with markers
+    pub conflicts: usize,
+}
+
+impl MergeResult {
+    pub fn is_clean(&self) -> bool {
+        self.conflicts == 0
+    }
+}
+
+/// Merge `local` and `remote`, both derived from `base`
+pub fn merge(base: &str, local: &str, remote: &str) -> MergeResult {
+    let base: Vec<&str> = base.lines().collect();
+    let local: Vec<&str> = local.lines().collect();
+    let remote: Vec<&str> = remote.lines().collect();
+
+    let to_local = matching_lines(&base, &local);
+    let to_remote = matching_lines(&base, &remote);
+
+    let mut out: Vec<&str> = Vec::new();
+    let mut conflicts = 0;
+    let (mut o, mut a, mut b) = (0, 0, 0);
+
+    loop {
+        // Stable run: base line matched at the current position on both sides
+        while o < base.len() && to_local[o] == Some(a) && to_remote[o] == Some(b) {
+            out.push(base[o]);
+            o += 1;
+            a += 1;
+            b += 1;
+        }
+
+        // Next base line that both sides kept
+        let next = (o..base.len()).find(
+            |&i| matches!((to_local[i], to_remote[i]), (Some(x), Some(y)) if x >= a && y >= b),
+        );
+
+        let (o_end, a_end, b_end) = match next {
+            Some(i) => (i, to_local[i].unwrap(), to_remote[i].unwrap()),
+            None => (base.len(), local.len(), remote.len()),
+        };
+
+        let base_chunk = &base[o..o_end];
+        let local_chunk = &local[a..a_end];
+        let remote_chunk = &remote[b..b_end];
+
+        if local_chunk == base_chunk {
+            out.extend_from_slice(remote_chunk);
+        } else if remote_chunk == base_chunk || local_chunk == remote_chunk {
+            out.extend_from_slice(local_chunk);
+        } else {
+            conflicts += 1;
+            out.push(MARKER_LOCAL);
+            out.extend_from_slice(local_chunk);
+            out.push(MARKER_SEPARATOR);
+            out.extend_from_slice(remote_chunk);
+            out.push(MARKER_REMOTE);
+        }
+
+        if next.is_none() {
+            break;
+        }
+        o = o_end;
+        a = a_end;
+        b = b_end;
+    }
+
+    let mut text = out.join("\n");
+    if !text.is_empty() {
+        text.push('\n');
+    }
+
+    MergeResult { text, conflicts }
+}
+
+/// Whether text still contains unresolved conflict markers
+pub fn has_conflict_markers(text: &str) -> bool {
+    text.lines()
+        .any(|line| line.starts_with(MARKER_LOCAL) || line.starts_with(MARKER_REMOTE))
+}
+
+/// For each line of `base`, the index of the matching line in `other` (LCS)
+fn matching_lines(base: &[&str], other: &[&str]) -> Vec<Option<usize>> {
+    let (n, m) = (base.len(), other.len());
+
+    // lcs[i][j] = LCS length of base[i..] and other[j..]
+    let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if base[i] == other[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut result = vec![None; n];
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if base[i] == other[j] {
+            result[i] = Some(j);
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_non_overlapping_changes_merge_cleanly() {
+        let base = "title\n\none\ntwo\nthree\n";
+        let local = "title\n\none (local)\ntwo\nthree\n";
+        let remote = "title\n\none\ntwo\nthree (typo fixed)\n";
+
+        let result = merge(base, local, remote);
+        assert!(result.is_clean());
+        assert_eq!(
+            result.text,
+            "title\n\none (local)\ntwo\nthree (typo fixed)\n"
+        );
+    }
+
+    #[test]
+    fn test_same_change_on_both_sides() {
+        let base = "a\nb\nc\n";
+        let changed = "a\nB\nc\n";
+        let result = merge(base, changed, changed);
+        assert!(result.is_clean());
+        assert_eq!(result.text, changed);
+    }
+
+    #[test]
+    fn test_conflicting_changes_get_markers() {
+        let base = "a\nb\nc\n";
+        let local = "a\nlocal\nc\n";
+        let remote = "a\nremote\nc\n";
+
+        let result = merge(base, local, remote);
+        assert_eq!(result.conflicts, 1);
+        assert_eq!(
+            result.text,
+            "a\n<<<<<<< local\nlocal\n=======\nremote\n>>>>>>> note.com\nc\n"
+        );
+        assert!(has_conflict_markers(&result.text));
+    }
+
+    #[test]
+    fn test_insertions_and_deletions() {
+        let base = "1\n2\n3\n4\n";
+        let local = "0\n1\n2\n3\n4\n";
+        let remote = "1\n2\n4\n5\n";
+
+        let result = merge(base, local, remote);
+        assert!(result.is_clean());
+        assert_eq!(result.text, "0\n1\n2\n4\n5\n");
+    }
+
+    #[test]
+    fn test_unchanged_sides() {
+        let base = "x\ny\n";
+        assert_eq!(merge(base, base, base).text, base);
+        assert_eq!(merge(base, "x\nz\n", base).text, "x\nz\n");
+        assert_eq!(merge("", "", "new\n").text, "new\n");
+    }
+
+    #[test]
+    fn test_has_conflict_markers() {
+        assert!(!has_conflict_markers("a\n=======\nb\n"));
+        assert!(has_conflict_markers("<<<<<<< local\n"));
+    }
+}
@@ -4,64 +4,78 @@
 //! - Messages are length-prefixed (4 bytes, little-endian)
 //! - Message body is JSON
 //! - Communication via stdin/stdout
+//!
+//! The browser starts a host for every `sendNativeMessage`, which answers one
+//! host command, and one for the extension's long-lived `connectNative` port.
+//! On that port the extension sends `open_relay`, and the host attaches to the
+//! noet daemon's socket: requests from noet commands are written to the port and
+//! the extension's replies go back to the daemon. The extension is then reachable
+//! without the WebSocket, e.g. where listening on a TCP port isn't allowed.
+//!
+//! The host also serves files of the configured workspace (see [`host_files`]),
+//! so the extension can push a local article or save one it's showing.
+//!
+//! [`host_files`]: crate::host_files
 
-use crate::error::Result;
+use crate::config;
+use crate::error::{NoetError, Result};
+use crate::host_files::HostWorkspace;
+use crate::protocol::{
+    Command, DebugMode, GetDebugMode, GetEndpoint, GetToken, ListWorkspaceArticles, OpenRelay,
+    PathParams, ReadWorkspaceArticle, ReadWorkspaceImage, RecordWorkspacePush, Request, Response,
+    SetDebugMode, SetDebugModeResult, TokenData, WriteWorkspaceArticle,
+};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
 use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
 
-/// Request from browser extension
-#[derive(Debug, Deserialize)]
-pub struct NativeRequest {
-    pub id: String,
-    pub command: String,
-    #[serde(default)]
-    pub params: serde_json::Value,
-}
+/// File holding what the host remembers between runs
+const STATE_FILE: &str = "host-state.json";
 
-/// Response to browser extension
-#[derive(Debug, Serialize)]
-pub struct NativeResponse {
-    pub id: String,
-    pub status: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub data: Option<serde_json::Value>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub error: Option<NativeError>,
-}
+/// Largest message the browser accepts from the host
+pub const MAX_MESSAGE_BYTES: usize = 1024 * 1024;
 
-#[derive(Debug, Serialize)]
-pub struct NativeError {
-    pub code: String,
-    pub message: String,
+/// State kept for the extension, which loses its own when the browser restarts
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+struct HostState {
+    #[serde(default)]
+    debug_mode: bool,
 }
 
-impl NativeResponse {
-    pub fn success(id: String, data: serde_json::Value) -> Self {
-        Self {
-            id,
-            status: "success".to_string(),
-            data: Some(data),
-            error: None,
-        }
+impl HostState {
+    fn load(path: &Path) -> Self {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
     }
 
-    pub fn error(id: String, code: &str, message: &str) -> Self {
-        Self {
-            id,
-            status: "error".to_string(),
-            data: None,
-            error: Some(NativeError {
-                code: code.to_string(),
-                message: message.to_string(),
-            }),
+    fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
         }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
     }
 }
 
-/// Read a message from stdin using Native Messaging protocol
-fn read_message() -> Result<Option<NativeRequest>> {
-    let mut stdin = io::stdin().lock();
+fn state_path() -> Result<PathBuf> {
+    Ok(config::global_config_dir()?.join(STATE_FILE))
+}
+
+/// Whether the browser started us as its host. Chrome passes the caller's
+/// origin, Firefox the path of the host manifest and the add-on ID.
+pub fn launched_by_browser(args: &[String]) -> bool {
+    args.get(1).is_some_and(|arg| {
+        arg.starts_with("chrome-extension://")
+            || arg.ends_with(&format!("{}.json", crate::browser::HOST_NAME))
+    })
+}
 
+/// Read a message from stdin using Native Messaging protocol
+fn read_message(stdin: &mut impl Read) -> Result<Option<Value>> {
     // Read 4-byte length prefix
     let mut len_bytes = [0u8; 4];
     match stdin.read_exact(&mut len_bytes) {
@@ -76,31 +90,34 @@ fn read_message() -> Result<Option<NativeRequest>> {
     let mut buffer = vec![0u8; len];
     stdin.read_exact(&mut buffer)?;
 
-    let request: NativeRequest = serde_json::from_slice(&buffer)?;
-    Ok(Some(request))
+    Ok(Some(serde_json::from_slice(&buffer)?))
 }
 
 /// Write a message to stdout using Native Messaging protocol
-fn write_message(response: &NativeResponse) -> Result<()> {
-    let json = serde_json::to_vec(response)?;
+fn write_message(message: &impl Serialize) -> Result<()> {
+    write_bytes(&serde_json::to_vec(message)?)
+}
+
+fn write_bytes(json: &[u8]) -> Result<()> {
     let len = json.len() as u32;
 
+    // One lock for the whole message, so relayed requests and replies don't interleave
     let mut stdout = io::stdout().lock();
     stdout.write_all(&len.to_le_bytes())?;
-    stdout.write_all(&json)?;
+    stdout.write_all(json)?;
     stdout.flush()?;
 
     Ok(())
 }
 
-/// Handle a single command from the extension
-async fn handle_command(request: NativeRequest) -> NativeResponse {
+/// Handle a single host command from the extension
+fn handle_command(request: Request, state_path: &Path) -> Response {
     let id = request.id.clone();
 
     match request.command.as_str() {
         "ping" => {
             let version = env!("CARGO_PKG_VERSION");
-            NativeResponse::success(
+            Response::success(
                 id,
                 serde_json::json!({
                     "version": version,
@@ -109,80 +126,91 @@ async fn handle_command(request: NativeRequest) -> NativeResponse {
             )
         }
 
-        "check_auth" => {
-            // TODO: Implement actual auth check
-            // For now, return placeholder
-            NativeResponse::success(
+        // Only the extension listed in the host manifest can reach us,
+        // so it may read the WebSocket token
+        GetToken::NAME => match crate::extension_auth::load_token() {
+            Ok(Some(token)) => Response::success(id, serde_json::json!(TokenData { token })),
+            Ok(None) => Response::error(
                 id,
-                serde_json::json!({
-                    "logged_in": false,
-                    "username": null
-                }),
-            )
-        }
+                "NO_TOKEN",
+                "認証トークンがありません。`noet setup` を実行してください",
+            ),
+            Err(e) => Response::error(id, "TOKEN_ERROR", &e.to_string()),
+        },
 
-        "list_articles" => {
-            // TODO: This will be handled by the extension, not the host
-            // The host just relays file operations
-            NativeResponse::error(
+        // Where the WebSocket server is listening, which may not be the default port
+        GetEndpoint::NAME => match crate::bridge::read() {
+            Ok(Some(endpoint)) => Response::success(id, serde_json::json!(endpoint)),
+            Ok(None) => Response::error(
                 id,
-                "NOT_IMPLEMENTED",
-                "list_articles should be called from extension",
-            )
-        }
-
-        "get_article" => NativeResponse::error(
-            id,
-            "NOT_IMPLEMENTED",
-            "get_article should be called from extension",
-        ),
+                "NO_ENDPOINT",
+                "noet の WebSocket サーバーは起動していません",
+            ),
+            Err(e) => Response::error(id, "ENDPOINT_ERROR", &e.to_string()),
+        },
 
-        "create_article" => NativeResponse::error(
-            id,
-            "NOT_IMPLEMENTED",
-            "create_article should be called from extension",
-        ),
-
-        "update_article" => NativeResponse::error(
-            id,
-            "NOT_IMPLEMENTED",
-            "update_article should be called from extension",
-        ),
+        #[cfg(unix)]
+        OpenRelay::NAME => match crate::daemon::socket_path() {
+            Ok(socket) => Response::success(
+                id,
+                serde_json::json!(crate::protocol::RelayInfo {
+                    socket: socket.display().to_string()
+                }),
+            ),
+            Err(e) => Response::error(id, "RELAY_ERROR", &e.to_string()),
+        },
 
-        "delete_article" => NativeResponse::error(
+        #[cfg(not(unix))]
+        OpenRelay::NAME => Response::error(
             id,
-            "NOT_IMPLEMENTED",
-            "delete_article should be called from extension",
+            "RELAY_ERROR",
+            "Native Messaging での中継は Unix 系 OS でのみ利用できます",
         ),
 
-        "set_debug_mode" => {
-            // Store debug mode state
+        SetDebugMode::NAME => {
             let enabled = request
                 .params
-                .get("enabled")
+                .as_ref()
+                .and_then(|p| p.get("enabled"))
                 .and_then(|v| v.as_bool())
                 .unwrap_or(false);
-            // TODO: Store this somewhere
-            NativeResponse::success(
-                id,
-                serde_json::json!({
-                    "success": true,
-                    "debug_mode": enabled
-                }),
-            )
+            let state = HostState {
+                debug_mode: enabled,
+            };
+            match state.save(state_path) {
+                Ok(()) => Response::success(
+                    id,
+                    serde_json::json!(SetDebugModeResult {
+                        success: true,
+                        debug_mode: enabled
+                    }),
+                ),
+                Err(e) => Response::error(id, "STATE_ERROR", &e.to_string()),
+            }
         }
 
-        "get_debug_mode" => {
-            // TODO: Retrieve stored debug mode
-            NativeResponse::success(
+        GetDebugMode::NAME => {
+            let state = HostState::load(state_path);
+            Response::success(
                 id,
-                serde_json::json!({
-                    "debug_mode": false
+                serde_json::json!(DebugMode {
+                    debug_mode: state.debug_mode
                 }),
             )
         }
 
-        _ => NativeResponse::error(
+        ListWorkspaceArticles::NAME
+        | ReadWorkspaceArticle::NAME
+        | ReadWorkspaceImage::NAME
+        | WriteWorkspaceArticle::NAME
+        | RecordWorkspacePush::NAME => {
+            match HostWorkspace::open().and_then(|ws| handle_file_command(&ws, &request)) {
+                Ok(data) => Response::success(id, data),
+                Err(e) => file_error(id, e),
+            }
+        }
+
+        _ => Response::error(
             id,
             "UNKNOWN_COMMAND",
             &format!("Unknown command: {}", request.command),
@@ -190,28 +218,291 @@ async fn handle_command(request: NativeRequest) -> NativeResponse {
     }
 }
 
+/// Run a workspace file command
+fn handle_file_command(workspace: &HostWorkspace, request: &Request) -> Result<Value> {
+    fn params<T: serde::de::DeserializeOwned>(request: &Request) -> Result<T> {
+        let params = request.params.clone().unwrap_or_default();
+        serde_json::from_value(params).map_err(|e| NoetError::InvalidParams {
+            message: e.to_string(),
+        })
+    }
+
+    let data = match request.command.as_str() {
+        ListWorkspaceArticles::NAME => serde_json::to_value(workspace.list_articles()?)?,
+        ReadWorkspaceArticle::NAME => {
+            let PathParams { path } = params(request)?;
+            serde_json::to_value(workspace.read_article(&path)?)?
+        }
+        ReadWorkspaceImage::NAME => {
+            let PathParams { path } = params(request)?;
+            serde_json::to_value(workspace.read_image(&path)?)?
+        }
+        WriteWorkspaceArticle::NAME => {
+            serde_json::to_value(workspace.write_article(params(request)?)?)?
+        }
+        _ => serde_json::to_value(workspace.record_push(params(request)?)?)?,
+    };
+    Ok(data)
+}
+
+/// Error reply for a failed file command
+fn file_error(id: String, error: NoetError) -> Response {
+    let code = match &error {
+        NoetError::PermissionDenied { .. } => "PERMISSION_DENIED",
+        NoetError::FileNotFound(_) => "NOT_FOUND",
+        NoetError::InvalidInput(_) | NoetError::InvalidParams { .. } => "INVALID_PARAMS",
+        NoetError::Conflict(_) => "CONFLICT",
+        NoetError::ConfigError(_) => "NO_WORKSPACE",
+        _ => "FILE_ERROR",
+    };
+    Response::error(id, code, &error.to_string())
+}
+
+/// A reply from the extension to a request the host relayed
+fn is_reply(message: &Value) -> bool {
+    message.get("status").is_some()
+}
+
+/// Relay between the daemon and the extension until the extension goes away.
+/// Attaches again whenever the daemon restarts.
+#[cfg(unix)]
+async fn relay(mut replies: tokio::sync::mpsc::Receiver<Value>) {
+    use crate::daemon;
+    use crate::protocol::RelayEvent;
+    use std::time::Duration;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    /// How often to look for a daemon while there is none
+    const RETRY_INTERVAL: Duration = Duration::from_secs(2);
+
+    let attach = Request::new(daemon::ATTACH_COMMAND, Value::Null);
+    let mut attach = serde_json::to_string(&attach).unwrap_or_default();
+    attach.push('\n');
+
+    loop {
+        let stream = match daemon::connect().await {
+            Ok(Some(stream)) => stream,
+            _ => {
+                tokio::time::sleep(RETRY_INTERVAL).await;
+                continue;
+            }
+        };
+        let (read, mut write) = stream.into_split();
+        if write.write_all(attach.as_bytes()).await.is_err() {
+            continue;
+        }
+        log::info!("Relaying for the daemon");
+        let _ = write_message(&RelayEvent::new(true));
+
+        let mut lines = BufReader::new(read).lines();
+        loop {
+            tokio::select! {
+                line = lines.next_line() => {
+                    let Ok(Some(line)) = line else { break };
+                    if let Some(mut reply) = forward_request(&line) {
+                        reply.push('\n');
+                        if write.write_all(reply.as_bytes()).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+                reply = replies.recv() => {
+                    let Some(reply) = reply else { return };
+                    let mut line = reply.to_string();
+                    line.push('\n');
+                    if write.write_all(line.as_bytes()).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+
+        log::info!("Daemon went away");
+        let _ = write_message(&RelayEvent::new(false));
+        tokio::time::sleep(RETRY_INTERVAL).await;
+    }
+}
+
+/// Pass a request from the daemon to the extension. Returns the error reply
+/// for the daemon if it can't be passed on.
+#[cfg(unix)]
+fn forward_request(line: &str) -> Option<String> {
+    let request: Request = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(e) => {
+            log::warn!("Invalid request from the daemon: {e}");
+            return None;
+        }
+    };
+    let reply = |code: &str, message: &str| {
+        serde_json::to_string(&Response::error(&request.id, code, message)).ok()
+    };
+
+    if line.len() > MAX_MESSAGE_BYTES {
+        return reply(
+            "INVALID_PARAMS",
+            &format!(
+                "Native Messaging では {} KB を超えるリクエストを送れません ({} KB)。WebSocket で接続してください",
+                MAX_MESSAGE_BYTES / 1024,
+                line.len() / 1024
+            ),
+        );
+    }
+    match write_bytes(line.as_bytes()) {
+        Ok(()) => None,
+        Err(e) => reply(crate::bridge::DISCONNECTED, &e.to_string()),
+    }
+}
+
 /// Main loop for Native Messaging host mode
 pub async fn run() -> Result<()> {
     log::info!("Starting Native Messaging host");
+    let state_path = state_path()?;
 
-    loop {
-        match read_message() {
-            Ok(Some(request)) => {
-                log::debug!("Received request: {request:?}");
-                let response = handle_command(request).await;
-                write_message(&response)?;
+    // stdin is read on its own thread, since reading it blocks
+    let (incoming_tx, mut incoming) = tokio::sync::mpsc::channel::<Value>(32);
+    std::thread::spawn(move || {
+        let mut stdin = io::stdin().lock();
+        loop {
+            match read_message(&mut stdin) {
+                Ok(Some(message)) => {
+                    if incoming_tx.blocking_send(message).is_err() {
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    log::error!("Error reading message: {e}");
+                    break;
+                }
             }
-            Ok(None) => {
-                // EOF - extension disconnected
-                log::info!("Extension disconnected");
-                break;
+        }
+    });
+
+    #[cfg(unix)]
+    let mut relay: Option<tokio::sync::mpsc::Sender<Value>> = None;
+
+    while let Some(message) = incoming.recv().await {
+        log::debug!("Received message: {message:?}");
+
+        if is_reply(&message) {
+            #[cfg(unix)]
+            if let Some(relay) = &relay {
+                // Dropped if the daemon is gone; nobody is waiting for it then
+                let _ = relay.try_send(message);
             }
+            continue;
+        }
+
+        let request: Request = match serde_json::from_value(message) {
+            Ok(request) => request,
             Err(e) => {
-                log::error!("Error reading message: {e}");
-                break;
+                log::warn!("Invalid request from the extension: {e}");
+                continue;
             }
+        };
+
+        #[cfg(unix)]
+        if request.command == OpenRelay::NAME && relay.is_none() {
+            let (tx, rx) = tokio::sync::mpsc::channel(32);
+            tokio::spawn(self::relay(rx));
+            relay = Some(tx);
         }
+
+        write_message(&handle_command(request, &state_path))?;
     }
 
+    // EOF - extension disconnected
+    log::info!("Extension disconnected");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn request(command: &str, params: Value) -> Request {
+        Request::new(command, params)
+    }
+
+    #[test]
+    fn test_launched_by_browser() {
+        let args = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        assert!(launched_by_browser(&args(&[
+            "noet",
+            "chrome-extension://abcdefghijklmnopabcdefghijklmnop/"
+        ])));
+        assert!(launched_by_browser(&args(&[
+            "noet",
+            "chrome-extension://abcdefghijklmnopabcdefghijklmnop/",
+            "--parent-window=0"
+        ])));
+        assert!(launched_by_browser(&args(&[
+            "noet",
+            "/home/me/.mozilla/native-messaging-hosts/com.noet.host.json",
+            "noet@kako-jun.github.io"
+        ])));
+        assert!(!launched_by_browser(&args(&["noet", "list"])));
+        assert!(!launched_by_browser(&args(&["noet"])));
+    }
+
+    #[test]
+    fn test_read_message() {
+        let body = br#"{"id":"1","command":"ping"}"#;
+        let mut input = (body.len() as u32).to_le_bytes().to_vec();
+        input.extend_from_slice(body);
+
+        let mut input = io::Cursor::new(input);
+        let message = read_message(&mut input).unwrap().unwrap();
+        assert_eq!(message["command"], "ping");
+        assert!(read_message(&mut input).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_debug_mode_is_kept() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(STATE_FILE);
+
+        let response = handle_command(request(GetDebugMode::NAME, Value::Null), &path);
+        assert_eq!(response.data.unwrap()["debug_mode"], false);
+
+        let params = serde_json::json!({ "enabled": true });
+        let response = handle_command(request(SetDebugMode::NAME, params), &path);
+        assert_eq!(response.data.unwrap()["debug_mode"], true);
+
+        let response = handle_command(request(GetDebugMode::NAME, Value::Null), &path);
+        assert_eq!(response.data.unwrap()["debug_mode"], true);
+    }
+
+    #[test]
+    fn test_article_commands_are_not_host_commands() {
+        let dir = TempDir::new().unwrap();
+        let response = handle_command(
+            request("list_articles", Value::Null),
+            &dir.path().join(STATE_FILE),
+        );
+        assert_eq!(response.error.unwrap().code, "UNKNOWN_COMMAND");
+    }
+
+    #[test]
+    fn test_is_reply() {
+        assert!(is_reply(
+            &serde_json::json!({"id": "1", "status": "success"})
+        ));
+        assert!(!is_reply(
+            &serde_json::json!({"id": "1", "command": "get_token"})
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_oversized_requests_are_refused() {
+        let params = serde_json::json!({ "body": "x".repeat(MAX_MESSAGE_BYTES) });
+        let line = serde_json::to_string(&request("create_article", params)).unwrap();
+
+        let reply: Value = serde_json::from_str(&forward_request(&line).unwrap()).unwrap();
+        assert_eq!(reply["status"], "error");
+        assert_eq!(reply["error"]["code"], "INVALID_PARAMS");
+    }
+}
@@ -0,0 +1,708 @@
+//! Messages exchanged with the browser extension
+//!
+//! `protocol.yaml` at the repository root is the specification shared with the
+//! extension. Each command there has a type here implementing [`Command`], and the
+//! tests check every type against the YAML, so the two can't drift apart silently.
+
+use crate::bridge::Endpoint;
+use crate::image_handler::ImageData;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Version of the protocol in `protocol.yaml`, as `major.minor`
+pub const PROTOCOL_VERSION: &str = "1.2";
+
+/// Characters of an image's base64 sent per `upload_image_chunk`, small enough
+/// for a chunk to pass through the Native Messaging relay
+pub const IMAGE_CHUNK_CHARS: usize = 256 * 1024;
+
+/// How an extension's protocol version relates to [`PROTOCOL_VERSION`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compatibility {
+    Same,
+    /// Same major version but older, or from before versions were exchanged
+    OlderExtension,
+    /// Same major version but newer
+    NewerExtension,
+    /// Another major version, or not a version at all
+    Incompatible,
+}
+
+/// Compare the protocol version an extension reported with ours
+pub fn compatibility(extension: Option<&str>) -> Compatibility {
+    let Some(extension) = extension else {
+        return Compatibility::OlderExtension;
+    };
+    let (Some(ours), Some(theirs)) = (parse_version(PROTOCOL_VERSION), parse_version(extension))
+    else {
+        return Compatibility::Incompatible;
+    };
+
+    match (ours.0 == theirs.0, theirs.1.cmp(&ours.1)) {
+        (false, _) => Compatibility::Incompatible,
+        (true, std::cmp::Ordering::Equal) => Compatibility::Same,
+        (true, std::cmp::Ordering::Less) => Compatibility::OlderExtension,
+        (true, std::cmp::Ordering::Greater) => Compatibility::NewerExtension,
+    }
+}
+
+fn parse_version(version: &str) -> Option<(u32, u32)> {
+    let (major, minor) = version.split_once('.')?;
+    Some((major.parse().ok()?, minor.parse().ok()?))
+}
+
+/// A command from the `commands` or `host_commands` section of the spec
+pub trait Command {
+    const NAME: &'static str;
+
+    /// Whether sending it twice does no harm, so it can be resent after a reconnect
+    const IDEMPOTENT: bool = false;
+
+    type Params: Serialize + DeserializeOwned;
+    type Returns: Serialize + DeserializeOwned;
+}
+
+/// Envelope of every request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Request {
+    pub id: String,
+    pub command: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+}
+
+impl Request {
+    /// A request with a fresh id. Null params are left out.
+    pub fn new(command: &str, params: Value) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            command: command.to_string(),
+            params: Some(params).filter(|p| !p.is_null()),
+            timestamp: Some(now_millis()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Success,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ErrorBody {
+    pub code: String,
+    pub message: String,
+}
+
+/// Envelope of every response
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Response {
+    pub id: String,
+    pub status: Status,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorBody>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+}
+
+impl Response {
+    pub fn success(id: impl Into<String>, data: Value) -> Self {
+        Self {
+            id: id.into(),
+            status: Status::Success,
+            data: Some(data),
+            error: None,
+            timestamp: Some(now_millis()),
+        }
+    }
+
+    pub fn error(id: impl Into<String>, code: &str, message: &str) -> Self {
+        Self {
+            id: id.into(),
+            status: Status::Error,
+            data: None,
+            error: Some(ErrorBody {
+                code: code.to_string(),
+                message: message.to_string(),
+            }),
+            timestamp: Some(now_millis()),
+        }
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Params of commands that take none
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NoParams {}
+
+/// Declare a command type
+macro_rules! command {
+    ($(#[$doc:meta])* $name:ident, $wire:literal, $params:ty => $returns:ty) => {
+        $(#[$doc])*
+        pub struct $name;
+
+        impl Command for $name {
+            const NAME: &'static str = $wire;
+            type Params = $params;
+            type Returns = $returns;
+        }
+    };
+}
+
+/// Declare a command type that is safe to resend
+macro_rules! idempotent_command {
+    ($(#[$doc:meta])* $name:ident, $wire:literal, $params:ty => $returns:ty) => {
+        $(#[$doc])*
+        pub struct $name;
+
+        impl Command for $name {
+            const NAME: &'static str = $wire;
+            const IDEMPOTENT: bool = true;
+            type Params = $params;
+            type Returns = $returns;
+        }
+    };
+}
+
+idempotent_command!(
+    /// Check that the extension is there
+    Ping, "ping", NoParams => PingData
+);
+idempotent_command!(
+    /// Whether the browser is logged in to note.com
+    CheckAuth, "check_auth", NoParams => AuthStatus
+);
+idempotent_command!(
+    /// Articles of the logged in user, drafts included
+    ListArticles, "list_articles", NoParams => ArticleList
+);
+idempotent_command!(
+    /// Raw HTML of a published article
+    GetArticle, "get_article", GetArticleParams => Article
+);
+idempotent_command!(
+    /// Part of an image, sent before the article that refers to it
+    UploadImageChunk, "upload_image_chunk", UploadImageChunkParams => ImageChunkAck
+);
+command!(
+    /// Create an article, publishing it or saving a draft
+    CreateArticle, "create_article", CreateArticleParams => ArticleResult
+);
+command!(
+    /// Replace the contents of an article
+    UpdateArticle, "update_article", UpdateArticleParams => ArticleResult
+);
+command!(
+    DeleteArticle, "delete_article", KeyParams => DeleteResult
+);
+command!(
+    /// Show or hide the tabs the extension works in
+    SetDebugMode, "set_debug_mode", DebugModeParams => SetDebugModeResult
+);
+idempotent_command!(
+    GetDebugMode, "get_debug_mode", NoParams => DebugMode
+);
+
+// Commands the extension sends to the Native Messaging host
+command!(
+    /// Token the WebSocket expects
+    GetToken, "get_token", NoParams => TokenData
+);
+command!(
+    /// Where the WebSocket server is listening
+    GetEndpoint, "get_endpoint", NoParams => Endpoint
+);
+command!(
+    /// Relay requests from noet commands over the Native Messaging port
+    OpenRelay, "open_relay", NoParams => RelayInfo
+);
+command!(
+    /// Article files of the workspace the host serves
+    ListWorkspaceArticles, "list_workspace_articles", NoParams => WorkspaceArticleList
+);
+command!(
+    /// An article file, ready to be sent with `create_article` or `update_article`
+    ReadWorkspaceArticle, "read_workspace_article", PathParams => WorkspaceArticle
+);
+command!(
+    /// A local image as base64
+    ReadWorkspaceImage, "read_workspace_image", PathParams => WorkspaceImage
+);
+command!(
+    /// Save an article fetched with `get_article` into the workspace
+    WriteWorkspaceArticle, "write_workspace_article", WriteWorkspaceArticleParams => WrittenArticle
+);
+command!(
+    /// Link a file to the article it was just published as
+    RecordWorkspacePush, "record_workspace_push", RecordPushParams => WrittenArticle
+);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PingData {
+    pub version: String,
+    pub extension_id: Option<String>,
+    /// Absent from extensions older than the version handshake
+    pub protocol_version: Option<String>,
+    /// Commands the extension handles
+    pub commands: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthStatus {
+    pub logged_in: bool,
+    pub username: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArticleList {
+    pub articles: Vec<ArticleSummary>,
+    pub count: usize,
+}
+
+/// An entry of the article list
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArticleSummary {
+    pub key: Option<String>,
+    pub title: String,
+    /// `published`, `draft` or `unknown`
+    pub status: String,
+    pub date: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetArticleParams {
+    pub username: String,
+    pub key: String,
+}
+
+/// A fetched article
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Article {
+    pub title: String,
+    pub html: String,
+    pub tags: Vec<String>,
+    pub published_at: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadImageChunkParams {
+    pub upload_id: String,
+    /// 0-based
+    pub index: usize,
+    /// Number of chunks of the image
+    pub total: usize,
+    /// Base64, [`IMAGE_CHUNK_CHARS`] long except in the last chunk
+    pub data: String,
+}
+
+/// The extension's acknowledgement of a chunk
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageChunkAck {
+    pub upload_id: String,
+    pub index: usize,
+    /// Chunks of this upload received so far
+    pub received: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateArticleParams {
+    pub title: String,
+    pub body: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub draft: bool,
+    #[serde(default)]
+    pub images: Vec<ImageData>,
+    pub header_image: Option<ImageData>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateArticleParams {
+    pub key: String,
+    pub title: String,
+    pub body: String,
+    /// Left unchanged when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    #[serde(default)]
+    pub draft: bool,
+    #[serde(default)]
+    pub images: Vec<ImageData>,
+    pub header_image: Option<ImageData>,
+}
+
+/// Outcome of creating or updating an article
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ArticleResult {
+    /// False when the editor couldn't be driven; `error` says why
+    pub success: bool,
+    pub status: Option<String>,
+    pub url: Option<String>,
+    /// Article key; when absent it is read from `url`
+    pub key: Option<String>,
+    pub message: Option<String>,
+    pub error: Option<String>,
+    pub uploaded_images: Option<Vec<UploadedImage>>,
+    pub header_image_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadedImage {
+    pub local_path: String,
+    pub note_url: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyParams {
+    pub key: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeleteResult {
+    pub success: bool,
+    pub message: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DebugModeParams {
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetDebugModeResult {
+    pub success: bool,
+    pub debug_mode: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DebugMode {
+    pub debug_mode: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenData {
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RelayInfo {
+    /// Socket of the daemon the host relays for
+    pub socket: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkspaceArticleList {
+    pub root: String,
+    pub articles: Vec<WorkspaceArticleEntry>,
+}
+
+/// An article file, with paths relative to the workspace root
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorkspaceArticleEntry {
+    pub path: String,
+    pub title: Option<String>,
+    pub note_key: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PathParams {
+    pub path: String,
+}
+
+/// An article file as `noet push` would send it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkspaceArticle {
+    pub path: String,
+    pub title: String,
+    pub body: String,
+    pub tags: Vec<String>,
+    pub draft: bool,
+    pub note_key: Option<String>,
+    pub images: Vec<WorkspaceImageRef>,
+    pub header_image: Option<WorkspaceImageRef>,
+}
+
+/// A local image referenced by an article
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorkspaceImageRef {
+    /// As written in the Markdown, sent as the image's `local_path`
+    pub reference: String,
+    /// Relative to the workspace root, for `read_workspace_image`
+    pub path: String,
+    pub caption: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkspaceImage {
+    pub path: String,
+    pub filename: String,
+    pub mime_type: String,
+    pub data: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WriteWorkspaceArticleParams {
+    pub key: String,
+    pub title: String,
+    pub html: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Discard unpushed local changes
+    #[serde(default)]
+    pub overwrite: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WrittenArticle {
+    pub path: String,
+    pub created: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordPushParams {
+    pub path: String,
+    pub key: String,
+    /// From the create or update result, to be sent by URL next time
+    #[serde(default)]
+    pub uploaded_images: Vec<UploadedImage>,
+}
+
+/// What the host tells the extension about the relay, unasked
+///
+/// Only sent on unix, where the daemon runs.
+#[cfg_attr(not(unix), allow(dead_code))]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RelayEvent {
+    pub event: String,
+    /// Whether noet commands can reach the extension through the host
+    pub connected: bool,
+}
+
+#[cfg_attr(not(unix), allow(dead_code))]
+impl RelayEvent {
+    pub fn new(connected: bool) -> Self {
+        Self {
+            event: "relay".to_string(),
+            connected,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Map};
+    use serde_yaml::Value as Yaml;
+
+    const SPEC: &str = include_str!("../../../protocol.yaml");
+
+    fn spec() -> Yaml {
+        let mut spec: Yaml = serde_yaml::from_str(SPEC).expect("protocol.yaml should parse");
+        spec.apply_merge().unwrap();
+        spec
+    }
+
+    /// JSON matching a field's schema. With `full` every optional field is filled in;
+    /// otherwise nullable fields are null and the rest of the optional ones left out.
+    fn sample(schema: &Yaml, full: bool) -> Value {
+        match schema["type"].as_str() {
+            Some("string") => match schema["enum"].get(0).and_then(Yaml::as_str) {
+                Some(first) => json!(first),
+                None => json!("text"),
+            },
+            Some("integer") => json!(1),
+            Some("boolean") => json!(true),
+            Some("array") => json!([sample(&schema["items"], full)]),
+            Some("object") => object(
+                &schema["properties"],
+                schema["required"].as_sequence(),
+                full,
+            ),
+            other => panic!("unsupported type {other:?} in {schema:?}"),
+        }
+    }
+
+    /// JSON object with `fields`. Without a `required` list, fields are required
+    /// unless marked nullable, given a default or `required: false`.
+    fn object(fields: &Yaml, required: Option<&Vec<Yaml>>, full: bool) -> Value {
+        let mut object = Map::new();
+        let Some(fields) = fields.as_mapping() else {
+            return Value::Object(object);
+        };
+
+        for (name, field) in fields {
+            let name = name.as_str().unwrap();
+            let optional = match required {
+                Some(list) => !list.iter().any(|r| r.as_str() == Some(name)),
+                None => field.get("default").is_some() || field["required"] == Yaml::Bool(false),
+            };
+            let nullable = field["nullable"] == Yaml::Bool(true);
+
+            let value = if full {
+                sample(field, full)
+            } else if nullable {
+                Value::Null
+            } else if optional {
+                continue;
+            } else {
+                sample(field, full)
+            };
+            object.insert(name.to_string(), value);
+        }
+        Value::Object(object)
+    }
+
+    /// `T` must read everything the spec allows and write back exactly the spec's fields
+    fn check_type<T: Serialize + DeserializeOwned>(what: &str, full: Value, minimal: Value) {
+        let parsed: T = serde_json::from_value(full.clone())
+            .unwrap_or_else(|e| panic!("{what}: can't read {full}: {e}"));
+        assert_eq!(
+            serde_json::to_value(parsed).unwrap(),
+            full,
+            "{what}: fields differ from protocol.yaml"
+        );
+        serde_json::from_value::<T>(minimal.clone())
+            .unwrap_or_else(|e| panic!("{what}: can't read {minimal}: {e}"));
+    }
+
+    fn check_command<C: Command>(section: &str) -> &'static str {
+        let spec = spec();
+        let command = &spec[section][C::NAME];
+        assert!(
+            command.is_mapping(),
+            "{} is missing from {section} in protocol.yaml",
+            C::NAME
+        );
+
+        for (part, fields) in [
+            ("params", &command["params"]),
+            ("returns", &command["returns"]),
+        ] {
+            let full = object(fields, None, true);
+            let minimal = object(fields, None, false);
+            let what = format!("{}.{part}", C::NAME);
+            if part == "params" {
+                check_type::<C::Params>(&what, full, minimal);
+            } else {
+                check_type::<C::Returns>(&what, full, minimal);
+            }
+        }
+        C::NAME
+    }
+
+    fn spec_names(section: &str) -> Vec<String> {
+        spec()[section]
+            .as_mapping()
+            .unwrap()
+            .keys()
+            .map(|k| k.as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_commands_match_the_spec() {
+        let checked = [
+            check_command::<Ping>("commands"),
+            check_command::<CheckAuth>("commands"),
+            check_command::<ListArticles>("commands"),
+            check_command::<GetArticle>("commands"),
+            check_command::<UploadImageChunk>("commands"),
+            check_command::<CreateArticle>("commands"),
+            check_command::<UpdateArticle>("commands"),
+            check_command::<DeleteArticle>("commands"),
+            check_command::<SetDebugMode>("commands"),
+            check_command::<GetDebugMode>("commands"),
+        ];
+        assert_eq!(spec_names("commands"), checked, "commands without a type");
+
+        let checked = [
+            check_command::<GetToken>("host_commands"),
+            check_command::<GetEndpoint>("host_commands"),
+            check_command::<OpenRelay>("host_commands"),
+            check_command::<ListWorkspaceArticles>("host_commands"),
+            check_command::<ReadWorkspaceArticle>("host_commands"),
+            check_command::<ReadWorkspaceImage>("host_commands"),
+            check_command::<WriteWorkspaceArticle>("host_commands"),
+            check_command::<RecordWorkspacePush>("host_commands"),
+        ];
+        assert_eq!(
+            spec_names("host_commands"),
+            checked,
+            "host commands without a type"
+        );
+    }
+
+    #[test]
+    fn test_protocol_version_matches_the_spec() {
+        assert_eq!(spec()["protocol_version"].as_str(), Some(PROTOCOL_VERSION));
+    }
+
+    #[test]
+    fn test_extension_reports_its_manifest_version() {
+        // doctor compares the ping version with ours, so background.js must not keep
+        // a copy of it that can go stale
+        let background = include_str!("../../extension/src/background.js");
+        assert!(background.contains("const VERSION = chrome.runtime.getManifest().version;"));
+        assert!(background.contains(&format!("const PROTOCOL_VERSION = \"{PROTOCOL_VERSION}\";")));
+    }
+
+    #[test]
+    fn test_image_chunk_size_matches_the_spec() {
+        assert_eq!(
+            spec()["communication"]["image_chunk_chars"].as_u64(),
+            Some(IMAGE_CHUNK_CHARS as u64)
+        );
+    }
+
+    #[test]
+    fn test_compatibility() {
+        assert_eq!(compatibility(Some(PROTOCOL_VERSION)), Compatibility::Same);
+        assert_eq!(compatibility(None), Compatibility::OlderExtension);
+        assert_eq!(compatibility(Some("1.9")), Compatibility::NewerExtension);
+        assert_eq!(compatibility(Some("2.0")), Compatibility::Incompatible);
+        assert_eq!(compatibility(Some("0.9")), Compatibility::Incompatible);
+        assert_eq!(compatibility(Some("latest")), Compatibility::Incompatible);
+    }
+
+    #[test]
+    fn test_envelopes_match_the_spec() {
+        let spec = spec();
+        check_type::<Request>(
+            "request",
+            sample(&spec["request"], true),
+            sample(&spec["request"], false),
+        );
+        check_type::<Response>(
+            "response",
+            sample(&spec["response"], true),
+            sample(&spec["response"], false),
+        );
+    }
+
+    #[test]
+    fn test_responses_are_well_formed() {
+        let reply = serde_json::to_value(Response::error("1", "NOT_FOUND", "x")).unwrap();
+        assert_eq!(reply["status"], "error");
+        assert_eq!(reply["error"]["code"], "NOT_FOUND");
+        assert!(reply.get("data").is_none());
+
+        let request = Request::new(ListArticles::NAME, json!({}));
+        assert_eq!(request.id.len(), 36);
+    }
+}
@@ -0,0 +1,472 @@
+//! Workspace sync state
+//!
+//! `.noet/state.json` maps each local article file to its note key, together with
+//! the content hash and remote timestamp recorded at the last push or pull.
+//! Comparing those against the current file and `list_articles` tells us what
+//! changed on which side. The synced content itself is kept in `.noet/base/` as
+//! the common ancestor for `noet merge`.
+
+use crate::error::Result;
+use crate::protocol::ArticleSummary;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const STATE_FILE: &str = "state.json";
+const BASE_DIR: &str = "base";
+
+/// Sync record for one article file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrackedArticle {
+    pub note_key: String,
+    /// SHA-256 of the file content at the last sync
+    pub content_hash: String,
+    /// `date` reported by `list_articles` at the last sync
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_updated: Option<String>,
+    /// Hash of the remote body converted to Markdown, if it was fetched at the last sync
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_hash: Option<String>,
+    /// Unix time of the last sync
+    pub synced_at: u64,
+}
+
+/// The whole state index, keyed by path relative to the workspace root
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SyncState {
+    #[serde(default)]
+    pub articles: BTreeMap<String, TrackedArticle>,
+}
+
+impl SyncState {
+    fn path(workspace_root: &Path) -> PathBuf {
+        workspace_root.join(".noet").join(STATE_FILE)
+    }
+
+    /// Load the state index, or an empty one if nothing has been synced yet
+    pub fn load(workspace_root: &Path) -> Result<Self> {
+        let path = Self::path(workspace_root);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    pub fn save(&self, workspace_root: &Path) -> Result<()> {
+        let path = Self::path(workspace_root);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Record that `rel_path` now matches the remote article `note_key`
+    pub fn record(
+        &mut self,
+        rel_path: &str,
+        note_key: &str,
+        content: &str,
+        remote_updated: Option<String>,
+    ) -> &mut TrackedArticle {
+        // A key belongs to exactly one file
+        self.articles
+            .retain(|path, tracked| path == rel_path || tracked.note_key != note_key);
+
+        self.articles.insert(
+            rel_path.to_string(),
+            TrackedArticle {
+                note_key: note_key.to_string(),
+                content_hash: content_hash(content),
+                remote_updated,
+                remote_hash: None,
+                synced_at: now(),
+            },
+        );
+        self.articles.get_mut(rel_path).unwrap()
+    }
+
+    /// Find the file tracking a note key
+    pub fn find_by_key(&self, note_key: &str) -> Option<(&String, &TrackedArticle)> {
+        self.articles
+            .iter()
+            .find(|(_, tracked)| tracked.note_key == note_key)
+    }
+}
+
+fn base_path(workspace_root: &Path, note_key: &str) -> PathBuf {
+    workspace_root
+        .join(".noet")
+        .join(BASE_DIR)
+        .join(format!("{note_key}.md"))
+}
+
+/// Keep the synced content of an article as the base for later merges
+pub fn save_base(workspace_root: &Path, note_key: &str, content: &str) -> Result<()> {
+    let path = base_path(workspace_root, note_key);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, content)?;
+    Ok(())
+}
+
+/// Content of an article at its last sync, if recorded
+pub fn load_base(workspace_root: &Path, note_key: &str) -> Result<Option<String>> {
+    let path = base_path(workspace_root, note_key);
+    if !path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(fs::read_to_string(path)?))
+}
+
+/// Sync status of a single article
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileStatus {
+    /// Local file that has never been published
+    New,
+    /// Local file changed since the last sync
+    Modified,
+    /// Remote article changed since the last sync
+    RemoteChanged,
+    /// Both sides changed since the last sync
+    Conflict,
+    /// Tracked file that no longer exists locally
+    Deleted,
+    /// Remote article with no local file
+    RemoteOnly,
+    InSync,
+}
+
+/// One line of `noet status`
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusEntry {
+    pub path: Option<String>,
+    pub note_key: Option<String>,
+    pub title: Option<String>,
+    pub status: FileStatus,
+}
+
+/// A local article file as seen by the sync engine
+#[derive(Debug, Clone)]
+pub struct LocalFile {
+    /// Path relative to the workspace root, with `/` separators
+    pub rel_path: String,
+    pub content: String,
+    /// `note_key` from the frontmatter, if any
+    pub note_key: Option<String>,
+}
+
+/// Compare local files and (optionally) the remote article list against the state index
+pub fn compute_status(
+    state: &SyncState,
+    local: &[LocalFile],
+    remote: Option<&[ArticleSummary]>,
+) -> Vec<StatusEntry> {
+    let mut entries = Vec::new();
+
+    for file in local {
+        let tracked = state.articles.get(&file.rel_path);
+        let note_key = tracked
+            .map(|t| t.note_key.clone())
+            .or_else(|| file.note_key.clone());
+
+        let local_changed = match tracked {
+            Some(t) => t.content_hash != content_hash(&file.content),
+            // Linked via frontmatter but never synced: we can't tell, assume changed
+            None => true,
+        };
+
+        let remote_article = note_key
+            .as_deref()
+            .and_then(|key| remote.and_then(|r| find_remote(r, key)));
+
+        let remote_changed = match (tracked, remote_article) {
+            (Some(t), Some(article)) => {
+                t.remote_updated.is_some() && t.remote_updated != remote_timestamp(article)
+            }
+            _ => false,
+        };
+
+        let status = match (note_key.is_some(), local_changed, remote_changed) {
+            (false, _, _) => FileStatus::New,
+            (true, true, true) => FileStatus::Conflict,
+            (true, true, false) => FileStatus::Modified,
+            (true, false, true) => FileStatus::RemoteChanged,
+            (true, false, false) => FileStatus::InSync,
+        };
+
+        entries.push(StatusEntry {
+            path: Some(file.rel_path.clone()),
+            note_key,
+            title: remote_article.map(|a| a.title.clone()),
+            status,
+        });
+    }
+
+    for (path, tracked) in &state.articles {
+        if !local.iter().any(|f| &f.rel_path == path) {
+            entries.push(StatusEntry {
+                path: Some(path.clone()),
+                note_key: Some(tracked.note_key.clone()),
+                title: None,
+                status: FileStatus::Deleted,
+            });
+        }
+    }
+
+    if let Some(remote) = remote {
+        for article in remote {
+            let Some(key) = &article.key else { continue };
+            let known = entries
+                .iter()
+                .any(|e| e.note_key.as_deref() == Some(key.as_str()));
+            if !known {
+                entries.push(StatusEntry {
+                    path: None,
+                    note_key: Some(key.clone()),
+                    title: Some(article.title.clone()),
+                    status: FileStatus::RemoteOnly,
+                });
+            }
+        }
+    }
+
+    entries
+}
+
+/// Timestamp used to detect remote changes
+pub fn remote_timestamp(article: &ArticleSummary) -> Option<String> {
+    article.date.clone().filter(|d| !d.is_empty())
+}
+
+pub fn find_remote<'a>(remote: &'a [ArticleSummary], key: &str) -> Option<&'a ArticleSummary> {
+    remote.iter().find(|a| a.key.as_deref() == Some(key))
+}
+
+/// Hex SHA-256 of a string
+pub fn content_hash(content: &str) -> String {
+    format!("{:x}", Sha256::digest(content.as_bytes()))
+}
+
+/// Hash of a remote body after conversion to Markdown, ignoring surrounding whitespace
+pub fn body_hash(markdown: &str) -> String {
+    content_hash(markdown.trim())
+}
+
+/// Collect article files: every `.md` under the articles directory plus any
+/// tracked file that lives elsewhere in the workspace
+pub fn scan_local_files(
+    workspace_root: &Path,
+    articles_dir: &str,
+    state: &SyncState,
+) -> Result<Vec<LocalFile>> {
+    let mut paths = Vec::new();
+    collect_markdown(&workspace_root.join(articles_dir), &mut paths)?;
+
+    for rel_path in state.articles.keys() {
+        let path = workspace_root.join(rel_path);
+        if path.exists() && !paths.contains(&path) {
+            paths.push(path);
+        }
+    }
+
+    paths.sort();
+
+    let mut files = Vec::new();
+    for path in paths {
+        let content = fs::read_to_string(&path)?;
+        let note_key = crate::frontmatter::Document::parse(&content)
+            .ok()
+            .and_then(|doc| doc.frontmatter.note_key);
+        files.push(LocalFile {
+            rel_path: relative_path(workspace_root, &path),
+            content,
+            note_key,
+        });
+    }
+
+    Ok(files)
+}
+
+/// Append every `.md` file under `dir`, skipping hidden entries
+pub fn collect_markdown(dir: &Path, out: &mut Vec<PathBuf>) -> Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let hidden = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|n| n.starts_with('.'));
+        if hidden {
+            continue;
+        }
+        if path.is_dir() {
+            collect_markdown(&path, out)?;
+        } else if path.extension().is_some_and(|ext| ext == "md") {
+            out.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Workspace-relative path with `/` separators, used as the state index key
+pub fn relative_path(workspace_root: &Path, path: &Path) -> String {
+    let rel = path.strip_prefix(workspace_root).unwrap_or(path);
+    rel.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn local(path: &str, content: &str, key: Option<&str>) -> LocalFile {
+        LocalFile {
+            rel_path: path.to_string(),
+            content: content.to_string(),
+            note_key: key.map(String::from),
+        }
+    }
+
+    fn remote(key: &str, date: &str) -> ArticleSummary {
+        ArticleSummary {
+            key: Some(key.to_string()),
+            title: format!("title {key}"),
+            status: "published".to_string(),
+            date: Some(date.to_string()),
+        }
+    }
+
+    fn status_of(entries: &[StatusEntry], key: &str) -> FileStatus {
+        entries
+            .iter()
+            .find(|e| e.note_key.as_deref() == Some(key) || e.path.as_deref() == Some(key))
+            .unwrap()
+            .status
+    }
+
+    #[test]
+    fn test_compute_status() {
+        let mut state = SyncState::default();
+        state.record("a.md", "na", "A", Some("2025/01/01".to_string()));
+        state.record("b.md", "nb", "B", Some("2025/01/01".to_string()));
+        state.record("c.md", "nc", "C", Some("2025/01/01".to_string()));
+        state.record("d.md", "nd", "D", Some("2025/01/01".to_string()));
+        state.record("gone.md", "ng", "G", None);
+
+        let files = vec![
+            local("a.md", "A", None),
+            local("b.md", "B changed", None),
+            local("c.md", "C", None),
+            local("d.md", "D changed", None),
+            local("new.md", "N", None),
+        ];
+        let remote = vec![
+            remote("na", "2025/01/01"),
+            remote("nb", "2025/01/01"),
+            remote("nc", "2025/02/01"),
+            remote("nd", "2025/02/01"),
+            remote("nx", "2025/03/01"),
+        ];
+
+        let entries = compute_status(&state, &files, Some(&remote));
+
+        assert_eq!(status_of(&entries, "na"), FileStatus::InSync);
+        assert_eq!(status_of(&entries, "nb"), FileStatus::Modified);
+        assert_eq!(status_of(&entries, "nc"), FileStatus::RemoteChanged);
+        assert_eq!(status_of(&entries, "nd"), FileStatus::Conflict);
+        assert_eq!(status_of(&entries, "new.md"), FileStatus::New);
+        assert_eq!(status_of(&entries, "ng"), FileStatus::Deleted);
+        assert_eq!(status_of(&entries, "nx"), FileStatus::RemoteOnly);
+    }
+
+    #[test]
+    fn test_untracked_file_with_note_key_is_modified() {
+        let state = SyncState::default();
+        let files = vec![local("x.md", "X", Some("nx"))];
+        let entries = compute_status(&state, &files, None);
+        assert_eq!(entries[0].status, FileStatus::Modified);
+        assert_eq!(entries[0].note_key.as_deref(), Some("nx"));
+    }
+
+    #[test]
+    fn test_record_moves_key_to_new_path() {
+        let mut state = SyncState::default();
+        state.record("old.md", "n1", "content", None);
+        state.record("new.md", "n1", "content", None);
+        assert_eq!(state.articles.len(), 1);
+        assert_eq!(state.find_by_key("n1").unwrap().0, "new.md");
+    }
+
+    #[test]
+    fn test_base_save_and_load() {
+        let temp_dir = TempDir::new().unwrap();
+        assert_eq!(load_base(temp_dir.path(), "n1").unwrap(), None);
+
+        save_base(temp_dir.path(), "n1", "base content").unwrap();
+        assert_eq!(
+            load_base(temp_dir.path(), "n1").unwrap().as_deref(),
+            Some("base content")
+        );
+    }
+
+    #[test]
+    fn test_state_save_and_load() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut state = SyncState::default();
+        state.record(
+            "articles/a.md",
+            "n1",
+            "content",
+            Some("2025/01/01".to_string()),
+        );
+        state.save(temp_dir.path()).unwrap();
+
+        let loaded = SyncState::load(temp_dir.path()).unwrap();
+        assert_eq!(loaded, state);
+    }
+
+    #[test]
+    fn test_scan_local_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("articles/2025")).unwrap();
+        fs::create_dir_all(root.join("notes")).unwrap();
+        fs::write(root.join("articles/a.md"), "---\nnote_key: n1\n---\nA").unwrap();
+        fs::write(root.join("articles/2025/b.md"), "B").unwrap();
+        fs::write(root.join("articles/readme.txt"), "x").unwrap();
+        fs::write(root.join("notes/tracked.md"), "T").unwrap();
+        fs::write(root.join("README.md"), "R").unwrap();
+
+        let mut state = SyncState::default();
+        state.record("notes/tracked.md", "n2", "T", None);
+
+        let files = scan_local_files(root, "articles", &state).unwrap();
+        let paths: Vec<_> = files.iter().map(|f| f.rel_path.as_str()).collect();
+
+        assert_eq!(
+            paths,
+            vec!["articles/2025/b.md", "articles/a.md", "notes/tracked.md"]
+        );
+        assert_eq!(files[1].note_key.as_deref(), Some("n1"));
+    }
+}
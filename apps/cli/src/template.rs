@@ -0,0 +1,377 @@
+//! Article templates
+//!
+//! Templates are rendered with minijinja, so `{{ TITLE }}`, `{% if %}`, `{% for %}` and
+//! `{% include "partials/footer.md" %}` work as in Jinja. Besides the built-in variables
+//! a template may declare its own in a `variables` list in its frontmatter:
+//!
+//! ```yaml
+//! variables:
+//!   - name: series
+//!     prompt: シリーズ名
+//!     default: 入門
+//! ```
+//!
+//! They are asked for at `noet new` time, and the list is not copied into the article.
+
+use crate::config::Settings;
+use crate::error::{NoetError, Result};
+use minijinja::{Environment, UndefinedBehavior, Value};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Variables every template can use
+pub const BUILTIN_VARIABLES: [&str; 6] = ["TITLE", "DATE", "SLUG", "USERNAME", "WORKSPACE", "TAGS"];
+
+const VARIABLES_KEY: &str = "variables:";
+
+/// A variable declared by a template
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Variable {
+    pub name: String,
+
+    /// Text shown when asking for the value (default: the name)
+    #[serde(default)]
+    pub prompt: Option<String>,
+
+    /// Default value, itself rendered as a template
+    #[serde(default)]
+    pub default: Option<String>,
+
+    /// Split the answer on commas into a list, e.g. for `{% for %}`
+    #[serde(default)]
+    pub list: bool,
+}
+
+#[derive(Deserialize)]
+struct Declarations {
+    variables: Vec<Variable>,
+}
+
+/// Values available while rendering
+pub type Context = BTreeMap<String, Value>;
+
+/// Built-in variables for an article called `title`
+pub fn builtins(
+    title: &str,
+    slug: &str,
+    date: &str,
+    settings: &Settings,
+    workspace_root: &Path,
+) -> Context {
+    let workspace = workspace_root
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    Context::from([
+        ("TITLE".to_string(), Value::from(title)),
+        ("DATE".to_string(), Value::from(date)),
+        ("SLUG".to_string(), Value::from(slug)),
+        (
+            "USERNAME".to_string(),
+            Value::from(settings.username.clone().unwrap_or_default()),
+        ),
+        ("WORKSPACE".to_string(), Value::from(workspace)),
+        (
+            "TAGS".to_string(),
+            Value::from(settings.default_tags.clone()),
+        ),
+    ])
+}
+
+/// Today's date in the local time zone, as `YYYY-MM-DD`
+pub fn today() -> String {
+    jiff::Zoned::now().date().to_string()
+}
+
+/// A parsed template
+#[derive(Debug, Clone)]
+pub struct Template {
+    name: String,
+    source: String,
+    pub variables: Vec<Variable>,
+    partials_dir: Option<PathBuf>,
+}
+
+impl Template {
+    /// Parse a template. `partials_dir` is where `{% include %}` looks for files.
+    pub fn parse(name: &str, source: &str, partials_dir: Option<&Path>) -> Result<Self> {
+        let (source, variables) = split_declarations(source)?;
+
+        for variable in &variables {
+            let valid = variable
+                .name
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                && variable
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !valid {
+                return Err(NoetError::Template(format!(
+                    "{name}: 変数名 '{}' は英数字と _ で書いてください",
+                    variable.name
+                )));
+            }
+            if BUILTIN_VARIABLES.contains(&variable.name.as_str()) {
+                return Err(NoetError::Template(format!(
+                    "{name}: 変数 '{}' は組み込み変数と同じ名前です",
+                    variable.name
+                )));
+            }
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            source,
+            variables,
+            partials_dir: partials_dir.map(Path::to_path_buf),
+        })
+    }
+
+    /// Fill in the declared variables. `ask` receives each variable with its
+    /// rendered default and returns the answer.
+    pub fn resolve(
+        &self,
+        context: &mut Context,
+        mut ask: impl FnMut(&Variable, Option<String>) -> Result<String>,
+    ) -> Result<()> {
+        let env = self.environment();
+
+        for variable in &self.variables {
+            if context.contains_key(&variable.name) {
+                continue;
+            }
+
+            let default = match &variable.default {
+                Some(default) => Some(env.render_str(default, &*context).map_err(|e| {
+                    NoetError::Template(format!("{} の既定値: {e}", variable.name))
+                })?),
+                None => None,
+            };
+            let answer = ask(variable, default)?;
+            context.insert(variable.name.clone(), value(variable, &answer));
+        }
+
+        Ok(())
+    }
+
+    /// Render with `context`, which must hold every variable the template uses
+    pub fn render(&self, context: &Context) -> Result<String> {
+        let mut env = self.environment();
+        env.add_template_owned(self.name.clone(), self.source.clone())
+            .and_then(|_| env.get_template(&self.name)?.render(context))
+            .map_err(|e| NoetError::Template(e.to_string()))
+    }
+
+    fn environment(&self) -> Environment<'static> {
+        let mut env = Environment::new();
+        // Catch misspelled variables, but let `{% if var %}` test optional ones
+        env.set_undefined_behavior(UndefinedBehavior::SemiStrict);
+        env.set_keep_trailing_newline(true);
+        env.set_trim_blocks(true);
+        env.set_lstrip_blocks(true);
+        if let Some(dir) = &self.partials_dir {
+            env.set_loader(minijinja::path_loader(dir));
+        }
+        env
+    }
+}
+
+/// The value a variable takes for an answer
+pub fn value(variable: &Variable, answer: &str) -> Value {
+    if variable.list {
+        let items: Vec<String> = answer
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(String::from)
+            .collect();
+        Value::from(items)
+    } else {
+        Value::from(answer.trim())
+    }
+}
+
+/// Take the `variables` list out of the template's frontmatter
+fn split_declarations(source: &str) -> Result<(String, Vec<Variable>)> {
+    let lines: Vec<&str> = source.split_inclusive('\n').collect();
+    if lines.first().map(|l| l.trim_end()) != Some("---") {
+        return Ok((source.to_string(), Vec::new()));
+    }
+    let Some(end) = lines
+        .iter()
+        .skip(1)
+        .position(|l| l.trim_end() == "---")
+        .map(|i| i + 1)
+    else {
+        return Ok((source.to_string(), Vec::new()));
+    };
+
+    let Some(start) = (1..end).find(|&i| lines[i].starts_with(VARIABLES_KEY)) else {
+        return Ok((source.to_string(), Vec::new()));
+    };
+    // The block runs until the next top-level key
+    let stop = (start + 1..end)
+        .find(|&i| {
+            let line = lines[i];
+            !(line.trim().is_empty() || line.starts_with([' ', '\t', '-', '#']))
+        })
+        .unwrap_or(end);
+
+    let block: String = lines[start..stop].concat();
+    let declarations: Declarations = serde_yaml::from_str(&block)
+        .map_err(|e| NoetError::Template(format!("variables の書式が正しくありません: {e}")))?;
+
+    let rest = [&lines[..start], &lines[stop..]].concat().concat();
+    Ok((rest, declarations.variables))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn context() -> Context {
+        let mut settings = Settings::default();
+        settings.username = Some("alice".to_string());
+        settings.default_tags = vec!["rust".to_string(), "cli".to_string()];
+        builtins(
+            "はじめての記事",
+            "はじめての記事",
+            "2025-01-02",
+            &settings,
+            Path::new("/home/alice/blog"),
+        )
+    }
+
+    #[test]
+    fn test_builtins() {
+        let template = Template::parse(
+            "t",
+            "{{TITLE}} {{ DATE }} {{ SLUG }} {{ USERNAME }} {{ WORKSPACE }}\n",
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            template.render(&context()).unwrap(),
+            "はじめての記事 2025-01-02 はじめての記事 alice blog\n"
+        );
+    }
+
+    #[test]
+    fn test_conditionals_and_loops() {
+        let source = "---\ntags:\n{% for tag in TAGS %}\n  - {{ tag }}\n{% endfor %}\n---\n{% if series %}\nシリーズ: {{ series }}\n{% endif %}\n本文\n";
+        let template = Template::parse("t", source, None).unwrap();
+
+        assert_eq!(
+            template.render(&context()).unwrap(),
+            "---\ntags:\n  - rust\n  - cli\n---\n本文\n"
+        );
+
+        let mut ctx = context();
+        ctx.insert("series".to_string(), Value::from("入門"));
+        assert!(template
+            .render(&ctx)
+            .unwrap()
+            .contains("シリーズ: 入門\n本文"));
+
+        let merged = Template::parse("t", "{{ (TAGS + topics) | join(\",\") }}", None).unwrap();
+        ctx.insert("topics".to_string(), Value::from(vec!["tips"]));
+        assert_eq!(merged.render(&ctx).unwrap(), "rust,cli,tips");
+    }
+
+    #[test]
+    fn test_declared_variables_are_asked_and_removed() {
+        let source = "---\ntitle: \"{{ TITLE }}\"\nvariables:\n  - name: series\n    prompt: シリーズ名\n    default: \"{{ USERNAME }}の連載\"\n  - name: topics\n    list: true\nstatus: draft\n---\n{{ series }}: {{ topics | join(\" / \") }}\n";
+        let template = Template::parse("t", source, None).unwrap();
+        assert_eq!(template.variables.len(), 2);
+        assert_eq!(template.variables[0].prompt.as_deref(), Some("シリーズ名"));
+
+        let mut asked = Vec::new();
+        let mut ctx = context();
+        template
+            .resolve(&mut ctx, |variable, default| {
+                asked.push((variable.name.clone(), default.clone()));
+                Ok(match variable.name.as_str() {
+                    "series" => default.unwrap(),
+                    _ => "a, b,".to_string(),
+                })
+            })
+            .unwrap();
+        assert_eq!(
+            asked,
+            vec![
+                ("series".to_string(), Some("aliceの連載".to_string())),
+                ("topics".to_string(), None),
+            ]
+        );
+
+        assert_eq!(
+            template.render(&ctx).unwrap(),
+            "---\ntitle: \"はじめての記事\"\nstatus: draft\n---\naliceの連載: a / b\n"
+        );
+    }
+
+    #[test]
+    fn test_preset_variables_are_not_asked() {
+        let source = "---\nvariables:\n  - name: series\n---\n{{ series }}\n";
+        let template = Template::parse("t", source, None).unwrap();
+
+        let mut ctx = context();
+        ctx.insert("series".to_string(), Value::from("preset"));
+        template
+            .resolve(&mut ctx, |_, _| panic!("should not ask"))
+            .unwrap();
+        assert_eq!(template.render(&ctx).unwrap(), "---\n---\npreset\n");
+    }
+
+    #[test]
+    fn test_include_partials() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir_all(temp_dir.path().join("partials")).unwrap();
+        fs::write(
+            temp_dir.path().join("partials/footer.md"),
+            "---\n書いた人: {{ USERNAME }}\n",
+        )
+        .unwrap();
+
+        let template = Template::parse(
+            "t",
+            "本文\n\n{% include \"partials/footer.md\" %}",
+            Some(temp_dir.path()),
+        )
+        .unwrap();
+        assert_eq!(
+            template.render(&context()).unwrap(),
+            "本文\n\n---\n書いた人: alice\n"
+        );
+
+        let missing =
+            Template::parse("t", "{% include \"../secret.md\" %}", Some(temp_dir.path())).unwrap();
+        assert!(missing.render(&context()).is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        // Misspelled variables are reported instead of rendering as nothing
+        let template = Template::parse("t", "{{ TITEL }}", None).unwrap();
+        assert!(matches!(
+            template.render(&context()),
+            Err(NoetError::Template(_))
+        ));
+
+        let shadowing = "---\nvariables:\n  - name: DATE\n---\n";
+        assert!(Template::parse("t", shadowing, None).is_err());
+
+        let invalid = "---\nvariables:\n  - name: my-var\n---\n";
+        assert!(Template::parse("t", invalid, None).is_err());
+
+        let unknown_field = "---\nvariables:\n  - name: a\n    promt: typo\n---\n";
+        assert!(Template::parse("t", unknown_field, None).is_err());
+    }
+}
@@ -6,18 +6,20 @@ use std::path::{Path, PathBuf};
 const WORKSPACE_DIR: &str = ".noet";
 const WORKSPACE_CONFIG: &str = "config.toml";
 
+/// Longest slug generated from a title, in characters
+const MAX_SLUG_CHARS: usize = 50;
+
 /// Get the workspace root directory by searching upwards from current directory
 pub fn find_workspace_root() -> Result<PathBuf> {
     let current_dir = env::current_dir()?;
     find_workspace_root_from(&current_dir)
 }
 
-fn find_workspace_root_from(start_dir: &Path) -> Result<PathBuf> {
+pub fn find_workspace_root_from(start_dir: &Path) -> Result<PathBuf> {
     let mut current = start_dir;
 
     loop {
-        let workspace_dir = current.join(WORKSPACE_DIR);
-        if workspace_dir.exists() && workspace_dir.is_dir() {
+        if is_workspace_root(current) {
             return Ok(current.to_path_buf());
         }
 
@@ -32,6 +34,16 @@ fn find_workspace_root_from(start_dir: &Path) -> Result<PathBuf> {
     }
 }
 
+/// Whether `dir` has been initialized as a workspace
+pub fn is_workspace_root(dir: &Path) -> bool {
+    dir.join(WORKSPACE_DIR).is_dir()
+}
+
+/// Path to the config file of the workspace at `root`
+pub fn config_path(root: &Path) -> PathBuf {
+    root.join(WORKSPACE_DIR).join(WORKSPACE_CONFIG)
+}
+
 /// Initialize a workspace in the specified directory
 pub fn init_workspace(path: Option<PathBuf>) -> Result<PathBuf> {
     let workspace_root = match path {
@@ -68,12 +80,27 @@ pub fn init_workspace(path: Option<PathBuf>) -> Result<PathBuf> {
 # Username for this workspace
 # username = "your-workspace-username"
 
-# Base URL (usually no need to change)
-# base_url = "https://note.com"
+# Directory for article files, relative to the workspace root
+# articles_dir = "articles"
+
+# Rewrites applied before publishing, for Markdown the note.com editor doesn't support
+# inline_code = "bold"   # bold, quote (「」) or plain
+# table_style = "code"   # code (code block) or list (bullets)
+
+# Images are prepared before upload: scaled down, recompressed and stripped of EXIF/GPS data
+# image_max_width = 1920      # 0 keeps the original size
+# image_quality = 85          # JPEG quality, 1-100
+# strip_image_metadata = true
 "#;
         fs::write(&config_path, default_config)?;
     }
 
+    // Create articles directory
+    let articles_dir = workspace_root.join("articles");
+    if !articles_dir.exists() {
+        fs::create_dir_all(&articles_dir)?;
+    }
+
     // Create templates directory
     let templates_dir = workspace_root.join("templates");
     if !templates_dir.exists() {
@@ -101,7 +128,6 @@ pub fn init_workspace(path: Option<PathBuf>) -> Result<PathBuf> {
 }
 
 /// Get the workspace directory (.noet/)
-#[allow(dead_code)]
 pub fn get_workspace_dir() -> Result<PathBuf> {
     let root = find_workspace_root()?;
     Ok(root.join(WORKSPACE_DIR))
@@ -118,6 +144,38 @@ pub fn is_in_workspace() -> bool {
     find_workspace_root().is_ok()
 }
 
+/// Turn a title into a file name stem that is safe on every platform.
+/// Letters and digits of any script are kept, so Japanese titles stay readable;
+/// everything else becomes a single `-`.
+pub fn slugify(title: &str) -> String {
+    let mut slug = String::new();
+    for c in title.chars() {
+        let c = match c {
+            c if c.is_alphanumeric() => c.to_ascii_lowercase(),
+            '_' => '_',
+            _ => '-',
+        };
+        if c == '-' && (slug.is_empty() || slug.ends_with('-')) {
+            continue;
+        }
+        slug.push(c);
+    }
+
+    let slug: String = slug.chars().take(MAX_SLUG_CHARS).collect();
+    slug.trim_end_matches('-').to_string()
+}
+
+/// `<dir>/<stem>.md`, or `<stem>-2.md`, `<stem>-3.md`, ... if it is taken
+pub fn unique_article_path(dir: &Path, stem: &str) -> PathBuf {
+    let mut path = dir.join(format!("{stem}.md"));
+    let mut n = 2;
+    while path.exists() {
+        path = dir.join(format!("{stem}-{n}.md"));
+        n += 1;
+    }
+    path
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -132,6 +190,7 @@ mod tests {
         assert!(workspace_root.join(".noet").exists());
         assert!(workspace_root.join(".noet/config.toml").exists());
         assert!(workspace_root.join("templates").exists());
+        assert!(workspace_root.join("articles").exists());
     }
 
     #[test]
@@ -157,4 +216,26 @@ mod tests {
         let result = find_workspace_root_from(temp_dir.path());
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(slugify("Rust入門　その1"), "rust入門-その1");
+        assert_eq!(slugify("a/b\\c:d*e?f\"g<h>i|j"), "a-b-c-d-e-f-g-h-i-j");
+        assert_eq!(slugify("「はじめに」・メモ"), "はじめに-メモ");
+        assert_eq!(slugify("../../etc"), "etc");
+        assert_eq!(slugify("？！"), "");
+        assert_eq!(slugify(&"あ".repeat(80)).chars().count(), MAX_SLUG_CHARS);
+    }
+
+    #[test]
+    fn test_unique_article_path() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        assert_eq!(unique_article_path(dir, "memo"), dir.join("memo.md"));
+
+        fs::write(dir.join("memo.md"), "").unwrap();
+        fs::write(dir.join("memo-2.md"), "").unwrap();
+        assert_eq!(unique_article_path(dir, "memo"), dir.join("memo-3.md"));
+    }
 }
@@ -3,9 +3,25 @@
 // Communicates with CLI via Native Messaging or WebSocket
 // Executes Note.com operations using DOM scraping (no API)
 
-const VERSION = "0.1.7";
+// Reported by ping; the manifest is the only place the version is written
+const VERSION = chrome.runtime.getManifest().version;
+// protocol_version in protocol.yaml that this file implements
+const PROTOCOL_VERSION = "1.2";
+// Commands answered below, reported to the CLI by ping
+const SUPPORTED_COMMANDS = [
+  "ping",
+  "check_auth",
+  "list_articles",
+  "get_article",
+  "upload_image_chunk",
+  "create_article",
+  "update_article",
+  "delete_article",
+  "set_debug_mode",
+  "get_debug_mode"
+];
 const NATIVE_HOST_NAME = "com.noet.host";
-const WEBSOCKET_URL = "ws://127.0.0.1:9876";
+const DEFAULT_WEBSOCKET_URL = "ws://127.0.0.1:9876";
 
 // Debug mode: when true, opens tabs visibly for DOM operations
 let debugMode = false;
@@ -14,6 +30,13 @@ let debugMode = false;
 let ws = null;
 let wsReconnectTimer = null;
 
+// Whether the Native Messaging host relays CLI requests, making the WebSocket unnecessary
+let relayConnected = false;
+
+// Images sent with upload_image_chunk, by upload_id, until an article uses them
+const imageUploads = new Map();
+const IMAGE_UPLOAD_TTL_MS = 10 * 60 * 1000;
+
 /**
  * Human-like behavior utilities
  * Mimics natural human browsing patterns to avoid bot detection
@@ -56,7 +79,16 @@ function connectToNativeHost() {
 
     port.onMessage.addListener((message) => {
       console.log("[noet] Received from host:", message);
-      handleHostMessage(message);
+      if (message.event === "relay") {
+        handleRelayEvent(message.connected);
+      } else if (message.status) {
+        // Reply to a host command we sent on the port
+        if (message.status === "error") {
+          console.log("[noet] Host error:", message.error.message);
+        }
+      } else {
+        handleHostMessage(message);
+      }
     });
 
     port.onDisconnect.addListener(() => {
@@ -65,16 +97,68 @@ function connectToNativeHost() {
         console.error("[noet] Disconnect error:", chrome.runtime.lastError.message);
       }
       port = null;
+      handleRelayEvent(false);
       // Reconnect after a delay
       setTimeout(connectToNativeHost, 1000);
     });
 
+    // Have the host relay requests from the CLI over this port
+    port.postMessage({ id: crypto.randomUUID(), command: "open_relay" });
+
     console.log("[noet] Connected to Native Host");
   } catch (e) {
     console.error("[noet] Failed to connect to Native Host:", e);
   }
 }
 
+/**
+ * The host attached to the CLI daemon, or lost it. Fall back to the WebSocket meanwhile.
+ */
+function handleRelayEvent(connected) {
+  relayConnected = connected;
+  if (connected) {
+    console.log("[noet] Connected to CLI through the Native Messaging host");
+    if (wsReconnectTimer) {
+      clearInterval(wsReconnectTimer);
+      wsReconnectTimer = null;
+    }
+    if (ws) {
+      ws.close();
+    }
+  } else {
+    connectWebSocket();
+  }
+}
+
+/**
+ * Debug mode is kept by the host, so it survives browser restarts
+ */
+async function loadDebugMode() {
+  try {
+    const response = await chrome.runtime.sendNativeMessage(NATIVE_HOST_NAME, {
+      id: crypto.randomUUID(),
+      command: "get_debug_mode"
+    });
+    if (response && response.data) {
+      debugMode = response.data.debug_mode;
+    }
+  } catch (e) {
+    console.log("[noet] Could not load debug mode from native host:", e.message);
+  }
+}
+
+async function saveDebugMode(enabled) {
+  try {
+    await chrome.runtime.sendNativeMessage(NATIVE_HOST_NAME, {
+      id: crypto.randomUUID(),
+      command: "set_debug_mode",
+      params: { enabled }
+    });
+  } catch (e) {
+    console.log("[noet] Could not save debug mode to native host:", e.message);
+  }
+}
+
 /**
  * Send message to Native Host
  */
@@ -112,6 +196,10 @@ async function handleHostMessage(request) {
         result = await handleGetArticle(params);
         break;
 
+      case "upload_image_chunk":
+        result = handleUploadImageChunk(params);
+        break;
+
       case "create_article":
         result = await handleCreateArticle(params);
         break;
@@ -126,6 +214,7 @@ async function handleHostMessage(request) {
 
       case "set_debug_mode":
         debugMode = params.enabled;
+        await saveDebugMode(debugMode);
         result = { success: true, debug_mode: debugMode };
         break;
 
@@ -162,7 +251,9 @@ async function handleHostMessage(request) {
 async function handlePing() {
   return {
     version: VERSION,
-    extension_id: chrome.runtime.id
+    extension_id: chrome.runtime.id,
+    protocol_version: PROTOCOL_VERSION,
+    commands: SUPPORTED_COMMANDS
   };
 }
 
@@ -185,6 +276,7 @@ async function handleListArticles(params) {
   // Scrape own articles from /notes page
   return await executeInTab("https://note.com/notes", async (tabId) => {
     await waitForTabLoad(tabId);
+    await ensureLoggedIn(tabId);
     await humanPageLoadWait();
     await randomDelay(500, 1500); // Extra wait for SPA content
 
@@ -201,9 +293,7 @@ async function handleGetArticle(params) {
   const { key, username } = params;
 
   if (!username || !key) {
-    const error = new Error("username and key are required");
-    error.code = "INVALID_PARAMS";
-    throw error;
+    throw protocolError("INVALID_PARAMS", "username and key are required");
   }
 
   // Scrape article from public page
@@ -218,16 +308,77 @@ async function handleGetArticle(params) {
       func: scrapeArticlePage
     });
 
-    return result[0].result;
+    // Reply with the fields protocol.yaml lists for get_article
+    const { success, error: reason, ...article } = result[0].result;
+    if (!success) {
+      throw protocolError("NOT_FOUND", reason);
+    }
+    return { ...article, published_at: article.published_at || null };
   });
 }
 
+/**
+ * Store one chunk of an image; the CLI resends a chunk whose ack it didn't get
+ */
+function handleUploadImageChunk(params) {
+  const { upload_id, index, total, data } = params;
+  if (typeof upload_id !== "string" || typeof data !== "string" ||
+      !Number.isInteger(total) || !Number.isInteger(index) || index < 0 || index >= total) {
+    throw protocolError("INVALID_PARAMS", "upload_image_chunk のパラメータが不正です");
+  }
+
+  let upload = imageUploads.get(upload_id);
+  if (!upload) {
+    upload = { total, chunks: new Map(), timer: null };
+    imageUploads.set(upload_id, upload);
+  }
+  if (upload.total !== total) {
+    throw protocolError("INVALID_PARAMS", `アップロード ${upload_id} のチャンク数が一致しません`);
+  }
+  upload.chunks.set(index, data);
+
+  // Drop uploads no article asked for
+  clearTimeout(upload.timer);
+  upload.timer = setTimeout(() => imageUploads.delete(upload_id), IMAGE_UPLOAD_TTL_MS);
+
+  return { upload_id, index, received: upload.chunks.size };
+}
+
+/**
+ * Turn an image sent with upload_image_chunk back into one with its data
+ */
+function takeUploadedImage(image) {
+  if (!image || !image.upload_id) {
+    return image;
+  }
+
+  const upload = imageUploads.get(image.upload_id);
+  if (!upload || upload.chunks.size !== upload.total) {
+    throw protocolError("INVALID_PARAMS", `画像 ${image.filename} のデータが揃っていません。もう一度実行してください`);
+  }
+  clearTimeout(upload.timer);
+  imageUploads.delete(image.upload_id);
+
+  const { upload_id, ...rest } = image;
+  const data = Array.from({ length: upload.total }, (_, i) => upload.chunks.get(i)).join("");
+  return { ...rest, data };
+}
+
+function withUploadedImages(params) {
+  return {
+    ...params,
+    images: (params.images || []).map(takeUploadedImage),
+    header_image: takeUploadedImage(params.header_image || null)
+  };
+}
+
 async function handleCreateArticle(params) {
-  const { title, body, tags = [], magazines = [], draft = false, images = [], header_image = null } = params;
+  const { title, body, tags = [], magazines = [], draft = false, images = [], header_image = null } = withUploadedImages(params);
 
   // Navigate via note.com/notes/new which redirects to editor.note.com
   return await executeInTab("https://note.com/notes/new", async (tabId) => {
     await waitForTabLoad(tabId);
+    await ensureLoggedIn(tabId);
     await humanPageLoadWait();
 
     // Wait for editor page to load (it's a redirect to editor.note.com)
@@ -277,9 +428,16 @@ async function handleCreateArticle(params) {
       // Wait for save to complete
       await randomDelay(2000, 3000);
 
+      // Editor URL contains the new article key (editor.note.com/notes/<key>/edit)
+      const draftUrlResult = await chrome.scripting.executeScript({
+        target: { tabId },
+        func: () => window.location.href
+      });
+
       return {
         success: true,
         status: "draft",
+        url: draftUrlResult[0].result,
         message: "Article saved as draft",
         uploaded_images: uploadedImages,
         header_image_url: headerImageUrl
@@ -336,11 +494,12 @@ async function handleCreateArticle(params) {
 }
 
 async function handleUpdateArticle(params) {
-  const { key, title, body, tags, magazines = [], draft = false, images = [], header_image = null } = params;
+  const { key, title, body, tags, magazines = [], draft = false, images = [], header_image = null } = withUploadedImages(params);
 
   // First go to /notes, find the article, click edit
   return await executeInTab("https://note.com/notes", async (tabId) => {
     await waitForTabLoad(tabId);
+    await ensureLoggedIn(tabId);
     await humanPageLoadWait();
     await randomDelay(500, 1500); // Wait for article list to render
 
@@ -466,6 +625,7 @@ async function handleDeleteArticle(params) {
 
   return await executeInTab("https://note.com/notes", async (tabId) => {
     await waitForTabLoad(tabId);
+    await ensureLoggedIn(tabId);
     await humanPageLoadWait();
     await randomDelay(500, 1500); // Wait for article list to render
 
@@ -516,9 +676,136 @@ async function handleDeleteArticle(params) {
   });
 }
 
+/**
+ * Popup actions on the CLI workspace, whose files the Native Messaging host serves
+ */
+
+async function sendHostCommand(command, params = {}) {
+  const response = await chrome.runtime.sendNativeMessage(NATIVE_HOST_NAME, {
+    id: crypto.randomUUID(),
+    command,
+    params
+  });
+  if (!response || response.status !== "success") {
+    throw protocolError(
+      response?.error?.code || "UNKNOWN",
+      response?.error?.message || `${command} failed`
+    );
+  }
+  return response.data;
+}
+
+async function readWorkspaceImage(image) {
+  const data = await sendHostCommand("read_workspace_image", { path: image.path });
+  return {
+    local_path: image.reference,
+    filename: data.filename,
+    caption: image.caption,
+    mime_type: data.mime_type,
+    data: data.data
+  };
+}
+
+// Publish a workspace file, as `noet push` does
+async function pushWorkspaceArticle(path) {
+  const article = await sendHostCommand("read_workspace_article", { path });
+
+  const images = [];
+  for (const image of article.images) {
+    images.push(await readWorkspaceImage(image));
+  }
+  const header_image = article.header_image ? await readWorkspaceImage(article.header_image) : null;
+
+  const params = {
+    title: article.title,
+    body: article.body,
+    tags: article.tags,
+    draft: article.draft,
+    images,
+    header_image
+  };
+  const result = article.note_key
+    ? await handleUpdateArticle({ ...params, key: article.note_key })
+    : await handleCreateArticle(params);
+  if (!result.success) {
+    throw protocolError("DOM_ERROR", result.error || "記事を投稿できませんでした");
+  }
+
+  // Link the file to the article so the next push updates it
+  const key = article.note_key || result.key || result.url?.match(/\/(?:n|notes)\/(n[0-9a-zA-Z]+)/)?.[1];
+  if (key) {
+    await sendHostCommand("record_workspace_push", {
+      path: article.path,
+      key,
+      uploaded_images: result.uploaded_images || []
+    });
+  }
+  return { ...result, key: key || null };
+}
+
+// Save the article shown at `url` into the workspace, as `noet pull` does
+async function saveArticleToWorkspace(url, overwrite = false) {
+  const match = url?.match(/^https:\/\/note\.com\/([^/]+)\/n\/(n[0-9a-zA-Z]+)/);
+  if (!match) {
+    throw protocolError("INVALID_PARAMS", "note の記事ページではありません");
+  }
+  const [, username, key] = match;
+
+  const article = await handleGetArticle({ username, key });
+  return await sendHostCommand("write_workspace_article", {
+    key,
+    title: article.title,
+    html: article.html,
+    tags: article.tags,
+    overwrite
+  });
+}
+
+// The popup closes when it loses focus, so the work is done here
+chrome.runtime.onMessage.addListener((message, sender, sendResponse) => {
+  let operation;
+  switch (message.action) {
+    case "push_workspace_article":
+      operation = pushWorkspaceArticle(message.path);
+      break;
+    case "save_to_workspace":
+      operation = saveArticleToWorkspace(message.url, message.overwrite);
+      break;
+    default:
+      return false;
+  }
+
+  operation
+    .then(data => sendResponse({ status: "success", data }))
+    .catch(e => sendResponse({
+      status: "error",
+      error: { code: e.code || "UNKNOWN", message: e.message }
+    }));
+  return true; // Keep channel open for async response
+});
+
 /**
  * Execute operation in a tab
  */
+/**
+ * Error carrying a code from the error_codes section of protocol.yaml
+ */
+function protocolError(code, message) {
+  const error = new Error(message);
+  error.code = code;
+  return error;
+}
+
+/**
+ * Fail with NOT_LOGGED_IN if note.com sent the tab to its login page
+ */
+async function ensureLoggedIn(tabId) {
+  const tab = await chrome.tabs.get(tabId);
+  if (tab.url && new URL(tab.url).pathname.startsWith("/login")) {
+    throw protocolError("NOT_LOGGED_IN", "note.com redirected to the login page");
+  }
+}
+
 async function executeInTab(url, operation) {
   const tab = await chrome.tabs.create({
     url,
@@ -581,7 +868,7 @@ async function waitForElement(tabId, selector, timeout = 10000) {
     await randomDelay(200, 500);
   }
 
-  throw new Error(`Element ${selector} not found within ${timeout}ms`);
+  throw protocolError("DOM_ERROR", `Element ${selector} not found within ${timeout}ms`);
 }
 
 /**
@@ -1408,13 +1695,81 @@ function confirmDeleteDialog() {
  * WebSocket Communication
  */
 
-function connectWebSocket() {
-  if (ws && ws.readyState === WebSocket.OPEN) {
+/**
+ * Token the CLI expects on the WebSocket, saved from the options page or
+ * fetched from the Native Messaging host
+ */
+async function getWebSocketToken() {
+  const { wsToken } = await chrome.storage.local.get("wsToken");
+  if (wsToken) {
+    return wsToken;
+  }
+
+  try {
+    const response = await chrome.runtime.sendNativeMessage(NATIVE_HOST_NAME, {
+      id: crypto.randomUUID(),
+      command: "get_token"
+    });
+    const token = response && response.data && response.data.token;
+    if (token) {
+      await chrome.storage.local.set({ wsToken: token });
+      return token;
+    }
+  } catch (e) {
+    console.log("[noet] Could not get token from native host:", e.message);
+  }
+  return null;
+}
+
+/**
+ * Where the CLI is listening. It moves off the default port when that is taken,
+ * and publishes the one it uses through the Native Messaging host.
+ */
+async function getWebSocketUrl() {
+  try {
+    const response = await chrome.runtime.sendNativeMessage(NATIVE_HOST_NAME, {
+      id: crypto.randomUUID(),
+      command: "get_endpoint"
+    });
+    const url = response && response.data && response.data.url;
+    if (url) {
+      return url;
+    }
+  } catch (e) {
+    console.log("[noet] Could not get endpoint from native host:", e.message);
+  }
+  return DEFAULT_WEBSOCKET_URL;
+}
+
+function scheduleWebSocketReconnect() {
+  // Attempt reconnection every 5 seconds
+  if (!wsReconnectTimer && !relayConnected) {
+    wsReconnectTimer = setInterval(connectWebSocket, 5000);
+  }
+}
+
+async function connectWebSocket() {
+  if (ws && (ws.readyState === WebSocket.OPEN || ws.readyState === WebSocket.CONNECTING)) {
     return; // Already connected
   }
+  if (relayConnected) {
+    return; // The Native Messaging host relays for the CLI
+  }
+
+  const token = await getWebSocketToken();
+  if (!token) {
+    console.log("[noet] No CLI token yet. Set it on the options page (run `noet setup`)");
+    scheduleWebSocketReconnect();
+    return;
+  }
+
+  const url = await getWebSocketUrl();
+  if (ws || relayConnected) {
+    return; // Connected while we were looking up the endpoint
+  }
 
   try {
-    ws = new WebSocket(WEBSOCKET_URL);
+    ws = new WebSocket(`${url}/?token=${encodeURIComponent(token)}`);
 
     ws.onopen = () => {
       console.log("[noet] WebSocket connected to CLI");
@@ -1437,10 +1792,7 @@ function connectWebSocket() {
     ws.onclose = () => {
       console.log("[noet] WebSocket disconnected");
       ws = null;
-      // Attempt reconnection every 5 seconds
-      if (!wsReconnectTimer) {
-        wsReconnectTimer = setInterval(connectWebSocket, 5000);
-      }
+      scheduleWebSocketReconnect();
     };
 
     ws.onerror = (error) => {
@@ -1482,6 +1834,10 @@ async function handleWebSocketMessage(request) {
         result = await handleGetArticle(params);
         break;
 
+      case "upload_image_chunk":
+        result = handleUploadImageChunk(params);
+        break;
+
       case "create_article":
         result = await handleCreateArticle(params);
         break;
@@ -1496,6 +1852,7 @@ async function handleWebSocketMessage(request) {
 
       case "set_debug_mode":
         debugMode = params.enabled;
+        await saveDebugMode(debugMode);
         result = { success: true, debug_mode: debugMode };
         break;
 
@@ -1528,8 +1885,20 @@ async function handleWebSocketMessage(request) {
 // Initialize
 console.log("[noet] Extension loaded, version:", VERSION);
 
+loadDebugMode();
+
 // Try to connect via Native Messaging (if host is installed)
 connectToNativeHost();
 
 // Also try WebSocket connection (for direct CLI communication)
 connectWebSocket();
+
+// Reconnect with the new token when it is changed on the options page
+chrome.storage.onChanged.addListener((changes, area) => {
+  if (area === "local" && changes.wsToken) {
+    if (ws) {
+      ws.close();
+    }
+    connectWebSocket();
+  }
+});
@@ -3,7 +3,7 @@
 // Injected into note.com pages for DOM operations
 // Receives messages from background.js
 
-const VERSION = "0.1.2";
+const VERSION = chrome.runtime.getManifest().version;
 
 /**
  * Message handler from background script
@@ -0,0 +1,49 @@
+// noet Browser Extension - Options page
+//
+// Shows the extension ID and the token used to authenticate to the CLI
+
+const NATIVE_HOST_NAME = "com.noet.host";
+
+const tokenInput = document.getElementById("token");
+const statusLine = document.getElementById("status");
+
+function showStatus(message) {
+  statusLine.textContent = message;
+}
+
+async function load() {
+  document.getElementById("extension-id").value = chrome.runtime.id;
+  const { wsToken } = await chrome.storage.local.get("wsToken");
+  tokenInput.value = wsToken || "";
+}
+
+document.getElementById("save").addEventListener("click", async () => {
+  const token = tokenInput.value.trim();
+  if (!token) {
+    await chrome.storage.local.remove("wsToken");
+    showStatus("トークンを削除しました");
+    return;
+  }
+  await chrome.storage.local.set({ wsToken: token });
+  showStatus("保存しました");
+});
+
+document.getElementById("fetch").addEventListener("click", async () => {
+  try {
+    const response = await chrome.runtime.sendNativeMessage(NATIVE_HOST_NAME, {
+      id: crypto.randomUUID(),
+      command: "get_token"
+    });
+    if (response.status !== "success") {
+      showStatus(response.error ? response.error.message : "取得に失敗しました");
+      return;
+    }
+    tokenInput.value = response.data.token;
+    await chrome.storage.local.set({ wsToken: response.data.token });
+    showStatus("CLI からトークンを取得しました");
+  } catch (e) {
+    showStatus(`CLI に接続できません (${e.message})。トークンを貼り付けてください`);
+  }
+});
+
+load();
@@ -0,0 +1,105 @@
+// noet Browser Extension - Popup
+//
+// Pushes files of the CLI workspace and saves the open article into it.
+// The Native Messaging host serves the files; background.js does the note.com work.
+
+const NATIVE_HOST_NAME = "com.noet.host";
+const ARTICLE_URL = /^https:\/\/note\.com\/[^/]+\/n\/n[0-9a-zA-Z]+/;
+
+const statusLine = document.getElementById("status");
+const saveButton = document.getElementById("save");
+
+function showStatus(message) {
+  statusLine.textContent = message;
+}
+
+async function runInBackground(message) {
+  const response = await chrome.runtime.sendMessage(message);
+  if (response.status !== "success") {
+    const error = new Error(response.error.message);
+    error.code = response.error.code;
+    throw error;
+  }
+  return response.data;
+}
+
+async function push(article, button) {
+  button.disabled = true;
+  showStatus(`${article.path} を投稿中...`);
+  try {
+    const result = await runInBackground({ action: "push_workspace_article", path: article.path });
+    showStatus(result.url ? `投稿しました: ${result.url}` : "投稿しました");
+    await loadArticles();
+  } catch (e) {
+    showStatus(`投稿できませんでした: ${e.message}`);
+  } finally {
+    button.disabled = false;
+  }
+}
+
+async function loadArticles() {
+  const list = document.getElementById("articles");
+  let data;
+  try {
+    const response = await chrome.runtime.sendNativeMessage(NATIVE_HOST_NAME, {
+      id: crypto.randomUUID(),
+      command: "list_workspace_articles"
+    });
+    if (response.status !== "success") {
+      showStatus(response.error.message);
+      return;
+    }
+    data = response.data;
+  } catch (e) {
+    showStatus(`CLI に接続できません (${e.message})。noet setup を実行してください`);
+    return;
+  }
+
+  document.getElementById("root").textContent = data.root;
+  list.replaceChildren();
+  for (const article of data.articles) {
+    const item = document.createElement("li");
+    const title = document.createElement("span");
+    title.className = "title";
+    title.textContent = article.title || article.path;
+    title.title = article.path;
+
+    const button = document.createElement("button");
+    button.textContent = article.note_key ? "更新" : "投稿";
+    button.addEventListener("click", () => push(article, button));
+
+    item.append(title, button);
+    list.append(item);
+  }
+  if (data.articles.length === 0) {
+    showStatus("記事ファイルがありません");
+  }
+}
+
+async function save(url, overwrite = false) {
+  saveButton.disabled = true;
+  showStatus("記事を取得中...");
+  try {
+    const result = await runInBackground({ action: "save_to_workspace", url, overwrite });
+    showStatus(`${result.path} に保存しました`);
+    await loadArticles();
+  } catch (e) {
+    if (e.code === "CONFLICT" && !overwrite && confirm(`${e.message}\n上書きしますか?`)) {
+      return await save(url, true);
+    }
+    showStatus(`保存できませんでした: ${e.message}`);
+  } finally {
+    saveButton.disabled = false;
+  }
+}
+
+async function init() {
+  const [tab] = await chrome.tabs.query({ active: true, currentWindow: true });
+  if (tab?.url && ARTICLE_URL.test(tab.url)) {
+    saveButton.disabled = false;
+    saveButton.addEventListener("click", () => save(tab.url));
+  }
+  await loadArticles();
+}
+
+init();